@@ -3,179 +3,18 @@ use tauri::{
 };
 use sqlx::sqlite::SqlitePool;
 use dirs;
-use sqlx::Row;
 use crate::{
     commands::node::start_ipfs_daemon,
+    utils::downloads::init_downloads,
+    utils::key_rotation::init_key_rotations,
+    utils::schema::ensure_table_schema,
+    utils::key_store::init_key_store,
+    utils::share::init_share_links,
     DB_POOL,
     constants::substrate::WSS_ENDPOINT,
 };
 
 
-async fn ensure_table_schema(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    // Define the expected table schemas
-    const TABLE_SCHEMAS: &[(&str, &[(&str, &str)])] = &[
-        (
-            "user_profiles",
-            &[
-                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
-                ("owner", "TEXT NOT NULL"),
-                ("cid", "TEXT NOT NULL"),
-                ("file_hash", "TEXT"),
-                ("file_name", "TEXT"),
-                ("file_size_in_bytes", "INTEGER"),
-                ("is_assigned", "BOOLEAN"),
-                ("last_charged_at", "INTEGER"),
-                ("main_req_hash", "TEXT"),
-                ("selected_validator", "TEXT"),
-                ("total_replicas", "INTEGER"),
-                ("block_number", "INTEGER NOT NULL"),
-                ("processed_timestamp", "TIMESTAMP DEFAULT CURRENT_TIMESTAMP"),
-                ("profile_cid", "TEXT"),
-                ("source", "TEXT"),
-                ("miner_ids", "TEXT"),
-                ("created_at", "INTEGER"),
-                ("type", "TEXT DEFAULT 'public'"),
-                ("is_folder", "BOOLEAN DEFAULT 0"),
-            ],
-        ),
-        (
-            "sync_folder_files",
-            &[
-                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
-                ("owner", "TEXT NOT NULL"),
-                ("cid", "TEXT NOT NULL"),
-                ("file_hash", "TEXT"),
-                ("file_name", "TEXT"),
-                ("file_size_in_bytes", "INTEGER"),
-                ("is_assigned", "BOOLEAN"),
-                ("last_charged_at", "INTEGER"),
-                ("main_req_hash", "TEXT"),
-                ("selected_validator", "TEXT"),
-                ("total_replicas", "INTEGER"),
-                ("block_number", "INTEGER NOT NULL"),
-                ("processed_timestamp", "TIMESTAMP DEFAULT CURRENT_TIMESTAMP"),
-                ("profile_cid", "TEXT"),
-                ("source", "TEXT"),
-                ("miner_ids", "TEXT"),
-                ("type", "TEXT"),
-                ("is_folder", "BOOLEAN"),
-            ],
-        ),
-        (
-            "file_paths",
-            &[
-                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
-                ("file_name", "TEXT NOT NULL"),
-                ("file_hash", "TEXT NOT NULL"),
-                ("timestamp", "INTEGER NOT NULL"),
-                ("path", "TEXT NOT NULL"),
-            ],
-        ),
-        (
-            "sub_accounts",
-            &[
-                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
-                ("account_id", "TEXT NOT NULL"),
-                ("sub_account_seed_phrase", "TEXT NOT NULL"),
-                ("created_at", "TIMESTAMP DEFAULT CURRENT_TIMESTAMP"),
-            ],
-        ),
-    ];
-
-    for (table_name, columns) in TABLE_SCHEMAS {
-        // Create table if it doesn't exist with basic structure
-        let create_table = format!(
-            "CREATE TABLE IF NOT EXISTS {} ({})",
-            table_name,
-            columns
-                .iter()
-                .map(|(name, typ)| format!("{} {}", name, typ))
-                .collect::<Vec<_>>()
-                .join(", ")
-        );
-        sqlx::query(&create_table).execute(pool).await?;
-
-        // Check and add any missing columns
-        let pragma_sql = format!("PRAGMA table_info({})", table_name);
-        let columns_info = sqlx::query(&pragma_sql)
-            .fetch_all(pool)
-            .await?;
-
-        for (column_name, column_type) in *columns {
-            let column_exists = columns_info.iter().any(|row| {
-                let name: String = row.get("name");
-                name == *column_name
-            });
-
-            if !column_exists {
-                println!("[Setup] Adding column {} to table {}", column_name, table_name);
-                sqlx::query(
-                    &format!("ALTER TABLE {} ADD COLUMN {} {}", table_name, column_name, column_type)
-                )
-                .execute(pool)
-                .await?;
-            }
-        }
-    }
-
-    // Create other tables that don't need schema migration
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS encryption_keys (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            key_name TEXT NOT NULL UNIQUE,
-            key BLOB NOT NULL
-        )"
-    )
-    .execute(pool)
-    .await?;
-
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS sync_paths (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            path TEXT NOT NULL,
-            type TEXT NOT NULL UNIQUE,
-            timestamp INTEGER NOT NULL
-        )"
-    )
-    .execute(pool)
-    .await?;
-
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS wss_endpoint (
-            id INTEGER PRIMARY KEY CHECK (id = 1),
-            endpoint TEXT NOT NULL,
-            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-        )"
-    )
-    .execute(pool)
-    .await?;
-
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS file_paths (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            file_name TEXT NOT NULL,
-            file_hash TEXT NOT NULL,
-            timestamp INTEGER NOT NULL,
-            path TEXT NOT NULL
-        )"
-    )
-    .execute(pool)
-    .await?;
-
-    sqlx::query(
-        "CREATE TABLE IF NOT EXISTS sub_accounts (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            account_id TEXT NOT NULL,
-            sub_account_seed_phrase TEXT NOT NULL,
-            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
-        )"
-    )
-    .execute(pool)
-    .await?;
-
-    Ok(())
-}
-
 pub fn setup(builder: Builder<Wry>) -> Builder<Wry> {
     builder.setup(|app| {
             println!("[Setup] .setup() closure called in setup.rs");
@@ -259,6 +98,13 @@ pub fn setup(builder: Builder<Wry>) -> Builder<Wry> {
                     eprintln!("[Setup] Failed to update default values in sync_folder_files: {}", e);
                 }
 
+                // Encrypted share links were once saved with their key in the URL fragment
+                if let Err(e) = sqlx::query(
+                    "UPDATE share_links SET url = substr(url, 1, instr(url, '#') - 1) WHERE instr(url, '#') > 0"
+                ).execute(&pool).await {
+                    eprintln!("[Setup] Failed to strip keys from saved share links: {}", e);
+                }
+
                 // Check if any encryption keys exist, create one if none found
                 let key_exists: Option<(i64,)> = sqlx::query_as(
                     "SELECT COUNT(*) as count FROM encryption_keys"
@@ -308,10 +154,22 @@ pub fn setup(builder: Builder<Wry>) -> Builder<Wry> {
 
                 println!("[Setup] Database initialized successfully");
 
+                // Tells the UI whether to ask for the passphrase
+                init_key_store(handle.clone()).await;
+
                 // Start IPFS daemon
+                let downloads_handle = handle.clone();
+                let rotations_handle = handle.clone();
                 if let Err(e) = start_ipfs_daemon(handle).await {
                     eprintln!("Failed to start IPFS daemon: {e:?}");
                 }
+
+                // Picks up downloads interrupted by the last shutdown
+                init_downloads(downloads_handle).await;
+                // And key rotations
+                init_key_rotations(rotations_handle).await;
+                // Expired share links stop being provided even if nobody opens the list
+                init_share_links();
             });
             
             Ok(())
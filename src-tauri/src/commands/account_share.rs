@@ -0,0 +1,187 @@
+use std::path::{Path, PathBuf};
+use base64::{engine::general_purpose, Engine as _};
+use sodiumoxide::crypto::box_;
+use crate::commands::ipfs_commands::{download_and_decrypt_file, download_and_decrypt_folder};
+use crate::commands::substrate_tx::{
+    canonical_account_id, fetch_data_public_key, fetch_public_item, notify_share_recipient,
+    set_public_item, sharing_keypair,
+};
+use crate::utils::account_share::{
+    self, encode_account_share, open_own_share, parse_sharing_public_key, publish_share_index,
+    read_published_index, remember_sharing_key, seal_share, unwrap_share_key, AccountShare,
+    SealedShare,
+};
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::pins::unpin_stored_object;
+use crate::utils::share::ShareSource;
+
+async fn recipient_sharing_key(recipient: &str) -> Result<box_::PublicKey, String> {
+    let key = fetch_data_public_key(recipient)
+        .await?
+        .ok_or_else(|| format!("{} has not published a sharing key yet", recipient))?;
+    parse_sharing_public_key(&key)
+}
+
+// Keeps the sharing key for the profile sync; a locked key store only delays that
+async fn keep_sharing_key(account_id: &str, secret_key: &box_::SecretKey) {
+    if let Err(e) = remember_sharing_key(account_id, secret_key).await {
+        eprintln!("[AccountShare] Failed to store the sharing key of {}: {}", account_id, e);
+    }
+}
+
+// The shares the account publishes right now, which may include ones made on other
+// devices
+async fn published_shares(
+    ipfs: &IpfsClient,
+    account_id: &str,
+    public_key: &box_::PublicKey,
+    secret_key: &box_::SecretKey,
+) -> Result<Vec<(AccountShare, SealedShare)>, String> {
+    let item = fetch_public_item(account_id).await?;
+    read_published_index(ipfs, account_id, item.as_deref())
+        .await?
+        .into_iter()
+        .map(|entry| Ok((open_own_share(&entry, account_id, public_key, secret_key)?, entry)))
+        .collect()
+}
+
+// Publishes the index listing `shares`, points the account's public item at it and
+// makes it this device's list of outgoing shares
+async fn publish_shares(
+    ipfs: &IpfsClient,
+    account_id: &str,
+    mnemonic: &str,
+    shares: &[(AccountShare, SealedShare)],
+) -> Result<(), String> {
+    let entries: Vec<SealedShare> = shares.iter().map(|(_, entry)| entry.clone()).collect();
+    let index_cid = publish_share_index(ipfs, account_id, &entries).await?;
+    set_public_item(mnemonic, hex::encode(index_cid).into_bytes()).await?;
+    let shares: Vec<AccountShare> = shares.iter().map(|(share, _)| share.clone()).collect();
+    account_share::replace_outgoing_shares(account_id, &shares).await
+}
+
+/// Shares a private file or folder with `recipient` (an SS58 address that has published
+/// its sharing key): either `file_path` on disk or the stored file or folder behind
+/// `metadata_cid` (its metadata or manifest CID), decrypted with `encryption_key`
+/// (base64) or the latest account key. The recipient is
+/// notified on chain so its profile sync picks the share up.
+#[tauri::command]
+pub async fn share_with_account(
+    account_id: String,
+    mnemonic: String,
+    recipient: String,
+    file_path: Option<String>,
+    metadata_cid: Option<String>,
+    encryption_key: Option<String>,
+) -> Result<AccountShare, String> {
+    let account_id = canonical_account_id(&account_id)?;
+    let recipient = canonical_account_id(&recipient)?;
+    // Fails early on a mnemonic for some other account
+    let (public_key, secret_key) = sharing_keypair(&mnemonic, &account_id)?;
+    keep_sharing_key(&account_id, &secret_key).await;
+
+    let source = match (file_path, metadata_cid) {
+        (Some(path), None) => ShareSource::Local(PathBuf::from(path)),
+        (None, Some(metadata_cid)) => {
+            let encryption_key = encryption_key
+                .map(|key_b64| general_purpose::STANDARD.decode(&key_b64))
+                .transpose()
+                .map_err(|e| format!("Failed to decode base64 key: {}", e))?;
+            ShareSource::Stored { metadata_cid, encryption_key }
+        }
+        _ => return Err("Give either a file path or a metadata CID to share".to_string()),
+    };
+
+    let recipient_key = recipient_sharing_key(&recipient).await?;
+    let ipfs = IpfsClient::from_settings().await;
+    // Read before uploading anything, so an unreadable index or a public item used for
+    // something else stops the share early
+    let mut shares = published_shares(&ipfs, &account_id, &public_key, &secret_key).await?;
+    let share = encode_account_share(&ipfs, &account_id, &recipient, &recipient_key, source).await?;
+    let entry = seal_share(&share, &recipient_key, &public_key)?;
+    shares.push((share.clone(), entry));
+    publish_shares(&ipfs, &account_id, &mnemonic, &shares).await?;
+    notify_share_recipient(&mnemonic, &recipient)
+        .await
+        .map_err(|e| format!("Shared '{}', but failed to notify {}: {}", share.name, recipient, e))?;
+    println!("[AccountShare] Shared '{}' with {}", share.name, recipient);
+    Ok(share)
+}
+
+#[tauri::command]
+pub async fn list_outgoing_shares(account_id: String) -> Result<Vec<AccountShare>, String> {
+    account_share::list_outgoing_shares(&canonical_account_id(&account_id)?).await
+}
+
+/// Items other accounts have shared with `account_id`, as found by the profile sync.
+#[tauri::command]
+pub async fn get_shared_with_me(account_id: String) -> Result<Vec<AccountShare>, String> {
+    account_share::list_shared_with_me(&canonical_account_id(&account_id)?).await
+}
+
+/// Stops sharing an item: it leaves the published index, so the recipient's next sync
+/// drops it, and its re-encrypted copy is unpinned locally.
+#[tauri::command]
+pub async fn revoke_account_share(account_id: String, mnemonic: String, share_id: String) -> Result<(), String> {
+    let account_id = canonical_account_id(&account_id)?;
+    let (public_key, secret_key) = sharing_keypair(&mnemonic, &account_id)?;
+    keep_sharing_key(&account_id, &secret_key).await;
+
+    let ipfs = IpfsClient::from_settings().await;
+    let mut shares = published_shares(&ipfs, &account_id, &public_key, &secret_key).await?;
+    let position = shares
+        .iter()
+        .position(|(s, _)| s.id == share_id)
+        .ok_or_else(|| format!("No share with id {}", share_id))?;
+    let (share, _) = shares.remove(position);
+
+    publish_shares(&ipfs, &account_id, &mnemonic, &shares).await?;
+    if let Err(e) = unpin_stored_object(&ipfs, &share.cid).await {
+        eprintln!("[AccountShare] Failed to unpin revoked share {}: {}", share.cid, e);
+    }
+    Ok(())
+}
+
+/// Downloads an item shared with `account_id` into `output_dir`, opening its key with
+/// the sharing key derived from `mnemonic`. Returns the path written.
+#[tauri::command]
+pub async fn download_shared_item(
+    account_id: String,
+    mnemonic: String,
+    share_id: String,
+    output_dir: String,
+) -> Result<String, String> {
+    let account_id = canonical_account_id(&account_id)?;
+    let (public_key, secret_key) = sharing_keypair(&mnemonic, &account_id)?;
+    keep_sharing_key(&account_id, &secret_key).await;
+    let share = account_share::get_shared_with_me(&account_id, &share_id).await?;
+    let key_b64 = general_purpose::STANDARD.encode(unwrap_share_key(&share, &public_key, &secret_key)?);
+
+    let name = Path::new(&share.name)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .ok_or_else(|| format!("Share {} has no usable name", share.id))?;
+    let output_path = Path::new(&output_dir).join(&name);
+    if share.is_folder {
+        download_and_decrypt_folder(
+            account_id,
+            share.cid,
+            name,
+            output_dir,
+            Some(key_b64),
+            String::new(),
+            None,
+        )
+        .await?;
+    } else {
+        download_and_decrypt_file(
+            account_id,
+            share.cid,
+            output_path.to_string_lossy().to_string(),
+            Some(key_b64),
+            String::new(),
+        )
+        .await?;
+    }
+    Ok(output_path.to_string_lossy().to_string())
+}
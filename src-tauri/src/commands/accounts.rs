@@ -1,6 +1,8 @@
 use crate::constants::substrate::WSS_ENDPOINT;
+use crate::commands::substrate_tx::account_secret;
 use crate::utils::accounts::{
-    create_and_store_encryption_key, import_encryption_key, list_encryption_keys,
+    create_and_store_encryption_key, create_derived_encryption_key, import_encryption_key,
+    list_encryption_keys, restore_derived_encryption_keys, DEFAULT_DERIVED_RESTORE_COUNT,
 };
 use chrono::Utc;
 use sqlx::Row;
@@ -10,19 +12,41 @@ pub async fn create_encryption_key() -> Result<(), String> {
     create_and_store_encryption_key().await
 }
 
+/// Creates the account's next key derived from `mnemonic` instead of a random one, so a
+/// fresh install can recover it with `restore_derived_keys`. Returns its index.
+#[tauri::command]
+pub async fn create_derived_key(account_id: String, mnemonic: String) -> Result<u64, String> {
+    create_derived_encryption_key(&account_id, &account_secret(&mnemonic, &account_id)?).await
+}
+
+/// Recreates the first `count` keys derived from `mnemonic` (10 by default) and
+/// returns how many were missing.
+#[tauri::command]
+pub async fn restore_derived_keys(
+    account_id: String,
+    mnemonic: String,
+    count: Option<u64>,
+) -> Result<usize, String> {
+    let secret = account_secret(&mnemonic, &account_id)?;
+    restore_derived_encryption_keys(&account_id, &secret, count.unwrap_or(DEFAULT_DERIVED_RESTORE_COUNT)).await
+}
+
 #[derive(serde::Serialize)]
 pub struct EncryptionKeyInfo {
     pub id: i64,
     pub key: String,
+    // Hex ID that encrypted files and erasure metadata record
+    pub key_id: String,
+    // Set for keys derived from the account mnemonic; absent for random ones
+    pub derivation_index: Option<i64>,
 }
 
 #[tauri::command]
 pub async fn get_encryption_keys() -> Result<Vec<EncryptionKeyInfo>, String> {
     let keys = list_encryption_keys().await?;
-    println!("keys : {:?}", keys);
     Ok(keys
         .into_iter()
-        .map(|(key, id)| EncryptionKeyInfo { id, key })
+        .map(|(key, id, key_id, derivation_index)| EncryptionKeyInfo { id, key, key_id, derivation_index })
         .collect())
 }
 
@@ -191,7 +215,7 @@ pub async fn export_app_data() -> Result<ExportDataResult, String> {
         .await
         .map_err(|e| format!("Failed to fetch encryption keys: {}", e))?;
 
-    let encryption_keys: Vec<String> = keys.into_iter().map(|(key, _id)| key).collect();
+    let encryption_keys: Vec<String> = keys.into_iter().map(|(key, ..)| key).collect();
 
     println!(
         "[Export] Exported {} encryption keys, public path: {:?}, private path: {:?}",
@@ -224,6 +248,12 @@ pub async fn reset_app() -> Result<(), String> {
         "sync_paths",
         "wss_endpoint",
         "sub_accounts",
+        "file_index",
+        "app_settings",
+        "key_store",
+        "sharing_keys",
+        "key_rotations",
+        "key_rotation_items",
     ];
 
     for table in tables_to_clear {
@@ -238,6 +268,8 @@ pub async fn reset_app() -> Result<(), String> {
         }
     }
     println!("[Reset App] All tables cleared.");
+    // The passphrase went with the key_store table
+    crate::utils::key_store::forget_key_store();
 
 
     println!("[Reset App] Restoring default WSS endpoint...");
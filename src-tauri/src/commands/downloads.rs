@@ -0,0 +1,33 @@
+use crate::utils::downloads::{self, DownloadJob, DownloadRequest};
+
+/// Queues a file, folder or public folder download and returns the job. Progress and
+/// state changes arrive as `download_progress` events carrying the job.
+#[tauri::command]
+pub async fn queue_download(request: DownloadRequest) -> Result<DownloadJob, String> {
+    downloads::enqueue_download(request).await
+}
+
+#[tauri::command]
+pub async fn list_downloads() -> Result<Vec<DownloadJob>, String> {
+    downloads::list_downloads().await
+}
+
+#[tauri::command]
+pub async fn pause_download(id: String) -> Result<DownloadJob, String> {
+    downloads::pause_download(&id).await
+}
+
+#[tauri::command]
+pub async fn resume_download(id: String) -> Result<DownloadJob, String> {
+    downloads::resume_download(&id).await
+}
+
+#[tauri::command]
+pub async fn cancel_download(id: String) -> Result<DownloadJob, String> {
+    downloads::cancel_download(&id).await
+}
+
+#[tauri::command]
+pub async fn clear_finished_downloads() -> Result<u64, String> {
+    downloads::clear_finished_downloads().await
+}
@@ -4,10 +4,15 @@ use crate::utils::{
      decrypt_file
     },
     ipfs::{
-        download_from_ipfs, download_from_ipfs_async,
+        IpfsClient,
     },
     file_operations::{ copy_to_sync_and_add_to_db,
-         remove_from_sync_folder, copy_to_sync_folder}
+         remove_from_sync_folder, copy_to_sync_folder},
+    car::{self, CarExportReport, CarImportReport},
+    erasure::{
+        check_erasure_health, encrypt_and_encode_file, encrypt_and_encode_folder, repair_erasure_file,
+        reconstruct_file_observed, reconstruct_file_streaming, ErasureParams, HealthReport,
+    },
 };
 use fs_extra;
 use std::fs;
@@ -18,11 +23,16 @@ use std::path::{Path, PathBuf};
 use base64::{Engine as _, engine::general_purpose};
 use futures::stream::{self, StreamExt};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use crate::utils::file_operations::sanitize_name;
 use std::collections::HashMap;
 use tauri::Manager;
 use tokio::process::Command;
 use crate::commands::node::get_aws_binary_path;
+use crate::sync_markers::{is_folder_marker, restore_folder_markers};
+use crate::sync_compat::local_name_for;
+use crate::utils::manifest::{check_entry_name, parse_folder_manifest};
+use crate::sync_metadata::{copy_local_metadata, finish_s3_download, finish_s3_folder_download, FileMetadata};
 
 
 // Drops the first segment and returns None if the remaining path is empty
@@ -86,7 +96,7 @@ pub async fn download_and_decrypt_file(
     source: String,
 ) -> Result<(), String> {
     println!("[download_and_decrypt_file] Downloading file with CID: {} to: {}", metadata_cid, output_file);
-    let api_url = "http://127.0.0.1:5001".to_string(); // Convert to owned String
+    let ipfs = IpfsClient::from_settings().await;
 
     // Dynamically get the AWS binary path
     let aws_binary_path = match get_aws_binary_path().await {
@@ -122,6 +132,7 @@ pub async fn download_and_decrypt_file(
         if Path::new(&source).exists() {
             std::fs::copy(&source, &output_file)
                 .map_err(|e| format!("Failed to copy from '{}' to '{}': {}", source, output_file, e))?;
+            copy_local_metadata(Path::new(&source), Path::new(&output_file)).await;
             println!("[download_and_decrypt_file] Copied locally from '{}' to '{}'", source, output_file);
             return Ok(());
         } else {
@@ -146,6 +157,9 @@ pub async fn download_and_decrypt_file(
                 ));
             }
 
+            // Private sync may have stored the object compressed
+            finish_s3_download(&aws_binary_path, &dynamic_path, "https://s3.hippius.com", &source, Path::new(&output_file)).await?;
+
             println!(
                 "[download_and_decrypt_file] Downloaded from S3 '{}' to '{}' via aws cli",
                 source, output_file
@@ -162,124 +176,41 @@ pub async fn download_and_decrypt_file(
         None
     };
 
-    // First try to download as folder file metadata
-    if let Ok(metadata_bytes) = download_from_ipfs_async(&api_url, &metadata_cid).await
-        .map_err(|e| format!("Failed to download metadata: {}", e)) 
-    {
-        if let Ok(file_entry) = serde_json::from_slice::<FileEntry>(&metadata_bytes) {
-            // This is a file from a folder, get the actual metadata
-            let actual_metadata_bytes = download_from_ipfs_async(&api_url, &file_entry.cid).await
-                .map_err(|e| format!("Failed to download file metadata: {}", e))?;
-            let metadata: Metadata = serde_json::from_slice(&actual_metadata_bytes)
-                .map_err(|e| format!("Failed to parse file metadata: {}", e))?;
-            
-            return reconstruct_and_decrypt_file(metadata, output_file, final_encryption_key, api_url).await;
-        }
-    }
+    let metadata = fetch_file_metadata(&ipfs, &metadata_cid).await?;
+    println!("[download_and_decrypt_file] Downloaded metadata");
+    reconstruct_and_decrypt_file(metadata, output_file, final_encryption_key, &ipfs).await
+}
 
-    // If not a folder file, proceed with direct download
-    let metadata_bytes = download_from_ipfs_async(&api_url, &metadata_cid).await
+/// The file metadata behind `metadata_cid`, following a folder's file entry to the
+/// metadata it points at.
+pub(crate) async fn fetch_file_metadata(ipfs: &IpfsClient, metadata_cid: &str) -> Result<Metadata, String> {
+    let metadata_bytes = ipfs.cat(metadata_cid).await
         .map_err(|e| format!("Failed to download metadata: {}", e))?;
-    let metadata: Metadata = serde_json::from_slice(&metadata_bytes)
-        .map_err(|e| format!("Failed to parse file metadata: {}", e))?;
-    println!("[download_and_decrypt_file] Downloaded metadata");
-    reconstruct_and_decrypt_file(metadata, output_file, final_encryption_key, api_url).await
+    let metadata_bytes = match serde_json::from_slice::<FileEntry>(&metadata_bytes) {
+        // This is a file from a folder, get the actual metadata
+        Ok(file_entry) => ipfs.cat(&file_entry.cid).await
+            .map_err(|e| format!("Failed to download file metadata: {}", e))?,
+        Err(_) => metadata_bytes,
+    };
+    serde_json::from_slice(&metadata_bytes)
+        .map_err(|e| format!("Failed to parse file metadata: {}", e))
 }
 
 async fn reconstruct_and_decrypt_file(
     metadata: Metadata,
     output_path: String,
     encryption_key: Option<Vec<u8>>,
-    api_url: String, // Changed to owned String
+    ipfs: &IpfsClient,
 ) -> Result<(), String> {
-    tokio::task::spawn_blocking(move || {
-        let k = metadata.erasure_coding.k;
-        let m = metadata.erasure_coding.m;
-        let chunk_size = metadata.erasure_coding.chunk_size;
-        let file_hash = &metadata.original_file.hash;
-
-        let mut chunk_map: std::collections::HashMap<usize, Vec<&ChunkInfo>> = std::collections::HashMap::new();
-        for chunk in &metadata.chunks {
-            chunk_map
-                .entry(chunk.original_chunk)
-                .or_default()
-                .push(chunk);
-        }
-
-        let mut reconstructed_chunks = Vec::with_capacity(chunk_map.len());
-
-        for orig_idx in 0..chunk_map.len() {
-            let available_chunks = chunk_map.get(&orig_idx).ok_or("Missing chunk info")?;
-            let mut shards: Vec<Option<Vec<u8>>> = vec![None; m];
-            for chunk in available_chunks {
-                let data = download_from_ipfs(&api_url, &chunk.cid.cid).map_err(|e| e.to_string())?;
-                shards[chunk.share_idx] = Some(data);
-            }
-
-            let available_count = shards.iter().filter(|s| s.is_some()).count();
-            if available_count < k {
-                return Err(format!(
-                    "Not enough shards for chunk {}: found {}, need {}",
-                    orig_idx, available_count, k
-                ));
-            }
-
-            let r = ReedSolomon::new(k, m - k).map_err(|e| format!("ReedSolomon error: {e}"))?;
-            r.reconstruct_data(&mut shards)
-                .map_err(|e| format!("Reconstruction failed: {e}"))?;
-
-            let is_last_chunk = orig_idx == chunk_map.len() - 1;
-            let chunk_bytes_needed = if !is_last_chunk {
-                chunk_size
-            } else {
-                let total_bytes_so_far: usize = chunk_size * orig_idx;
-                metadata.erasure_coding.encrypted_size.saturating_sub(total_bytes_so_far)
-            };
-
-            let mut chunk_data = Vec::with_capacity(chunk_bytes_needed);
-            let mut bytes_collected = 0;
-            for i in 0..k {
-                if let Some(ref shard) = shards[i] {
-                    let bytes_to_take = std::cmp::min(chunk_bytes_needed - bytes_collected, shard.len());
-                    chunk_data.extend_from_slice(&shard[..bytes_to_take]);
-                    bytes_collected += bytes_to_take;
-                    if bytes_collected == chunk_bytes_needed {
-                        break;
-                    }
-                }
-            }
-            println!("Chunk {}: reconstructed size {}, expected {}", orig_idx, chunk_data.len(), chunk_bytes_needed);
-            reconstructed_chunks.push(chunk_data);
-        }
-
-        let mut encrypted_data = Vec::new();
-        for chunk in reconstructed_chunks {
-            encrypted_data.extend_from_slice(&chunk);
-        }
-        println!("Combined encrypted data size: {}, expected: {}", encrypted_data.len(), metadata.erasure_coding.encrypted_size);
-
-        let decrypted_data = tauri::async_runtime::block_on(decrypt_file(
-            &encrypted_data,
-            encryption_key,
-        ))?;
-        println!("Decrypted data size: {}, expected original size: {}", decrypted_data.len(), metadata.original_file.size);
-
-        let mut hasher = Sha256::new();
-        hasher.update(&decrypted_data);
-        let actual_hash = format!("{:x}", hasher.finalize());
-        if actual_hash != *file_hash {
-            return Err(format!(
-                "Hash mismatch: expected {}, got {}",
-                file_hash, actual_hash
-            ));
-        }
-
-        std::fs::write(&output_path, &decrypted_data).map_err(|e| format!("Failed to write output file: {}", e))?;
-        println!("File written to {} with size {}", output_path, decrypted_data.len());
-        Ok(())
-    })
-    .await
-    .map_err(|e| format!("Spawn blocking error: {}", e))?
+    let report = reconstruct_file_streaming(&metadata, Path::new(&output_path), encryption_key, ipfs).await?;
+    if !report.unavailable_shares.is_empty() {
+        eprintln!(
+            "[download_and_decrypt_file] Rebuilt {} despite {} unavailable shares",
+            metadata.original_file.name,
+            report.unavailable_shares.len()
+        );
+    }
+    Ok(())
 }
 
 #[tauri::command]
@@ -317,6 +248,86 @@ pub async fn encrypt_and_upload_folder(
     Ok(folder_name)
 }
 
+fn decode_key_b64(encryption_key: Option<String>) -> Result<Option<Vec<u8>>, String> {
+    match encryption_key {
+        Some(key_b64) => general_purpose::STANDARD
+            .decode(&key_b64)
+            .map(Some)
+            .map_err(|e| format!("Failed to decode base64 key: {}", e)),
+        None => Ok(None),
+    }
+}
+
+/// Encrypts and erasure codes a file onto the local IPFS node and returns the metadata
+/// CID, which `download_and_decrypt_file` accepts. k, m, chunk size and whether to
+/// compress fall back to the saved settings.
+#[tauri::command]
+pub async fn erasure_upload_file(
+    file_path: String,
+    k: Option<usize>,
+    m: Option<usize>,
+    chunk_size: Option<usize>,
+    compress: Option<bool>,
+    encryption_key: Option<String>,
+) -> Result<String, String> {
+    let params = ErasureParams::resolve(k, m, chunk_size, compress).await?;
+    let key = decode_key_b64(encryption_key)?;
+    let (_, metadata_cid) = encrypt_and_encode_file(Path::new(&file_path), params, key, &IpfsClient::from_settings().await).await?;
+    Ok(metadata_cid)
+}
+
+/// Folder counterpart of `erasure_upload_file`; returns the manifest CID that
+/// `download_and_decrypt_folder` accepts.
+#[tauri::command]
+pub async fn erasure_upload_folder(
+    folder_path: String,
+    k: Option<usize>,
+    m: Option<usize>,
+    chunk_size: Option<usize>,
+    compress: Option<bool>,
+    encryption_key: Option<String>,
+) -> Result<String, String> {
+    let folder_path = Path::new(&folder_path);
+    if !folder_path.is_dir() {
+        return Err("Provided path is not a directory".to_string());
+    }
+    let params = ErasureParams::resolve(k, m, chunk_size, compress).await?;
+    let key = decode_key_b64(encryption_key)?;
+    encrypt_and_encode_folder(folder_path, params, key, &IpfsClient::from_settings().await).await
+}
+
+/// Reports how many shares of each chunk behind `metadata_cid` are still retrievable.
+/// With `repair`, lost shares are regenerated and the owning account's records and
+/// storage requests move to the updated metadata, whose CID the report carries.
+#[tauri::command]
+pub async fn check_file_health(
+    metadata_cid: String,
+    repair: Option<bool>,
+    account_id: Option<String>,
+    mnemonic: Option<String>,
+) -> Result<HealthReport, String> {
+    let ipfs = IpfsClient::from_settings().await;
+    if !repair.unwrap_or(false) {
+        return check_erasure_health(&ipfs, &metadata_cid, false).await;
+    }
+    match (account_id, mnemonic) {
+        (Some(account_id), Some(mnemonic)) => repair_erasure_file(&ipfs, &account_id, &mnemonic, &metadata_cid).await,
+        _ => Err("Repairing a file needs the account that stores it and its seed phrase".to_string()),
+    }
+}
+
+/// Saves the file or folder behind `cid` (metadata, manifests and shards) as one CAR.
+#[tauri::command]
+pub async fn export_car(cid: String, output_path: String) -> Result<CarExportReport, String> {
+    car::export_car(&IpfsClient::from_settings().await, &cid, Path::new(&output_path)).await
+}
+
+/// Loads a CAR from `export_car` into the node and lists it for `account_id`.
+#[tauri::command]
+pub async fn import_car(account_id: String, car_path: String) -> Result<CarImportReport, String> {
+    car::import_car(&IpfsClient::from_settings().await, &account_id, Path::new(&car_path)).await
+}
+
 #[tauri::command]
 pub async fn download_and_decrypt_folder(
     _account_id: String,
@@ -325,7 +336,8 @@ pub async fn download_and_decrypt_folder(
     output_dir: String,
     encryption_key: Option<String>,
     source: String,
-) -> Result<(), String> {
+    policy: Option<FolderFailurePolicy>,
+) -> Result<FolderDownloadReport, String> {
     println!("[+] Starting download for folder with manifest CID: {}", folder_metadata_cid);
 
     if folder_metadata_cid == "s3" || folder_metadata_cid == "local" {
@@ -367,9 +379,10 @@ pub async fn download_and_decrypt_folder(
             let options = fs_extra::dir::CopyOptions::new().overwrite(true);
             fs_extra::dir::copy(source_path, &output_dir, &options)
                 .map_err(|e| format!("Failed to copy directory locally: {}", e))?;
+            copy_local_metadata(source_path, &destination_path).await;
 
             println!("[✔] Successfully copied folder from '{}' to '{}'", source, destination_path.display());
-            return Ok(());
+            return Ok(report_from_disk(source_path));
         } else {
             println!("[i] Source is an S3 path. Downloading with AWS CLI...");
 
@@ -378,7 +391,7 @@ pub async fn download_and_decrypt_folder(
                    .map_err(|e| format!("Failed to create output directory: {}", e))?;
            }
 
-            let status = Command::new(&aws_binary_path)
+            let output = Command::new(&aws_binary_path)
                 .env("AWS_PAGER", "")
                 .env("PATH", &dynamic_path)
                 .arg("s3")
@@ -386,25 +399,39 @@ pub async fn download_and_decrypt_folder(
                 .arg(&source) 
                 .arg(&destination_path) 
                 .arg("--recursive") 
+                .arg("--no-progress")
                 .arg("--endpoint-url")
                 .arg("https://s3.hippius.com")
-                .status()
+                .stderr(std::process::Stdio::inherit())
+                .output()
                 .await
                 .map_err(|e| format!("Failed to spawn 'aws s3 cp --recursive': {}", e))?;
 
-            if !status.success() {
+            if !output.status.success() {
                 return Err(format!(
                     "aws s3 cp --recursive failed for source '{}' with status {:?}",
-                    source, status.code()
+                    source, output.status.code()
                 ));
             }
+            let report = report_from_cli_output(&output.stdout, &destination_path);
+
+            match restore_folder_markers(&aws_binary_path, &dynamic_path, "https://s3.hippius.com", &source, &destination_path).await {
+                Ok(created) if created > 0 => println!("[i] Recreated {} empty folders from folder markers", created),
+                Ok(_) => {}
+                Err(e) => eprintln!("[download_and_decrypt_folder] Failed to restore empty folders: {}", e),
+            }
+
+            let failed = finish_s3_folder_download(&aws_binary_path, &dynamic_path, "https://s3.hippius.com", &source, &destination_path).await;
+            if failed > 0 {
+                return Err(format!("{} downloaded files could not be expanded or looked up", failed));
+            }
 
             println!("[✔] Successfully downloaded folder from S3 '{}' to '{}'", source, destination_path.display());
-            return Ok(());
+            return Ok(report);
         }
     }
 
-    let api_url = Arc::new("http://127.0.0.1:5001".to_string());
+    let ipfs = IpfsClient::from_settings().await;
 
     let encryption_key_bytes = if let Some(key_b64) = encryption_key {
         Some(Arc::new(general_purpose::STANDARD.decode(&key_b64).map_err(|e| format!("Key decode error: {}", e))?))
@@ -412,154 +439,323 @@ pub async fn download_and_decrypt_folder(
         None
     };
 
-    let folder_manifest_bytes = download_from_ipfs_async(&api_url, &folder_metadata_cid).await
+    let report = download_folder_from_ipfs(&ipfs, &folder_metadata_cid, &folder_name, Path::new(&output_dir), encryption_key_bytes, None, false).await?;
+    println!(
+        "[✔] Folder download process complete: {} succeeded, {} failed, {} skipped.",
+        report.succeeded.len(), report.failed.len(), report.skipped.len()
+    );
+    apply_failure_policy(report, policy.unwrap_or_default())
+}
+
+/// Downloads again only the entries a `partial` folder download reported as failed,
+/// into the same places. `output_dir` and `folder_name` are those of that download.
+#[tauri::command]
+pub async fn retry_folder_download(
+    output_dir: String,
+    folder_name: String,
+    failed: Vec<FailedFolderEntry>,
+    encryption_key: Option<String>,
+    policy: Option<FolderFailurePolicy>,
+) -> Result<FolderDownloadReport, String> {
+    let ipfs = IpfsClient::from_settings().await;
+    let encryption_key_bytes = decode_key_b64(encryption_key)?.map(Arc::new);
+    let root = Path::new(&output_dir).join(&folder_name);
+    let reports: Vec<FolderDownloadReport> = stream::iter(failed)
+        .map(|failed| {
+            let ipfs = ipfs.clone();
+            let encryption_key = encryption_key_bytes.clone();
+            let root = root.clone();
+            async move {
+                let prefix = failed.path.rsplit_once('/').map(|(parent, _)| parent.to_string()).unwrap_or_default();
+                match retry_parent(&root, &prefix) {
+                    Ok(parent_dir) => {
+                        download_folder_entry(&ipfs, failed.entry, &parent_dir, encryption_key, None, false, &prefix).await
+                    }
+                    Err(reason) => FolderDownloadReport {
+                        failed: vec![FailedFolderEntry { reason, ..failed }],
+                        ..Default::default()
+                    },
+                }
+            }
+        })
+        .buffer_unordered(8)
+        .collect()
+        .await;
+
+    let mut report = FolderDownloadReport::default();
+    for entry_report in reports {
+        report.merge(entry_report);
+    }
+    println!(
+        "[retry_folder_download] {} succeeded, {} still failing",
+        report.succeeded.len(), report.failed.len()
+    );
+    apply_failure_policy(report, policy.unwrap_or_default())
+}
+
+// Strict downloads turn any failed entry into an error naming the first few
+pub(crate) fn apply_failure_policy(
+    report: FolderDownloadReport,
+    policy: FolderFailurePolicy,
+) -> Result<FolderDownloadReport, String> {
+    if policy == FolderFailurePolicy::Partial || report.failed.is_empty() {
+        return Ok(report);
+    }
+    let mut failures: Vec<String> = report
+        .failed
+        .iter()
+        .take(5)
+        .map(|f| format!("{} ({})", f.path, f.reason))
+        .collect();
+    if report.failed.len() > 5 {
+        failures.push(format!("and {} more", report.failed.len() - 5));
+    }
+    Err(format!(
+        "{} of {} entries failed to download: {}",
+        report.failed.len(),
+        report.failed.len() + report.succeeded.len(),
+        failures.join("; ")
+    ))
+}
+
+// The folder a failed entry goes back into: `prefix`, its reported parent path, below
+// `root`. The path comes back from the frontend, so each part must stay a plain name.
+fn retry_parent(root: &Path, prefix: &str) -> Result<PathBuf, String> {
+    let mut parent_dir = root.to_path_buf();
+    if !prefix.is_empty() {
+        for part in prefix.split('/') {
+            check_entry_name(part).map_err(|_| format!("Invalid path {:?} to retry", prefix))?;
+            parent_dir.push(part);
+        }
+    }
+    Ok(parent_dir)
+}
+
+// The files a local copy took from `source`, which is everything below it
+fn report_from_disk(source: &Path) -> FolderDownloadReport {
+    let succeeded = walkdir::WalkDir::new(source)
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| {
+            entry.path().strip_prefix(source).ok().map(|relative| {
+                relative.components().map(|c| c.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/")
+            })
+        })
+        .collect();
+    FolderDownloadReport { succeeded, ..Default::default() }
+}
+
+// The files `aws s3 cp --no-progress` wrote below `destination`, from the
+// "download: <source> to <path>" line it prints for each
+fn report_from_cli_output(stdout: &[u8], destination: &Path) -> FolderDownloadReport {
+    let marker = format!(" to {}", destination.display());
+    let succeeded = String::from_utf8_lossy(stdout)
+        .lines()
+        .filter(|line| line.starts_with("download: "))
+        .filter_map(|line| line.split_once(&marker).map(|(_, relative)| relative))
+        .map(|relative| relative.trim_start_matches(['/', '\\']).replace('\\', "/"))
+        .filter(|relative| !relative.is_empty())
+        .collect();
+    FolderDownloadReport { succeeded, ..Default::default() }
+}
+
+fn report_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", prefix, name)
+    }
+}
+
+/// Bytes written so far by a folder download, for callers reporting progress.
+pub type FolderProgress = Arc<AtomicU64>;
+
+/// Rebuilds the folder behind the manifest `folder_metadata_cid` as
+/// `output_dir/folder_name` and reports what became of every entry. Only an unreadable
+/// manifest or an output folder that cannot be created is an error; entries that fail
+/// go in the report. With `skip_existing`, files already on disk at the size the
+/// manifest records are skipped, so an interrupted download can carry on.
+pub(crate) async fn download_folder_from_ipfs(
+    ipfs: &IpfsClient,
+    folder_metadata_cid: &str,
+    folder_name: &str,
+    output_dir: &Path,
+    encryption_key_bytes: Option<Arc<Vec<u8>>>,
+    progress: Option<FolderProgress>,
+    skip_existing: bool,
+) -> Result<FolderDownloadReport, String> {
+    download_folder_tree(ipfs, folder_metadata_cid, folder_name, output_dir, encryption_key_bytes, progress, skip_existing, "").await
+}
+
+// `prefix` is the folder's own path within the download, for the report
+#[allow(clippy::too_many_arguments)]
+async fn download_folder_tree(
+    ipfs: &IpfsClient,
+    folder_metadata_cid: &str,
+    folder_name: &str,
+    output_dir: &Path,
+    encryption_key_bytes: Option<Arc<Vec<u8>>>,
+    progress: Option<FolderProgress>,
+    skip_existing: bool,
+    prefix: &str,
+) -> Result<FolderDownloadReport, String> {
+    let folder_manifest_bytes = ipfs.cat(folder_metadata_cid).await
         .map_err(|e| format!("Failed to download folder manifest: {}", e))?;
-    let file_entries: Vec<FileEntry> = serde_json::from_slice(&folder_manifest_bytes)
+    let manifest = parse_folder_manifest(&folder_manifest_bytes, folder_name)
         .map_err(|e| format!("Failed to parse folder manifest (CID: {}): {}", folder_metadata_cid, e))?;
-    println!("[i] Folder manifest contains {} entries.", file_entries.len());
+    println!("[i] Folder manifest (v{}) contains {} entries.", manifest.version, manifest.entries.len());
 
-    let output_root_path = Path::new(&output_dir).join(&folder_name);
+    let output_root_path = output_dir.join(folder_name);
     tokio::fs::create_dir_all(&output_root_path).await.map_err(|e| format!("Failed to create output directory: {}", e))?;
 
-    stream::iter(file_entries)
-        .for_each_concurrent(Some(8), |entry| {
-            let api_url_clone = Arc::clone(&api_url);
-            let output_root_clone = output_root_path.clone();
-            let encryption_key_clone = encryption_key_bytes.as_ref().map(Arc::clone);
+    let mut report = FolderDownloadReport::default();
 
+    let entry_reports: Vec<FolderDownloadReport> = stream::iter(manifest.entries)
+        .map(|entry| {
+            let output_root = output_root_path.clone();
+            let encryption_key = encryption_key_bytes.clone();
+            let progress = progress.clone();
             async move {
-                println!("[download_and_decrypt_folder] Processing entry with CID: {} to: {:?}", 
-                    entry.cid, output_root_clone.join(&entry.file_name));
-
-                // Check if the entry is a subfolder metadata (new hierarchical logic)
-                if entry.file_name.ends_with(".folder.ec_metadata") {
-                    let subfolder_name = entry.file_name.trim_end_matches(".s.folder.ec_metadata");
-                    // Remove leading '.' and '.s' if present
-                    let cleaned_name = subfolder_name.trim_end_matches(".folder.ec_metadata");
-                    let subfolder_path = output_root_clone.join(cleaned_name);
-                    if let Err(e) = tokio::fs::create_dir_all(&subfolder_path).await {
-                        eprintln!("[!] Failed to create subfolder {}: {}", subfolder_name, e);
-                        return;
-                    }
-                    // Recursively call download_and_decrypt_folder for subfolder
-                    if let Err(e) = download_and_decrypt_folder(
-                        String::new(), // _account_id not needed for recursion
-                        entry.cid.clone(),
-                        subfolder_name.to_string(),
-                        output_root_clone.to_string_lossy().to_string(),
-                        encryption_key_clone.as_ref().map(|k| base64::engine::general_purpose::STANDARD.encode(&**k)),
-                        String::new() // Pass an empty string as source is not needed for IPFS subfolder
-                    ).await {
-                        eprintln!("[!] Failed to download/decrypt subfolder {}: {}", subfolder_name, e);
-                    }
-                } else { // Combined the two identical `else if` and `else` blocks
-                    // Handle regular file
-                    let file_metadata_bytes = match download_from_ipfs_async(&api_url_clone, &entry.cid).await {
-                        Ok(bytes) => bytes,
-                        Err(e) => {
-                            eprintln!("[!] Failed to download metadata for {} (CID: {}): {}", 
-                                entry.file_name, entry.cid, e);
-                            return;
-                        }
-                    };
-
-                    let clean_file_name = entry.file_name.trim_end_matches(".ff.ec_metadata");
-                    let output_file_path = output_root_clone.join(clean_file_name);
+                download_folder_entry(ipfs, entry, &output_root, encryption_key, progress, skip_existing, prefix).await
+            }
+        })
+        .buffer_unordered(8)
+        .collect()
+        .await;
+    for entry_report in entry_reports {
+        report.merge(entry_report);
+    }
+    Ok(report)
+}
 
-                    if let Some(parent) = output_file_path.parent() {
-                        if let Err(e) = tokio::fs::create_dir_all(parent).await {
-                            eprintln!("[!] Failed to create directory for {}: {}", clean_file_name, e);
-                            return;
+// Downloads one manifest entry into `parent_dir`; a folder brings its whole subtree
+#[allow(clippy::too_many_arguments)]
+async fn download_folder_entry(
+    ipfs: &IpfsClient,
+    entry: ManifestEntry,
+    parent_dir: &Path,
+    encryption_key: Option<Arc<Vec<u8>>>,
+    progress: Option<FolderProgress>,
+    skip_existing: bool,
+    prefix: &str,
+) -> FolderDownloadReport {
+    let local_name = local_name_for(&entry.name).await;
+    let path = report_path(prefix, &local_name);
+    let mut report = FolderDownloadReport::default();
+    // Retried entries come from the frontend rather than a parsed manifest
+    if let Err(reason) = check_entry_name(&local_name) {
+        report.failed.push(FailedFolderEntry { path, reason, entry });
+        return report;
+    }
+    let output_path = parent_dir.join(&local_name);
+    let file_metadata = FileMetadata { mtime: entry.mtime, mode: entry.mode, xattrs: None };
+    println!("[download_and_decrypt_folder] Processing entry with CID: {} to: {:?}", entry.cid, output_path);
+
+    let result = match entry.kind {
+        EntryKind::Folder => {
+            Box::pin(download_folder_tree(
+                ipfs,
+                &entry.cid,
+                &local_name,
+                parent_dir,
+                encryption_key,
+                progress,
+                skip_existing,
+                &path,
+            ))
+            .await
+            .map(|subtree| report.merge(subtree))
+        }
+        EntryKind::File => {
+            if skip_existing {
+                if let Ok(existing) = tokio::fs::metadata(&output_path).await {
+                    if existing.is_file() && existing.len() == entry.size {
+                        if let Some(progress) = &progress {
+                            progress.fetch_add(entry.size, Ordering::Relaxed);
                         }
-                    }
-
-                    match reconstruct_and_decrypt_single_file(
-                        file_metadata_bytes,
-                        output_file_path.clone(),
-                        api_url_clone,
-                        encryption_key_clone,
-                    ).await {
-                        Ok(()) => println!("[✔] Successfully downloaded and decrypted {}", clean_file_name),
-                        Err(e) => eprintln!("[!] Failed to download/decrypt {}: {}", clean_file_name, e),
+                        report.skipped.push(SkippedFolderEntry { path, reason: "Already downloaded".to_string() });
+                        return report;
                     }
                 }
             }
-        }).await;
+            match ipfs.cat(&entry.cid).await {
+                Ok(file_metadata_bytes) => reconstruct_and_decrypt_single_file(
+                    file_metadata_bytes,
+                    output_path.clone(),
+                    ipfs.clone(),
+                    encryption_key,
+                    entry.content_hash.as_deref(),
+                    progress,
+                ).await,
+                Err(e) => Err(format!("Failed to download metadata (CID: {}): {}", entry.cid, e)),
+            }
+        }
+    };
 
-    println!("[✔] Folder download process complete.");
-    Ok(())
+    match result {
+        Ok(()) => {
+            // Set last for folders, writing the children would bump the mtime again
+            if !file_metadata.is_empty() {
+                if let Err(e) = file_metadata.apply_to(&output_path) {
+                    eprintln!("[!] Failed to restore metadata for {}: {}", entry.name, e);
+                }
+            }
+            if entry.kind == EntryKind::File {
+                println!("[✔] Successfully downloaded and decrypted {}", entry.name);
+                report.succeeded.push(path);
+            }
+        }
+        Err(reason) => {
+            eprintln!("[!] Failed to download/decrypt {}: {}", entry.name, reason);
+            report.failed.push(FailedFolderEntry { path, reason, entry });
+        }
+    }
+    report
 }
 
 async fn reconstruct_and_decrypt_single_file(
     metadata_bytes: Vec<u8>,
     output_path: PathBuf,
-    api_url: Arc<String>,
+    ipfs: IpfsClient,
     encryption_key: Option<Arc<Vec<u8>>>,
+    expected_hash: Option<&str>,
+    progress: Option<FolderProgress>,
 ) -> Result<(), String> {
     let metadata: Metadata = serde_json::from_slice(&metadata_bytes)
         .map_err(|e| format!("Failed to parse file metadata: {}", e))?;
-
-    if metadata.chunks.is_empty() {
-        return Err(format!("Cannot reconstruct file '{}': metadata contains no chunk information.", metadata.original_file.name));
-    }
-
-    let k = metadata.erasure_coding.k;
-    let m = metadata.erasure_coding.m;
-    let chunk_size = metadata.erasure_coding.chunk_size;
-
-    let mut chunk_map: std::collections::HashMap<usize, Vec<&ChunkInfo>> = std::collections::HashMap::new();
-    for chunk in &metadata.chunks {
-        chunk_map.entry(chunk.original_chunk).or_default().push(chunk);
-    }
-    
-    let mut reconstructed_chunks_data = Vec::with_capacity(chunk_map.len());
-    for orig_idx in 0..chunk_map.len() {
-        let available_shards = chunk_map.get(&orig_idx).ok_or("Missing chunk group in map")?;
-        let mut shards: Vec<Option<Vec<u8>>> = vec![None; m];
-        
-        for shard_info in available_shards.iter() {
-            let data = download_from_ipfs_async(&api_url, &shard_info.cid.cid).await.map_err(|e| e.to_string())?;
-            shards[shard_info.share_idx] = Some(data);
-        }
-
-        if shards.iter().filter(|s| s.is_some()).count() < k {
-            return Err(format!("Not enough shards for chunk {}", orig_idx));
-        }
-        
-        let r = ReedSolomon::new(k, m - k).map_err(|e| e.to_string())?;
-        r.reconstruct_data(&mut shards).map_err(|e| format!("Reconstruction failed: {}", e))?;
-        
-        let mut chunk_data = Vec::new();
-        let mut bytes_collected = 0;
-        let is_last_chunk = orig_idx == chunk_map.len() - 1;
-        let chunk_bytes_needed = if !is_last_chunk {
-            chunk_size
-        } else {
-            metadata.erasure_coding.encrypted_size.saturating_sub(chunk_size * orig_idx)
-        };
-
-        for i in 0..k {
-            if let Some(shard) = &shards[i] {
-                let bytes_to_take = std::cmp::min(chunk_bytes_needed - bytes_collected, shard.len());
-                chunk_data.extend_from_slice(&shard[..bytes_to_take]);
-                bytes_collected += bytes_to_take;
-                if bytes_collected == chunk_bytes_needed {
-                    break;
-                }
-            }
+    // The manifest and the file metadata must describe the same content
+    if let Some(expected) = expected_hash {
+        if !expected.eq_ignore_ascii_case(&metadata.original_file.hash) {
+            return Err(format!(
+                "Metadata for {} does not match the folder manifest (hash {}, expected {})",
+                metadata.original_file.name, metadata.original_file.hash, expected
+            ));
         }
-        reconstructed_chunks_data.push(chunk_data);
     }
 
-    let encrypted_data: Vec<u8> = reconstructed_chunks_data.into_iter().flatten().collect();
-    let decrypted_data = decrypt_file(&encrypted_data, encryption_key.map(|k| (*k).clone())).await?;
-    
-    let actual_hash = format!("{:x}", Sha256::digest(&decrypted_data));
-    if actual_hash != metadata.original_file.hash {
-        return Err(format!("Hash mismatch for {}", metadata.original_file.name));
+    // Counted chunk by chunk, so large files move the total while they download
+    let mut counted = 0u64;
+    let report = reconstruct_file_observed(
+        &metadata,
+        &output_path,
+        encryption_key.map(|k| (*k).clone()),
+        &ipfs,
+        &mut |checkpoint| {
+            if let Some(progress) = &progress {
+                progress.fetch_add(checkpoint.bytes_written - counted, Ordering::Relaxed);
+            }
+            counted = checkpoint.bytes_written;
+        },
+    )
+    .await?;
+    if !report.unavailable_shares.is_empty() {
+        eprintln!(
+            "[!] Rebuilt {} despite {} unavailable shares",
+            metadata.original_file.name,
+            report.unavailable_shares.len()
+        );
     }
-
-    tokio::fs::write(&output_path, &decrypted_data).await.map_err(|e| e.to_string())?;
     println!("[✔] Successfully downloaded and wrote file to {}", output_path.display());
     Ok(())
 }
@@ -715,8 +911,6 @@ pub async fn download_file_public(
     output_file: String,
     source: String,
 ) -> Result<(), String> {
-    let api_url = "http://127.0.0.1:5001";
-
     println!("[download_file_public] Start: file_cid='{}', output_file='{}'", file_cid, output_file);
     // Special handling when the 'CID' indicates S3 source
     if file_cid == "s3" || file_cid == "local" {
@@ -755,6 +949,7 @@ pub async fn download_file_public(
         if source_exists {
             std::fs::copy(&source, &output_file)
                 .map_err(|e| format!("[download_file_public] Failed to copy from '{}' to '{}': {}", source, output_file, e))?;
+            copy_local_metadata(Path::new(&source), Path::new(&output_file)).await;
             println!("[download_file_public] Copied locally from '{}' to '{}'. Done.", source, output_file);
             return Ok(());
         } else {
@@ -780,6 +975,10 @@ pub async fn download_file_public(
                 ));
             }
 
+            if let Err(e) = finish_s3_download(&aws_binary_path, &dynamic_path, "https://s3.hippius.com", &source, Path::new(&output_file)).await {
+                eprintln!("[download_file_public] Failed to restore file metadata: {}", e);
+            }
+
             println!(
                 "[download_file_public] Downloaded from S3 '{}' to '{}' via aws cli. Done.",
                 source, output_file
@@ -789,16 +988,12 @@ pub async fn download_file_public(
     }
     
     // Default IPFS path for non-S3
-    println!("[download_file_public] Proceeding with IPFS download. api_url='{}', cid='{}'", api_url, file_cid);
-    let file_cid_cloned = file_cid.clone();
-    let api_url_cloned = api_url.to_string();
-    println!("[download_file_public] Spawning blocking task to download from IPFS");
-    let file_data = tokio::task::spawn_blocking(move || {
-        download_from_ipfs(&api_url_cloned, &file_cid_cloned)
-    })
-    .await
-    .map_err(|e| format!("[download_file_public] Task join error during IPFS download: {}", e))?
-    .map_err(|e| format!("[download_file_public] Failed to download file from IPFS (cid='{}'): {}", file_cid, e))?;
+    let ipfs = IpfsClient::from_settings().await;
+    println!("[download_file_public] Proceeding with IPFS download. api_url='{}', cid='{}'", ipfs.api_url(), file_cid);
+    let file_data = ipfs
+        .cat(&file_cid)
+        .await
+        .map_err(|e| format!("[download_file_public] Failed to download file from IPFS (cid='{}'): {}", file_cid, e))?;
     println!("[download_file_public] IPFS download complete. Bytes received={}", file_data.len());
     
     println!("[download_file_public] Writing {} bytes to '{}'", file_data.len(), output_file);
@@ -877,6 +1072,7 @@ pub async fn public_download_folder(
             let options = fs_extra::dir::CopyOptions::new().overwrite(true);
             fs_extra::dir::copy(source_path, &output_dir, &options)
                 .map_err(|e| format!("Failed to copy directory locally: {}", e))?;
+            copy_local_metadata(source_path, &destination_path).await;
 
             println!("[✔] Successfully copied folder from '{}' to '{}'", source, destination_path.display());
             return Ok(());
@@ -911,86 +1107,141 @@ pub async fn public_download_folder(
                 ));
             }
 
+            match restore_folder_markers(&aws_binary_path, &dynamic_path, "https://s3.hippius.com", &source, &destination_path).await {
+                Ok(created) if created > 0 => println!("[i] Recreated {} empty folders from folder markers", created),
+                Ok(_) => {}
+                Err(e) => eprintln!("[public_download_folder] Failed to restore empty folders: {}", e),
+            }
+
+            let failed = finish_s3_folder_download(&aws_binary_path, &dynamic_path, "https://s3.hippius.com", &source, &destination_path).await;
+            if failed > 0 {
+                eprintln!("[public_download_folder] Could not restore metadata for {} files", failed);
+            }
+
             println!("[✔] Successfully downloaded folder from S3 '{}' to '{}'", source, destination_path.display());
             return Ok(());
         }
     }
-    public_download_folder_inner(
-        &_account_id,
+    let report = download_public_folder_from_ipfs(
+        &IpfsClient::from_settings().await,
         &folder_metadata_cid,
         &folder_name,
-        &output_dir,
-    ).await
+        Path::new(&output_dir),
+        None,
+        false,
+    )
+    .await?;
+    if !report.failed.is_empty() {
+        eprintln!(
+            "[public_download_folder] {} of {} files failed to download",
+            report.failed.len(),
+            report.failed.len() + report.succeeded.len()
+        );
+    }
+    Ok(())
 }
 
-async fn public_download_folder_inner(
-    _account_id: &str,
+/// Rebuilds the public folder behind the manifest `folder_metadata_cid` as
+/// `output_dir/folder_name`, reporting every entry like `download_folder_from_ipfs`
+/// does for private folders. `progress` counts the bytes of finished files, and
+/// `skip_existing` leaves files already on disk at their manifest size alone.
+pub(crate) async fn download_public_folder_from_ipfs(
+    ipfs: &IpfsClient,
     folder_metadata_cid: &str,
     folder_name: &str,
-    output_dir: &str,
-) -> Result<(), String> {
-    let api_url = "http://127.0.0.1:5001";
-    
-    // Download folder metadata (blocking for sync with legacy code)
-    let metadata_bytes = tokio::task::spawn_blocking({
-        let api_url = api_url.to_string();
-        let folder_metadata_cid = folder_metadata_cid.to_string();
-        move || {
-            download_from_ipfs(&api_url, &folder_metadata_cid)
-                .map_err(|e| format!("Failed to download folder metadata for CID {}: {}", folder_metadata_cid, e))
-        }
-    })
-    .await
-    .map_err(|e| format!("Failed to execute blocking task: {}", e))??;
+    output_dir: &Path,
+    progress: Option<FolderProgress>,
+    skip_existing: bool,
+) -> Result<FolderDownloadReport, String> {
+    public_folder_tree(ipfs, folder_metadata_cid, folder_name, output_dir, progress.as_ref(), skip_existing, "").await
+}
 
-    let file_entries: Vec<FileEntry> = serde_json::from_slice(&metadata_bytes)
+// `prefix` is the folder's own path within the download, for the report
+async fn public_folder_tree(
+    ipfs: &IpfsClient,
+    folder_metadata_cid: &str,
+    folder_name: &str,
+    output_dir: &Path,
+    progress: Option<&FolderProgress>,
+    skip_existing: bool,
+    prefix: &str,
+) -> Result<FolderDownloadReport, String> {
+    let metadata_bytes = ipfs
+        .cat(folder_metadata_cid)
+        .await
+        .map_err(|e| format!("Failed to download folder metadata for CID {}: {}", folder_metadata_cid, e))?;
+    let manifest = parse_folder_manifest(&metadata_bytes, folder_name)
         .map_err(|e| format!("Failed to parse folder metadata: {}", e))?;
 
-    let output_path = std::path::Path::new(output_dir).join(folder_name);
-    if !output_path.exists() {
-        fs::create_dir_all(&output_path)
-            .map_err(|e| format!("Failed to create output directory {}: {}", output_path.display(), e))?;
-    }
-
-    // Recursively process folder entries using public suffix conventions
-    for entry in &file_entries {
-        if entry.file_name.ends_with(".folder") || entry.file_name.ends_with(".s.folder") {
-            // Subfolder: recursively download
-            // Remove leading ".s" from subfolder name if present
-            let mut subfolder_name = if entry.file_name.ends_with(".s.folder") {
-                entry.file_name.strip_suffix(".s.folder").unwrap_or(&entry.file_name)
-            } else {
-                entry.file_name.strip_suffix(".folder").unwrap_or(&entry.file_name)
-            };
-            if let Some(stripped) = subfolder_name.strip_prefix(".s") {
-                subfolder_name = stripped;
+    let output_path = output_dir.join(folder_name);
+    fs::create_dir_all(&output_path)
+        .map_err(|e| format!("Failed to create output directory {}: {}", output_path.display(), e))?;
+
+    let mut report = FolderDownloadReport::default();
+    for entry in manifest.entries {
+        let local_name = local_name_for(&entry.name).await;
+        let path = report_path(prefix, &local_name);
+        let entry_path = output_path.join(&local_name);
+        let file_metadata = FileMetadata { mtime: entry.mtime, mode: entry.mode, xattrs: None };
+        let result = match entry.kind {
+            EntryKind::Folder => {
+                Box::pin(public_folder_tree(ipfs, &entry.cid, &local_name, &output_path, progress, skip_existing, &path))
+                    .await
+                    .map(|subtree| report.merge(subtree))
             }
-            let subfolder_metadata_cid = &entry.cid;
-            if let Err(e) = Box::pin(public_download_folder_inner(
-                _account_id,
-                subfolder_metadata_cid,
-                subfolder_name,
-                &output_path.to_string_lossy()
-            )).await {
-                eprintln!("[public_download_folder] Failed to download subfolder {}: {}", subfolder_name, e);
+            EntryKind::File => {
+                let existing = fs::metadata(&entry_path);
+                if skip_existing && existing.is_ok_and(|m| m.is_file() && m.len() == entry.size) {
+                    if let Some(progress) = progress {
+                        progress.fetch_add(entry.size, Ordering::Relaxed);
+                    }
+                    report.skipped.push(SkippedFolderEntry { path, reason: "Already downloaded".to_string() });
+                    continue;
+                }
+                download_public_entry(ipfs, &entry, &entry_path).await
             }
-        } else if entry.file_name.ends_with(".ff") {
-            // Regular file
-            let clean_file_name = entry.file_name.strip_suffix(".ff").unwrap_or(&entry.file_name);
-            let output_file_path = output_path.join(clean_file_name);
-            if let Some(parent) = output_file_path.parent() {
-                fs::create_dir_all(parent)
-                    .map_err(|e| format!("Failed to create parent directory {}: {}", parent.display(), e))?;
+        };
+
+        match result {
+            Ok(()) => {
+                if !file_metadata.is_empty() {
+                    if let Err(e) = file_metadata.apply_to(&entry_path) {
+                        eprintln!("[public_download_folder] Failed to restore metadata for {}: {}", entry.name, e);
+                    }
+                }
+                if entry.kind == EntryKind::File {
+                    if let Some(progress) = progress {
+                        progress.fetch_add(entry.size, Ordering::Relaxed);
+                    }
+                    report.succeeded.push(path);
+                }
             }
-            if let Err(e) = download_file_public(entry.cid.clone(), output_file_path.to_string_lossy().to_string(), "".to_string()).await {
-                eprintln!("[public_download_folder] Failed to download file {}: {}", clean_file_name, e);
+            Err(reason) => {
+                eprintln!("[public_download_folder] Failed to download {}: {}", entry.name, reason);
+                report.failed.push(FailedFolderEntry { path, reason, entry });
             }
-        } else {
-            // Log or ignore unexpected entries
-            println!("[public_download_folder] Skipping unknown entry: {}", entry.file_name);
         }
     }
+    Ok(report)
+}
 
+// Fetches one public file and checks it against the hash its manifest records
+async fn download_public_entry(ipfs: &IpfsClient, entry: &ManifestEntry, entry_path: &Path) -> Result<(), String> {
+    ipfs.cat_to_file(&entry.cid, entry_path).await?;
+    let Some(expected) = &entry.content_hash else {
+        return Ok(());
+    };
+    let actual = match crate::sync_index::hash_file_blocking(entry_path.to_path_buf()).await {
+        Ok(actual) => actual,
+        Err(e) => {
+            let _ = fs::remove_file(entry_path);
+            return Err(format!("Failed to hash the download: {}; removed", e));
+        }
+    };
+    if !expected.eq_ignore_ascii_case(&actual) {
+        let _ = fs::remove_file(entry_path);
+        return Err(format!("Does not match the folder manifest (hash {}, expected {}); removed", actual, expected));
+    }
     Ok(())
 }
 
@@ -1165,7 +1416,13 @@ pub async fn list_folder_contents(
         let full_s3_key = parts[3..].join(" ");
 
         if let Some(relative_path) = full_s3_key.strip_prefix(&s3_prefix) {
-            if let Some(slash_index) = relative_path.find('/') {
+            if is_folder_marker(&full_s3_key, size) {
+                // Empty folders only exist as marker objects; list them without adding size
+                let subfolder_name = relative_path.split('/').next().unwrap_or("").to_string();
+                if !subfolder_name.is_empty() {
+                    subfolder_data.entry(subfolder_name).or_insert((0, last_modified));
+                }
+            } else if let Some(slash_index) = relative_path.find('/') {
                 let subfolder_name = relative_path[..slash_index].to_string();
                 let entry = subfolder_data.entry(subfolder_name).or_insert((0, String::new()));
                 entry.0 += size;
@@ -1595,4 +1852,112 @@ pub async fn wipe_s3_objects(
 
     println!("[remove_s3_objects] Successfully removed all objects from bucket: {}", bucket_name);
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::ipfs::IpfsConfig;
+
+    fn failed(path: &str) -> FailedFolderEntry {
+        FailedFolderEntry {
+            path: path.to_string(),
+            reason: "unavailable".to_string(),
+            entry: ManifestEntry {
+                name: path.rsplit('/').next().unwrap().to_string(),
+                kind: EntryKind::File,
+                cid: "c1".to_string(),
+                size: 1,
+                mtime: None,
+                mode: None,
+                content_hash: None,
+            },
+        }
+    }
+
+    #[test]
+    fn merged_reports_keep_every_entry() {
+        let mut report = FolderDownloadReport {
+            succeeded: vec!["a".to_string()],
+            failed: vec![failed("b")],
+            skipped: Vec::new(),
+        };
+        report.merge(FolderDownloadReport {
+            succeeded: vec!["sub/c".to_string()],
+            failed: vec![failed("sub/d")],
+            skipped: vec![SkippedFolderEntry { path: "sub/e".to_string(), reason: "Already downloaded".to_string() }],
+        });
+        assert_eq!(report.succeeded, ["a", "sub/c"]);
+        assert_eq!(report.failed.iter().map(|f| f.path.as_str()).collect::<Vec<_>>(), ["b", "sub/d"]);
+        assert_eq!(report.skipped.iter().map(|s| s.path.as_str()).collect::<Vec<_>>(), ["sub/e"]);
+    }
+
+    #[test]
+    fn strict_downloads_fail_when_any_entry_failed() {
+        let partial = FolderDownloadReport {
+            succeeded: vec!["a".to_string()],
+            failed: vec![failed("b")],
+            ..Default::default()
+        };
+        let kept = apply_failure_policy(partial.clone(), FolderFailurePolicy::Partial).unwrap();
+        assert_eq!(kept.failed.len(), 1);
+        assert_eq!(
+            apply_failure_policy(partial, FolderFailurePolicy::Strict).unwrap_err(),
+            "1 of 2 entries failed to download: b (unavailable)"
+        );
+        let clean = FolderDownloadReport { succeeded: vec!["a".to_string()], ..Default::default() };
+        assert!(apply_failure_policy(clean, FolderFailurePolicy::Strict).is_ok());
+
+        // Only the first five are named
+        let many = FolderDownloadReport {
+            failed: (0..7).map(|i| failed(&format!("f{}", i))).collect(),
+            ..Default::default()
+        };
+        let error = apply_failure_policy(many, FolderFailurePolicy::Strict).unwrap_err();
+        assert!(error.starts_with("7 of 7 entries failed to download: f0 (unavailable)"));
+        assert!(error.contains("f4 (unavailable)") && !error.contains("f5"));
+        assert!(error.ends_with("; and 2 more"));
+    }
+
+    #[test]
+    fn retries_stay_inside_the_downloaded_folder() {
+        let root = Path::new("downloads").join("album");
+        assert_eq!(retry_parent(&root, "").unwrap(), root);
+        assert_eq!(retry_parent(&root, "2024/june").unwrap(), root.join("2024").join("june"));
+        for prefix in [".", "..", "2024/../..", "/etc", "a//b", "a\\..\\.."] {
+            assert!(retry_parent(&root, prefix).is_err(), "{:?}", prefix);
+        }
+    }
+
+    #[tokio::test]
+    async fn retried_entries_cannot_name_a_path() {
+        let dir = tempfile::tempdir().unwrap();
+        // Never reached: the name is refused first
+        let ipfs = IpfsClient::new(IpfsConfig::default());
+        let mut entry = failed("x").entry;
+        entry.name = "../escaped".to_string();
+        let report = download_folder_entry(&ipfs, entry, dir.path(), None, None, false, "").await;
+        assert_eq!(report.failed.len(), 1);
+        assert!(report.failed[0].reason.contains("invalid entry name"));
+        assert!(!dir.path().parent().unwrap().join("escaped").exists());
+    }
+
+    #[test]
+    fn copies_report_only_the_files_they_brought() {
+        let source = tempfile::tempdir().unwrap();
+        fs::create_dir(source.path().join("2024")).unwrap();
+        fs::write(source.path().join("a.jpg"), b"a").unwrap();
+        fs::write(source.path().join("2024").join("b.jpg"), b"b").unwrap();
+        let mut copied = report_from_disk(source.path()).succeeded;
+        copied.sort();
+        assert_eq!(copied, ["2024/b.jpg", "a.jpg"]);
+
+        // Files already in the destination get no line from the CLI
+        let destination = Path::new("downloads").join("album");
+        let stdout = format!(
+            "download: s3://bucket/album/a.jpg to {}\ndownload: s3://bucket/album/2024/b.jpg to {}\n",
+            destination.join("a.jpg").display(),
+            destination.join("2024").join("b.jpg").display(),
+        );
+        assert_eq!(report_from_cli_output(stdout.as_bytes(), &destination).succeeded, ["a.jpg", "2024/b.jpg"]);
+    }
+}
@@ -0,0 +1,25 @@
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::ipns::{self, IpnsRecord};
+
+/// Publishes a public file or folder (path relative to the public sync folder) under a
+/// permanent IPNS name. Publishing an already published path republishes it.
+#[tauri::command]
+pub async fn publish_public_ipns(account_id: String, path: String) -> Result<IpnsRecord, String> {
+    ipns::publish_public_path(&IpfsClient::from_settings().await, &account_id, &path, true).await
+}
+
+#[tauri::command]
+pub async fn list_ipns_names(account_id: String) -> Result<Vec<IpnsRecord>, String> {
+    ipns::list_ipns_names(&account_id).await
+}
+
+#[tauri::command]
+pub async fn resolve_ipns_name(name: String) -> Result<String, String> {
+    ipns::resolve_ipns_name(&IpfsClient::from_settings().await, &name).await
+}
+
+/// Moves a published path to a new IPNS key. The old `/ipns/` link stops updating.
+#[tauri::command]
+pub async fn rotate_ipns_key(account_id: String, path: String) -> Result<IpnsRecord, String> {
+    ipns::rotate_ipns_key(&IpfsClient::from_settings().await, &account_id, &path).await
+}
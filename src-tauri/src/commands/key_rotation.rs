@@ -0,0 +1,36 @@
+use base64::{engine::general_purpose, Engine as _};
+use crate::utils::key_rotation::{self, KeyRotation};
+
+/// Re-encrypts the account's private files and folders under `new_key` (base64), or
+/// under a newly created key when none is given, and swaps the new copies in for the
+/// old ones. Progress arrives as `key_rotation_progress` events carrying the rotation;
+/// each item reports the CID that replaces it.
+#[tauri::command]
+pub async fn start_key_rotation(
+    account_id: String,
+    mnemonic: String,
+    new_key: Option<String>,
+) -> Result<KeyRotation, String> {
+    let new_key = new_key
+        .map(|key_b64| general_purpose::STANDARD.decode(&key_b64))
+        .transpose()
+        .map_err(|e| format!("Failed to decode base64 key: {}", e))?;
+    key_rotation::start_key_rotation(&account_id, &mnemonic, new_key).await
+}
+
+#[tauri::command]
+pub async fn get_key_rotation(id: String) -> Result<KeyRotation, String> {
+    key_rotation::get_key_rotation(&id).await
+}
+
+#[tauri::command]
+pub async fn list_key_rotations() -> Result<Vec<KeyRotation>, String> {
+    key_rotation::list_key_rotations().await
+}
+
+/// Continues a failed or interrupted rotation. The mnemonic is asked for again since
+/// rotations never store it.
+#[tauri::command]
+pub async fn retry_key_rotation(id: String, mnemonic: String) -> Result<KeyRotation, String> {
+    key_rotation::retry_key_rotation(&id, &mnemonic).await
+}
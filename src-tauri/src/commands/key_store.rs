@@ -0,0 +1,39 @@
+use crate::utils::key_store::{self, KeyStoreStatus};
+
+/// Whether a passphrase is set and the store is unlocked. The same status arrives as
+/// `key_store_status` events at startup and whenever the store locks or unlocks.
+#[tauri::command]
+pub async fn key_store_status() -> Result<KeyStoreStatus, String> {
+    key_store::key_store_status().await
+}
+
+/// Encrypts every stored key and seed phrase under `passphrase`.
+#[tauri::command]
+pub async fn set_key_store_passphrase(passphrase: String) -> Result<KeyStoreStatus, String> {
+    key_store::set_passphrase(&passphrase).await
+}
+
+#[tauri::command]
+pub async fn unlock_key_store(passphrase: String) -> Result<KeyStoreStatus, String> {
+    key_store::unlock_key_store(&passphrase).await
+}
+
+#[tauri::command]
+pub async fn lock_key_store() -> Result<KeyStoreStatus, String> {
+    key_store::lock_key_store().await
+}
+
+/// Re-wraps every stored secret under `new_passphrase`.
+#[tauri::command]
+pub async fn change_key_store_passphrase(
+    old_passphrase: String,
+    new_passphrase: String,
+) -> Result<KeyStoreStatus, String> {
+    key_store::change_passphrase(&old_passphrase, &new_passphrase).await
+}
+
+/// Stores every secret unencrypted again.
+#[tauri::command]
+pub async fn remove_key_store_passphrase(passphrase: String) -> Result<KeyStoreStatus, String> {
+    key_store::remove_passphrase(&passphrase).await
+}
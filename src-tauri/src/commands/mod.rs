@@ -1,6 +1,14 @@
+pub mod account_share;
+pub mod downloads;
 pub mod ipfs_commands;
+pub mod ipns;
+pub mod key_rotation;
+pub mod key_store;
 pub mod node;
+pub mod pins;
 pub mod substrate_tx;
 pub mod types;
 pub mod accounts;
-pub mod syncing;
\ No newline at end of file
+pub mod syncing;
+pub mod settings;
+pub mod share;
\ No newline at end of file
@@ -7,11 +7,11 @@ use tokio::{
     io::{AsyncBufReadExt, BufReader},
     process::Command,
 };
-use crate::constants::ipfs::{AppSetupPhase, APP_SETUP_EVENT, API_URL};
+use crate::constants::ipfs::{AppSetupPhase, APP_SETUP_EVENT};
+use crate::utils::ipfs::IpfsClient;
 use crate::utils::binary::ensure_ipfs_binary;
 use std::time::Duration;
 use tokio::time::sleep;
-use reqwest::Client;
 use std::path::{Path, PathBuf};
 use tokio::fs; 
 
@@ -68,12 +68,12 @@ pub fn spawn_ipfs_command(bin_path: &std::path::Path, args: &[&str]) -> tokio::p
     cmd
 }
 
-async fn is_ipfs_api_up() -> bool {
-    let client = Client::builder()
+async fn is_ipfs_api_up(ipfs: &IpfsClient) -> bool {
+    let response = ipfs
+        .api_post("version")
         .timeout(Duration::from_secs(5))
-        .build()
-        .unwrap();
-    let response = client.get(format!("{}/api/v0/version", API_URL)).send().await;
+        .send()
+        .await;
     matches!(response, Ok(resp) if resp.status().is_success())
 }
 
@@ -102,8 +102,10 @@ pub async fn start_ipfs_daemon(app: AppHandle) -> Result<(), String> {
     ensure_aws_cli_installed_blocking().await;
 
     // If an IPFS daemon is already up, skip starting and just move to final phases
-    if is_ipfs_api_up().await {
-        println!("[IPFS] Existing IPFS daemon detected on {}. Skipping start.", API_URL);
+    // A configured remote node that answers makes the bundled daemon unnecessary
+    let ipfs = IpfsClient::from_settings().await;
+    if is_ipfs_api_up(&ipfs).await {
+        println!("[IPFS] Existing IPFS daemon detected on {}. Skipping start.", ipfs.api_url());
         sleep(Duration::from_secs(SMALL_SLEEP)).await;
         let app = emit_and_update_phase(app.clone(), AppSetupPhase::InitialisingDatabase).await;
         sleep(Duration::from_secs(SMALL_SLEEP)).await;
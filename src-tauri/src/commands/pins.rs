@@ -0,0 +1,76 @@
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::pins::{
+    self, list_remote_pins, load_pinning_services, GcReport, LocalPin, PinningService, RemotePin,
+};
+
+#[tauri::command]
+pub async fn list_local_pins(pin_type: Option<String>) -> Result<Vec<LocalPin>, String> {
+    pins::list_local_pins(&IpfsClient::from_settings().await, pin_type.as_deref()).await
+}
+
+#[tauri::command]
+pub async fn pin_cid(cid: String) -> Result<(), String> {
+    pins::pin_local(&IpfsClient::from_settings().await, &cid).await
+}
+
+/// Unpins one CID, or with `recursive` the file or folder behind a metadata or manifest
+/// CID including all of its shards. Returns how many CIDs were unpinned.
+#[tauri::command]
+pub async fn unpin_cid(cid: String, recursive: Option<bool>) -> Result<usize, String> {
+    let ipfs = IpfsClient::from_settings().await;
+    if recursive.unwrap_or(false) {
+        pins::unpin_stored_object(&ipfs, &cid).await
+    } else {
+        pins::unpin_local(&ipfs, &cid).await.map(|_| 1)
+    }
+}
+
+#[tauri::command]
+pub async fn run_ipfs_gc() -> Result<GcReport, String> {
+    pins::run_gc(&IpfsClient::from_settings().await).await
+}
+
+/// Registered remote pinning services, with their access tokens masked.
+#[tauri::command]
+pub async fn get_pinning_services() -> Result<Vec<PinningService>, String> {
+    Ok(load_pinning_services()
+        .await?
+        .iter()
+        .map(PinningService::masked)
+        .collect())
+}
+
+#[tauri::command]
+pub async fn add_pinning_service(name: String, endpoint: String, access_token: String) -> Result<(), String> {
+    pins::register_pinning_service(PinningService { name, endpoint, access_token }).await
+}
+
+#[tauri::command]
+pub async fn remove_pinning_service(name: String) -> Result<(), String> {
+    pins::remove_pinning_service(&name).await
+}
+
+/// Pins a file's or folder's metadata and shard CIDs to a registered service.
+#[tauri::command]
+pub async fn pin_to_remote_service(
+    service: String,
+    cid: String,
+    name: Option<String>,
+) -> Result<Vec<RemotePin>, String> {
+    let name = name.unwrap_or_else(|| cid.clone());
+    pins::pin_to_service(&IpfsClient::from_settings().await, &service, &cid, &name).await
+}
+
+#[tauri::command]
+pub async fn unpin_from_remote_service(service: String, cid: String) -> Result<usize, String> {
+    pins::unpin_from_service(&IpfsClient::from_settings().await, &service, &cid).await
+}
+
+/// Tracked remote pins; with `refresh`, pending ones are re-checked with the service first.
+#[tauri::command]
+pub async fn get_remote_pins(service: Option<String>, refresh: Option<bool>) -> Result<Vec<RemotePin>, String> {
+    match (service, refresh.unwrap_or(false)) {
+        (Some(service), true) => pins::refresh_remote_pins(&service).await,
+        (service, _) => list_remote_pins(service.as_deref(), None).await,
+    }
+}
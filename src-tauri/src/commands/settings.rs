@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+use crate::utils::ipfs::{load_ipfs_config, save_ipfs_config, IpfsConfig};
+use crate::utils::downloads::pump;
+use crate::utils::settings::{get_all_settings, set_setting, DOWNLOAD_CONCURRENCY, GENERAL_SETTINGS};
+
+#[tauri::command]
+pub async fn get_app_settings() -> Result<HashMap<String, String>, String> {
+    let mut settings = get_all_settings().await?;
+    settings.retain(|key, _| GENERAL_SETTINGS.contains(&key.as_str()));
+    Ok(settings)
+}
+
+#[tauri::command]
+pub async fn set_app_setting(key: String, value: String) -> Result<(), String> {
+    if !GENERAL_SETTINGS.contains(&key.as_str()) {
+        return Err(format!("'{}' is not a setting that can be changed here", key));
+    }
+    set_setting(&key, &value).await?;
+    // A higher limit can start queued downloads right away
+    if key == DOWNLOAD_CONCURRENCY {
+        pump().await;
+    }
+    Ok(())
+}
+
+/// The IPFS config with API header values masked.
+#[tauri::command]
+pub async fn get_ipfs_config() -> Result<IpfsConfig, String> {
+    Ok(load_ipfs_config().await.masked())
+}
+
+/// Validates and saves the IPFS endpoint, API headers and gateway list. Takes effect
+/// for the next request; nothing needs restarting. Header values sent back still
+/// masked keep their saved value.
+#[tauri::command]
+pub async fn set_ipfs_config(config: IpfsConfig) -> Result<IpfsConfig, String> {
+    let config = config.unmasked(&load_ipfs_config().await);
+    Ok(save_ipfs_config(config).await?.masked())
+}
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+use base64::{engine::general_purpose, Engine as _};
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::share::{self, ShareLink, ShareSource};
+
+/// Creates a "gateway" link for a public file or folder (path relative to the public
+/// sync folder), optionally expiring after `expires_in_secs`.
+#[tauri::command]
+pub async fn create_share_link(
+    account_id: String,
+    path: String,
+    kind: String,
+    expires_in_secs: Option<u64>,
+) -> Result<ShareLink, String> {
+    let ipfs = IpfsClient::from_settings().await;
+    share::create_share_link(&ipfs, &account_id, &path, &kind, expires_in_secs).await
+}
+
+/// Shares one file through a link carrying its own key: either `file_path` on disk or
+/// the stored file behind `metadata_cid` (decrypted with `encryption_key`, base64, or the
+/// latest account key).
+#[tauri::command]
+pub async fn create_encrypted_share_link(
+    account_id: String,
+    file_path: Option<String>,
+    metadata_cid: Option<String>,
+    encryption_key: Option<String>,
+    expires_in_secs: Option<u64>,
+) -> Result<ShareLink, String> {
+    let source = match (file_path, metadata_cid) {
+        (Some(path), None) => ShareSource::Local(PathBuf::from(path)),
+        (None, Some(metadata_cid)) => {
+            let encryption_key = encryption_key
+                .map(|key_b64| general_purpose::STANDARD.decode(&key_b64))
+                .transpose()
+                .map_err(|e| format!("Failed to decode base64 key: {}", e))?;
+            ShareSource::Stored { metadata_cid, encryption_key }
+        }
+        _ => return Err("Give either a file path or a metadata CID to share".to_string()),
+    };
+    let ipfs = IpfsClient::from_settings().await;
+    share::create_encrypted_share_link(&ipfs, &account_id, source, expires_in_secs).await
+}
+
+/// Downloads and decrypts an encrypted share link into `output_path` (a file or a
+/// directory) and returns where the file was written.
+#[tauri::command]
+pub async fn open_share_link(link: String, output_path: String) -> Result<String, String> {
+    let ipfs = IpfsClient::from_settings().await;
+    share::open_share_link(&ipfs, &link, &PathBuf::from(output_path))
+        .await
+        .map(|path| path.to_string_lossy().to_string())
+}
+
+#[tauri::command]
+pub async fn list_share_links(account_id: String, include_inactive: Option<bool>) -> Result<Vec<ShareLink>, String> {
+    share::list_share_links(&account_id, include_inactive.unwrap_or(false)).await
+}
+
+#[tauri::command]
+pub async fn revoke_share_link(account_id: String, id: String) -> Result<ShareLink, String> {
+    share::revoke_share_link(&IpfsClient::from_settings().await, &account_id, &id).await
+}
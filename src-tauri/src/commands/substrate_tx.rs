@@ -349,4 +349,167 @@ pub async fn add_sub_account_tauri(
     // small cooldown similar to other txs
     tokio::time::sleep(std::time::Duration::from_secs(6)).await;
     Ok(format!("✅ add_sub_account submitted! Finalized in block: {tx_hash}"))
-}
\ No newline at end of file
+}
+/// The raw sr25519 secret of the account behind `mnemonic`, after checking it really is
+/// `account_id`'s. Keys the app derives for the account start from this.
+pub fn account_secret(mnemonic: &str, account_id: &str) -> Result<Vec<u8>, String> {
+    use sp_core::crypto::Ss58Codec;
+
+    let pair = sr25519::Pair::from_string(mnemonic, None)
+        .map_err(|e| format!("Failed to create signer pair: {e:?}"))?;
+    let account = sp_core::crypto::AccountId32::from_ss58check(account_id)
+        .map_err(|e| format!("Invalid account id {}: {:?}", account_id, e))?;
+    if sp_core::crypto::AccountId32::from(pair.public()) != account {
+        return Err(format!("Mnemonic does not belong to account {}", account_id));
+    }
+    Ok(pair.to_raw_vec())
+}
+
+/// The sharing keypair of the account behind `mnemonic`, after checking it really is
+/// `account_id`'s.
+pub fn sharing_keypair(
+    mnemonic: &str,
+    account_id: &str,
+) -> Result<(sodiumoxide::crypto::box_::PublicKey, sodiumoxide::crypto::box_::SecretKey), String> {
+    Ok(crate::utils::account_share::derive_sharing_keypair(&account_secret(mnemonic, account_id)?))
+}
+
+// Signs and submits one call from the account behind `mnemonic`, waiting for
+// finalization
+async fn submit_account_tx<Call: subxt::tx::Payload>(
+    mnemonic: &str,
+    tx: Call,
+    what: &str,
+) -> Result<(), String> {
+    let _lock = SUBSTRATE_TX_LOCK.lock().await;
+
+    let pair = sr25519::Pair::from_string(mnemonic, None)
+        .map_err(|e| format!("Failed to create signer pair: {e:?}"))?;
+    let signer = PairSigner::new(pair);
+    let api = get_substrate_client()
+        .await
+        .map_err(|e| format!("Failed to connect to Substrate node: {e}"))?;
+
+    println!("[Substrate] Submitting {} transaction...", what);
+    let tx_hash = api
+        .tx()
+        .sign_and_submit_then_watch_default(&tx, &signer)
+        .await
+        .map_err(|e| format!("Failed to submit transaction: {}", e))?
+        .wait_for_finalized_success()
+        .await
+        .map_err(|e| format!("Transaction failed: {}", e))?
+        .extrinsic_hash();
+    println!("[Substrate] {} finalized: {:?}", what, tx_hash);
+    Ok(())
+}
+
+/// Publishes the account's sharing public key, which senders seal item keys for.
+pub async fn set_data_public_key(mnemonic: &str, key: Vec<u8>) -> Result<(), String> {
+    let tx = custom_runtime::tx().account_profile().set_data_public_key(key);
+    submit_account_tx(mnemonic, tx, "set_data_public_key").await
+}
+
+/// Sets the account's public storage item, which holds the hex CID of its share index.
+pub async fn set_public_item(mnemonic: &str, item: Vec<u8>) -> Result<(), String> {
+    let tx = custom_runtime::tx().account_profile().set_public_item(item);
+    submit_account_tx(mnemonic, tx, "set_public_item").await
+}
+
+/// Leaves a notification for `recipient`. Notifications are stored per recipient, so
+/// its profile sync finds new senders without walking every account's public storage.
+pub async fn notify_share_recipient(mnemonic: &str, recipient: &str) -> Result<(), String> {
+    let recipient: subxt::utils::AccountId32 = recipient
+        .parse()
+        .map_err(|e| format!("Invalid account id {}: {:?}", recipient, e))?;
+    let api = get_substrate_client()
+        .await
+        .map_err(|e| format!("Failed to connect to Substrate node: {e}"))?;
+    let block: u64 = api
+        .blocks()
+        .at_latest()
+        .await
+        .map_err(|e| format!("Failed to get latest block: {}", e))?
+        .number()
+        .into();
+    let tx = custom_runtime::tx()
+        .notifications()
+        .send_notification(recipient, block, false, None, None);
+    submit_account_tx(mnemonic, tx, "send_notification").await
+}
+
+/// Asks the marketplace to store `cid` under `file_name` for the account behind
+/// `mnemonic`. The chain keeps file hashes as the hex of the CID string.
+pub async fn request_file_storage(mnemonic: &str, file_name: &str, cid: &str) -> Result<(), String> {
+    let input = FileInput::from(FileInputWrapper {
+        file_hash: hex::encode(cid).into_bytes(),
+        file_name: file_name.as_bytes().to_vec(),
+    });
+    let tx = custom_runtime::tx().marketplace().storage_request(vec![input], None);
+    submit_account_tx(mnemonic, tx, "storage_request").await
+}
+
+/// Asks the marketplace to stop storing `cid` for the account behind `mnemonic`.
+pub async fn request_file_unpin(mnemonic: &str, cid: &str) -> Result<(), String> {
+    let file_hash = FileHash::try_from(FileHashWrapper {
+        file_hash: hex::encode(cid).into_bytes(),
+    })?;
+    let tx = custom_runtime::tx().marketplace().storage_unpin_request(file_hash);
+    submit_account_tx(mnemonic, tx, "storage_unpin_request").await
+}
+
+/// The sharing public key `account_id` has published, if any.
+pub async fn fetch_data_public_key(account_id: &str) -> Result<Option<Vec<u8>>, String> {
+    let account: subxt::utils::AccountId32 = account_id
+        .parse()
+        .map_err(|e| format!("Invalid account id {}: {:?}", account_id, e))?;
+    let api = get_substrate_client()
+        .await
+        .map_err(|e| format!("Failed to connect to Substrate node: {e}"))?;
+    api.storage()
+        .at_latest()
+        .await
+        .map_err(|e| format!("Failed to get latest storage: {}", e))?
+        .fetch(&custom_runtime::storage().account_profile().data_public_keys(&account))
+        .await
+        .map_err(|e| format!("Failed to fetch sharing key of {}: {}", account_id, e))
+}
+
+/// The public storage item `account_id` has set, if any.
+pub async fn fetch_public_item(account_id: &str) -> Result<Option<Vec<u8>>, String> {
+    let account: subxt::utils::AccountId32 = account_id
+        .parse()
+        .map_err(|e| format!("Invalid account id {}: {:?}", account_id, e))?;
+    let api = get_substrate_client()
+        .await
+        .map_err(|e| format!("Failed to connect to Substrate node: {e}"))?;
+    api.storage()
+        .at_latest()
+        .await
+        .map_err(|e| format!("Failed to get latest storage: {}", e))?
+        .fetch(&custom_runtime::storage().account_profile().user_public_storage(&account))
+        .await
+        .map_err(|e| format!("Failed to fetch public item of {}: {}", account_id, e))
+}
+
+#[tauri::command]
+pub async fn publish_sharing_key(account_id: String, mnemonic: String) -> Result<String, String> {
+    let account_id = canonical_account_id(&account_id)?;
+    let (public_key, secret_key) = sharing_keypair(&mnemonic, &account_id)?;
+    set_data_public_key(&mnemonic, public_key.0.to_vec()).await?;
+    // The profile sync opens shares addressed to this key without the mnemonic
+    if let Err(e) = crate::utils::account_share::remember_sharing_key(&account_id, &secret_key).await {
+        eprintln!("[AccountShare] Failed to store the sharing key of {}: {}", account_id, e);
+    }
+    Ok(hex::encode(public_key.0))
+}
+
+/// `account_id` in the SS58 form the chain sync writes, so ids typed with another
+/// network prefix still compare equal.
+pub fn canonical_account_id(account_id: &str) -> Result<String, String> {
+    account_id
+        .trim()
+        .parse::<subxt::utils::AccountId32>()
+        .map(|account| account.to_string())
+        .map_err(|e| format!("Invalid account id {}: {:?}", account_id, e))
+}
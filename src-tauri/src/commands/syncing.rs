@@ -3,6 +3,8 @@ use crate::private_folder_sync::start_private_folder_sync_tauri;
 use crate::public_folder_sync::start_public_folder_sync_tauri;
 use crate::sync_shared::{reset_all_sync_state, prepare_for_new_sync};
 use crate::utils::sync::{get_private_sync_path, get_public_sync_path};
+use crate::utils::account_share::remember_sharing_key;
+use crate::commands::substrate_tx::{canonical_account_id, sharing_keypair};
 use tauri::Manager;
 use tokio::sync::Mutex;
 use std::sync::Arc;
@@ -74,6 +76,20 @@ pub async fn initialize_sync(
         let account_clone3 = account_for_bg.clone();
         let mnemonic_clone = mnemonic_for_bg.clone();
 
+        // The profile sync opens items shared with this account with the stored
+        // sharing key, as it never sees the mnemonic
+        let sharing_key = canonical_account_id(&account_for_bg).and_then(|account| {
+            sharing_keypair(&mnemonic_for_bg, &account).map(|(_, secret_key)| (account, secret_key))
+        });
+        match sharing_key {
+            Ok((account, secret_key)) => {
+                if let Err(e) = remember_sharing_key(&account, &secret_key).await {
+                    eprintln!("[SyncInit] Failed to store the sharing key: {}", e);
+                }
+            }
+            Err(e) => eprintln!("[SyncInit] Failed to derive the sharing key: {}", e),
+        }
+
         let user_profile_task = tokio::spawn(async move {
             start_user_profile_sync_tauri(app_handle_clone, account_clone).await;
         });
@@ -216,7 +232,8 @@ async fn load_encryption_key(pool: &sqlx::SqlitePool) -> Option<SbKey> {
         .fetch_optional(pool)
         .await
     {
-        Ok(Some((bytes,))) => {
+        Ok(Some((stored,))) => {
+            let bytes = crate::utils::key_store::open_secret(&stored).ok()?;
             if bytes.len() == secretbox::KEYBYTES {
                 Some(SbKey::from_slice(&bytes).unwrap())
             } else { None }
@@ -263,6 +280,14 @@ async fn resolve_or_create_subaccount_seed(account_id: String, mnemonic: String)
         .fetch_optional(pool)
         .await {
             Ok(Some((stored_str,))) => {
+                // Wrapped by the key store when a passphrase is set
+                let stored_str = match crate::utils::key_store::open_text(&stored_str) {
+                    Ok(opened) => opened,
+                    Err(e) => {
+                        eprintln!("[SyncInit] Cannot read subaccount seed for {} ({}), falling back to provided mnemonic", account_id, e);
+                        return mnemonic.clone();
+                    }
+                };
                 if let Some(key) = &maybe_key {
                     // Try decrypt; if fails, treat as legacy plaintext and migrate
                     if let Some(decrypted) = decrypt_phrase(&stored_str, key) {
@@ -283,16 +308,28 @@ async fn resolve_or_create_subaccount_seed(account_id: String, mnemonic: String)
                 } else {
                     phrase.clone()
                 };
-                if let Err(e) = sqlx::query(
-                    "INSERT INTO sub_accounts (account_id, sub_account_seed_phrase) VALUES (?, ?)"
-                )
-                .bind(&account_id)
-                .bind(&to_store)
-                .execute(pool)
-                .await {
-                    eprintln!("[SyncInit] Failed to insert new subaccount for {}: {}", account_id, e);
-                } else {
-                    println!("[SyncInit] Stored new subaccount seed phrase for account_id={}", account_id);
+                {
+                    let hold = crate::utils::key_store::hold_secrets().await;
+                    match crate::utils::key_store::seal_text(&hold, &to_store).await {
+                        Err(e) => {
+                            // Don't register a subaccount whose seed can't be kept
+                            eprintln!("[SyncInit] Cannot store a new subaccount for {} ({}), falling back to provided mnemonic", account_id, e);
+                            return mnemonic.clone();
+                        }
+                        Ok(to_store) => {
+                            if let Err(e) = sqlx::query(
+                                "INSERT INTO sub_accounts (account_id, sub_account_seed_phrase) VALUES (?, ?)"
+                            )
+                            .bind(&account_id)
+                            .bind(&to_store)
+                            .execute(pool)
+                            .await {
+                                eprintln!("[SyncInit] Failed to insert new subaccount for {}: {}", account_id, e);
+                            } else {
+                                println!("[SyncInit] Stored new subaccount seed phrase for account_id={}", account_id);
+                            }
+                        }
+                    }
                 }
 
                 // Try to register subaccount on-chain; if we get the specific
@@ -315,6 +352,14 @@ async fn resolve_or_create_subaccount_seed(account_id: String, mnemonic: String)
                                 } else {
                                     mnemonic.clone()
                                 };
+                                let hold = crate::utils::key_store::hold_secrets().await;
+                                let to_store = match crate::utils::key_store::seal_text(&hold, &to_store).await {
+                                    Ok(sealed) => sealed,
+                                    Err(e) => {
+                                        eprintln!("[SyncInit] Not storing provided mnemonic for {}: {}", account_id, e);
+                                        return mnemonic.clone();
+                                    }
+                                };
                                 if let Err(e) = sqlx::query(
                                     "UPDATE sub_accounts SET sub_account_seed_phrase = ? WHERE account_id = ?"
                                 )
@@ -326,6 +371,7 @@ async fn resolve_or_create_subaccount_seed(account_id: String, mnemonic: String)
                                 } else {
                                     println!("[SyncInit] Updated subaccount seed to provided mnemonic for account_id={}", account_id);
                                 }
+                                drop(hold);
                                 // Use mnemonic for this session going forward
                                 chosen_seed_for_session = mnemonic.clone();
                             }
@@ -43,6 +43,32 @@ pub struct ErasureCodingInfo {
     pub encrypted: bool,
     pub file_id: String,
     pub encrypted_size: usize,
+    // Applied to the plaintext before encryption; absent for uncompressed files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    // Size of the compressed plaintext that was encrypted
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub compressed_size: Option<usize>,
+    // Absent for files in the legacy one-shot secretbox layout
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<Cipher>,
+    // Hex `stream_crypto::key_id` of the key the file was encrypted with; absent in
+    // metadata written before key IDs were recorded
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key_id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    // utils::stream_crypto's chunked XChaCha20-Poly1305 format
+    #[serde(rename = "xchacha20poly1305-stream-v1")]
+    XChaCha20Poly1305StreamV1,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Compression {
+    Zstd,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -50,6 +76,11 @@ pub struct FileEntry {
     pub file_name: String,
     pub file_size: usize,
     pub cid: String,
+    // Original modification time (unix seconds) and mode bits; absent in older manifests
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -82,11 +113,80 @@ pub struct FileDetail {
 }
 
 
-#[derive(Serialize, Deserialize)]
-struct FolderMetadata {
-    version: u32,
-    files: Vec<FileEntry>,
-    erasure_info: Option<ErasureCodingInfo>, // Only for encrypted folders
-    encrypted: bool,
-    original_folder_name: String,
-}
\ No newline at end of file
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EntryKind {
+    File,
+    Folder,
+}
+
+/// One entry of a v2 folder manifest. `name` is the plain name on disk; `cid` points at
+/// the file (erasure metadata for private folders, content for public ones) or at the
+/// subfolder's manifest.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ManifestEntry {
+    pub name: String,
+    pub kind: EntryKind,
+    pub cid: String,
+    // Folders: total size of every file below
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+    // Hex SHA-256 of the plaintext, files only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content_hash: Option<String>,
+}
+
+/// Folder manifest. Version 1 is the legacy bare `Vec<FileEntry>` layout, which is
+/// converted on read (see `utils::manifest`) and never written.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FolderMetadata {
+    pub version: u32,
+    pub original_folder_name: String,
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// What a folder download did with each entry. Paths are relative to the downloaded
+/// folder and use `/` as the separator.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct FolderDownloadReport {
+    pub succeeded: Vec<String>,
+    pub failed: Vec<FailedFolderEntry>,
+    pub skipped: Vec<SkippedFolderEntry>,
+}
+
+impl FolderDownloadReport {
+    pub fn merge(&mut self, other: FolderDownloadReport) {
+        self.succeeded.extend(other.succeeded);
+        self.failed.extend(other.failed);
+        self.skipped.extend(other.skipped);
+    }
+}
+
+/// An entry that could not be downloaded, with what a retry needs to fetch it again.
+/// `path` is where it goes below the downloaded folder.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct FailedFolderEntry {
+    pub path: String,
+    pub reason: String,
+    pub entry: ManifestEntry,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SkippedFolderEntry {
+    pub path: String,
+    pub reason: String,
+}
+
+/// What a folder download does when some entries fail: `Strict` returns an error
+/// naming them (the default), `Partial` returns the report and lets the caller decide.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum FolderFailurePolicy {
+    #[default]
+    Strict,
+    Partial,
+}
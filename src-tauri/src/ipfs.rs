@@ -1,4 +1,4 @@
-use reqwest::Client;
+use crate::utils::ipfs::IpfsClient;
 use serde::{Deserialize, Serialize};
 use std::time::Duration;
 
@@ -16,19 +16,11 @@ pub struct IpfsInfo {
 
 #[tauri::command]
 pub async fn get_ipfs_node_info() -> Result<IpfsInfo, String> {
-    let client = Client::builder()
+    // Configured node (local or remote), with its auth headers
+    let response = IpfsClient::from_settings()
+        .await
+        .api_post("id")
         .timeout(Duration::from_secs(10))
-        .build()
-        .map_err(|e| e.to_string())?;
-    
-    // Get the IPFS node URL from environment or config
-    let ipfs_url = std::env::var("IPFS_NODE_URL")
-        .unwrap_or_else(|_| "http://127.0.0.1:5001".to_string());
-    
-    let url = format!("{}/api/v0/id", ipfs_url);
-    
-    // Make the POST request
-    let response = client.post(&url)
         .send()
         .await
         .map_err(|e| format!("Request failed: {}", e))?;
@@ -47,19 +39,10 @@ pub async fn get_ipfs_node_info() -> Result<IpfsInfo, String> {
 
 #[tauri::command]
 pub async fn get_ipfs_bandwidth() -> Result<serde_json::Value, String> {
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(5))
-        .build()
-        .map_err(|e| e.to_string())?;
-    
-    // Get the IPFS node URL from environment or config
-    let ipfs_url = std::env::var("IPFS_NODE_URL")
-        .unwrap_or_else(|_| "http://127.0.0.1:5001".to_string());
-    
-    let url = format!("{}/api/v0/stats/bw", ipfs_url);
-    
-    // Make the POST request
-    let response = client.post(&url)
+    let response = IpfsClient::from_settings()
+        .await
+        .api_post("stats/bw")
+        .timeout(Duration::from_secs(5))
         .send()
         .await
         .map_err(|e| format!("Request failed: {}", e))?;
@@ -78,19 +61,10 @@ pub async fn get_ipfs_bandwidth() -> Result<serde_json::Value, String> {
 
 #[tauri::command]
 pub async fn get_ipfs_peers() -> Result<serde_json::Value, String> {
-    let client = reqwest::Client::builder()
-        .timeout(std::time::Duration::from_secs(5))
-        .build()
-        .map_err(|e| e.to_string())?;
-    
-    // Get the IPFS node URL from environment or config
-    let ipfs_url = std::env::var("IPFS_NODE_URL")
-        .unwrap_or_else(|_| "http://127.0.0.1:5001".to_string());
-    
-    let url = format!("{}/api/v0/swarm/peers", ipfs_url);
-    
-    // Make the POST request
-    let response = client.post(&url)
+    let response = IpfsClient::from_settings()
+        .await
+        .api_post("swarm/peers")
+        .timeout(Duration::from_secs(5))
         .send()
         .await
         .map_err(|e| format!("Request failed: {}", e))?;
@@ -9,6 +9,12 @@ mod private_folder_sync;
 mod ipfs;
 mod public_folder_sync;
 mod substrate_client;
+mod sync_compat;
+mod sync_compression;
+mod sync_index;
+mod sync_markers;
+mod sync_metadata;
+mod sync_planner;
 mod sync_shared;
 mod user_profile_sync;
 mod utils;
@@ -18,23 +24,50 @@ use crate::private_folder_sync::start_private_folder_sync_tauri;
 use crate::ipfs::{get_ipfs_bandwidth, get_ipfs_node_info, get_ipfs_peers};
 use crate::public_folder_sync::start_public_folder_sync_tauri;
 use crate::sync_shared::{app_close, get_sync_status,get_sync_activity};
+use crate::sync_index::get_sync_folder_index;
+use crate::sync_compat::get_compat_report;
 use crate::user_profile_sync::{get_user_synced_files, get_user_total_file_size};
 use builder_blocks::{on_window_event::on_window_event, setup::setup};
 use commands::accounts::{
-    create_encryption_key, export_app_data, get_encryption_keys, import_app_data, import_key,
-    reset_app,
+    create_derived_key, create_encryption_key, export_app_data, get_encryption_keys,
+    import_app_data, import_key, reset_app, restore_derived_keys,
 };
 use commands::ipfs_commands::{
     download_and_decrypt_file, encrypt_and_upload_file, read_file, write_file, delete_file,
     upload_file_public, download_file_public, wipe_s3_objects,
-    encrypt_and_upload_folder, download_and_decrypt_folder, public_download_folder, public_upload_folder, list_folder_contents,
+    encrypt_and_upload_folder, download_and_decrypt_folder, retry_folder_download, public_download_folder, public_upload_folder, list_folder_contents,
     remove_file_from_public_folder, add_file_to_public_folder, remove_file_from_private_folder, add_file_to_private_folder, add_folder_to_public_folder,
-    remove_folder_from_public_folder, add_folder_to_private_folder, remove_folder_from_private_folder
+    remove_folder_from_public_folder, add_folder_to_private_folder, remove_folder_from_private_folder,
+    erasure_upload_file, erasure_upload_folder, check_file_health, export_car, import_car
 };
 use utils::file_operations::delete_and_unpin_file_by_name;
+use commands::settings::{get_app_settings, get_ipfs_config, set_app_setting, set_ipfs_config};
+use commands::share::{
+    create_encrypted_share_link, create_share_link, list_share_links, open_share_link, revoke_share_link,
+};
+use commands::account_share::{
+    download_shared_item, get_shared_with_me, list_outgoing_shares, revoke_account_share,
+    share_with_account,
+};
+use commands::downloads::{
+    cancel_download, clear_finished_downloads, list_downloads, pause_download, queue_download,
+    resume_download,
+};
+use commands::key_rotation::{
+    get_key_rotation, list_key_rotations, retry_key_rotation, start_key_rotation,
+};
+use commands::key_store::{
+    change_key_store_passphrase, key_store_status, lock_key_store, remove_key_store_passphrase,
+    set_key_store_passphrase, unlock_key_store,
+};
 use commands::node::{get_current_setup_phase, start_ipfs_daemon, stop_ipfs_daemon};
+use commands::ipns::{list_ipns_names, publish_public_ipns, resolve_ipns_name, rotate_ipns_key};
+use commands::pins::{
+    add_pinning_service, get_pinning_services, get_remote_pins, list_local_pins, pin_cid,
+    pin_to_remote_service, remove_pinning_service, run_ipfs_gc, unpin_cid, unpin_from_remote_service,
+};
 use commands::substrate_tx::{
-    get_sync_path, get_wss_endpoint, set_sync_path, test_wss_endpoint_command,
+    get_sync_path, get_wss_endpoint, publish_sharing_key, set_sync_path, test_wss_endpoint_command,
     transfer_balance_tauri, update_wss_endpoint_command,
 };
 use once_cell::sync::OnceCell;
@@ -98,12 +131,15 @@ fn main() {
             encrypt_and_upload_folder,
             list_folder_contents,
             download_and_decrypt_folder,
+            retry_folder_download,
             remove_file_from_public_folder,
             reset_app,
             add_file_to_public_folder,
             remove_file_from_private_folder,
             add_file_to_private_folder,
             create_encryption_key,
+            create_derived_key,
+            restore_derived_keys,
             get_encryption_keys,
             import_key,
             wipe_s3_objects,
@@ -118,7 +154,59 @@ fn main() {
             remove_folder_from_public_folder,
             add_folder_to_private_folder,
             remove_folder_from_private_folder,
-            get_sync_activity
+            get_sync_activity,
+            get_sync_folder_index,
+            get_app_settings,
+            set_app_setting,
+            get_compat_report,
+            erasure_upload_file,
+            erasure_upload_folder,
+            check_file_health,
+            get_ipfs_config,
+            set_ipfs_config,
+            export_car,
+            import_car,
+            list_local_pins,
+            pin_cid,
+            unpin_cid,
+            run_ipfs_gc,
+            get_pinning_services,
+            add_pinning_service,
+            remove_pinning_service,
+            pin_to_remote_service,
+            unpin_from_remote_service,
+            get_remote_pins,
+            publish_public_ipns,
+            list_ipns_names,
+            resolve_ipns_name,
+            rotate_ipns_key,
+            create_share_link,
+            list_share_links,
+            revoke_share_link,
+            create_encrypted_share_link,
+            open_share_link,
+            publish_sharing_key,
+            share_with_account,
+            list_outgoing_shares,
+            get_shared_with_me,
+            revoke_account_share,
+            download_shared_item,
+            queue_download,
+            list_downloads,
+            pause_download,
+            resume_download,
+            cancel_download,
+            clear_finished_downloads,
+            start_key_rotation,
+            get_key_rotation,
+            list_key_rotations,
+            retry_key_rotation,
+            key_store_status,
+            set_key_store_passphrase,
+            unlock_key_store,
+            lock_key_store,
+            change_key_store_passphrase,
+            remove_key_store_passphrase
         ]);
 
     let builder = setup(builder);
@@ -17,6 +17,13 @@ use crate::sync_shared::parse_s3_sync_line;
 use serde_json::json;
 use tauri::{Emitter, Manager};
 use crate::DB_POOL;
+use crate::sync_index::{apply_sync_item_to_index, reconcile_index_with_disk, IndexDiff};
+use crate::sync_planner::{apply_moves, detect_moves, parse_sync_plan, record_moves};
+use crate::sync_markers::sync_folder_markers;
+use crate::sync_metadata::stamp_uploaded_file;
+use crate::sync_compression::{clear_staging, stage_sync_folder, staging_dir};
+use crate::utils::settings::{get_bool_setting, COMPRESS_UPLOADS};
+use crate::sync_compat::log_compat_issues;
 use chrono;
 use std::env;
 use crate::commands::node::get_aws_binary_path;
@@ -191,6 +198,56 @@ pub async fn start_private_folder_sync(app_handle: AppHandle, account_id: String
             }
         };
 
+        // Keep the nested file index in step with the disk before planning the sync
+        let index_diff = match DB_POOL.get() {
+            Some(pool) => match reconcile_index_with_disk(pool, &account_id, "private", std::path::Path::new(&sync_path)).await {
+                Ok(diff) => diff,
+                Err(e) => {
+                    eprintln!("[PrivateFolderSync] Failed to reconcile file index: {}", e);
+                    IndexDiff::default()
+                }
+            },
+            None => IndexDiff::default(),
+        };
+        if index_diff.changes > 0 {
+            log_compat_issues(&account_id, "private").await;
+        }
+
+        // With compression on, a staged copy of the folder is what gets uploaded
+        let staging = match staging_dir(&account_id) {
+            Ok(dir) => dir,
+            Err(e) => {
+                eprintln!("[PrivateFolderSync] {}", e);
+                sleep(Duration::from_secs(60)).await;
+                continue;
+            }
+        };
+        let staged = if get_bool_setting(COMPRESS_UPLOADS, false).await {
+            let sync_root = std::path::PathBuf::from(&sync_path);
+            match tokio::task::spawn_blocking(move || stage_sync_folder(&sync_root, &staging)).await {
+                Ok(Ok(staged)) => Some(staged),
+                Ok(Err(e)) => {
+                    eprintln!("[PrivateFolderSync] Failed to stage compressed uploads: {}", e);
+                    sleep(Duration::from_secs(60)).await;
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("[PrivateFolderSync] Staging task failed: {}", e);
+                    sleep(Duration::from_secs(60)).await;
+                    continue;
+                }
+            }
+        } else {
+            if let Err(e) = clear_staging(&staging) {
+                eprintln!("[PrivateFolderSync] {}", e);
+            }
+            None
+        };
+        let upload_root = staged
+            .as_ref()
+            .map(|s| s.root.to_string_lossy().to_string())
+            .unwrap_or_else(|| sync_path.clone());
+
         let s3_destination = format!("s3://{}/", bucket_name);
 
         println!("[PrivateFolderSync] Starting dry run to calculate changes...");
@@ -199,7 +256,7 @@ pub async fn start_private_folder_sync(app_handle: AppHandle, account_id: String
             .env("PATH", &dynamic_path)
             .arg("s3")
             .arg("sync")
-            .arg(&sync_path)
+            .arg(&upload_root)
             .arg(&s3_destination)
             .arg("--endpoint-url")
             .arg(endpoint_url)
@@ -215,19 +272,49 @@ pub async fn start_private_folder_sync(app_handle: AppHandle, account_id: String
             .arg(".git/*")
             .output();
 
-        let total_changes = match dry_run_output {
-            Ok(output) => {
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                stdout.lines()
-                    .filter_map(|line| parse_s3_sync_line(line, "private"))
-                    .count()
-            },
+        let dry_run_lines: Vec<String> = match dry_run_output {
+            Ok(output) => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|line| line.to_string())
+                .collect(),
             Err(e) => {
                 eprintln!("[PrivateFolderSync] Dry run command failed: {}", e);
                 continue;
             }
         };
 
+        let mut total_changes = dry_run_lines
+            .iter()
+            .filter_map(|line| parse_s3_sync_line(line, "private"))
+            .count();
+
+        // Turn delete + upload pairs of the same content into server-side moves
+        if !index_diff.removed.is_empty() && !index_diff.added.is_empty() {
+            let moves = detect_moves(&parse_sync_plan(&dry_run_lines), &index_diff);
+            if !moves.is_empty() {
+                let applied = apply_moves(&aws_binary_path, &dynamic_path, endpoint_url, &bucket_name, &moves, false);
+                total_changes = total_changes.saturating_sub(applied.len() * 2);
+                if let Some(pool) = DB_POOL.get() {
+                    record_moves(pool, &account_id, "private", std::path::Path::new(&sync_path), &applied, &S3_PRIVATE_SYNC_STATE).await;
+                }
+            }
+        }
+
+        // `aws s3 sync` ignores empty directories; keep folder markers in step with the index
+        if let Some(pool) = DB_POOL.get() {
+            sync_folder_markers(
+                pool,
+                &account_id,
+                "private",
+                &aws_binary_path,
+                &dynamic_path,
+                endpoint_url,
+                &bucket_name,
+                &index_diff.removed_folders,
+            )
+            .await;
+        }
+
         if total_changes == 0 {
             println!("[PrivateFolderSync] No changes detected. Waiting for next cycle.");
             {
@@ -251,7 +338,7 @@ pub async fn start_private_folder_sync(app_handle: AppHandle, account_id: String
             .env("PATH", &dynamic_path)
             .arg("s3")
             .arg("sync")
-            .arg(&sync_path)
+            .arg(&upload_root)
             .arg(&s3_destination)
             .arg("--endpoint-url")
             .arg(endpoint_url)
@@ -274,11 +361,21 @@ pub async fn start_private_folder_sync(app_handle: AppHandle, account_id: String
             let reader = BufReader::new(stdout);
             let account_id_clone = account_id.clone();
             let sync_path_str = sync_path.clone();
+            let aws_binary_path_clone = aws_binary_path.clone();
+            let dynamic_path_clone = dynamic_path.clone();
+            let bucket_name_clone = bucket_name.clone();
+            let staged = staged.clone();
             thread::spawn(move || {
                 for line in reader.lines() {
                     if let Ok(line) = line {
                         println!("[AWS Sync] {}", line);
-                        if let Some(item) = parse_s3_sync_line(&line, "private") {
+                        if let Some(mut item) = parse_s3_sync_line(&line, "private") {
+                            // Report the file in the sync folder rather than its staged copy
+                            let compression = staged.as_ref().and_then(|s| s.compression_of(std::path::Path::new(&item.path)));
+                            if let Some(source) = staged.as_ref().and_then(|s| s.source_of(std::path::Path::new(&item.path))) {
+                                item.path = source.to_string_lossy().to_string();
+                            }
+
                             let mut state = S3_PRIVATE_SYNC_STATE.lock().unwrap();
                             state.processed_files += 1;
                             if state.processed_files > state.total_files {
@@ -299,54 +396,24 @@ pub async fn start_private_folder_sync(app_handle: AppHandle, account_id: String
                                 let pool = pool.clone();
                                 let owner = account_id_clone.clone();
                                 let sync_root = std::path::PathBuf::from(&sync_path_str);
+                                let item = item.clone();
+                                tauri::async_runtime::spawn(async move {
+                                    apply_sync_item_to_index(&pool, &owner, "private", &sync_root, &item).await;
+                                });
+                            }
 
-                                if item.action == "uploaded" {
-                                    let abs_path = std::path::PathBuf::from(&item.path);
-                                    if let Ok(rel_path) = abs_path.strip_prefix(&sync_root) {
-                                        if let Some(first_component) = rel_path.components().next() {
-                                            let name = first_component.as_os_str().to_string_lossy().to_string();
-                                            let is_folder = abs_path.is_dir() || rel_path.components().count() > 1;
-                                            let bucket_item = BucketItem {
-                                                path: name.clone(),
-                                                size: if is_folder { 0 } else { abs_path.metadata().map(|m| m.len()).unwrap_or(0) },
-                                                last_modified: String::new(),
-                                                is_folder,
-                                            };
-
-                                            tauri::async_runtime::spawn(async move {
-                                                if let Err(e) = insert_bucket_item_if_absent(&pool, &owner, "private", &bucket_item).await {
-                                                    eprintln!("[PrivateFolderSync] Failed to insert bucket item '{}': {}", name, e);
-                                                }
-                                                
-                                                if !is_folder {
-                                                    let file_hash = ""; // Compute if needed
-                                                    if let Err(e) = sqlx::query(
-                                                        "INSERT OR REPLACE INTO file_paths (file_name, file_hash, timestamp, path) VALUES (?, ?, ?, ?)"
-                                                    )
-                                                    .bind(&name)
-                                                    .bind(file_hash)
-                                                    .bind(chrono::Utc::now().timestamp())
-                                                    .bind(&abs_path.to_string_lossy())
-                                                    .execute(&pool)
-                                                    .await {
-                                                        eprintln!("[PrivateFolderSync] Failed to insert into file_paths '{}': {}", name, e);
-                                                    }
-                                                }
-                                            });
-                                        }
-                                    }
-                                } else if item.action == "deleted" {
-                                    if let Some(key) = item.path.splitn(4, '/').nth(3) {
-                                        if !key.is_empty() && !key.contains('/') {
-                                            let name = key.to_string();
-                                            tauri::async_runtime::spawn(async move {
-                                                if let Err(e) = delete_bucket_item_by_name(&pool, &owner, "private", &name).await {
-                                                    eprintln!("[PrivateFolderSync] Failed to delete bucket item '{}': {}", name, e);
-                                                }
-                                            });
-                                        }
-                                    }
-                                }
+                            // Store mtime/mode on the uploaded object so downloads can restore them
+                            if item.action == "uploaded" {
+                                tauri::async_runtime::spawn(stamp_uploaded_file(
+                                    aws_binary_path_clone.clone(),
+                                    dynamic_path_clone.clone(),
+                                    endpoint_url.to_string(),
+                                    bucket_name_clone.clone(),
+                                    std::path::PathBuf::from(&sync_path_str),
+                                    std::path::PathBuf::from(&item.path),
+                                    false,
+                                    compression,
+                                ));
                             }
                         }
                     }
@@ -18,6 +18,13 @@ use crate::sync_shared::{insert_bucket_item_if_absent, delete_bucket_item_by_nam
 use serde_json::json;
 use tauri::Emitter;
 use crate::DB_POOL;
+use crate::sync_index::{apply_sync_item_to_index, reconcile_index_with_disk, IndexDiff};
+use crate::sync_planner::{apply_moves, detect_moves, parse_sync_plan, record_moves};
+use crate::sync_markers::sync_folder_markers;
+use crate::sync_metadata::stamp_uploaded_file;
+use crate::sync_compat::log_compat_issues;
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::ipns::republish_public_names;
 pub use crate::sync_shared::{SYNCING_ACCOUNTS, GLOBAL_CANCEL_TOKEN, S3_PUBLIC_SYNC_STATE,  BucketItem};
 use std::env;
 use crate::commands::node::get_aws_binary_path;
@@ -231,6 +238,23 @@ pub async fn start_public_folder_sync(app_handle: AppHandle, account_id: String,
         };
 
         println!("[PublicFolderSync] Starting dry run to calculate changes...");
+        // Keep the nested file index in step with the disk before planning the sync
+        let index_diff = match DB_POOL.get() {
+            Some(pool) => match reconcile_index_with_disk(pool, &account_id, "public", std::path::Path::new(&sync_path)).await {
+                Ok(diff) => diff,
+                Err(e) => {
+                    eprintln!("[PublicFolderSync] Failed to reconcile file index: {}", e);
+                    IndexDiff::default()
+                }
+            },
+            None => IndexDiff::default(),
+        };
+        if index_diff.changes > 0 {
+            log_compat_issues(&account_id, "public").await;
+            // IPNS names are published from the folder on disk, so only changes there matter
+            republish_public_names(&IpfsClient::from_settings().await, &account_id, &index_diff.paths).await;
+        }
+
         let s3_destination = format!("s3://{}/", bucket_name);
 
         let dry_run_output = Command::new(&aws_binary_path)
@@ -254,20 +278,50 @@ pub async fn start_public_folder_sync(app_handle: AppHandle, account_id: String,
             .arg(".git/*")
             .output();
 
-        let total_changes = match dry_run_output {
-            Ok(output) => {
-                let stdout = String::from_utf8_lossy(&output.stdout);
-                stdout.lines()
-                    .filter_map(|line| parse_s3_sync_line(line, "public"))
-                    .count()
-            },
+        let dry_run_lines: Vec<String> = match dry_run_output {
+            Ok(output) => String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .map(|line| line.to_string())
+                .collect(),
             Err(_) => {
                 continue;
             }
         };
 
+        let mut total_changes = dry_run_lines
+            .iter()
+            .filter_map(|line| parse_s3_sync_line(line, "public"))
+            .count();
+
+        // Turn delete + upload pairs of the same content into server-side moves
+        if !index_diff.removed.is_empty() && !index_diff.added.is_empty() {
+            let moves = detect_moves(&parse_sync_plan(&dry_run_lines), &index_diff);
+            if !moves.is_empty() {
+                let applied = apply_moves(&aws_binary_path, &dynamic_path, endpoint_url, &bucket_name, &moves, true);
+                total_changes = total_changes.saturating_sub(applied.len() * 2);
+                if let Some(pool) = DB_POOL.get() {
+                    record_moves(pool, &account_id, "public", std::path::Path::new(&sync_path), &applied, &S3_PUBLIC_SYNC_STATE).await;
+                }
+            }
+        }
+
         println!("[PublicFolderSync] Dry run complete. Found {} changes.", total_changes);
 
+        // `aws s3 sync` ignores empty directories; keep folder markers in step with the index
+        if let Some(pool) = DB_POOL.get() {
+            sync_folder_markers(
+                pool,
+                &account_id,
+                "public",
+                &aws_binary_path,
+                &dynamic_path,
+                endpoint_url,
+                &bucket_name,
+                &index_diff.removed_folders,
+            )
+            .await;
+        }
+
         if total_changes == 0 {
             println!("[PublicFolderSync] No changes detected. Waiting for next cycle.");
             {
@@ -316,6 +370,9 @@ pub async fn start_public_folder_sync(app_handle: AppHandle, account_id: String,
             let reader = BufReader::new(stdout);
             let account_id_clone = account_id.clone();
             let sync_path_str = sync_path.clone();
+            let aws_binary_path_clone = aws_binary_path.clone();
+            let dynamic_path_clone = dynamic_path.clone();
+            let bucket_name_clone = bucket_name.clone();
             thread::spawn(move || {
                 for line in reader.lines() {
                     if let Ok(line) = line {
@@ -341,54 +398,24 @@ pub async fn start_public_folder_sync(app_handle: AppHandle, account_id: String,
                                 let pool = pool.clone();
                                 let owner = account_id_clone.clone();
                                 let sync_root = std::path::PathBuf::from(&sync_path_str);
+                                let item = item.clone();
+                                tauri::async_runtime::spawn(async move {
+                                    apply_sync_item_to_index(&pool, &owner, "public", &sync_root, &item).await;
+                                });
+                            }
 
-                                if item.action == "uploaded" {
-                                    let abs_path = std::path::PathBuf::from(&item.path);
-                                    if let Ok(rel_path) = abs_path.strip_prefix(&sync_root) {
-                                        if let Some(first_component) = rel_path.components().next() {
-                                            let name = first_component.as_os_str().to_string_lossy().to_string();
-                                            let is_folder = abs_path.is_dir() || rel_path.components().count() > 1;
-                                            let bucket_item = BucketItem {
-                                                path: name.clone(),
-                                                size: if is_folder { 0 } else { abs_path.metadata().map(|m| m.len()).unwrap_or(0) },
-                                                last_modified: String::new(),
-                                                is_folder,
-                                            };
-
-                                            tauri::async_runtime::spawn(async move {
-                                                if let Err(e) = insert_bucket_item_if_absent(&pool, &owner, "public", &bucket_item).await {
-                                                    eprintln!("[PublicFolderSync] Failed to insert bucket item '{}': {}", name, e);
-                                                }
-                                                
-                                                if !is_folder {
-                                                    let file_hash = ""; // You might want to compute this
-                                                    if let Err(e) = sqlx::query(
-                                                        "INSERT OR REPLACE INTO file_paths (file_name, file_hash, timestamp, path) VALUES (?, ?, ?, ?)"
-                                                    )
-                                                    .bind(&name)
-                                                    .bind(file_hash)
-                                                    .bind(chrono::Utc::now().timestamp())
-                                                    .bind(&abs_path.to_string_lossy())
-                                                    .execute(&pool)
-                                                    .await {
-                                                        eprintln!("[PublicFolderSync] Failed to insert into file_paths '{}': {}", name, e);
-                                                    }
-                                                }
-                                            });
-                                        }
-                                    }
-                                } else if item.action == "deleted" {
-                                    if let Some(key) = item.path.splitn(4, '/').nth(3) {
-                                        if !key.is_empty() && !key.contains('/') {
-                                            let name = key.to_string();
-                                            tauri::async_runtime::spawn(async move {
-                                                if let Err(e) = delete_bucket_item_by_name(&pool, &owner, "public", &name).await {
-                                                    eprintln!("[PublicFolderSync] Failed to delete bucket item '{}': {}", name, e);
-                                                }
-                                            });
-                                        }
-                                    }
-                                }
+                            // Store mtime/mode on the uploaded object so downloads can restore them
+                            if item.action == "uploaded" {
+                                tauri::async_runtime::spawn(stamp_uploaded_file(
+                                    aws_binary_path_clone.clone(),
+                                    dynamic_path_clone.clone(),
+                                    endpoint_url.to_string(),
+                                    bucket_name_clone.clone(),
+                                    std::path::PathBuf::from(&sync_path_str),
+                                    std::path::PathBuf::from(&item.path),
+                                    true,
+                                    None,
+                                ));
                             }
                         }
                     }
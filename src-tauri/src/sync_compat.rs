@@ -0,0 +1,317 @@
+use std::collections::HashMap;
+use serde::Serialize;
+use unicode_normalization::{is_nfc, UnicodeNormalization};
+use crate::utils::settings::{get_bool_setting, MAP_INCOMPATIBLE_NAMES};
+use crate::DB_POOL;
+
+// Base names Windows refuses regardless of extension
+const WINDOWS_RESERVED_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+const WINDOWS_INVALID_CHARS: &[char] = &['<', '>', ':', '"', '\\', '|', '?', '*'];
+const MAX_S3_KEY_BYTES: usize = 1024;
+const MAX_COMPONENT_BYTES: usize = 255;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CompatIssue {
+    pub path: String,
+    // reserved_name, invalid_character, trailing_dot_or_space, not_nfc, case_collision,
+    // normalization_collision, name_too_long, key_too_long
+    pub kind: String,
+    pub detail: String,
+    pub conflicts_with: Option<String>,
+    pub suggested_name: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct CompatReport {
+    pub checked: usize,
+    pub issues: Vec<CompatIssue>,
+}
+
+fn is_reserved_name(name: &str) -> bool {
+    let base = name.split('.').next().unwrap_or(name).trim_end_matches(' ');
+    WINDOWS_RESERVED_NAMES.iter().any(|r| r.eq_ignore_ascii_case(base))
+}
+
+fn is_invalid_char(c: char) -> bool {
+    WINDOWS_INVALID_CHARS.contains(&c) || (c as u32) < 0x20
+}
+
+/// Problems a single path component would cause on some platform, as (kind, detail).
+pub fn check_name(name: &str) -> Vec<(&'static str, String)> {
+    let mut problems = Vec::new();
+    if is_reserved_name(name) {
+        problems.push(("reserved_name", format!("'{}' is a reserved device name on Windows", name)));
+    }
+    if let Some(c) = name.chars().find(|c| is_invalid_char(*c)) {
+        problems.push(("invalid_character", format!("'{}' contains {:?}, which Windows does not allow", name, c)));
+    }
+    if name.ends_with('.') || name.ends_with(' ') {
+        problems.push(("trailing_dot_or_space", format!("'{}' ends with a dot or space, which Windows strips", name)));
+    }
+    if !is_nfc(name) {
+        problems.push(("not_nfc", format!("'{}' is not NFC-normalized and may be renamed by macOS or Linux tools", name)));
+    }
+    if name.len() > MAX_COMPONENT_BYTES {
+        problems.push(("name_too_long", format!("name is {} bytes, most filesystems allow {}", name.len(), MAX_COMPONENT_BYTES)));
+    }
+    problems
+}
+
+/// A name that is valid everywhere and keeps as much of the original as possible.
+pub fn suggest_portable_name(name: &str) -> String {
+    let mut out: String = name
+        .nfc()
+        .map(|c| if is_invalid_char(c) { '_' } else { c })
+        .collect();
+    while out.ends_with('.') || out.ends_with(' ') {
+        out.pop();
+    }
+    if out.is_empty() {
+        out.push('_');
+    }
+    if is_reserved_name(&out) {
+        match out.find('.') {
+            Some(idx) => out.insert(idx, '_'),
+            None => out.push('_'),
+        }
+    }
+    if out.len() > MAX_COMPONENT_BYTES {
+        let mut cut = MAX_COMPONENT_BYTES;
+        while !out.is_char_boundary(cut) {
+            cut -= 1;
+        }
+        out.truncate(cut);
+    }
+    out
+}
+
+// "report.pdf" -> "report (2).pdf"
+fn with_copy_suffix(name: &str) -> String {
+    match name.rfind('.') {
+        Some(idx) if idx > 0 => format!("{} (2){}", &name[..idx], &name[idx..]),
+        _ => format!("{} (2)", name),
+    }
+}
+
+/// Reversible mapping: characters a platform rejects are percent-encoded, as is '%'
+/// itself, so `decode_portable_name(encode_portable_name(x)) == x`.
+pub fn encode_portable_name(name: &str) -> String {
+    let mut out = String::with_capacity(name.len());
+    let char_count = name.chars().count();
+    for (i, c) in name.chars().enumerate() {
+        let is_last = i + 1 == char_count;
+        let needs_escape = c == '%'
+            || is_invalid_char(c)
+            || (is_last && (c == '.' || c == ' '))
+            // Breaking the reserved base name is enough to make it legal
+            || (i == 0 && is_reserved_name(name));
+        if needs_escape {
+            let mut buf = [0u8; 4];
+            for b in c.encode_utf8(&mut buf).bytes() {
+                out.push_str(&format!("%{:02X}", b));
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+pub fn decode_portable_name(name: &str) -> String {
+    let bytes = name.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%'
+            && i + 2 < bytes.len()
+            && bytes[i + 1].is_ascii_hexdigit()
+            && bytes[i + 2].is_ascii_hexdigit()
+        {
+            let hex_pair = [bytes[i + 1], bytes[i + 2]];
+            if let Ok(b) = u8::from_str_radix(std::str::from_utf8(&hex_pair).unwrap_or("zz"), 16) {
+                out.push(b);
+                i += 3;
+                continue;
+            }
+        }
+        out.push(bytes[i]);
+        i += 1;
+    }
+    String::from_utf8(out).unwrap_or_else(|_| name.to_string())
+}
+
+fn rejected_by_this_platform(name: &str) -> bool {
+    cfg!(windows)
+        && check_name(name)
+            .iter()
+            .any(|(kind, _)| matches!(*kind, "reserved_name" | "invalid_character" | "trailing_dot_or_space"))
+}
+
+/// Name to use when writing `name` to the local disk. Only names this platform rejects
+/// are mapped, and only when the user enabled mapping.
+pub async fn local_name_for(name: &str) -> String {
+    if rejected_by_this_platform(name) && get_bool_setting(MAP_INCOMPATIBLE_NAMES, false).await {
+        encode_portable_name(name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// Reverses `local_name_for` when a local name goes back into a manifest. A name is only
+/// decoded if encoding the result gives it back, so names that merely contain '%' stay.
+pub async fn remote_name_for(local_name: &str) -> String {
+    let decoded = decode_portable_name(local_name);
+    if decoded != local_name
+        && encode_portable_name(&decoded) == local_name
+        && get_bool_setting(MAP_INCOMPATIBLE_NAMES, false).await
+    {
+        decoded
+    } else {
+        local_name.to_string()
+    }
+}
+
+/// Checks '/'-separated relative paths (as stored in the file index) for names that
+/// would be invalid or collide on another platform, and for keys S3 would reject.
+pub fn build_compat_report(paths: &[String]) -> CompatReport {
+    let mut report = CompatReport {
+        checked: paths.len(),
+        issues: Vec::new(),
+    };
+
+    // Siblings keyed by (parent, folded name) to find case and normalization collisions
+    let mut by_case: HashMap<(String, String), String> = HashMap::new();
+    let mut by_nfc: HashMap<(String, String), String> = HashMap::new();
+
+    for path in paths {
+        if path.len() > MAX_S3_KEY_BYTES {
+            report.issues.push(CompatIssue {
+                path: path.clone(),
+                kind: "key_too_long".to_string(),
+                detail: format!("object key is {} bytes, S3 allows {}", path.len(), MAX_S3_KEY_BYTES),
+                conflicts_with: None,
+                suggested_name: None,
+            });
+        }
+
+        let (parent, name) = match path.rsplit_once('/') {
+            Some((p, n)) => (p.to_string(), n.to_string()),
+            None => (String::new(), path.clone()),
+        };
+
+        for (kind, detail) in check_name(&name) {
+            report.issues.push(CompatIssue {
+                path: path.clone(),
+                kind: kind.to_string(),
+                detail,
+                conflicts_with: None,
+                suggested_name: Some(suggest_portable_name(&name)),
+            });
+        }
+
+        let nfc: String = name.nfc().collect();
+        if let Some(other) = by_nfc.insert((parent.clone(), nfc.clone()), path.clone()) {
+            report.issues.push(CompatIssue {
+                path: path.clone(),
+                kind: "normalization_collision".to_string(),
+                detail: format!("'{}' and '{}' are the same name after Unicode normalization", path, other),
+                conflicts_with: Some(other),
+                suggested_name: Some(suggest_portable_name(&with_copy_suffix(&nfc))),
+            });
+            continue;
+        }
+        if let Some(other) = by_case.insert((parent, nfc.to_lowercase()), path.clone()) {
+            report.issues.push(CompatIssue {
+                path: path.clone(),
+                kind: "case_collision".to_string(),
+                detail: format!("'{}' and '{}' differ only in case", path, other),
+                conflicts_with: Some(other),
+                suggested_name: Some(suggest_portable_name(&with_copy_suffix(&name))),
+            });
+        }
+    }
+    report
+}
+
+/// Builds the report for everything currently in the file index for this owner/scope.
+pub async fn compat_report_from_index(owner: &str, scope: &str) -> Result<CompatReport, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let paths: Vec<String> = sqlx::query_scalar(
+        "SELECT path FROM file_index WHERE owner = ? AND scope = ? ORDER BY path"
+    )
+    .bind(owner)
+    .bind(scope)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to read file index: {}", e))?;
+    Ok(build_compat_report(&paths))
+}
+
+/// Logs a summary after the index changed, so problems show up before another machine
+/// trips over them.
+pub async fn log_compat_issues(owner: &str, scope: &str) {
+    let log_tag = if scope == "public" { "[PublicFolderSync]" } else { "[PrivateFolderSync]" };
+    match compat_report_from_index(owner, scope).await {
+        Ok(report) if !report.issues.is_empty() => {
+            eprintln!(
+                "{} {} of {} paths are not portable across platforms",
+                log_tag,
+                report.issues.len(),
+                report.checked
+            );
+            for issue in report.issues.iter().take(10) {
+                eprintln!("{}   {}: {}", log_tag, issue.kind, issue.detail);
+            }
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("{} Failed to build compatibility report: {}", log_tag, e),
+    }
+}
+
+#[tauri::command]
+pub async fn get_compat_report(account_id: String, scope: String) -> Result<CompatReport, String> {
+    if scope != "public" && scope != "private" {
+        return Err("Invalid scope provided. Must be 'public' or 'private'.".to_string());
+    }
+    compat_report_from_index(&account_id, &scope).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::settings::set_setting;
+    use crate::utils::test_db::test_db;
+
+    #[test]
+    fn portable_names_round_trip() {
+        for name in ["con.txt", "a:b", "what?.md", "trailing.", "trailing ", "100%.txt", "plain.txt", "ünï<>.txt", "%41"] {
+            let encoded = encode_portable_name(name);
+            assert!(check_name(&encoded).iter().all(|(kind, _)| *kind == "not_nfc"), "{} -> {}", name, encoded);
+            assert_eq!(decode_portable_name(&encoded), name);
+        }
+        assert_eq!(encode_portable_name("con.txt"), "%63on.txt");
+        assert_eq!(encode_portable_name("a:b"), "a%3Ab");
+        assert_eq!(encode_portable_name("plain.txt"), "plain.txt");
+    }
+
+    #[tokio::test]
+    async fn only_mapped_names_are_decoded_for_upload() {
+        test_db().await;
+        set_setting(MAP_INCOMPATIBLE_NAMES, "true").await.unwrap();
+        assert_eq!(remote_name_for("a%3Ab").await, "a:b");
+        assert_eq!(remote_name_for("%63on.txt").await, "con.txt");
+        // Never produced by the mapping, so kept as they are
+        assert_eq!(remote_name_for("%41").await, "%41");
+        assert_eq!(remote_name_for("100%.txt").await, "100%.txt");
+        assert_eq!(remote_name_for("plain.txt").await, "plain.txt");
+        assert_eq!(local_name_for("plain.txt").await, "plain.txt");
+        if cfg!(windows) {
+            assert_eq!(local_name_for("a:b").await, "a%3Ab");
+        } else {
+            assert_eq!(local_name_for("a:b").await, "a:b");
+        }
+    }
+}
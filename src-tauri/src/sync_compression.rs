@@ -0,0 +1,274 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use filetime::FileTime;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+use crate::commands::types::Compression;
+use crate::sync_index::to_index_path;
+use crate::utils::compression::{compress_file, is_already_compressed};
+
+const FILES_DIR: &str = "files";
+// What was staged last time, kept beside the staged files rather than among them
+const STATE_FILE: &str = "staged.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct StagedFile {
+    size: u64,
+    mtime_secs: i64,
+    mtime_nanos: u32,
+    compressed: bool,
+}
+
+/// With `compress_uploads` on, private sync uploads a staged copy of the sync folder
+/// instead of the folder itself. Files worth compressing are kept there zstd-compressed
+/// and the rest as hard links (copies where linking fails). `aws s3 sync` decides what
+/// to upload by size and mtime, so staged files keep their source's mtime and are only
+/// rewritten when the source changes.
+#[derive(Debug, Clone)]
+pub struct StagedFolder {
+    pub root: PathBuf,
+    source_root: PathBuf,
+    compressed: HashSet<String>,
+}
+
+impl StagedFolder {
+    /// The file in the sync folder that `staged_path`, as reported by `aws s3 sync`,
+    /// was staged from. None for paths outside the staged copy.
+    pub fn source_of(&self, staged_path: &Path) -> Option<PathBuf> {
+        let rel = staged_path.strip_prefix(&self.root).ok()?;
+        Some(self.source_root.join(rel))
+    }
+
+    /// How the object uploaded from `staged_path` was compressed, if it was.
+    pub fn compression_of(&self, staged_path: &Path) -> Option<Compression> {
+        let rel = staged_path.strip_prefix(&self.root).ok()?;
+        self.compressed.contains(&to_index_path(rel)).then_some(Compression::Zstd)
+    }
+}
+
+/// Where the staged copy of an account's private sync folder is kept.
+pub fn staging_dir(account_id: &str) -> Result<PathBuf, String> {
+    let home = dirs::home_dir().ok_or_else(|| "Could not find home directory".to_string())?;
+    Ok(home.join(".hippius").join("private-sync-staging").join(account_id))
+}
+
+/// Drops a staged copy that is no longer uploaded from.
+pub fn clear_staging(staging_dir: &Path) -> Result<(), String> {
+    if !staging_dir.exists() {
+        return Ok(());
+    }
+    fs::remove_dir_all(staging_dir).map_err(|e| format!("Failed to remove {}: {}", staging_dir.display(), e))
+}
+
+/// Brings the staged copy in `staging_dir` in line with `sync_root`. A file that cannot
+/// be staged keeps its previous staged copy, so the `--delete` sync that follows does
+/// not remove it from the bucket.
+pub fn stage_sync_folder(sync_root: &Path, staging_dir: &Path) -> Result<StagedFolder, String> {
+    let root = staging_dir.join(FILES_DIR);
+    fs::create_dir_all(&root).map_err(|e| format!("Failed to create {}: {}", root.display(), e))?;
+    let state_path = staging_dir.join(STATE_FILE);
+    let previous: HashMap<String, StagedFile> = fs::read(&state_path)
+        .ok()
+        .and_then(|bytes| serde_json::from_slice(&bytes).ok())
+        .unwrap_or_default();
+
+    let mut staged = HashMap::new();
+    for entry in WalkDir::new(sync_root).follow_links(true) {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                eprintln!("[SyncCompression] Failed to read {}: {}", sync_root.display(), e);
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = match entry.path().strip_prefix(sync_root) {
+            Ok(rel) => rel,
+            Err(_) => continue,
+        };
+        let index_path = to_index_path(rel);
+        let target = root.join(rel);
+        let kept = previous.get(&index_path).filter(|_| target.is_file()).cloned();
+
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(e) => {
+                eprintln!("[SyncCompression] Failed to stat {}: {}", entry.path().display(), e);
+                if let Some(kept) = kept {
+                    staged.insert(index_path, kept);
+                }
+                continue;
+            }
+        };
+        let mtime = FileTime::from_last_modification_time(&meta);
+        let unchanged = kept.as_ref().is_some_and(|k| {
+            k.size == meta.len() && k.mtime_secs == mtime.unix_seconds() && k.mtime_nanos == mtime.nanoseconds()
+        });
+        if unchanged {
+            staged.insert(index_path, kept.unwrap());
+            continue;
+        }
+
+        match stage_file(entry.path(), &target, mtime) {
+            Ok(compressed) => {
+                staged.insert(index_path, StagedFile {
+                    size: meta.len(),
+                    mtime_secs: mtime.unix_seconds(),
+                    mtime_nanos: mtime.nanoseconds(),
+                    compressed,
+                });
+            }
+            Err(e) => {
+                eprintln!("[SyncCompression] {}", e);
+                if let Some(kept) = kept {
+                    staged.insert(index_path, kept);
+                }
+            }
+        }
+    }
+
+    remove_unstaged(&root, &staged);
+    let state = serde_json::to_vec(&staged).map_err(|e| format!("Failed to encode staging state: {}", e))?;
+    fs::write(&state_path, state).map_err(|e| format!("Failed to write {}: {}", state_path.display(), e))?;
+
+    Ok(StagedFolder {
+        root: fs::canonicalize(&root).map_err(|e| format!("Failed to resolve {}: {}", root.display(), e))?,
+        source_root: fs::canonicalize(sync_root).map_err(|e| format!("Failed to resolve {}: {}", sync_root.display(), e))?,
+        compressed: staged.into_iter().filter(|(_, f)| f.compressed).map(|(path, _)| path).collect(),
+    })
+}
+
+// Writes the staged copy of one file and returns whether it was compressed
+fn stage_file(source: &Path, target: &Path, mtime: FileTime) -> Result<bool, String> {
+    let parent = target.parent().ok_or_else(|| format!("{} has no parent folder", target.display()))?;
+    fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+
+    let mut head = Vec::with_capacity(16);
+    fs::File::open(source)
+        .and_then(|f| f.take(16).read_to_end(&mut head))
+        .map_err(|e| format!("Failed to read {}: {}", source.display(), e))?;
+    let compress = !is_already_compressed(source, &head);
+
+    if compress {
+        let temp = tempfile::NamedTempFile::new_in(parent)
+            .map_err(|e| format!("Failed to create temp file in {}: {}", parent.display(), e))?
+            .into_temp_path();
+        compress_file(source, &temp, Compression::Zstd)?;
+        temp.persist(target).map_err(|e| format!("Failed to stage {}: {}", target.display(), e))?;
+    } else {
+        if target.exists() {
+            fs::remove_file(target).map_err(|e| format!("Failed to replace {}: {}", target.display(), e))?;
+        }
+        if fs::hard_link(source, target).is_ok() {
+            return Ok(false);
+        }
+        fs::copy(source, target).map_err(|e| format!("Failed to stage {}: {}", target.display(), e))?;
+    }
+    filetime::set_file_mtime(target, mtime).map_err(|e| format!("Failed to set mtime on {}: {}", target.display(), e))?;
+    Ok(compress)
+}
+
+// Removes staged files whose source is gone, then the folders that leaves empty
+fn remove_unstaged(root: &Path, staged: &HashMap<String, StagedFile>) {
+    for entry in WalkDir::new(root).contents_first(true).into_iter().flatten() {
+        let path = entry.path();
+        if path == root {
+            continue;
+        }
+        if entry.file_type().is_dir() {
+            // Only succeeds for folders that are empty by now
+            let _ = fs::remove_dir(path);
+            continue;
+        }
+        let rel = match path.strip_prefix(root) {
+            Ok(rel) => rel,
+            Err(_) => continue,
+        };
+        if !staged.contains_key(&to_index_path(rel)) {
+            if let Err(e) = fs::remove_file(path) {
+                eprintln!("[SyncCompression] Failed to remove {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_with_mtime(path: &Path, data: &[u8], mtime: i64) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, data).unwrap();
+        filetime::set_file_mtime(path, FileTime::from_unix_time(mtime, 0)).unwrap();
+    }
+
+    #[test]
+    fn staging_compresses_what_is_worth_it_and_keeps_mtimes() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("sync");
+        let staging = dir.path().join("staging");
+        let log = b"2026-10-19 INFO request served\n".repeat(2000);
+        write_with_mtime(&source.join("logs/app.log"), &log, 1_700_000_000);
+        write_with_mtime(&source.join("photo.jpg"), b"\xff\xd8\xff not really a photo", 1_700_000_100);
+        write_with_mtime(&source.join(".hidden"), b"dot files are synced too", 1_700_000_200);
+
+        let staged = stage_sync_folder(&source, &staging).unwrap();
+        let log_copy = staged.root.join("logs/app.log");
+        let compressed = fs::read(&log_copy).unwrap();
+        assert!(compressed.len() * 10 < log.len());
+        assert_eq!(zstd::stream::decode_all(&compressed[..]).unwrap(), log);
+        assert_eq!(fs::read(staged.root.join("photo.jpg")).unwrap(), b"\xff\xd8\xff not really a photo");
+        assert!(staged.root.join(".hidden").is_file());
+        let mtime = FileTime::from_last_modification_time(&fs::metadata(&log_copy).unwrap());
+        assert_eq!(mtime.unix_seconds(), 1_700_000_000);
+
+        assert_eq!(staged.compression_of(&log_copy), Some(Compression::Zstd));
+        assert_eq!(staged.compression_of(&staged.root.join("photo.jpg")), None);
+        assert_eq!(
+            staged.source_of(&log_copy).unwrap(),
+            fs::canonicalize(&source).unwrap().join("logs/app.log")
+        );
+        assert_eq!(staged.source_of(Path::new("s3://bucket-private/logs/app.log")), None);
+    }
+
+    #[test]
+    fn restaging_follows_changes_and_removals() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("sync");
+        let staging = dir.path().join("staging");
+        write_with_mtime(&source.join("notes/a.txt"), &b"first draft\n".repeat(100), 1_700_000_000);
+        write_with_mtime(&source.join("notes/b.txt"), &b"keep me\n".repeat(100), 1_700_000_000);
+        stage_sync_folder(&source, &staging).unwrap();
+
+        write_with_mtime(&source.join("notes/a.txt"), &b"second draft\n".repeat(300), 1_700_000_500);
+        fs::remove_file(source.join("notes/b.txt")).unwrap();
+        write_with_mtime(&source.join("other/c.txt"), &b"new\n".repeat(100), 1_700_000_600);
+        let staged = stage_sync_folder(&source, &staging).unwrap();
+
+        let a = fs::read(staged.root.join("notes/a.txt")).unwrap();
+        assert_eq!(zstd::stream::decode_all(&a[..]).unwrap(), b"second draft\n".repeat(300));
+        assert!(!staged.root.join("notes/b.txt").exists());
+        assert!(staged.root.join("other/c.txt").is_file());
+
+        // Nothing changed, nothing is rewritten
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let before = fs::metadata(staged.root.join("other/c.txt")).unwrap().ino();
+            let again = stage_sync_folder(&source, &staging).unwrap();
+            assert_eq!(fs::metadata(again.root.join("other/c.txt")).unwrap().ino(), before);
+        }
+
+        fs::remove_dir_all(&source).unwrap();
+        fs::create_dir_all(&source).unwrap();
+        let emptied = stage_sync_folder(&source, &staging).unwrap();
+        assert!(!emptied.root.join("notes").exists() && !emptied.root.join("other").exists());
+
+        clear_staging(&staging).unwrap();
+        assert!(!staging.exists());
+    }
+}
@@ -0,0 +1,634 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::io::Read;
+use std::time::UNIX_EPOCH;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{Row, SqlitePool};
+use crate::sync_shared::{
+    delete_bucket_item_by_name, insert_bucket_item_if_absent, update_bucket_item_size, BucketItem,
+    RecentItem,
+};
+use crate::DB_POOL;
+
+/// One row of the local file index. `path` is relative to the sync root and always
+/// uses '/' as separator so it matches the S3 key of the object.
+#[derive(Debug, Clone, Serialize)]
+pub struct IndexEntry {
+    pub path: String,
+    pub parent_path: String,
+    pub name: String,
+    pub is_folder: bool,
+    pub size: i64,
+    pub mtime: i64,
+    pub content_hash: Option<String>,
+    pub updated_at: i64,
+}
+
+/// What changed on disk since the index was last reconciled. `added` and `removed` only
+/// list files; `removed` keeps the last known hash so moves can be matched against
+/// `added`. Folders that disappeared are listed separately so their markers can go.
+/// `paths` has every file or folder path that was added, modified or removed.
+#[derive(Debug, Clone, Default)]
+pub struct IndexDiff {
+    pub changes: usize,
+    pub added: Vec<IndexEntry>,
+    pub removed: Vec<IndexEntry>,
+    pub removed_folders: Vec<IndexEntry>,
+    pub paths: Vec<String>,
+}
+
+/// Converts a path relative to the sync root into an index path ("a/b/c.txt").
+pub fn to_index_path(rel_path: &Path) -> String {
+    rel_path
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Splits an index path into (parent_path, name). Root-level entries have an empty parent.
+pub fn split_index_path(path: &str) -> (String, String) {
+    match path.rsplit_once('/') {
+        Some((parent, name)) => (parent.to_string(), name.to_string()),
+        None => (String::new(), path.to_string()),
+    }
+}
+
+/// Extracts the object key from an `s3://bucket/key` path as printed by `aws s3 sync`.
+pub fn s3_key_from_path(s3_path: &str) -> Option<&str> {
+    s3_path.splitn(4, '/').nth(3).filter(|k| !k.is_empty())
+}
+
+pub fn modified_secs(meta: &std::fs::Metadata) -> i64 {
+    meta.modified()
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+/// Hex-encoded SHA-256 of a file's contents, read in chunks.
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = std::fs::File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(hex::encode(hasher.finalize()))
+}
+
+/// `hash_file` on the blocking pool, for callers on the async runtime.
+pub async fn hash_file_blocking(path: PathBuf) -> std::io::Result<String> {
+    tokio::task::spawn_blocking(move || hash_file(&path))
+        .await
+        .map_err(std::io::Error::other)?
+}
+
+// LIKE pattern matching everything below `path`, with wildcards in the path escaped.
+fn descendants_pattern(path: &str) -> String {
+    let escaped = path
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    format!("{}/%", escaped)
+}
+
+/// Inserts or updates an entry, creating folder rows for any missing ancestors.
+#[allow(clippy::too_many_arguments)]
+pub async fn upsert_index_entry(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    path: &str,
+    is_folder: bool,
+    size: i64,
+    mtime: i64,
+    content_hash: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    let now = chrono::Utc::now().timestamp();
+
+    let mut ancestor = String::new();
+    let parts: Vec<&str> = path.split('/').collect();
+    for part in &parts[..parts.len().saturating_sub(1)] {
+        let parent = ancestor.clone();
+        if !ancestor.is_empty() {
+            ancestor.push('/');
+        }
+        ancestor.push_str(part);
+        sqlx::query(
+            "INSERT OR IGNORE INTO file_index (owner, scope, path, parent_path, name, is_folder, size, mtime, created_at, updated_at)
+             VALUES (?, ?, ?, ?, ?, 1, 0, 0, ?, ?)"
+        )
+        .bind(owner)
+        .bind(scope)
+        .bind(&ancestor)
+        .bind(&parent)
+        .bind(*part)
+        .bind(now)
+        .bind(now)
+        .execute(pool)
+        .await?;
+    }
+
+    let (parent_path, name) = split_index_path(path);
+    sqlx::query(
+        "INSERT INTO file_index (owner, scope, path, parent_path, name, is_folder, size, mtime, content_hash, created_at, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(owner, scope, path) DO UPDATE SET
+            is_folder = excluded.is_folder,
+            size = excluded.size,
+            mtime = excluded.mtime,
+            content_hash = excluded.content_hash,
+            updated_at = excluded.updated_at"
+    )
+    .bind(owner)
+    .bind(scope)
+    .bind(path)
+    .bind(&parent_path)
+    .bind(&name)
+    .bind(is_folder)
+    .bind(if is_folder { 0 } else { size })
+    .bind(mtime)
+    .bind(content_hash)
+    .bind(now)
+    .bind(now)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Removes an entry and everything below it.
+pub async fn remove_index_entry(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    path: &str,
+) -> Result<u64, sqlx::Error> {
+    let res = sqlx::query(
+        "DELETE FROM file_index WHERE owner = ? AND scope = ? AND (path = ? OR path LIKE ? ESCAPE '\\')"
+    )
+    .bind(owner)
+    .bind(scope)
+    .bind(path)
+    .bind(descendants_pattern(path))
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
+
+/// Total size of all files below `path`, taken from the index rather than the disk.
+pub async fn folder_size_from_index(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    path: &str,
+) -> Result<i64, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT COALESCE(SUM(size), 0) AS total FROM file_index
+         WHERE owner = ? AND scope = ? AND is_folder = 0 AND path LIKE ? ESCAPE '\\'"
+    )
+    .bind(owner)
+    .bind(scope)
+    .bind(descendants_pattern(path))
+    .fetch_one(pool)
+    .await?;
+    Ok(row.get::<i64, _>("total"))
+}
+
+pub async fn get_index_entry(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    path: &str,
+) -> Result<Option<IndexEntry>, sqlx::Error> {
+    let row = sqlx::query(
+        "SELECT path, parent_path, name, is_folder, size, mtime, content_hash, updated_at FROM file_index
+         WHERE owner = ? AND scope = ? AND path = ?"
+    )
+    .bind(owner)
+    .bind(scope)
+    .bind(path)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.map(|r| row_to_entry(&r)))
+}
+
+/// Lists the direct children of `parent_path` ("" for the sync root). Folder sizes are
+/// summed from the index.
+pub async fn list_index_children(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    parent_path: &str,
+) -> Result<Vec<IndexEntry>, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT path, parent_path, name, is_folder, size, mtime, content_hash, updated_at FROM file_index
+         WHERE owner = ? AND scope = ? AND parent_path = ?
+         ORDER BY is_folder DESC, name ASC"
+    )
+    .bind(owner)
+    .bind(scope)
+    .bind(parent_path)
+    .fetch_all(pool)
+    .await?;
+
+    let mut entries = Vec::with_capacity(rows.len());
+    for row in rows {
+        let mut entry = row_to_entry(&row);
+        if entry.is_folder {
+            entry.size = folder_size_from_index(pool, owner, scope, &entry.path).await?;
+        }
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+fn row_to_entry(row: &sqlx::sqlite::SqliteRow) -> IndexEntry {
+    IndexEntry {
+        path: row.get("path"),
+        parent_path: row.get("parent_path"),
+        name: row.get("name"),
+        is_folder: row.get("is_folder"),
+        size: row.get("size"),
+        mtime: row.get("mtime"),
+        content_hash: row.get("content_hash"),
+        updated_at: row.get::<Option<i64>, _>("updated_at").unwrap_or(0),
+    }
+}
+
+/// Indexes a local path that lives inside `sync_root`. Returns the index path on success.
+pub async fn index_local_path(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    sync_root: &Path,
+    abs_path: &Path,
+) -> Result<Option<String>, String> {
+    let rel_path = match abs_path.strip_prefix(sync_root) {
+        Ok(rel) => rel,
+        Err(_) => return Ok(None),
+    };
+    let index_path = to_index_path(rel_path);
+    if index_path.is_empty() {
+        return Ok(None);
+    }
+    let meta = std::fs::metadata(abs_path)
+        .map_err(|e| format!("Failed to stat {}: {}", abs_path.display(), e))?;
+    let size = meta.len() as i64;
+    let mtime = modified_secs(&meta);
+
+    // Reuse the hash from the last reconcile when the file is unchanged
+    let content_hash = if meta.is_file() {
+        let existing = get_index_entry(pool, owner, scope, &index_path)
+            .await
+            .map_err(|e| format!("Failed to read index entry {}: {}", index_path, e))?;
+        match existing {
+            Some(e) if e.size == size && e.mtime == mtime && e.content_hash.is_some() => e.content_hash,
+            _ => hash_file_blocking(abs_path.to_path_buf()).await.ok(),
+        }
+    } else {
+        None
+    };
+
+    upsert_index_entry(
+        pool,
+        owner,
+        scope,
+        &index_path,
+        meta.is_dir(),
+        size,
+        mtime,
+        content_hash.as_deref(),
+    )
+    .await
+    .map_err(|e| format!("Failed to index {}: {}", index_path, e))?;
+    Ok(Some(index_path))
+}
+
+/// Brings the top-level `user_profiles` record for `name` in line with the index:
+/// removed when it no longer exists locally, otherwise inserted and its size refreshed.
+pub async fn refresh_top_level_entry(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    sync_root: &Path,
+    name: &str,
+) -> Result<(), sqlx::Error> {
+    let abs_path = sync_root.join(name);
+    if !abs_path.exists() {
+        delete_bucket_item_by_name(pool, owner, scope, name).await?;
+        return Ok(());
+    }
+
+    let is_folder = abs_path.is_dir();
+    let size = if is_folder {
+        folder_size_from_index(pool, owner, scope, name).await?
+    } else {
+        get_index_entry(pool, owner, scope, name)
+            .await?
+            .map(|e| e.size)
+            .unwrap_or_else(|| abs_path.metadata().map(|m| m.len() as i64).unwrap_or(0))
+    };
+
+    let bucket_item = BucketItem {
+        path: name.to_string(),
+        size: size as u64,
+        last_modified: String::new(),
+        is_folder,
+    };
+    if !insert_bucket_item_if_absent(pool, owner, scope, &bucket_item).await? {
+        update_bucket_item_size(pool, owner, scope, name, size).await?;
+    }
+    Ok(())
+}
+
+/// Applies a single parsed `aws s3 sync` action to the index and the top-level listing.
+pub async fn apply_sync_item_to_index(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    sync_root: &Path,
+    item: &RecentItem,
+) {
+    let log_tag = if scope == "public" { "[PublicFolderSync]" } else { "[PrivateFolderSync]" };
+
+    let index_path = match item.action.as_str() {
+        "uploaded" => {
+            let abs_path = PathBuf::from(&item.path);
+            match index_local_path(pool, owner, scope, sync_root, &abs_path).await {
+                Ok(Some(path)) => {
+                    if !path.contains('/') && abs_path.is_file() {
+                        if let Err(e) = sqlx::query(
+                            "INSERT OR REPLACE INTO file_paths (file_name, file_hash, timestamp, path) VALUES (?, ?, ?, ?)"
+                        )
+                        .bind(&path)
+                        .bind("")
+                        .bind(chrono::Utc::now().timestamp())
+                        .bind(abs_path.to_string_lossy().to_string())
+                        .execute(pool)
+                        .await
+                        {
+                            eprintln!("{} Failed to insert into file_paths '{}': {}", log_tag, path, e);
+                        }
+                    }
+                    path
+                }
+                Ok(None) => return,
+                Err(e) => {
+                    eprintln!("{} {}", log_tag, e);
+                    return;
+                }
+            }
+        }
+        "deleted" => {
+            let key = match s3_key_from_path(&item.path) {
+                Some(key) => key.trim_end_matches('/').to_string(),
+                None => return,
+            };
+            if let Err(e) = remove_index_entry(pool, owner, scope, &key).await {
+                eprintln!("{} Failed to remove '{}' from index: {}", log_tag, key, e);
+            }
+            key
+        }
+        _ => return,
+    };
+
+    let top_level = index_path.split('/').next().unwrap_or("").to_string();
+    if top_level.is_empty() {
+        return;
+    }
+    if let Err(e) = refresh_top_level_entry(pool, owner, scope, sync_root, &top_level).await {
+        eprintln!("{} Failed to refresh bucket item '{}': {}", log_tag, top_level, e);
+    }
+}
+
+// Walks the sync folder the same way `collect_files_recursively` does (hidden entries skipped).
+// Symlinks are skipped rather than followed, so a link can neither loop nor lead outside the root.
+fn walk_sync_folder(root: &Path) -> std::io::Result<HashMap<String, (bool, i64, i64)>> {
+    let mut out = HashMap::new();
+    let walker = walkdir::WalkDir::new(root)
+        .min_depth(1)
+        .follow_links(false)
+        .into_iter()
+        .filter_entry(|e| !e.file_name().to_string_lossy().starts_with('.'));
+    for entry in walker {
+        let entry = entry?;
+        if entry.file_type().is_symlink() {
+            continue;
+        }
+        let meta = match entry.metadata() {
+            Ok(meta) => meta,
+            Err(_) => continue,
+        };
+        let rel = match entry.path().strip_prefix(root) {
+            Ok(rel) => to_index_path(rel),
+            Err(_) => continue,
+        };
+        if meta.is_dir() {
+            out.insert(rel, (true, 0, modified_secs(&meta)));
+        } else if meta.is_file() {
+            out.insert(rel, (false, meta.len() as i64, modified_secs(&meta)));
+        }
+    }
+    Ok(out)
+}
+
+/// Reconciles the index with what is on disk. Catches changes the sync output never
+/// reports (excluded files, edits made while the app was closed). Changed files are
+/// re-hashed; unchanged ones keep their stored hash.
+pub async fn reconcile_index_with_disk(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    sync_root: &Path,
+) -> Result<IndexDiff, String> {
+    let on_disk = if sync_root.is_dir() {
+        let root = sync_root.to_path_buf();
+        tokio::task::spawn_blocking(move || walk_sync_folder(&root))
+            .await
+            .map_err(|e| format!("Failed to walk {}: {}", sync_root.display(), e))?
+            .map_err(|e| format!("Failed to walk {}: {}", sync_root.display(), e))?
+    } else {
+        HashMap::new()
+    };
+
+    let rows = sqlx::query(
+        "SELECT path, parent_path, name, is_folder, size, mtime, content_hash, updated_at FROM file_index
+         WHERE owner = ? AND scope = ?"
+    )
+    .bind(owner)
+    .bind(scope)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to load file index: {}", e))?;
+    let indexed: HashMap<String, IndexEntry> = rows
+        .iter()
+        .map(|r| {
+            let entry = row_to_entry(r);
+            (entry.path.clone(), entry)
+        })
+        .collect();
+
+    let mut diff = IndexDiff::default();
+    for (path, (is_folder, size, mtime)) in &on_disk {
+        let existing = indexed.get(path);
+        let unchanged = existing.is_some_and(|e| {
+            e.is_folder == *is_folder && e.size == *size && e.mtime == *mtime
+                && (*is_folder || e.content_hash.is_some())
+        });
+        if unchanged {
+            continue;
+        }
+
+        let content_hash = if *is_folder {
+            None
+        } else {
+            hash_file_blocking(sync_root.join(path)).await.ok()
+        };
+        upsert_index_entry(pool, owner, scope, path, *is_folder, *size, *mtime, content_hash.as_deref())
+            .await
+            .map_err(|e| format!("Failed to index {}: {}", path, e))?;
+        diff.changes += 1;
+        diff.paths.push(path.clone());
+
+        if !*is_folder && existing.is_none() {
+            let (parent_path, name) = split_index_path(path);
+            diff.added.push(IndexEntry {
+                path: path.clone(),
+                parent_path,
+                name,
+                is_folder: false,
+                size: *size,
+                mtime: *mtime,
+                content_hash,
+                updated_at: chrono::Utc::now().timestamp(),
+            });
+        }
+    }
+    for (path, entry) in &indexed {
+        if !on_disk.contains_key(path) {
+            // Descendants go with their parent, so this may already be gone
+            let removed = remove_index_entry(pool, owner, scope, path)
+                .await
+                .map_err(|e| format!("Failed to remove {} from index: {}", path, e))?;
+            diff.changes += removed as usize;
+            diff.paths.push(path.clone());
+            if entry.is_folder {
+                diff.removed_folders.push(entry.clone());
+            } else {
+                diff.removed.push(entry.clone());
+            }
+        }
+    }
+
+    if diff.changes > 0 {
+        println!("[SyncIndex] Reconciled {} index entries for {} {}", diff.changes, owner, scope);
+    }
+    Ok(diff)
+}
+
+#[tauri::command]
+pub async fn get_sync_folder_index(
+    account_id: String,
+    scope: String,
+    parent_path: Option<String>,
+) -> Result<Vec<IndexEntry>, String> {
+    if scope != "public" && scope != "private" {
+        return Err("Invalid scope provided. Must be 'public' or 'private'.".to_string());
+    }
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let parent = parent_path.unwrap_or_default();
+    list_index_children(pool, &account_id, &scope, parent.trim_matches('/'))
+        .await
+        .map_err(|e| format!("Failed to read file index: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_db::test_db;
+
+    fn backdate(path: &Path, secs: i64) {
+        let time = filetime::FileTime::from_unix_time(secs, 0);
+        filetime::set_file_mtime(path, time).unwrap();
+    }
+
+    #[tokio::test]
+    async fn reconcile_tracks_additions_edits_and_removals() {
+        let pool = test_db().await;
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("docs/empty")).unwrap();
+        std::fs::write(root.join("docs/report.txt"), b"first").unwrap();
+        std::fs::write(root.join("top.txt"), b"top").unwrap();
+        std::fs::write(root.join(".hidden"), b"skipped").unwrap();
+
+        let diff = reconcile_index_with_disk(pool, "reconcile-owner", "public", root).await.unwrap();
+        let mut added: Vec<&str> = diff.added.iter().map(|e| e.path.as_str()).collect();
+        added.sort();
+        assert_eq!(added, ["docs/report.txt", "top.txt"]);
+        assert_eq!(diff.changes, 4);
+        let report = get_index_entry(pool, "reconcile-owner", "public", "docs/report.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(report.content_hash, Some(hash_file(&root.join("docs/report.txt")).unwrap()));
+        assert!(get_index_entry(pool, "reconcile-owner", "public", ".hidden").await.unwrap().is_none());
+
+        // Nothing changed, nothing to do
+        let diff = reconcile_index_with_disk(pool, "reconcile-owner", "public", root).await.unwrap();
+        assert_eq!(diff.changes, 0);
+
+        std::fs::write(root.join("docs/report.txt"), b"second").unwrap();
+        backdate(&root.join("docs/report.txt"), 1_000);
+        std::fs::remove_file(root.join("top.txt")).unwrap();
+        let diff = reconcile_index_with_disk(pool, "reconcile-owner", "public", root).await.unwrap();
+        assert!(diff.added.is_empty());
+        assert_eq!(diff.removed.iter().map(|e| e.path.as_str()).collect::<Vec<_>>(), ["top.txt"]);
+        assert_eq!(diff.removed[0].content_hash, Some(hex::encode(Sha256::digest(b"top"))));
+        let report = get_index_entry(pool, "reconcile-owner", "public", "docs/report.txt")
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!((report.size, report.mtime), (6, 1_000));
+        assert_eq!(report.content_hash, Some(hex::encode(Sha256::digest(b"second"))));
+
+        std::fs::remove_dir_all(root.join("docs")).unwrap();
+        let diff = reconcile_index_with_disk(pool, "reconcile-owner", "public", root).await.unwrap();
+        let mut folders: Vec<&str> = diff.removed_folders.iter().map(|e| e.path.as_str()).collect();
+        folders.sort();
+        assert_eq!(folders, ["docs", "docs/empty"]);
+        assert!(list_index_children(pool, "reconcile-owner", "public", "").await.unwrap().is_empty());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn reconcile_does_not_follow_symlinks() {
+        let pool = test_db().await;
+        let outside = tempfile::tempdir().unwrap();
+        std::fs::write(outside.path().join("secret.txt"), b"outside the root").unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir(root.join("folder")).unwrap();
+        std::fs::write(root.join("folder/kept.txt"), b"kept").unwrap();
+        // One link loops back to the root, the other leads out of it
+        std::os::unix::fs::symlink(root, root.join("folder/loop")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), root.join("escape")).unwrap();
+        std::os::unix::fs::symlink(outside.path().join("secret.txt"), root.join("secret.txt")).unwrap();
+
+        let diff = reconcile_index_with_disk(pool, "symlink-owner", "public", root).await.unwrap();
+        let mut paths = diff.paths.clone();
+        paths.sort();
+        assert_eq!(paths, ["folder", "folder/kept.txt"]);
+    }
+}
@@ -0,0 +1,293 @@
+use std::path::Path;
+use std::process::Command;
+use sqlx::{Row, SqlitePool};
+use crate::sync_index::IndexEntry;
+
+/// Zero-byte objects whose key ends in '/' stand in for directories. `aws s3 sync` and
+/// `aws s3 cp --recursive` skip them, so they are created, removed and restored here.
+pub fn is_folder_marker(key: &str, size: u64) -> bool {
+    size == 0 && key.ends_with('/')
+}
+
+pub fn folder_marker_key(index_path: &str) -> String {
+    format!("{}/", index_path.trim_end_matches('/'))
+}
+
+/// Folders whose marker has to be created or deleted. Only empty folders need one:
+/// a folder with anything in it already shows up through the keys below it.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MarkerChanges {
+    pub create: Vec<String>,
+    pub remove: Vec<String>,
+}
+
+pub async fn pending_marker_changes(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+) -> Result<MarkerChanges, sqlx::Error> {
+    let rows = sqlx::query(
+        "SELECT f.path, f.has_marker, EXISTS (
+            SELECT 1 FROM file_index c WHERE c.owner = f.owner AND c.scope = f.scope AND c.parent_path = f.path
+         ) AS has_children
+         FROM file_index f
+         WHERE f.owner = ? AND f.scope = ? AND f.is_folder = 1
+         ORDER BY f.path"
+    )
+    .bind(owner)
+    .bind(scope)
+    .fetch_all(pool)
+    .await?;
+
+    let mut changes = MarkerChanges::default();
+    for row in rows {
+        let path: String = row.get("path");
+        match (row.get::<bool, _>("has_marker"), row.get::<bool, _>("has_children")) {
+            (false, false) => changes.create.push(path),
+            (true, true) => changes.remove.push(path),
+            _ => {}
+        }
+    }
+    Ok(changes)
+}
+
+async fn set_has_marker(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    path: &str,
+    has_marker: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE file_index SET has_marker = ? WHERE owner = ? AND scope = ? AND path = ?")
+        .bind(has_marker)
+        .bind(owner)
+        .bind(scope)
+        .bind(path)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+fn delete_marker(
+    aws_binary_path: &Path,
+    dynamic_path: &str,
+    endpoint_url: &str,
+    bucket_name: &str,
+    key: &str,
+    log_tag: &str,
+) -> bool {
+    let output = Command::new(aws_binary_path)
+        .env("AWS_PAGER", "")
+        .env("PATH", dynamic_path)
+        .arg("s3api")
+        .arg("delete-object")
+        .arg("--bucket")
+        .arg(bucket_name)
+        .arg("--key")
+        .arg(key)
+        .arg("--endpoint-url")
+        .arg(endpoint_url)
+        .output();
+    match output {
+        Ok(o) if o.status.success() => {
+            println!("{} Removed folder marker {}", log_tag, key);
+            true
+        }
+        Ok(o) => {
+            eprintln!(
+                "{} Failed to remove folder marker {}: {}",
+                log_tag,
+                key,
+                String::from_utf8_lossy(&o.stderr)
+            );
+            false
+        }
+        Err(e) => {
+            eprintln!("{} Failed to execute aws s3api delete-object: {}", log_tag, e);
+            false
+        }
+    }
+}
+
+/// Creates markers for empty indexed folders that do not have one yet, and deletes the
+/// markers of folders that disappeared locally or are no longer empty.
+#[allow(clippy::too_many_arguments)]
+pub async fn sync_folder_markers(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    aws_binary_path: &Path,
+    dynamic_path: &str,
+    endpoint_url: &str,
+    bucket_name: &str,
+    removed_folders: &[IndexEntry],
+) {
+    let log_tag = if scope == "public" { "[PublicFolderSync]" } else { "[PrivateFolderSync]" };
+
+    for folder in removed_folders {
+        let key = folder_marker_key(&folder.path);
+        delete_marker(aws_binary_path, dynamic_path, endpoint_url, bucket_name, &key, log_tag);
+    }
+
+    let changes = match pending_marker_changes(pool, owner, scope).await {
+        Ok(changes) => changes,
+        Err(e) => {
+            eprintln!("{} Failed to load folders needing markers: {}", log_tag, e);
+            return;
+        }
+    };
+
+    for path in changes.remove {
+        let key = folder_marker_key(&path);
+        if delete_marker(aws_binary_path, dynamic_path, endpoint_url, bucket_name, &key, log_tag) {
+            if let Err(e) = set_has_marker(pool, owner, scope, &path, false).await {
+                eprintln!("{} Failed to record folder marker removal for {}: {}", log_tag, path, e);
+            }
+        }
+    }
+
+    for path in changes.create {
+        let key = folder_marker_key(&path);
+        let mut cmd = Command::new(aws_binary_path);
+        cmd.env("AWS_PAGER", "")
+            .env("PATH", dynamic_path)
+            .arg("s3api")
+            .arg("put-object")
+            .arg("--bucket")
+            .arg(bucket_name)
+            .arg("--key")
+            .arg(&key)
+            .arg("--content-length")
+            .arg("0")
+            .arg("--endpoint-url")
+            .arg(endpoint_url);
+        if scope == "public" {
+            cmd.arg("--acl").arg("public-read");
+        }
+
+        match cmd.output() {
+            Ok(o) if o.status.success() => {
+                if let Err(e) = set_has_marker(pool, owner, scope, &path, true).await {
+                    eprintln!("{} Failed to record folder marker for {}: {}", log_tag, path, e);
+                }
+            }
+            Ok(o) => eprintln!(
+                "{} Failed to create folder marker {}: {}",
+                log_tag,
+                key,
+                String::from_utf8_lossy(&o.stderr)
+            ),
+            Err(e) => eprintln!("{} Failed to execute aws s3api put-object: {}", log_tag, e),
+        }
+    }
+}
+
+/// Recreates the directories represented by folder markers below `source`
+/// (`s3://bucket/prefix`) inside `destination`. Used after `aws s3 cp --recursive`,
+/// which does not download markers and so drops empty directories.
+pub async fn restore_folder_markers(
+    aws_binary_path: &Path,
+    dynamic_path: &str,
+    endpoint_url: &str,
+    source: &str,
+    destination: &Path,
+) -> Result<usize, String> {
+    let without_scheme = source
+        .strip_prefix("s3://")
+        .ok_or_else(|| format!("Not an S3 path: {}", source))?;
+    let prefix = match without_scheme.split_once('/') {
+        Some((_, key)) => folder_marker_key(key).trim_start_matches('/').to_string(),
+        None => String::new(),
+    };
+    let list_uri = format!("s3://{}", folder_marker_key(without_scheme));
+
+    let output = tokio::process::Command::new(aws_binary_path)
+        .env("AWS_PAGER", "")
+        .env("PATH", dynamic_path)
+        .arg("s3")
+        .arg("ls")
+        .arg(&list_uri)
+        .arg("--recursive")
+        .arg("--endpoint-url")
+        .arg(endpoint_url)
+        .output()
+        .await
+        .map_err(|e| format!("Failed to execute aws command: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "Failed to list folder markers: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let mut created = 0usize;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() < 4 {
+            continue;
+        }
+        let size: u64 = parts[2].parse().unwrap_or(1);
+        let key = parts[3..].join(" ");
+        if !is_folder_marker(&key, size) {
+            continue;
+        }
+        let relative = key.strip_prefix(&prefix).unwrap_or(&key).trim_end_matches('/');
+        if relative.is_empty() {
+            continue;
+        }
+        let dir = relative
+            .split('/')
+            .filter(|part| !part.is_empty() && *part != "." && *part != "..")
+            .fold(destination.to_path_buf(), |acc, part| acc.join(part));
+        if !dir.exists() {
+            tokio::fs::create_dir_all(&dir)
+                .await
+                .map_err(|e| format!("Failed to create directory {}: {}", dir.display(), e))?;
+            created += 1;
+        }
+    }
+    Ok(created)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync_index::upsert_index_entry;
+    use crate::utils::test_db::test_db;
+
+    #[tokio::test]
+    async fn markers_are_kept_for_empty_folders_only() {
+        let pool = test_db().await;
+        let (owner, scope) = ("markers-owner", "public");
+        upsert_index_entry(pool, owner, scope, "docs/report.txt", false, 5, 1, Some("h")).await.unwrap();
+        upsert_index_entry(pool, owner, scope, "docs/empty", true, 0, 1, None).await.unwrap();
+        upsert_index_entry(pool, owner, scope, "photos", true, 0, 1, None).await.unwrap();
+        upsert_index_entry(pool, owner, scope, "top.txt", false, 3, 1, Some("h")).await.unwrap();
+
+        let changes = pending_marker_changes(pool, owner, scope).await.unwrap();
+        assert_eq!(
+            changes,
+            MarkerChanges { create: vec!["docs/empty".to_string(), "photos".to_string()], remove: vec![] }
+        );
+        for path in &changes.create {
+            set_has_marker(pool, owner, scope, path, true).await.unwrap();
+        }
+        assert_eq!(pending_marker_changes(pool, owner, scope).await.unwrap(), MarkerChanges::default());
+
+        // A file lands in a folder that had a marker, so the marker can go
+        upsert_index_entry(pool, owner, scope, "photos/cat.jpg", false, 9, 1, Some("h")).await.unwrap();
+        assert_eq!(
+            pending_marker_changes(pool, owner, scope).await.unwrap(),
+            MarkerChanges { create: vec![], remove: vec!["photos".to_string()] }
+        );
+    }
+
+    #[test]
+    fn marker_keys() {
+        assert_eq!(folder_marker_key("a/b"), "a/b/");
+        assert_eq!(folder_marker_key("a/b/"), "a/b/");
+        assert!(is_folder_marker("a/b/", 0));
+        assert!(!is_folder_marker("a/b/", 3));
+        assert!(!is_folder_marker("a/b", 0));
+    }
+}
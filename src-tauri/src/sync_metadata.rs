@@ -0,0 +1,534 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use base64::{Engine as _, engine::general_purpose};
+use futures::stream::{self, StreamExt};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+use crate::commands::types::Compression;
+use crate::sync_index::to_index_path;
+use crate::sync_shared::collect_files_recursively;
+use crate::utils::compression::expand_file;
+use crate::utils::settings::{get_bool_setting, PRESERVE_XATTRS};
+
+// Object metadata keys (sent as x-amz-meta-*)
+pub const META_MTIME: &str = "mtime";
+pub const META_MODE: &str = "mode";
+pub const META_XATTRS: &str = "xattrs";
+// Set on objects private sync uploaded compressed; downloads expand them
+pub const META_COMPRESSION: &str = "compression";
+
+// S3 caps user metadata at 2 KB per object; larger xattr sets are not stored.
+const MAX_XATTR_METADATA_LEN: usize = 1536;
+
+// Each stamp spawns an aws process; keep large syncs from forking hundreds at once.
+static METADATA_STAMP_LIMIT: Lazy<Semaphore> = Lazy::new(|| Semaphore::new(4));
+
+/// File attributes that survive a round trip through S3 object metadata or an IPFS
+/// folder manifest.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FileMetadata {
+    pub mtime: Option<i64>,
+    pub mode: Option<u32>,
+    // xattr name -> base64 value
+    pub xattrs: Option<HashMap<String, String>>,
+}
+
+impl FileMetadata {
+    pub fn read_from(path: &Path, include_xattrs: bool) -> std::io::Result<Self> {
+        let meta = std::fs::metadata(path)?;
+        let mtime = filetime::FileTime::from_last_modification_time(&meta).unix_seconds();
+
+        #[cfg(unix)]
+        let mode = {
+            use std::os::unix::fs::PermissionsExt;
+            Some(meta.permissions().mode() & 0o7777)
+        };
+        #[cfg(not(unix))]
+        let mode: Option<u32> = None;
+
+        let xattrs = if include_xattrs { read_xattrs(path) } else { None };
+
+        Ok(FileMetadata {
+            mtime: Some(mtime),
+            mode,
+            xattrs,
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.mtime.is_none() && self.mode.is_none() && self.xattrs.is_none()
+    }
+
+    pub fn to_s3_metadata(&self) -> HashMap<String, String> {
+        let mut map = HashMap::new();
+        if let Some(mtime) = self.mtime {
+            map.insert(META_MTIME.to_string(), mtime.to_string());
+        }
+        if let Some(mode) = self.mode {
+            map.insert(META_MODE.to_string(), format!("{:o}", mode));
+        }
+        if let Some(xattrs) = &self.xattrs {
+            if let Ok(json) = serde_json::to_vec(xattrs) {
+                let encoded = general_purpose::STANDARD.encode(json);
+                if encoded.len() <= MAX_XATTR_METADATA_LEN {
+                    map.insert(META_XATTRS.to_string(), encoded);
+                }
+            }
+        }
+        map
+    }
+
+    pub fn from_s3_metadata(map: &HashMap<String, String>) -> Self {
+        FileMetadata {
+            mtime: map.get(META_MTIME).and_then(|v| v.parse().ok()),
+            mode: map.get(META_MODE).and_then(|v| u32::from_str_radix(v, 8).ok()),
+            xattrs: map
+                .get(META_XATTRS)
+                .and_then(|v| general_purpose::STANDARD.decode(v).ok())
+                .and_then(|json| serde_json::from_slice(&json).ok()),
+        }
+    }
+
+    /// Restores whatever is present. Permissions are applied before the mtime, since
+    /// changing them does not touch it.
+    pub fn apply_to(&self, path: &Path) -> Result<(), String> {
+        if let Some(mode) = self.mode {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::PermissionsExt;
+                std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+                    .map_err(|e| format!("Failed to set permissions on {}: {}", path.display(), e))?;
+            }
+            #[cfg(not(unix))]
+            {
+                // Only the read-only bit maps to Windows
+                if mode & 0o222 == 0 {
+                    let mut perms = std::fs::metadata(path)
+                        .map_err(|e| format!("Failed to stat {}: {}", path.display(), e))?
+                        .permissions();
+                    perms.set_readonly(true);
+                    std::fs::set_permissions(path, perms)
+                        .map_err(|e| format!("Failed to set permissions on {}: {}", path.display(), e))?;
+                }
+            }
+        }
+
+        if let Some(xattrs) = &self.xattrs {
+            write_xattrs(path, xattrs);
+        }
+
+        if let Some(mtime) = self.mtime {
+            filetime::set_file_mtime(path, filetime::FileTime::from_unix_time(mtime, 0))
+                .map_err(|e| format!("Failed to set mtime on {}: {}", path.display(), e))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn read_xattrs(path: &Path) -> Option<HashMap<String, String>> {
+    let names = xattr::list(path).ok()?;
+    let mut attrs = HashMap::new();
+    for name in names {
+        let name = name.to_string_lossy().to_string();
+        // Namespaces that need privileges to write back are skipped
+        if name.starts_with("security.") || name.starts_with("system.") || name.starts_with("trusted.") {
+            continue;
+        }
+        if let Ok(Some(value)) = xattr::get(path, &name) {
+            attrs.insert(name, general_purpose::STANDARD.encode(value));
+        }
+    }
+    if attrs.is_empty() { None } else { Some(attrs) }
+}
+
+#[cfg(not(unix))]
+fn read_xattrs(_path: &Path) -> Option<HashMap<String, String>> {
+    None
+}
+
+#[cfg(unix)]
+fn write_xattrs(path: &Path, xattrs: &HashMap<String, String>) {
+    for (name, value) in xattrs {
+        let value = match general_purpose::STANDARD.decode(value) {
+            Ok(v) => v,
+            Err(_) => continue,
+        };
+        if let Err(e) = xattr::set(path, name, &value) {
+            eprintln!("[SyncMetadata] Failed to set xattr {} on {}: {}", name, path.display(), e);
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn write_xattrs(_path: &Path, _xattrs: &HashMap<String, String>) {}
+
+fn split_s3_uri(uri: &str) -> Option<(String, String)> {
+    let rest = uri.strip_prefix("s3://")?;
+    let (bucket, key) = rest.split_once('/')?;
+    Some((bucket.to_string(), key.to_string()))
+}
+
+/// Returns (content type, user metadata) of an object via `aws s3api head-object`.
+pub async fn head_object_metadata(
+    aws_binary_path: &Path,
+    dynamic_path: &str,
+    endpoint_url: &str,
+    bucket_name: &str,
+    key: &str,
+) -> Result<(Option<String>, HashMap<String, String>), String> {
+    let output = Command::new(aws_binary_path)
+        .env("AWS_PAGER", "")
+        .env("PATH", dynamic_path)
+        .arg("s3api")
+        .arg("head-object")
+        .arg("--bucket")
+        .arg(bucket_name)
+        .arg("--key")
+        .arg(key)
+        .arg("--endpoint-url")
+        .arg(endpoint_url)
+        .arg("--output")
+        .arg("json")
+        .output()
+        .await
+        .map_err(|e| format!("Failed to execute aws s3api head-object: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "head-object failed for {}: {}",
+            key,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    let json: serde_json::Value = serde_json::from_slice(&output.stdout)
+        .map_err(|e| format!("Failed to parse head-object output: {}", e))?;
+    let content_type = json.get("ContentType").and_then(|v| v.as_str()).map(|s| s.to_string());
+    let metadata = json
+        .get("Metadata")
+        .and_then(|v| v.as_object())
+        .map(|m| {
+            m.iter()
+                .filter_map(|(k, v)| v.as_str().map(|s| (k.to_lowercase(), s.to_string())))
+                .collect()
+        })
+        .unwrap_or_default();
+    Ok((content_type, metadata))
+}
+
+/// Stores the local file's attributes on an already uploaded object by copying it onto
+/// itself with replaced metadata. `aws s3 cp` guesses the content type from the key
+/// just as the upload did, so a single process is enough and no head-object is needed;
+/// a freshly uploaded object has no metadata of its own to compare against.
+/// `compression` records how the uploaded bytes were compressed, if they were.
+#[allow(clippy::too_many_arguments)]
+pub async fn stamp_uploaded_file(
+    aws_binary_path: PathBuf,
+    dynamic_path: String,
+    endpoint_url: String,
+    bucket_name: String,
+    sync_root: PathBuf,
+    local_path: PathBuf,
+    public: bool,
+    compression: Option<Compression>,
+) {
+    if !local_path.is_file() {
+        return;
+    }
+    let key = match local_path.strip_prefix(&sync_root) {
+        Ok(rel) => to_index_path(rel),
+        Err(_) => return,
+    };
+    let include_xattrs = get_bool_setting(PRESERVE_XATTRS, false).await;
+    let mut metadata = match FileMetadata::read_from(&local_path, include_xattrs) {
+        Ok(m) => m.to_s3_metadata(),
+        Err(e) => {
+            eprintln!("[SyncMetadata] Failed to read metadata of {}: {}", local_path.display(), e);
+            return;
+        }
+    };
+    if let Some(name) = compression.and_then(|c| serde_json::to_value(c).ok()).and_then(|v| v.as_str().map(str::to_string)) {
+        metadata.insert(META_COMPRESSION.to_string(), name);
+    }
+    if metadata.is_empty() {
+        return;
+    }
+    let metadata_json = match serde_json::to_string(&metadata) {
+        Ok(json) => json,
+        Err(_) => return,
+    };
+
+    let _permit = match METADATA_STAMP_LIMIT.acquire().await {
+        Ok(p) => p,
+        Err(_) => return,
+    };
+
+    let object = format!("s3://{}/{}", bucket_name, key);
+    let mut cmd = Command::new(&aws_binary_path);
+    cmd.env("AWS_PAGER", "")
+        .env("PATH", &dynamic_path)
+        .arg("s3")
+        .arg("cp")
+        .arg(&object)
+        .arg(&object)
+        .arg("--metadata")
+        .arg(&metadata_json)
+        .arg("--metadata-directive")
+        .arg("REPLACE")
+        .arg("--endpoint-url")
+        .arg(&endpoint_url)
+        .arg("--no-progress");
+    if public {
+        cmd.arg("--acl").arg("public-read");
+    }
+
+    match cmd.output().await {
+        Ok(o) if o.status.success() => {}
+        Ok(o) => eprintln!(
+            "[SyncMetadata] Failed to store metadata for {}: {}",
+            key,
+            String::from_utf8_lossy(&o.stderr)
+        ),
+        Err(e) => eprintln!("[SyncMetadata] Failed to execute aws s3 cp: {}", e),
+    }
+}
+
+/// Finishes a file downloaded from `s3_uri`: expands it if private sync stored it
+/// compressed, then restores its stored attributes. Failing to look the object up or
+/// to expand it is an error, since the file may still hold compressed bytes; attributes
+/// that cannot be set are only logged.
+pub async fn finish_s3_download(
+    aws_binary_path: &Path,
+    dynamic_path: &str,
+    endpoint_url: &str,
+    s3_uri: &str,
+    local_path: &Path,
+) -> Result<(), String> {
+    let (bucket, key) = split_s3_uri(s3_uri).ok_or_else(|| format!("Not an S3 object path: {}", s3_uri))?;
+    let (_, metadata) = head_object_metadata(aws_binary_path, dynamic_path, endpoint_url, &bucket, &key).await?;
+    if let Some(name) = metadata.get(META_COMPRESSION) {
+        let compression: Compression = serde_json::from_value(serde_json::Value::String(name.clone()))
+            .map_err(|_| format!("{} is stored with unknown compression '{}'", s3_uri, name))?;
+        let path = local_path.to_path_buf();
+        tokio::task::spawn_blocking(move || expand_in_place(&path, compression))
+            .await
+            .map_err(|e| format!("Expansion task failed: {}", e))??;
+    }
+    let file_metadata = FileMetadata::from_s3_metadata(&metadata);
+    if !file_metadata.is_empty() {
+        if let Err(e) = file_metadata.apply_to(local_path) {
+            eprintln!("[SyncMetadata] {}", e);
+        }
+    }
+    Ok(())
+}
+
+// Replaces a compressed download with its expanded contents
+fn expand_in_place(path: &Path, compression: Compression) -> Result<(), String> {
+    let parent = path.parent().ok_or_else(|| format!("{} has no parent folder", path.display()))?;
+    let expanded = tempfile::NamedTempFile::new_in(parent)
+        .map_err(|e| format!("Failed to create temp file in {}: {}", parent.display(), e))?
+        .into_temp_path();
+    expand_file(path, &expanded, compression)?;
+    expanded
+        .persist(path)
+        .map_err(|e| format!("Failed to replace {}: {}", path.display(), e))
+}
+
+/// Runs `finish_s3_download` for every file downloaded from `s3_prefix` into
+/// `destination`. Returns how many files could not be finished.
+pub async fn finish_s3_folder_download(
+    aws_binary_path: &Path,
+    dynamic_path: &str,
+    endpoint_url: &str,
+    s3_prefix: &str,
+    destination: &Path,
+) -> usize {
+    let mut files = Vec::new();
+    if let Err(e) = collect_files_recursively(destination, &mut files) {
+        eprintln!("[SyncMetadata] Failed to list {}: {}", destination.display(), e);
+        return 0;
+    }
+    let prefix = s3_prefix.trim_end_matches('/').to_string();
+
+    stream::iter(files)
+        .map(|file| {
+            let prefix = prefix.clone();
+            async move {
+                let rel = match file.strip_prefix(destination) {
+                    Ok(rel) => to_index_path(rel),
+                    Err(_) => return false,
+                };
+                let uri = format!("{}/{}", prefix, rel);
+                match finish_s3_download(aws_binary_path, dynamic_path, endpoint_url, &uri, &file).await {
+                    Ok(()) => true,
+                    Err(e) => {
+                        eprintln!("[SyncMetadata] {}", e);
+                        false
+                    }
+                }
+            }
+        })
+        .buffer_unordered(8)
+        .filter(|ok| futures::future::ready(!*ok))
+        .count()
+        .await
+}
+
+/// Carries attributes over from a local source when a download is served by copying.
+pub async fn copy_local_metadata(source: &Path, destination: &Path) {
+    let include_xattrs = get_bool_setting(PRESERVE_XATTRS, false).await;
+    let pairs: Vec<(PathBuf, PathBuf)> = if source.is_dir() {
+        let mut files = Vec::new();
+        let _ = collect_files_recursively(source, &mut files);
+        files
+            .into_iter()
+            .filter_map(|f| {
+                let rel = f.strip_prefix(source).ok()?.to_path_buf();
+                Some((f, destination.join(rel)))
+            })
+            .collect()
+    } else {
+        vec![(source.to_path_buf(), destination.to_path_buf())]
+    };
+
+    for (src, dst) in pairs {
+        if !dst.exists() {
+            continue;
+        }
+        match FileMetadata::read_from(&src, include_xattrs) {
+            Ok(meta) => {
+                if let Err(e) = meta.apply_to(&dst) {
+                    eprintln!("[SyncMetadata] {}", e);
+                }
+            }
+            Err(e) => eprintln!("[SyncMetadata] Failed to read metadata of {}: {}", src.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_db::test_db;
+
+    #[test]
+    fn s3_metadata_round_trips() {
+        let mut xattrs = HashMap::new();
+        xattrs.insert("user.origin".to_string(), general_purpose::STANDARD.encode(b"camera"));
+        let meta = FileMetadata { mtime: Some(1_700_000_000), mode: Some(0o755), xattrs: Some(xattrs) };
+        let s3 = meta.to_s3_metadata();
+        assert_eq!(s3[META_MTIME], "1700000000");
+        assert_eq!(s3[META_MODE], "755");
+        assert_eq!(FileMetadata::from_s3_metadata(&s3), meta);
+
+        // Too large for S3 user metadata, so left out rather than failing the stamp
+        let mut big = HashMap::new();
+        big.insert("user.blob".to_string(), general_purpose::STANDARD.encode(vec![7u8; 2048]));
+        let meta = FileMetadata { mtime: Some(1), mode: None, xattrs: Some(big) };
+        assert!(!meta.to_s3_metadata().contains_key(META_XATTRS));
+        assert!(FileMetadata::from_s3_metadata(&HashMap::new()).is_empty());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn applied_metadata_reads_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("build.sh");
+        std::fs::write(&path, b"#!/bin/sh\n").unwrap();
+        let meta = FileMetadata { mtime: Some(1_600_000_000), mode: Some(0o750), xattrs: None };
+        meta.apply_to(&path).unwrap();
+        assert_eq!(FileMetadata::read_from(&path, false).unwrap(), meta);
+    }
+
+    // An `aws` stand-in that logs each invocation's arguments and answers head-object
+    #[cfg(unix)]
+    fn fake_aws(dir: &Path, metadata: &str) -> (PathBuf, PathBuf) {
+        use std::os::unix::fs::PermissionsExt;
+        let log = dir.join("aws.log");
+        let script = dir.join("aws");
+        std::fs::write(
+            &script,
+            format!(
+                "#!/bin/sh\necho \"$*\" >> '{}'\nif [ \"$2\" = head-object ]; then echo '{{\"ContentType\":\"text/plain\",\"Metadata\":{}}}'; fi\n",
+                log.display(),
+                metadata
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        (script, log)
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn stamping_an_upload_runs_one_aws_process() {
+        test_db().await;
+        let dir = tempfile::tempdir().unwrap();
+        let (aws, log) = fake_aws(dir.path(), "{}");
+        let root = dir.path().join("sync");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        let file = root.join("docs/notes.txt");
+        std::fs::write(&file, b"notes").unwrap();
+        FileMetadata { mtime: Some(1_650_000_000), mode: Some(0o644), xattrs: None }.apply_to(&file).unwrap();
+
+        stamp_uploaded_file(
+            aws,
+            String::new(),
+            "https://s3.example".to_string(),
+            "bucket".to_string(),
+            root,
+            file,
+            true,
+            Some(Compression::Zstd),
+        )
+        .await;
+
+        let calls = std::fs::read_to_string(&log).unwrap();
+        let calls: Vec<&str> = calls.lines().collect();
+        assert_eq!(calls.len(), 1, "{:?}", calls);
+        assert!(calls[0].starts_with("s3 cp s3://bucket/docs/notes.txt s3://bucket/docs/notes.txt --metadata "));
+        assert!(calls[0].contains("\"mtime\":\"1650000000\"") && calls[0].contains("\"mode\":\"644\"")
+            && calls[0].contains("\"compression\":\"zstd\""));
+        assert!(calls[0].contains("--metadata-directive REPLACE") && calls[0].ends_with("--acl public-read"));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn restores_metadata_from_the_object() {
+        let dir = tempfile::tempdir().unwrap();
+        let (aws, log) = fake_aws(dir.path(), r#"{"mtime":"1500000000","mode":"640"}"#);
+        let file = dir.path().join("downloaded.txt");
+        std::fs::write(&file, b"data").unwrap();
+
+        finish_s3_download(&aws, "", "https://s3.example", "s3://bucket/docs/downloaded.txt", &file)
+            .await
+            .unwrap();
+        assert_eq!(
+            FileMetadata::read_from(&file, false).unwrap(),
+            FileMetadata { mtime: Some(1_500_000_000), mode: Some(0o640), xattrs: None }
+        );
+        let calls = std::fs::read_to_string(&log).unwrap();
+        assert!(calls.starts_with("s3api head-object --bucket bucket --key docs/downloaded.txt "));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn compressed_downloads_are_expanded() {
+        let dir = tempfile::tempdir().unwrap();
+        let (aws, _) = fake_aws(dir.path(), r#"{"mtime":"1500000000","compression":"zstd"}"#);
+        let plain = b"line of a private log file\n".repeat(500);
+        let file = dir.path().join("app.log");
+        std::fs::write(&file, zstd::stream::encode_all(&plain[..], 3).unwrap()).unwrap();
+
+        finish_s3_download(&aws, "", "https://s3.example", "s3://bucket/app.log", &file).await.unwrap();
+        assert_eq!(std::fs::read(&file).unwrap(), plain);
+        assert_eq!(FileMetadata::read_from(&file, false).unwrap().mtime, Some(1_500_000_000));
+
+        // Bytes that are not what the object says they are must not pass as finished
+        let error = finish_s3_download(&aws, "", "https://s3.example", "s3://bucket/app.log", &file).await.unwrap_err();
+        assert!(error.contains("decompress"), "{}", error);
+    }
+}
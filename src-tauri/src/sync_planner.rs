@@ -0,0 +1,328 @@
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+use sqlx::SqlitePool;
+use crate::sync_index::{refresh_top_level_entry, s3_key_from_path, IndexDiff, IndexEntry};
+use crate::sync_shared::{RecentItem, S3SyncState, MAX_RECENT_ITEMS};
+
+/// A single operation `aws s3 sync --dryrun` intends to perform.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlannedAction {
+    Upload { local_path: String, key: String },
+    Delete { key: String },
+}
+
+/// A delete/upload pair that refers to the same content and can be replaced by a
+/// server-side copy followed by a delete.
+#[derive(Debug, Clone)]
+pub struct DetectedMove {
+    pub from_key: String,
+    pub to_key: String,
+    pub size: i64,
+}
+
+/// Parses the dry-run output into planned actions. Lines that are not uploads or
+/// deletes are ignored.
+pub fn parse_sync_plan<S: AsRef<str>>(lines: &[S]) -> Vec<PlannedAction> {
+    let mut plan = Vec::new();
+    for line in lines {
+        let s = line.as_ref().trim().trim_start_matches("(dryrun)").trim();
+        if let Some(rest) = s.strip_prefix("upload:") {
+            // Split at the destination URL, since names may contain " to " themselves
+            if let Some((src, dest)) = rest.trim_start().split_once(" to s3://") {
+                if let Some(key) = s3_key_from_path(&format!("s3://{}", dest.trim())) {
+                    plan.push(PlannedAction::Upload {
+                        local_path: src.to_string(),
+                        key: key.to_string(),
+                    });
+                }
+            }
+        } else if let Some(rest) = s.strip_prefix("delete:") {
+            if let Some(key) = s3_key_from_path(rest.trim()) {
+                plan.push(PlannedAction::Delete { key: key.to_string() });
+            }
+        }
+    }
+    plan
+}
+
+/// Matches planned deletes against planned uploads using the index journal: the removed
+/// entry and the new one must share content hash and size. Candidates with the same
+/// mtime are preferred, since a rename keeps it.
+pub fn detect_moves(plan: &[PlannedAction], diff: &IndexDiff) -> Vec<DetectedMove> {
+    let uploads: HashSet<&str> = plan
+        .iter()
+        .filter_map(|a| match a {
+            PlannedAction::Upload { key, .. } => Some(key.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    let mut claimed: HashSet<&str> = HashSet::new();
+    let mut moves = Vec::new();
+
+    for action in plan {
+        let key = match action {
+            PlannedAction::Delete { key } => key,
+            _ => continue,
+        };
+        let removed = match diff.removed.iter().find(|e| &e.path == key) {
+            Some(e) => e,
+            None => continue,
+        };
+        let hash = match removed.content_hash.as_deref() {
+            Some(h) => h,
+            None => continue,
+        };
+
+        let candidates: Vec<&IndexEntry> = diff
+            .added
+            .iter()
+            .filter(|e| {
+                e.size == removed.size
+                    && e.content_hash.as_deref() == Some(hash)
+                    && uploads.contains(e.path.as_str())
+                    && !claimed.contains(e.path.as_str())
+            })
+            .collect();
+        let target = candidates
+            .iter()
+            .find(|e| e.mtime == removed.mtime)
+            .or_else(|| candidates.first());
+
+        if let Some(target) = target {
+            claimed.insert(target.path.as_str());
+            moves.push(DetectedMove {
+                from_key: removed.path.clone(),
+                to_key: target.path.clone(),
+                size: target.size,
+            });
+        }
+    }
+    moves
+}
+
+/// Performs the moves with `aws s3 mv` between two keys of the same bucket, which the
+/// CLI executes as a server-side copy and delete. Returns the moves that succeeded.
+pub fn apply_moves(
+    aws_binary_path: &Path,
+    dynamic_path: &str,
+    endpoint_url: &str,
+    bucket_name: &str,
+    moves: &[DetectedMove],
+    public: bool,
+) -> Vec<DetectedMove> {
+    let log_tag = if public { "[PublicFolderSync]" } else { "[PrivateFolderSync]" };
+    let mut applied = Vec::new();
+
+    for mv in moves {
+        let mut cmd = Command::new(aws_binary_path);
+        cmd.env("AWS_PAGER", "")
+            .env("PATH", dynamic_path)
+            .arg("s3")
+            .arg("mv")
+            .arg(format!("s3://{}/{}", bucket_name, mv.from_key))
+            .arg(format!("s3://{}/{}", bucket_name, mv.to_key))
+            .arg("--endpoint-url")
+            .arg(endpoint_url)
+            .arg("--no-progress");
+        if public {
+            cmd.arg("--acl").arg("public-read");
+        }
+
+        match cmd.output() {
+            Ok(output) if output.status.success() => {
+                println!("{} Moved {} -> {} on the server", log_tag, mv.from_key, mv.to_key);
+                applied.push(mv.clone());
+            }
+            Ok(output) => {
+                eprintln!(
+                    "{} Server-side move {} -> {} failed, falling back to upload: {}",
+                    log_tag,
+                    mv.from_key,
+                    mv.to_key,
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            Err(e) => {
+                eprintln!("{} Failed to execute aws s3 mv: {}", log_tag, e);
+            }
+        }
+    }
+    applied
+}
+
+/// Records applied moves in the activity log and refreshes the affected top-level
+/// records. The index itself was already updated by the reconcile pass.
+pub async fn record_moves(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    sync_root: &Path,
+    moves: &[DetectedMove],
+    state: &Arc<Mutex<S3SyncState>>,
+) {
+    let now_ms: i64 = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0);
+
+    for mv in moves {
+        let local_path: PathBuf = sync_root.join(&mv.to_key);
+        let item = RecentItem {
+            name: mv.to_key.rsplit('/').next().unwrap_or(&mv.to_key).to_string(),
+            scope: scope.to_string(),
+            action: "moved".to_string(),
+            kind: "file".to_string(),
+            path: local_path.to_string_lossy().to_string(),
+            timestamp: now_ms,
+        };
+        {
+            let mut state = state.lock().unwrap();
+            state.recent_items.push_front(item);
+            if state.recent_items.len() > MAX_RECENT_ITEMS {
+                state.recent_items.pop_back();
+            }
+        }
+
+        let mut tops: Vec<&str> = vec![
+            mv.from_key.split('/').next().unwrap_or(""),
+            mv.to_key.split('/').next().unwrap_or(""),
+        ];
+        tops.dedup();
+        for top in tops.into_iter().filter(|t| !t.is_empty()) {
+            if let Err(e) = refresh_top_level_entry(pool, owner, scope, sync_root, top).await {
+                eprintln!("[SyncPlanner] Failed to refresh bucket item '{}': {}", top, e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(path: &str, size: i64, mtime: i64, hash: Option<&str>) -> IndexEntry {
+        let (parent_path, name) = crate::sync_index::split_index_path(path);
+        IndexEntry {
+            path: path.to_string(),
+            parent_path,
+            name,
+            is_folder: false,
+            size,
+            mtime,
+            content_hash: hash.map(str::to_string),
+            updated_at: 0,
+        }
+    }
+
+    fn upload(key: &str) -> PlannedAction {
+        PlannedAction::Upload { local_path: format!("/sync/{}", key), key: key.to_string() }
+    }
+
+    fn delete(key: &str) -> PlannedAction {
+        PlannedAction::Delete { key: key.to_string() }
+    }
+
+    #[test]
+    fn parses_uploads_and_deletes_from_the_dry_run() {
+        let lines = [
+            "(dryrun) upload: /sync/My Docs/a to b.txt to s3://bucket/My Docs/a to b.txt",
+            "(dryrun) delete: s3://bucket/old/report.pdf",
+            "upload: /sync/top.txt to s3://bucket/top.txt",
+            "(dryrun) download: s3://bucket/x to /sync/x",
+            "Completed 1 of 3 part(s) with 2 file(s) remaining",
+            "(dryrun) delete: s3://bucket/",
+            "",
+        ];
+        assert_eq!(
+            parse_sync_plan(&lines),
+            vec![
+                PlannedAction::Upload {
+                    local_path: "/sync/My Docs/a to b.txt".to_string(),
+                    key: "My Docs/a to b.txt".to_string(),
+                },
+                delete("old/report.pdf"),
+                PlannedAction::Upload { local_path: "/sync/top.txt".to_string(), key: "top.txt".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn pairs_deletes_with_uploads_of_the_same_content() {
+        let diff = IndexDiff {
+            removed: vec![
+                entry("old/a.bin", 100, 10, Some("aaa")),
+                entry("old/b.bin", 100, 10, Some("bbb")),
+                entry("unhashed.bin", 5, 10, None),
+            ],
+            added: vec![
+                entry("new/a.bin", 100, 10, Some("aaa")),
+                // Same content under another size can't be the same file
+                entry("new/b.bin", 99, 10, Some("bbb")),
+                entry("unhashed-copy.bin", 5, 10, None),
+            ],
+            ..IndexDiff::default()
+        };
+        let plan = [
+            delete("old/a.bin"),
+            delete("old/b.bin"),
+            delete("unhashed.bin"),
+            upload("new/a.bin"),
+            upload("new/b.bin"),
+            upload("unhashed-copy.bin"),
+        ];
+        let moves = detect_moves(&plan, &diff);
+        assert_eq!(moves.len(), 1);
+        assert_eq!((moves[0].from_key.as_str(), moves[0].to_key.as_str(), moves[0].size), ("old/a.bin", "new/a.bin", 100));
+    }
+
+    #[test]
+    fn moves_need_both_halves_in_the_plan() {
+        let diff = IndexDiff {
+            removed: vec![entry("a.bin", 100, 10, Some("aaa"))],
+            added: vec![entry("b.bin", 100, 10, Some("aaa"))],
+            ..IndexDiff::default()
+        };
+        // Excluded from the sync, so no upload is planned
+        assert!(detect_moves(&[delete("a.bin")], &diff).is_empty());
+        // Never uploaded, so there is nothing to delete
+        assert!(detect_moves(&[upload("b.bin")], &diff).is_empty());
+    }
+
+    #[test]
+    fn prefers_the_copy_that_kept_its_mtime_and_claims_each_target_once() {
+        let diff = IndexDiff {
+            removed: vec![
+                entry("one.bin", 100, 50, Some("same")),
+                entry("two.bin", 100, 70, Some("same")),
+                entry("three.bin", 100, 90, Some("same")),
+            ],
+            added: vec![
+                entry("copy-a.bin", 100, 70, Some("same")),
+                entry("copy-b.bin", 100, 50, Some("same")),
+            ],
+            ..IndexDiff::default()
+        };
+        let plan = [
+            delete("one.bin"),
+            delete("two.bin"),
+            delete("three.bin"),
+            upload("copy-a.bin"),
+            upload("copy-b.bin"),
+        ];
+        let moves: Vec<(String, String)> = detect_moves(&plan, &diff)
+            .into_iter()
+            .map(|m| (m.from_key, m.to_key))
+            .collect();
+        assert_eq!(
+            moves,
+            vec![
+                ("one.bin".to_string(), "copy-b.bin".to_string()),
+                ("two.bin".to_string(), "copy-a.bin".to_string()),
+            ]
+        );
+    }
+}
@@ -17,6 +17,7 @@ use std::time::{SystemTime, UNIX_EPOCH};
 use crate::user_profile_sync::UserProfileFileWithType;
 use crate::utils::file_operations::calculate_local_size;
 use crate::commands::node::get_aws_binary_path;
+use crate::sync_markers::is_folder_marker;
 
 /// Parses a line from the `aws s3 sync` output to create a RecentItem.
 pub fn parse_s3_sync_line(line: &str, scope: &str) -> Option<RecentItem> {
@@ -343,6 +344,7 @@ pub struct BucketItem {
 
 /// Lists all root-level files and folders in a given S3 bucket using AWS CLI.
 /// Folders will have their total size calculated by summing up the sizes of their contents.
+/// Folder marker objects ("name/") make empty folders show up as well.
 pub async fn list_bucket_contents(account_id: String, scope: String) -> Result<Vec<BucketItem>, String> {
     if scope != "public" && scope != "private" {
         return Err("Invalid scope provided. Must be 'public' or 'private'.".to_string());
@@ -411,7 +413,14 @@ pub async fn list_bucket_contents(account_id: String, scope: String) -> Result<V
                     let path = parts[3..].join(" ");
 
                     if let Ok(size) = size_str.parse::<u64>() {
-                        if let Some(slash_index) = path.find('/') {
+                        if is_folder_marker(&path, size) {
+                            // Marker objects keep empty folders visible; they add no size.
+                            let root_folder_name = path.split('/').next().unwrap_or("").to_string();
+                            if !root_folder_name.is_empty() {
+                                folder_sizes.entry(root_folder_name.clone()).or_insert(0);
+                                folder_last_modified.entry(root_folder_name).or_insert(last_modified.clone());
+                            }
+                        } else if let Some(slash_index) = path.find('/') {
                             // This is inside a folder.
                             let root_folder_name = path[..slash_index].to_string();
                             *folder_sizes.entry(root_folder_name.clone()).or_insert(0) += size;
@@ -609,3 +618,24 @@ pub async fn delete_bucket_item_by_name(
     Ok(res.rows_affected())
 }
 
+
+// Update the stored size of an S3-derived top-level record, e.g. after a nested change.
+pub async fn update_bucket_item_size(
+    pool: &SqlitePool,
+    owner: &str,
+    scope: &str,
+    name: &str,
+    size: i64,
+) -> Result<u64, sqlx::Error> {
+    let file_type = if scope == "public" { "public" } else { "private" };
+    let res = sqlx::query(
+        "UPDATE user_profiles SET file_size_in_bytes = ? WHERE owner = ? AND type = ? AND file_name = ? AND main_req_hash = 's3'",
+    )
+    .bind(size)
+    .bind(owner)
+    .bind(file_type)
+    .bind(name)
+    .execute(pool)
+    .await?;
+    Ok(res.rows_affected())
+}
@@ -3,7 +3,11 @@ use std::time::Duration;
 use tokio::time;
 use crate::substrate_client::get_substrate_client;
 use subxt::utils::AccountId32;
-use reqwest::Client;
+use crate::utils::account_share::{
+    apply_share_indexes, index_cid_from_item, known_share_senders, stored_sharing_keypair,
+};
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::manifest::parse_folder_manifest;
 use crate::DB_POOL;
 use crate::commands::substrate_tx::custom_runtime;
 use hex;
@@ -93,6 +97,42 @@ pub fn decode_file_hash(file_hash_bytes: &[u8]) -> Result<String, String> {
     Ok(decoded_str.to_string())
 }
 
+// Checks the public item of every account that has notified `recipient` or shared
+// with it before, and applies the share indexes found there. Both lookups are keyed by
+// account, so a pass never walks the whole chain.
+async fn sync_shared_with_me(
+    storage: &subxt::storage::Storage<subxt::PolkadotConfig, subxt::OnlineClient<subxt::PolkadotConfig>>,
+    recipient: &AccountId32,
+    ipfs: &IpfsClient,
+) -> Result<usize, String> {
+    let recipient_id = recipient.to_string();
+    let notifications = storage
+        .fetch(&custom_runtime::storage().notifications().notifications(recipient))
+        .await
+        .map_err(|e| format!("Failed to fetch notifications: {}", e))?
+        .unwrap_or_default();
+    let mut senders: HashSet<String> = notifications.iter().map(|n| n.sender.to_string()).collect();
+    senders.extend(known_share_senders(&recipient_id).await?);
+    senders.remove(&recipient_id);
+
+    let mut indexes = Vec::new();
+    for sender in senders {
+        let account: AccountId32 = match sender.parse() {
+            Ok(account) => account,
+            Err(_) => continue,
+        };
+        let item = storage
+            .fetch(&custom_runtime::storage().account_profile().user_public_storage(&account))
+            .await
+            .map_err(|e| format!("Failed to fetch public item of {}: {}", sender, e))?;
+        if let Some(cid) = item.as_deref().and_then(index_cid_from_item) {
+            indexes.push((sender, cid));
+        }
+    }
+    let keypair = stored_sharing_keypair(&recipient_id).await?;
+    apply_share_indexes(ipfs, &recipient_id, keypair.as_ref(), &indexes).await
+}
+
 pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
     {
         let mut syncing_accounts = SYNCING_ACCOUNTS.lock().unwrap();
@@ -106,13 +146,6 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
     let app_handle_clone = app_handle.clone();
     let account_id = account_id.to_string();
     tokio::spawn(async move {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(30))
-            .build()
-            .unwrap_or_else(|e| {
-                eprintln!("[UserSync] Failed to build HTTP client: {}", e);
-                Client::new()
-            });
         let mut retry_count = 0;
         let max_retries = 5;
 
@@ -191,6 +224,10 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
 
             let public_sync_path = get_public_sync_path().await.ok();
             let private_sync_path = get_private_sync_path().await.ok();
+            // Reloaded every pass so endpoint changes apply without a restart
+            let ipfs = IpfsClient::from_settings()
+                .await
+                .with_timeout(Duration::from_secs(30));
 
             // Step 1: Fetch and parse user profile data
             let profile_res = storage
@@ -221,17 +258,16 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
             };
 
             if !profile_cid.is_empty() {
-                let ipfs_url = format!("https://get.hippius.network/ipfs/{}", profile_cid);
                 let max_ipfs_retries = 3;
                 let mut ipfs_retry_count = 0;
                 let mut profile_fetched = false;
 
                 while ipfs_retry_count < max_ipfs_retries && !profile_fetched {
-                    match client.get(&ipfs_url).send().await {
-                        Ok(resp) => {
+                    match ipfs.cat(&profile_cid).await {
+                        Ok(bytes) => {
 
-                            if resp.status().is_success() {
-                                match resp.text().await {
+                            if !bytes.is_empty() {
+                                match String::from_utf8(bytes) {
                                     Ok(data) => {
                                         match serde_json::from_str::<serde_json::Value>(&data) {
                                             Ok(profile_data) => {
@@ -323,10 +359,9 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
                                                         if file_name.ends_with(".ec_metadata") && !file_name.ends_with(".folder.ec_metadata") && !file_name.ends_with("-folder.ec_metadata") {
                                                             let decoded_hash = decode_file_hash(&file_hash.as_bytes())
                                                                 .unwrap_or_else(|_| "Invalid file hash".to_string());
-                                                            let ipfs_url = format!("https://get.hippius.network/ipfs/{}", decoded_hash);
-                                                            match client.get(&ipfs_url).send().await {
-                                                                Ok(resp) => {
-                                                                    if let Ok(data) = resp.text().await {
+                                                            match ipfs.cat(&decoded_hash).await {
+                                                                Ok(bytes) => {
+                                                                    if let Ok(data) = String::from_utf8(bytes) {
                                                                         if let Ok(metadata) = serde_json::from_str::<serde_json::Value>(&data) {
                                                                             if let Some(original_file) = metadata.get("original_file") {
                                                                                 if let Some(size) = original_file.get("size").and_then(|v| v.as_i64()) {
@@ -355,19 +390,10 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
                                                         } else if is_folder {
                                                             let decoded_hash = decode_file_hash(&file_hash.as_bytes())
                                                                 .unwrap_or_else(|_| "Invalid file hash".to_string());
-                                                            let ipfs_url = format!("https://get.hippius.network/ipfs/{}", decoded_hash);
-                                                            match client.get(&ipfs_url).send().await {
-                                                                Ok(resp) => {
-                                                                    if let Ok(data) = resp.text().await {
-                                                                        if let Ok(folder_data) = serde_json::from_str::<serde_json::Value>(&data) {
-                                                                            if let Some(files) = folder_data.as_array() {
-                                                                                let total_size: i64 = files.iter()
-                                                                                    .filter_map(|file| file.get("file_size"))
-                                                                                    .filter_map(|size| size.as_i64())
-                                                                                    .sum();
-                                                                                actual_file_size = total_size;
-                                                                            }
-                                                                        }
+                                                            match ipfs.cat(&decoded_hash).await {
+                                                                Ok(bytes) => {
+                                                                    if let Ok(manifest) = parse_folder_manifest(&bytes, &file_name) {
+                                                                        actual_file_size = manifest.entries.iter().map(|entry| entry.size as i64).sum();
                                                                     }
                                                                 }
                                                                 Err(e) => {
@@ -431,7 +457,7 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
                                         }
                                     }
                                     Err(e) => {
-                                        eprintln!("[UserSync] Failed to get text from IPFS response for CID {}: {}", profile_cid, e);
+                                        eprintln!("[UserSync] Profile data for CID {} is not valid UTF-8: {}", profile_cid, e);
                                         let _ = app_handle_clone.emit("app-event", AppEvent {
                                             event_type: "error".to_string(),
                                             message: "Failed to parse IPFS response".to_string(),
@@ -446,11 +472,11 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
                                     }
                                 }
                             } else {
-                                eprintln!("[UserSync] IPFS request failed for CID {}: Status {}", profile_cid, resp.status());
+                                eprintln!("[UserSync] IPFS returned an empty profile for CID {}", profile_cid);
                                 let _ = app_handle_clone.emit("app-event", AppEvent {
                                     event_type: "error".to_string(),
-                                    message: "Failed to fetch from IPFS".to_string(),
-                                    details: Some(format!("CID: {}, Status: {}", profile_cid, resp.status())),
+                                    message: "Empty profile data".to_string(),
+                                    details: Some(format!("CID: {}", profile_cid)),
                                 });
                                 ipfs_retry_count += 1;
                                 if ipfs_retry_count < max_ipfs_retries {
@@ -581,12 +607,11 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
                                     name
                                 };
 
-                                let ipfs_api_url = "http://127.00.1:5001";
                                 // Handle IPFS content fetching for non-folder files
                                 if !file_name.ends_with("-folder") && !file_name.ends_with(".folder")
                                     && !file_name.ends_with(".folder.ec_metadata") && !file_name.ends_with("-folder.ec_metadata") && !file_name.ends_with(".ec_metadata"){
                                     if decoded_hash != "Invalid file hash" {
-                                        match crate::utils::ipfs::download_content_from_ipfs(&ipfs_api_url, &decoded_hash).await {
+                                        match ipfs.cat(&decoded_hash).await {
                                             Ok(json_bytes) => {
                                                 match String::from_utf8(json_bytes) {
                                                     Ok(json_str) => {
@@ -642,7 +667,7 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
                                 } else {
                                     // Handle folder or folder metadata
                                     if decoded_hash != "Invalid file hash" {
-                                        match crate::utils::ipfs::download_content_from_ipfs(&ipfs_api_url, &decoded_hash).await {
+                                        match ipfs.cat(&decoded_hash).await {
                                             Ok(json_bytes) => {
                                                 match String::from_utf8(json_bytes) {
                                                     Ok(json_str) => {
@@ -689,7 +714,7 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
                                                                                         let cid_vec = cid.to_string().as_bytes().to_vec();
                                                                                         file_hash = hex::encode(cid_vec);
                                                                                         // Download the .ec_metadata content
-                                                                                        match crate::utils::ipfs::download_content_from_ipfs(&ipfs_api_url, cid).await {
+                                                                                        match ipfs.cat(cid).await {
                                                                                             Ok(metadata_bytes) => {
                                                                                                 if let Ok(metadata_str) = String::from_utf8(metadata_bytes) {
                                                                                                     if let Ok(metadata_json) = serde_json::from_str::<serde_json::Value>(&metadata_str) {
@@ -898,7 +923,7 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
             // Step 3: Clear and insert into user_profiles table
             if let Some(pool) = DB_POOL.get() {
                 if records_to_insert.len() > 0 && profile_parsed_successfully {
-                    match sqlx::query("DELETE FROM user_profiles WHERE main_req_hash <> 's3'")
+                    match sqlx::query("DELETE FROM user_profiles WHERE main_req_hash NOT IN ('s3', 'car')")
                         .execute(pool)
                         .await
                     {
@@ -959,6 +984,15 @@ pub fn start_user_sync(app_handle: AppHandle, account_id: &str) {
                 }
             }
 
+            // Step 4: Pick up items other accounts have shared with this one
+            match sync_shared_with_me(&storage, &account, &ipfs).await {
+                Ok(fetched) if fetched > 0 => {
+                    println!("[UserSync] Applied {} updated share index(es)", fetched)
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("[UserSync] Failed to sync items shared with {}: {}", account_id, e),
+            }
+
             time::sleep(Duration::from_secs(120)).await;
         }
     });
@@ -0,0 +1,623 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use base64::{engine::general_purpose, Engine as _};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sodiumoxide::crypto::{box_, sealedbox, secretbox};
+use sqlx::Row;
+use crate::commands::ipfs_commands::{apply_failure_policy, download_folder_from_ipfs};
+use crate::commands::types::{FolderFailurePolicy, Metadata};
+use crate::utils::erasure::{
+    encrypt_and_encode_file, encrypt_and_encode_folder, reconstruct_file_streaming, ErasureParams,
+};
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::key_store::{hold_secrets, open_secret, seal_secret};
+use crate::utils::manifest::parse_folder_manifest;
+use crate::utils::share::ShareSource;
+use crate::DB_POOL;
+
+const SHARE_INDEX_KIND: &str = "hippius-share-index";
+const SHARE_INDEX_VERSION: u32 = 1;
+// Keeps the sharing key independent of anything else derived from the account secret
+const SHARING_KEY_DOMAIN: &[u8] = b"hippius/account-sharing/x25519/v1";
+
+/// One item shared with another account. It is published sealed in the sender's share
+/// index, and kept in `account_shares` by the sender and in `shared_with_me` by the
+/// recipient.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountShare {
+    pub id: String,
+    pub from: String,
+    pub to: String,
+    pub name: String,
+    pub is_folder: bool,
+    pub size: u64,
+    // File metadata CID, or the top-level folder manifest CID
+    pub cid: String,
+    // The item's secretbox key, sealed (`crypto_box_seal`) for the recipient, base64
+    pub wrapped_key: String,
+    pub created_at: i64,
+}
+
+/// A share as it appears in the published index: sealed (`crypto_box_seal`, base64)
+/// once for the recipient and once for the sender, whose devices rebuild their list of
+/// outgoing shares from it. Names, sizes and recipients stay private.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SealedShare {
+    pub id: String,
+    pub for_recipient: String,
+    pub for_sender: String,
+}
+
+// What an account publishes (as a hex CID in its public storage item) for recipients
+// to discover
+#[derive(Debug, Serialize, Deserialize)]
+struct ShareIndex {
+    kind: String,
+    version: u32,
+    from: String,
+    entries: Vec<SealedShare>,
+}
+
+/// The X25519 keypair an account receives shares with, derived from its raw sr25519
+/// secret so any device holding the mnemonic ends up with the same key.
+pub fn derive_sharing_keypair(account_secret: &[u8]) -> (box_::PublicKey, box_::SecretKey) {
+    let mut hasher = Sha256::new();
+    hasher.update(SHARING_KEY_DOMAIN);
+    hasher.update(account_secret);
+    let secret = box_::SecretKey(hasher.finalize().into());
+    (secret.public_key(), secret)
+}
+
+/// Reads a sharing key as published on chain: the raw 32 bytes, or their hex.
+pub fn parse_sharing_public_key(bytes: &[u8]) -> Result<box_::PublicKey, String> {
+    let raw = if bytes.len() == box_::PUBLICKEYBYTES {
+        bytes.to_vec()
+    } else {
+        let text = String::from_utf8_lossy(bytes);
+        hex::decode(text.trim().trim_start_matches("0x"))
+            .map_err(|_| "Published sharing key is neither raw bytes nor hex".to_string())?
+    };
+    box_::PublicKey::from_slice(&raw)
+        .ok_or_else(|| format!("Published sharing key has {} bytes, expected 32", raw.len()))
+}
+
+/// Opens the item key of a share addressed to the holder of `public_key`/`secret_key`.
+pub fn unwrap_share_key(
+    share: &AccountShare,
+    public_key: &box_::PublicKey,
+    secret_key: &box_::SecretKey,
+) -> Result<Vec<u8>, String> {
+    let wrapped = general_purpose::STANDARD
+        .decode(&share.wrapped_key)
+        .map_err(|e| format!("Invalid wrapped key for share {}: {}", share.id, e))?;
+    let key = sealedbox::open(&wrapped, public_key, secret_key)
+        .map_err(|_| format!("Share {} was not sealed for this account", share.id))?;
+    if key.len() != secretbox::KEYBYTES {
+        return Err(format!("Share {} carries a malformed key", share.id));
+    }
+    Ok(key)
+}
+
+fn seal_for(share: &AccountShare, key: &box_::PublicKey) -> Result<String, String> {
+    let json = serde_json::to_vec(share).map_err(|e| format!("Failed to serialize share {}: {}", share.id, e))?;
+    Ok(general_purpose::STANDARD.encode(sealedbox::seal(&json, key)))
+}
+
+/// Seals `share` for its recipient and for the sender, ready for the share index.
+pub fn seal_share(
+    share: &AccountShare,
+    recipient_key: &box_::PublicKey,
+    sender_key: &box_::PublicKey,
+) -> Result<SealedShare, String> {
+    Ok(SealedShare {
+        id: share.id.clone(),
+        for_recipient: seal_for(share, recipient_key)?,
+        for_sender: seal_for(share, sender_key)?,
+    })
+}
+
+// The share inside one sealed copy, or None when it was not sealed for this keypair
+fn open_sealed(
+    sealed: &str,
+    public_key: &box_::PublicKey,
+    secret_key: &box_::SecretKey,
+) -> Option<AccountShare> {
+    let bytes = general_purpose::STANDARD.decode(sealed).ok()?;
+    let json = sealedbox::open(&bytes, public_key, secret_key).ok()?;
+    serde_json::from_slice(&json).ok()
+}
+
+/// Opens the sender's copy of an entry from the sender's own index.
+pub fn open_own_share(
+    entry: &SealedShare,
+    from: &str,
+    public_key: &box_::PublicKey,
+    secret_key: &box_::SecretKey,
+) -> Result<AccountShare, String> {
+    open_sealed(&entry.for_sender, public_key, secret_key)
+        .filter(|share| share.id == entry.id && share.from == from)
+        .ok_or_else(|| format!("Share {} in the published index was not sealed for {}", entry.id, from))
+}
+
+/// Uploads a private file or folder again under a fresh key and seals that key for
+/// `recipient_key`, so the recipient never learns the sender's account key. Stored
+/// files and folders are rebuilt and decrypted with their own key first. Nothing is published yet;
+/// see `seal_share` and `publish_share_index`.
+pub async fn encode_account_share(
+    ipfs: &IpfsClient,
+    from: &str,
+    to: &str,
+    recipient_key: &box_::PublicKey,
+    source: ShareSource,
+) -> Result<AccountShare, String> {
+    if from == to {
+        return Err("Cannot share an item with the same account".to_string());
+    }
+    let params = ErasureParams::resolve(None, None, None, None).await?;
+    let key = secretbox::gen_key();
+
+    let (name, is_folder, size, cid) = match source {
+        ShareSource::Local(path) => {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .ok_or_else(|| format!("{} has no file name", path.display()))?;
+            if path.is_dir() {
+                let cid = encrypt_and_encode_folder(&path, params, Some(key.0.to_vec()), ipfs).await?;
+                let manifest = parse_folder_manifest(&ipfs.cat(&cid).await?, &name)?;
+                let size = manifest.entries.iter().map(|e| e.size).sum();
+                (name, true, size, cid)
+            } else if path.is_file() {
+                let (metadata, cid) =
+                    encrypt_and_encode_file(&path, params, Some(key.0.to_vec()), ipfs).await?;
+                (name, false, metadata.original_file.size as u64, cid)
+            } else {
+                return Err(format!("{} does not exist", path.display()));
+            }
+        }
+        ShareSource::Stored { metadata_cid, encryption_key } => {
+            let stored = ipfs.cat(&metadata_cid).await?;
+            let dir = tempfile::tempdir()
+                .map_err(|e| format!("Failed to create temp directory: {}", e))?;
+            if let Ok(metadata) = serde_json::from_slice::<Metadata>(&stored) {
+                let path = dir.path().join(&metadata.original_file.name);
+                reconstruct_file_streaming(&metadata, &path, encryption_key, ipfs).await?;
+                let (_, cid) = encrypt_and_encode_file(&path, params, Some(key.0.to_vec()), ipfs).await?;
+                (metadata.original_file.name, false, metadata.original_file.size as u64, cid)
+            } else {
+                let manifest = parse_folder_manifest(&stored, "")
+                    .map_err(|e| format!("{} is neither file metadata nor a folder manifest: {}", metadata_cid, e))?;
+                // Legacy manifests carry no name, and the name becomes a directory here
+                let name = Path::new(&manifest.original_folder_name)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().to_string())
+                    .unwrap_or_else(|| metadata_cid.clone());
+                let report = download_folder_from_ipfs(
+                    ipfs,
+                    &metadata_cid,
+                    &name,
+                    dir.path(),
+                    encryption_key.map(Arc::new),
+                    None,
+                    false,
+                )
+                .await?;
+                apply_failure_policy(report, FolderFailurePolicy::Strict)?;
+                let cid =
+                    encrypt_and_encode_folder(&dir.path().join(&name), params, Some(key.0.to_vec()), ipfs).await?;
+                let size = manifest.entries.iter().map(|e| e.size).sum();
+                (name, true, size, cid)
+            }
+        }
+    };
+
+    Ok(AccountShare {
+        id: uuid::Uuid::new_v4().to_string(),
+        from: from.to_string(),
+        to: to.to_string(),
+        name,
+        is_folder,
+        size,
+        cid,
+        wrapped_key: general_purpose::STANDARD.encode(sealedbox::seal(&key.0, recipient_key)),
+        created_at: chrono::Utc::now().timestamp(),
+    })
+}
+
+/// Adds the sender's share index listing `entries` to IPFS and returns its CID, which
+/// the caller publishes on chain.
+pub async fn publish_share_index(ipfs: &IpfsClient, from: &str, entries: &[SealedShare]) -> Result<String, String> {
+    let index = ShareIndex {
+        kind: SHARE_INDEX_KIND.to_string(),
+        version: SHARE_INDEX_VERSION,
+        from: from.to_string(),
+        entries: entries.to_vec(),
+    };
+    let json = serde_json::to_vec(&index).map_err(|e| format!("Failed to serialize share index: {}", e))?;
+    ipfs.add("share-index.json", json).await
+}
+
+/// The CID held by a public storage item, which stores it hex-encoded.
+pub fn index_cid_from_item(item: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(item);
+    let bytes = hex::decode(text.trim().trim_start_matches("0x")).ok()?;
+    String::from_utf8(bytes).ok().filter(|cid| !cid.is_empty())
+}
+
+fn parse_share_index(bytes: &[u8], from: &str) -> Option<ShareIndex> {
+    serde_json::from_slice::<ShareIndex>(bytes)
+        .ok()
+        .filter(|index| index.kind == SHARE_INDEX_KIND && index.from == from)
+}
+
+/// Reads the entries of the index `from` publishes in its public storage item (`item`,
+/// as stored on chain), so a new index can build on it instead of on this device's
+/// records. Fails when the index cannot be fetched, and when the item holds something
+/// other than a share index, which sharing must not overwrite.
+pub async fn read_published_index(
+    ipfs: &IpfsClient,
+    from: &str,
+    item: Option<&[u8]>,
+) -> Result<Vec<SealedShare>, String> {
+    let Some(item) = item.filter(|item| !item.is_empty()) else {
+        return Ok(Vec::new());
+    };
+    let foreign = || {
+        format!(
+            "The public storage item of {} holds something other than a share index; not replacing it",
+            from
+        )
+    };
+    let index_cid = index_cid_from_item(item).ok_or_else(foreign)?;
+    let bytes = ipfs
+        .cat(&index_cid)
+        .await
+        .map_err(|e| format!("Failed to fetch the published share index {}: {}", index_cid, e))?;
+    Ok(parse_share_index(&bytes, from).ok_or_else(foreign)?.entries)
+}
+
+fn row_to_share(row: &sqlx::sqlite::SqliteRow) -> AccountShare {
+    let size: i64 = row.get("size");
+    AccountShare {
+        id: row.get("id"),
+        from: row.get("sender"),
+        to: row.get("recipient"),
+        name: row.get("name"),
+        is_folder: row.get("is_folder"),
+        size: size as u64,
+        cid: row.get("cid"),
+        wrapped_key: row.get("wrapped_key"),
+        created_at: row.get("created_at"),
+    }
+}
+
+async fn insert_share<'e, E>(executor: E, table: &str, share: &AccountShare) -> Result<(), String>
+where
+    E: sqlx::Executor<'e, Database = sqlx::Sqlite>,
+{
+    sqlx::query(&format!(
+        "INSERT INTO {} (id, sender, recipient, name, is_folder, size, cid, wrapped_key, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)
+         ON CONFLICT(sender, id) DO UPDATE SET
+            recipient = excluded.recipient, name = excluded.name, is_folder = excluded.is_folder,
+            size = excluded.size, cid = excluded.cid, wrapped_key = excluded.wrapped_key,
+            created_at = excluded.created_at",
+        table
+    ))
+    .bind(&share.id)
+    .bind(&share.from)
+    .bind(&share.to)
+    .bind(&share.name)
+    .bind(share.is_folder)
+    .bind(share.size as i64)
+    .bind(&share.cid)
+    .bind(&share.wrapped_key)
+    .bind(share.created_at)
+    .execute(executor)
+    .await
+    .map_err(|e| format!("Failed to save share {}: {}", share.id, e))?;
+    Ok(())
+}
+
+/// Replaces this device's list of `from`'s shares with the ones just published.
+pub async fn replace_outgoing_shares(from: &str, shares: &[AccountShare]) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let mut tx = pool.begin().await.map_err(|e| format!("DB error (begin): {}", e))?;
+    sqlx::query("DELETE FROM account_shares WHERE sender = ?")
+        .bind(from)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to clear shares of {}: {}", from, e))?;
+    for share in shares {
+        insert_share(&mut *tx, "account_shares", share).await?;
+    }
+    tx.commit().await.map_err(|e| format!("DB error (commit): {}", e))?;
+    Ok(())
+}
+
+pub async fn list_outgoing_shares(from: &str) -> Result<Vec<AccountShare>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query("SELECT * FROM account_shares WHERE sender = ? ORDER BY created_at")
+        .bind(from)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to list shares: {}", e))?;
+    Ok(rows.iter().map(row_to_share).collect())
+}
+
+pub async fn list_shared_with_me(recipient: &str) -> Result<Vec<AccountShare>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query("SELECT * FROM shared_with_me WHERE recipient = ? ORDER BY created_at DESC")
+        .bind(recipient)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to list shared items: {}", e))?;
+    Ok(rows.iter().map(row_to_share).collect())
+}
+
+pub async fn get_shared_with_me(recipient: &str, id: &str) -> Result<AccountShare, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query("SELECT * FROM shared_with_me WHERE recipient = ? AND id = ?")
+        .bind(recipient)
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to look up share {}: {}", id, e))?
+        .map(|row| row_to_share(&row))
+        .ok_or_else(|| format!("No item shared with {} has id {}", recipient, id))
+}
+
+async fn delete_sender_shares(recipient: &str, sender: &str, keep: &[String]) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query("SELECT id FROM shared_with_me WHERE recipient = ? AND sender = ?")
+        .bind(recipient)
+        .bind(sender)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to read shares from {}: {}", sender, e))?;
+    for row in rows {
+        let id: String = row.get("id");
+        if keep.contains(&id) {
+            continue;
+        }
+        sqlx::query("DELETE FROM shared_with_me WHERE recipient = ? AND sender = ? AND id = ?")
+            .bind(recipient)
+            .bind(sender)
+            .bind(&id)
+            .execute(pool)
+            .await
+            .map_err(|e| format!("Failed to remove share {}: {}", id, e))?;
+    }
+    Ok(())
+}
+
+/// Keeps `account_id`'s sharing secret key, sealed like the other stored keys, so the
+/// profile sync can open shares without the mnemonic.
+pub async fn remember_sharing_key(account_id: &str, secret_key: &box_::SecretKey) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let hold = hold_secrets().await;
+    sqlx::query("INSERT OR REPLACE INTO sharing_keys (account_id, secret_key) VALUES (?, ?)")
+        .bind(account_id)
+        .bind(seal_secret(&hold, &secret_key.0).await?)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to save sharing key of {}: {}", account_id, e))?;
+    Ok(())
+}
+
+/// The sharing keypair kept by `remember_sharing_key`, if any.
+pub async fn stored_sharing_keypair(account_id: &str) -> Result<Option<(box_::PublicKey, box_::SecretKey)>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let stored: Option<Vec<u8>> = sqlx::query_scalar("SELECT secret_key FROM sharing_keys WHERE account_id = ?")
+        .bind(account_id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to read sharing key of {}: {}", account_id, e))?;
+    let Some(stored) = stored else {
+        return Ok(None);
+    };
+    let secret_key = box_::SecretKey::from_slice(&open_secret(&stored)?)
+        .ok_or_else(|| format!("Stored sharing key of {} is malformed", account_id))?;
+    Ok(Some((secret_key.public_key(), secret_key)))
+}
+
+/// Senders whose index `recipient` has applied before, and so keeps checking.
+pub async fn known_share_senders(recipient: &str) -> Result<Vec<String>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query_scalar("SELECT sender FROM share_indexes WHERE recipient = ?")
+        .bind(recipient)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to read share index state: {}", e))
+}
+
+/// Brings the "Shared with me" list in line with the share indexes the checked senders
+/// currently publish on chain, as `(sender, index CID)` pairs. An index is only fetched
+/// when its CID has changed since the last pass, and its entries are opened with
+/// `keypair`, the recipient's sharing key; known senders no longer publishing one drop
+/// their shares. Returns how many indexes were fetched.
+pub async fn apply_share_indexes(
+    ipfs: &IpfsClient,
+    recipient: &str,
+    keypair: Option<&(box_::PublicKey, box_::SecretKey)>,
+    indexes: &[(String, String)],
+) -> Result<usize, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let mut fetched = 0;
+
+    for (sender, index_cid) in indexes {
+        if sender == recipient {
+            continue;
+        }
+        let seen: Option<String> = sqlx::query_scalar(
+            "SELECT index_cid FROM share_indexes WHERE recipient = ? AND sender = ?",
+        )
+        .bind(recipient)
+        .bind(sender)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to read share index state: {}", e))?;
+        if seen.as_deref() == Some(index_cid.as_str()) {
+            continue;
+        }
+
+        // The public item may hold something other than a share index; those senders
+        // simply have nothing shared
+        let index = match ipfs.cat(index_cid).await {
+            Ok(bytes) => parse_share_index(&bytes, sender),
+            Err(e) => {
+                // Leave the state alone so the next pass tries again
+                eprintln!("[AccountShare] Failed to fetch share index {} of {}: {}", index_cid, sender, e);
+                continue;
+            }
+        };
+        let mut shares = Vec::new();
+        if let Some(index) = index.filter(|index| !index.entries.is_empty()) {
+            let Some((public_key, secret_key)) = keypair else {
+                // Also left for a later pass, once the key has been stored
+                eprintln!(
+                    "[AccountShare] No sharing key stored for {}; cannot open the index of {}",
+                    recipient, sender
+                );
+                continue;
+            };
+            // Entries sealed for other recipients simply do not open
+            shares.extend(index.entries.iter().filter_map(|entry| {
+                open_sealed(&entry.for_recipient, public_key, secret_key).filter(|share| share.id == entry.id)
+            }));
+        }
+        fetched += 1;
+
+        let mut keep = Vec::new();
+        for share in shares.into_iter().filter(|s| s.to == recipient && s.from == *sender) {
+            insert_share(pool, "shared_with_me", &share).await?;
+            keep.push(share.id);
+        }
+        delete_sender_shares(recipient, sender, &keep).await?;
+
+        sqlx::query(
+            "INSERT INTO share_indexes (recipient, sender, index_cid) VALUES (?, ?, ?)
+             ON CONFLICT(recipient, sender) DO UPDATE SET index_cid = excluded.index_cid",
+        )
+        .bind(recipient)
+        .bind(sender)
+        .bind(index_cid)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to save share index state: {}", e))?;
+    }
+
+    let publishing: HashSet<&str> = indexes.iter().map(|(sender, _)| sender.as_str()).collect();
+    for sender in known_share_senders(recipient).await? {
+        if !publishing.contains(sender.as_str()) {
+            delete_sender_shares(recipient, &sender, &[]).await?;
+            sqlx::query("DELETE FROM share_indexes WHERE recipient = ? AND sender = ?")
+                .bind(recipient)
+                .bind(&sender)
+                .execute(pool)
+                .await
+                .map_err(|e| format!("Failed to clear share index state: {}", e))?;
+        }
+    }
+
+    Ok(fetched)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_db::test_db;
+    use crate::utils::test_http::mock_ipfs;
+
+    fn share(to: &str) -> AccountShare {
+        AccountShare {
+            id: uuid::Uuid::new_v4().to_string(),
+            from: "alice".to_string(),
+            to: to.to_string(),
+            name: "holiday-photos.zip".to_string(),
+            is_folder: false,
+            size: 1234,
+            cid: "bafkqaaa".to_string(),
+            wrapped_key: String::new(),
+            created_at: 0,
+        }
+    }
+
+    #[tokio::test]
+    async fn published_index_hides_shares_from_everyone_but_both_ends() {
+        let (ipfs, _) = mock_ipfs().await;
+        let (alice_pk, alice_sk) = derive_sharing_keypair(b"alice");
+        let (bob_pk, bob_sk) = derive_sharing_keypair(b"bob");
+        let (eve_pk, eve_sk) = derive_sharing_keypair(b"eve");
+        let for_bob = share("bob");
+        let entry = seal_share(&for_bob, &bob_pk, &alice_pk).unwrap();
+
+        let index_cid = publish_share_index(&ipfs, "alice", std::slice::from_ref(&entry)).await.unwrap();
+        let raw = String::from_utf8(ipfs.cat(&index_cid).await.unwrap()).unwrap();
+        assert!(!raw.contains("holiday") && !raw.contains("bob") && !raw.contains("1234"));
+
+        let item = hex::encode(&index_cid).into_bytes();
+        let published = read_published_index(&ipfs, "alice", Some(&item)).await.unwrap();
+        let own = open_own_share(&published[0], "alice", &alice_pk, &alice_sk).unwrap();
+        assert_eq!(own.name, for_bob.name);
+        let received = open_sealed(&published[0].for_recipient, &bob_pk, &bob_sk).unwrap();
+        assert_eq!(received.to, "bob");
+        assert!(open_sealed(&published[0].for_recipient, &eve_pk, &eve_sk).is_none());
+        assert!(open_own_share(&published[0], "alice", &eve_pk, &eve_sk).is_err());
+    }
+
+    #[tokio::test]
+    async fn public_item_holding_something_else_is_not_replaced() {
+        let (ipfs, _) = mock_ipfs().await;
+        let other = ipfs.add("profile.json", b"{\"bio\":\"hi\"}".to_vec()).await.unwrap();
+        let error = read_published_index(&ipfs, "alice", Some(hex::encode(&other).as_bytes()))
+            .await
+            .unwrap_err();
+        assert!(error.contains("something other than a share index"));
+        // An index that cannot be fetched is an error too, not an empty index
+        let missing = hex::encode("bafkreigh2akiscaildcqabsyg3dfr6chu3fgpregiymsck7e7aqa4s52zy");
+        assert!(read_published_index(&ipfs, "alice", Some(missing.as_bytes())).await.is_err());
+        assert!(read_published_index(&ipfs, "alice", None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stored_folders_are_shared_under_a_fresh_key() {
+        test_db().await;
+        let (ipfs, _) = mock_ipfs().await;
+        let dir = tempfile::tempdir().unwrap();
+        let album = dir.path().join("album");
+        std::fs::create_dir_all(album.join("2024")).unwrap();
+        std::fs::write(album.join("cover.jpg"), b"cover").unwrap();
+        std::fs::write(album.join("2024").join("beach.jpg"), vec![3u8; 5000]).unwrap();
+        let account_key = secretbox::gen_key().0.to_vec();
+        let params = ErasureParams { k: 2, m: 4, chunk_size: 1024, compression: None };
+        let manifest_cid = encrypt_and_encode_folder(&album, params, Some(account_key.clone()), &ipfs)
+            .await
+            .unwrap();
+
+        let (bob_pk, bob_sk) = derive_sharing_keypair(b"bob");
+        let source = ShareSource::Stored { metadata_cid: manifest_cid.clone(), encryption_key: Some(account_key.clone()) };
+        let shared = encode_account_share(&ipfs, "alice", "bob", &bob_pk, source).await.unwrap();
+        assert!(shared.is_folder);
+        assert_eq!((shared.name.as_str(), shared.size), ("album", 5005));
+        assert_ne!(shared.cid, manifest_cid);
+
+        let key = unwrap_share_key(&shared, &bob_pk, &bob_sk).unwrap();
+        let out = tempfile::tempdir().unwrap();
+        let report = download_folder_from_ipfs(&ipfs, &shared.cid, &shared.name, out.path(), Some(Arc::new(key)), None, false)
+            .await
+            .unwrap();
+        assert!(report.failed.is_empty(), "{:?}", report.failed);
+        assert_eq!(std::fs::read(out.path().join("album").join("cover.jpg")).unwrap(), b"cover");
+        assert_eq!(std::fs::read(out.path().join("album").join("2024").join("beach.jpg")).unwrap(), vec![3u8; 5000]);
+
+        // The copy is not readable with the sender's own key
+        let other = tempfile::tempdir().unwrap();
+        let report = download_folder_from_ipfs(&ipfs, &shared.cid, &shared.name, other.path(), Some(Arc::new(account_key)), None, false)
+            .await
+            .unwrap();
+        assert_eq!(report.failed.len(), 2);
+    }
+}
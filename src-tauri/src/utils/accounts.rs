@@ -1,4 +1,8 @@
-use sodiumoxide::crypto::secretbox;
+use sodiumoxide::crypto::{generichash, kdf, secretbox};
+use crate::utils::key_store::{hold_secrets, open_secret, seal_secret};
+use crate::utils::stream_crypto::{
+    is_stream_format, key_id, open_stream, StreamHeader, DECRYPTION_FAILED, KEY_ID_BYTES,
+};
 use crate::DB_POOL;
 use sqlx::Row;
 use rand::{thread_rng, Rng};
@@ -20,18 +24,12 @@ pub async fn create_and_store_encryption_key() -> Result<(), String> {
     let key_name = generate_key_name();
 
     if let Some(pool) = DB_POOL.get() {
-        sqlx::query(
-            "CREATE TABLE IF NOT EXISTS encryption_keys (id INTEGER PRIMARY KEY AUTOINCREMENT, key_name TEXT NOT NULL UNIQUE, key BLOB NOT NULL)"
-        )
-        .execute(pool)
-        .await
-        .map_err(|e| format!("DB error (create table): {}", e))?;
-        
+        let hold = hold_secrets().await;
         sqlx::query(
             "INSERT INTO encryption_keys (key_name, key) VALUES (?, ?)"
         )
         .bind(&key_name)
-        .bind(&key_bytes)
+        .bind(seal_secret(&hold, &key_bytes).await?)
         .execute(pool)
         .await
         .map_err(|e| format!("DB error (insert key): {}", e))?;
@@ -54,11 +52,12 @@ pub async fn import_encryption_key(key_bytes: Vec<u8>) -> Result<String, String>
         // Generate random name for imported key
         let key_name = generate_key_name();
         
+        let hold = hold_secrets().await;
         sqlx::query(
             "INSERT INTO encryption_keys (key_name, key) VALUES (?, ?)"
         )
         .bind(&key_name)
-        .bind(&key_bytes)
+        .bind(seal_secret(&hold, &key_bytes).await?)
         .execute(pool)
         .await
         .map_err(|e| format!("DB error (insert imported key): {}", e))?;
@@ -69,6 +68,84 @@ pub async fn import_encryption_key(key_bytes: Vec<u8>) -> Result<String, String>
     }
 }
 
+// Domain the account secret is hashed under to get the KDF master key, and the
+// context every derived key is taken under
+const DERIVED_KEY_DOMAIN: &[u8] = b"hippius-encryption-keys-v1";
+const DERIVED_KEY_CONTEXT: [u8; kdf::CONTEXTBYTES] = *b"hipenckv";
+/// How many derived keys `restore_derived_encryption_keys` recreates by default.
+pub const DEFAULT_DERIVED_RESTORE_COUNT: u64 = 10;
+
+/// Encryption key number `index` of the account whose raw sr25519 secret is
+/// `account_secret`, so any device holding the mnemonic can recreate it. The mnemonic was
+/// stretched with PBKDF2 on its way to that secret; hashing it under its own domain
+/// keeps these keys unrelated to the account's signing and sharing keys.
+pub fn derive_encryption_key(account_secret: &[u8], index: u64) -> secretbox::Key {
+    let digest = generichash::hash(account_secret, Some(kdf::KEYBYTES), Some(DERIVED_KEY_DOMAIN))
+        .expect("valid generichash parameters");
+    let master = kdf::Key::from_slice(digest.as_ref()).expect("digest is a full key");
+    let mut key = [0u8; secretbox::KEYBYTES];
+    kdf::derive_from_key(&mut key, index, DERIVED_KEY_CONTEXT, &master)
+        .expect("secretbox keys are a valid subkey length");
+    secretbox::Key(key)
+}
+
+// Stores `account_id`'s derived key unless the DB has it already. Returns whether it
+// was added.
+async fn store_derived_key(account_id: &str, key: &secretbox::Key, index: u64) -> Result<bool, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    if find_encryption_key(&key_id(key)).await?.is_some() {
+        return Ok(false);
+    }
+    let hold = hold_secrets().await;
+    sqlx::query("INSERT INTO encryption_keys (key_name, key, derivation_index, account_id) VALUES (?, ?, ?, ?)")
+        .bind(generate_key_name())
+        .bind(seal_secret(&hold, &key.0).await?)
+        .bind(index as i64)
+        .bind(account_id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("DB error (insert derived key): {}", e))?;
+    Ok(true)
+}
+
+/// Derives `account_id`'s next encryption key, past every index it has in the DB, and
+/// stores it; it becomes the key new uploads use. Returns its index.
+pub async fn create_derived_encryption_key(account_id: &str, account_secret: &[u8]) -> Result<u64, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let next: Option<i64> = sqlx::query(
+        "SELECT MAX(derivation_index) + 1 AS next FROM encryption_keys WHERE account_id = ?",
+    )
+    .bind(account_id)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| format!("DB error (fetch derivation index): {}", e))?
+    .get("next");
+    let mut index = next.unwrap_or(0) as u64;
+    // Keys stored before they recorded their account are claimed and skipped over
+    while !store_derived_key(account_id, &derive_encryption_key(account_secret, index), index).await? {
+        index += 1;
+    }
+    println!("Created derived encryption key {} for {}", index, account_id);
+    Ok(index)
+}
+
+/// Recreates `account_id`'s derived keys `0..count`, e.g. on a fresh install, skipping
+/// ones the DB already holds. Returns how many were added.
+pub async fn restore_derived_encryption_keys(
+    account_id: &str,
+    account_secret: &[u8],
+    count: u64,
+) -> Result<usize, String> {
+    let mut added = 0;
+    for index in 0..count {
+        if store_derived_key(account_id, &derive_encryption_key(account_secret, index), index).await? {
+            added += 1;
+        }
+    }
+    println!("Restored {} of {} derived encryption keys for {}", added, count, account_id);
+    Ok(added)
+}
+
 /// Fetch the encryption key from the DB
 async fn get_latest_encryption_key_from_db() -> Result<secretbox::Key, String> {
     if let Some(pool) = DB_POOL.get() {
@@ -76,44 +153,186 @@ async fn get_latest_encryption_key_from_db() -> Result<secretbox::Key, String> {
             .fetch_one(pool)
             .await
             .map_err(|e| format!("DB error (fetch key): {}", e))?;
-        let key_bytes: Vec<u8> = row.get("key");
+        let key_bytes = open_secret(&row.get::<Vec<u8>, _>("key"))?;
         secretbox::Key::from_slice(&key_bytes).ok_or("Invalid key length".to_string())
     } else {
         Err("DB_POOL not initialized".to_string())
     }
 }
 
-/// Decrypts file data using the key from the DB, extracting the nonce.
+/// Uses the provided key bytes, or the latest key from the DB when none are given.
+pub async fn resolve_encryption_key(encryption_key: Option<Vec<u8>>) -> Result<secretbox::Key, String> {
+    match encryption_key {
+        Some(key_bytes) => secretbox::Key::from_slice(&key_bytes).ok_or("Invalid key length".to_string()),
+        None => get_latest_encryption_key_from_db().await,
+    }
+}
+
+/// Every key in the DB, newest first.
+async fn all_encryption_keys() -> Result<Vec<secretbox::Key>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query("SELECT key FROM encryption_keys ORDER BY id DESC")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("DB error (fetch keys): {}", e))?;
+    let mut keys = Vec::with_capacity(rows.len());
+    for row in &rows {
+        if let Some(key) = secretbox::Key::from_slice(&open_secret(&row.get::<Vec<u8>, _>("key"))?) {
+            keys.push(key);
+        }
+    }
+    Ok(keys)
+}
+
+/// The key in the DB whose `stream_crypto::key_id` is `id`, if there is one.
+pub async fn find_encryption_key(id: &[u8]) -> Result<Option<secretbox::Key>, String> {
+    Ok(all_encryption_keys()
+        .await?
+        .into_iter()
+        .find(|key| key_id(key).as_slice() == id))
+}
+
+/// Parses the hex key ID recorded in erasure metadata.
+pub fn parse_key_id(id: &str) -> Result<[u8; KEY_ID_BYTES], String> {
+    let bytes = hex::decode(id).map_err(|e| format!("Invalid key ID {}: {}", id, e))?;
+    bytes
+        .try_into()
+        .map_err(|_| format!("Invalid key ID {}: expected {} bytes", id, KEY_ID_BYTES))
+}
+
+/// Keys to try, in order, for data encrypted under the key with ID `id`: the given key
+/// alone, else the DB key with that ID, else (for data that names no key) every key in
+/// the DB, newest first.
+pub async fn candidate_keys(
+    encryption_key: Option<Vec<u8>>,
+    id: Option<&[u8]>,
+) -> Result<Vec<secretbox::Key>, String> {
+    if encryption_key.is_some() {
+        return Ok(vec![resolve_encryption_key(encryption_key).await?]);
+    }
+    if let Some(id) = id {
+        return match find_encryption_key(id).await? {
+            Some(key) => Ok(vec![key]),
+            None => Err(format!(
+                "{}: encrypted with key {}, which is not in this app",
+                DECRYPTION_FAILED,
+                hex::encode(id)
+            )),
+        };
+    }
+    let keys = all_encryption_keys().await?;
+    if keys.is_empty() {
+        return Err("No encryption keys found".to_string());
+    }
+    Ok(keys)
+}
+
+/// Decrypts file data with the given key, or else the DB key its header names, or
+/// else every DB key in turn. Reads the chunked stream format and the legacy
+/// `nonce || secretbox` layout.
 pub async fn decrypt_file(encrypted_data: &[u8], encryption_key: Option<Vec<u8>>) -> Result<Vec<u8>, String> {
+    let header = if is_stream_format(encrypted_data) {
+        StreamHeader::parse(encrypted_data).ok()
+    } else {
+        None
+    };
+    // A legacy nonce can start with the stream magic by chance, so an unknown ID from
+    // a header still falls back to every key
+    let keys = match candidate_keys(encryption_key.clone(), header.as_ref().map(|h| &h.key_id[..])).await {
+        Ok(keys) => keys,
+        Err(_) if header.is_some() && encryption_key.is_none() => candidate_keys(None, None).await?,
+        Err(e) => return Err(e),
+    };
+
+    let mut last_error = DECRYPTION_FAILED.to_string();
+    for key in &keys {
+        match decrypt_with_key(encrypted_data, key) {
+            Ok(plaintext) => return Ok(plaintext),
+            Err(e) => last_error = e,
+        }
+    }
+    Err(last_error)
+}
+
+fn decrypt_with_key(encrypted_data: &[u8], key: &secretbox::Key) -> Result<Vec<u8>, String> {
+    let stream_error = if is_stream_format(encrypted_data) {
+        match open_stream(encrypted_data, key) {
+            Ok(plaintext) => return Ok(plaintext),
+            Err(e) => Some(e),
+        }
+    } else {
+        None
+    };
+
     if encrypted_data.len() < secretbox::NONCEBYTES {
         return Err("Encrypted data too short".to_string());
     }
     let (nonce_bytes, ciphertext) = encrypted_data.split_at(secretbox::NONCEBYTES);
-    let key = match encryption_key {
-        Some(key_bytes) => {
-            secretbox::Key::from_slice(&key_bytes).ok_or("Invalid key length".to_string())?
-        },
-        None => get_latest_encryption_key_from_db().await?
-    };
     let nonce = secretbox::Nonce::from_slice(nonce_bytes).ok_or("Invalid nonce")?;
-    secretbox::open(ciphertext, &nonce, &key).map_err(|_| "Decryption failed".to_string())
+    secretbox::open(ciphertext, &nonce, key)
+        .map_err(|_| stream_error.unwrap_or_else(|| DECRYPTION_FAILED.to_string()))
 }
 
-/// List all encryption keys in the DB (returns base64-encoded key values and their IDs)
+/// List all encryption keys in the DB (returns base64-encoded key values, their IDs,
+/// the hex key IDs encrypted data refers to them by and, for derived keys, their index)
 #[allow(deprecated)]
-pub async fn list_encryption_keys() -> Result<Vec<(String, i64)>, String> {
+pub async fn list_encryption_keys() -> Result<Vec<(String, i64, String, Option<i64>)>, String> {
     if let Some(pool) = DB_POOL.get() {
-        let rows = sqlx::query("SELECT key, id FROM encryption_keys ORDER BY id DESC")
+        let rows = sqlx::query("SELECT key, id, derivation_index FROM encryption_keys ORDER BY id DESC")
             .fetch_all(pool)
             .await
             .map_err(|e| format!("DB error (fetch keys): {}", e))?;
             
-        Ok(rows.iter().map(|row| {
-            let key_bytes: Vec<u8> = row.get("key");
+        rows.iter().map(|row| {
+            let key_bytes = open_secret(&row.get::<Vec<u8>, _>("key"))?;
             let key_b64 = base64::encode(&key_bytes);
-            (key_b64, row.get::<i64, _>("id"))
-        }).collect())
+            let fingerprint = secretbox::Key::from_slice(&key_bytes)
+                .map(|key| hex::encode(key_id(&key)))
+                .unwrap_or_default();
+            Ok((key_b64, row.get::<i64, _>("id"), fingerprint, row.get("derivation_index")))
+        }).collect()
     } else {
         Err("DB_POOL not initialized".to_string())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::account_share::derive_sharing_keypair;
+    use crate::utils::test_db::test_db;
+
+    #[test]
+    fn derivation_is_deterministic() {
+        let secret = [42u8; 64];
+        assert_eq!(derive_encryption_key(&secret, 0).0, derive_encryption_key(&secret, 0).0);
+        assert_eq!(derive_encryption_key(&secret, 7).0, derive_encryption_key(&secret, 7).0);
+        assert_ne!(derive_encryption_key(&secret, 0).0, derive_encryption_key(&secret, 1).0);
+        assert_ne!(derive_encryption_key(&secret, 0).0, derive_encryption_key(&[43u8; 64], 0).0);
+    }
+
+    #[test]
+    fn derivation_is_separate_from_the_sharing_key() {
+        for secret in [[0u8; 64], [42u8; 64]] {
+            let (public, sharing) = derive_sharing_keypair(&secret);
+            for index in 0..16 {
+                let key = derive_encryption_key(&secret, index);
+                assert_ne!(key.0, sharing.0);
+                assert_ne!(key.0, public.0);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn derivation_indexes_count_per_account() {
+        test_db().await;
+        let (alice, bob) = ([1u8; 64], [2u8; 64]);
+        assert_eq!(create_derived_encryption_key("derive-alice", &alice).await.unwrap(), 0);
+        assert_eq!(create_derived_encryption_key("derive-alice", &alice).await.unwrap(), 1);
+        // Another account starts from its own first key
+        assert_eq!(create_derived_encryption_key("derive-bob", &bob).await.unwrap(), 0);
+        assert_eq!(create_derived_encryption_key("derive-alice", &alice).await.unwrap(), 2);
+        assert_eq!(restore_derived_encryption_keys("derive-bob", &bob, 3).await.unwrap(), 2);
+        assert_eq!(restore_derived_encryption_keys("derive-bob", &bob, 3).await.unwrap(), 0);
+    }
+}
@@ -0,0 +1,498 @@
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use crate::commands::types::{FileEntry, FolderMetadata, Metadata};
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::manifest::parse_folder_manifest;
+use crate::utils::trustless::{
+    index_car, write_car_block, write_car_header, write_file, Blocks, CarFile, CarIndex, Cid,
+};
+use crate::DB_POOL;
+
+// Shards fetched at once while exporting
+const EXPORT_CONCURRENCY: usize = 8;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CarExportReport {
+    pub root_cid: String,
+    pub output_path: String,
+    pub is_folder: bool,
+    pub objects: usize,
+    pub blocks: usize,
+    pub bytes_written: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CarImportReport {
+    pub root_cid: String,
+    pub file_name: String,
+    pub is_folder: bool,
+    pub size: i64,
+    pub roots: usize,
+    pub blocks: usize,
+    // False when an entry with the same name was already listed for the account
+    pub indexed: bool,
+}
+
+/// What a stored CID turned out to be.
+enum StoredObject {
+    File(Metadata),
+    Folder(FolderMetadata),
+    // A folder's entry for one file, pointing at its metadata
+    Entry(FileEntry),
+    // Content added as is, like a public file
+    Plain,
+}
+
+fn parse_stored_object(cid: &str, data: &[u8]) -> Result<StoredObject, String> {
+    if let Ok(metadata) = serde_json::from_slice::<Metadata>(data) {
+        return Ok(StoredObject::File(metadata));
+    }
+    if let Ok(entry) = serde_json::from_slice::<FileEntry>(data) {
+        return Ok(StoredObject::Entry(entry));
+    }
+    // Anything shaped like a manifest has to parse as one
+    match serde_json::from_slice::<serde_json::Value>(data) {
+        Ok(value) if value.is_array() || value.get("entries").is_some() => parse_folder_manifest(data, "")
+            .map(StoredObject::Folder)
+            .map_err(|e| format!("{} is not a valid folder manifest: {}", cid, e)),
+        _ => Ok(StoredObject::Plain),
+    }
+}
+
+// Everything reachable from one metadata or manifest CID, gathered before any block is
+// written so the CAR header can list every object as a root.
+struct ExportPlan {
+    // Metadata and manifest blocks, already fetched
+    blocks: Vec<Blocks>,
+    roots: Vec<String>,
+    shards: Vec<String>,
+}
+
+async fn plan_object(ipfs: &IpfsClient, cid: &str, plan: &mut ExportPlan) -> Result<bool, String> {
+    let (data, blocks) = ipfs.cat_with_blocks(cid).await?;
+    plan.roots.push(cid.to_string());
+    plan.blocks.push(blocks);
+    match parse_stored_object(cid, &data)? {
+        StoredObject::File(metadata) => {
+            plan.shards
+                .extend(metadata.chunks.into_iter().map(|chunk| chunk.cid.cid));
+            Ok(false)
+        }
+        StoredObject::Entry(entry) => {
+            Box::pin(plan_object(ipfs, &entry.cid, plan)).await?;
+            Ok(false)
+        }
+        // Its whole DAG came with the fetch
+        StoredObject::Plain => Ok(false),
+        StoredObject::Folder(manifest) => {
+            for entry in manifest.entries {
+                Box::pin(plan_object(ipfs, &entry.cid, plan)).await?;
+            }
+            Ok(true)
+        }
+    }
+}
+
+/// Every CID behind a stored file or folder: the metadata, manifest or plain file at
+/// `root_cid`, nested manifests, entries and metadata, then all shards.
+pub async fn object_cids(ipfs: &IpfsClient, root_cid: &str) -> Result<Vec<String>, String> {
+    let mut plan = ExportPlan { blocks: Vec::new(), roots: Vec::new(), shards: Vec::new() };
+    plan_object(ipfs, root_cid, &mut plan).await?;
+    plan.roots.extend(plan.shards);
+    Ok(plan.roots)
+}
+
+fn write_blocks(
+    blocks: &Blocks,
+    seen: &mut HashSet<Vec<u8>>,
+    out: &mut std::fs::File,
+    buf: &mut Vec<u8>,
+) -> Result<usize, String> {
+    let mut written = 0;
+    for (multihash, (cid, block)) in blocks {
+        if !seen.insert(multihash.clone()) {
+            continue;
+        }
+        buf.clear();
+        write_car_block(cid, block, buf);
+        out.write_all(buf)
+            .map_err(|e| format!("Failed to write CAR: {}", e))?;
+        written += 1;
+    }
+    Ok(written)
+}
+
+/// Writes the file or folder behind `root_cid` (metadata, manifest or plain public file,
+/// every nested manifest, entry and metadata, and all shards) to a single CARv1 at
+/// `output_path`. Every
+/// object is listed as a root so an import pins all of them, not only the top one.
+pub async fn export_car(
+    ipfs: &IpfsClient,
+    root_cid: &str,
+    output_path: &Path,
+) -> Result<CarExportReport, String> {
+    let mut plan = ExportPlan { blocks: Vec::new(), roots: Vec::new(), shards: Vec::new() };
+    let is_folder = plan_object(ipfs, root_cid, &mut plan).await?;
+    let roots = plan
+        .roots
+        .iter()
+        .chain(plan.shards.iter())
+        .map(|cid| Cid::parse(cid))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let parent = output_path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    let mut temp = tempfile::NamedTempFile::new_in(parent)
+        .map_err(|e| format!("Failed to create temp file in {}: {}", parent.display(), e))?;
+
+    let mut buf = Vec::new();
+    write_car_header(&roots, &mut buf);
+    temp.as_file_mut()
+        .write_all(&buf)
+        .map_err(|e| format!("Failed to write CAR: {}", e))?;
+
+    let mut seen = HashSet::new();
+    let mut block_count = 0;
+    for blocks in &plan.blocks {
+        block_count += write_blocks(blocks, &mut seen, temp.as_file_mut(), &mut buf)?;
+    }
+
+    let mut fetches = stream::iter(plan.shards.iter())
+        .map(|cid| async move {
+            ipfs.cat_with_blocks(cid)
+                .await
+                .map_err(|e| format!("Failed to export shard {}: {}", cid, e))
+        })
+        .buffer_unordered(EXPORT_CONCURRENCY);
+    while let Some(fetched) = fetches.next().await {
+        let (_, blocks) = fetched?;
+        block_count += write_blocks(&blocks, &mut seen, temp.as_file_mut(), &mut buf)?;
+    }
+
+    temp.as_file_mut()
+        .sync_all()
+        .map_err(|e| format!("Failed to write CAR: {}", e))?;
+    let bytes_written = temp
+        .as_file()
+        .metadata()
+        .map(|m| m.len())
+        .unwrap_or(0);
+    temp.persist(output_path)
+        .map_err(|e| format!("Failed to save {}: {}", output_path.display(), e))?;
+
+    println!(
+        "[CAR] Exported {} ({} objects, {} blocks) to {}",
+        root_cid,
+        roots.len(),
+        block_count,
+        output_path.display()
+    );
+    Ok(CarExportReport {
+        root_cid: root_cid.to_string(),
+        output_path: output_path.to_string_lossy().to_string(),
+        is_folder,
+        objects: roots.len(),
+        blocks: block_count,
+        bytes_written,
+    })
+}
+
+// Manifests nest about as deep as folders do
+const MAX_OBJECT_DEPTH: usize = 64;
+
+// The bytes of the object `cid` in a CAR on disk
+fn read_object(file: &mut std::fs::File, index: &CarIndex, cid: &Cid) -> Result<Vec<u8>, String> {
+    let mut object = Vec::new();
+    write_file(cid, &CarFile { file: &*file, index }, &mut object)?;
+    Ok(object)
+}
+
+// "private" or "public", from the first file metadata reachable from `object`. Entries
+// missing from the CAR are passed over. None when nothing below tells.
+fn object_type(
+    file: &mut std::fs::File,
+    index: &CarIndex,
+    cid: &str,
+    object: &StoredObject,
+    depth: usize,
+) -> Result<Option<&'static str>, String> {
+    if depth > MAX_OBJECT_DEPTH {
+        return Err(format!("Folders nest too deeply below {}", cid));
+    }
+    let children = match object {
+        StoredObject::File(metadata) => {
+            return Ok(Some(if metadata.erasure_coding.encrypted { "private" } else { "public" }));
+        }
+        StoredObject::Plain => return Ok(Some("public")),
+        StoredObject::Entry(entry) => vec![entry.cid.clone()],
+        StoredObject::Folder(manifest) => manifest.entries.iter().map(|e| e.cid.clone()).collect(),
+    };
+    for child in children {
+        let Ok(data) = Cid::parse(&child).and_then(|c| read_object(file, index, &c)) else {
+            continue;
+        };
+        let object = parse_stored_object(&child, &data)?;
+        if let Some(file_type) = object_type(file, index, &child, &object, depth + 1)? {
+            return Ok(Some(file_type));
+        }
+    }
+    Ok(None)
+}
+
+// What `import_car` lists for the top object
+struct CarRoot {
+    roots: Vec<Cid>,
+    block_count: usize,
+    file_name: Option<String>,
+    is_folder: bool,
+    size: i64,
+    file_type: &'static str,
+}
+
+// Verifies the whole CAR and describes its first root.
+fn read_car_root(car_path: &Path) -> Result<CarRoot, String> {
+    let mut file = std::fs::File::open(car_path)
+        .map_err(|e| format!("Failed to read {}: {}", car_path.display(), e))?;
+    let index = index_car(&mut file)?;
+    let root = index
+        .roots
+        .first()
+        .ok_or_else(|| format!("{} has no root CID", car_path.display()))?
+        .clone();
+    let root_cid = root.to_string_form();
+    let data = read_object(&mut file, &index, &root)?;
+    let object = parse_stored_object(&root_cid, &data)?;
+    // A folder of nothing but empty folders gives no hint; those were always private
+    let file_type = object_type(&mut file, &index, &root_cid, &object, 0)?.unwrap_or("private");
+    let (file_name, is_folder, size) = match object {
+        StoredObject::File(metadata) => (Some(metadata.original_file.name), false, metadata.original_file.size as i64),
+        StoredObject::Entry(entry) => (Some(entry.file_name), false, entry.file_size as i64),
+        StoredObject::Plain => (None, false, data.len() as i64),
+        StoredObject::Folder(manifest) => {
+            let size = manifest.entries.iter().map(|e| e.size as i64).sum();
+            // Legacy manifests do not record the folder name
+            let name = Some(manifest.original_folder_name).filter(|n| !n.is_empty());
+            (name, true, size)
+        }
+    };
+    Ok(CarRoot { block_count: index.blocks.len(), roots: index.roots, file_name, is_folder, size, file_type })
+}
+
+/// Imports a CAR written by `export_car` (or any CARv1/v2 whose first root is file
+/// metadata, a folder manifest or a plain file): every block is verified, the CAR is
+/// added to the node with its roots pinned, and the top-level entry is listed for
+/// `account_id`, as private or public depending on the files it holds.
+pub async fn import_car(
+    ipfs: &IpfsClient,
+    account_id: &str,
+    car_path: &Path,
+) -> Result<CarImportReport, String> {
+    // The top object must be readable from the archive alone before anything is imported
+    let path = PathBuf::from(car_path);
+    let CarRoot { roots, block_count, file_name, is_folder, size, file_type } =
+        tokio::task::spawn_blocking(move || read_car_root(&path))
+            .await
+            .map_err(|e| format!("CAR verification task failed: {}", e))??;
+    let root_cid = roots[0].to_string_form();
+    // Plain files and legacy manifests do not record a name
+    let file_name = file_name.unwrap_or_else(|| {
+        car_path
+            .file_stem()
+            .and_then(|n| n.to_str())
+            .unwrap_or(&root_cid)
+            .to_string()
+    });
+
+    ipfs.dag_import(car_path).await?;
+
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let exists: Option<(String,)> = sqlx::query_as(
+        "SELECT file_name FROM user_profiles WHERE owner = ? AND file_name = ? LIMIT 1"
+    )
+    .bind(account_id)
+    .bind(&file_name)
+    .fetch_optional(pool)
+    .await
+    .map_err(|e| format!("Failed to query user_profiles: {}", e))?;
+
+    let indexed = exists.is_none();
+    if indexed {
+        // main_req_hash 'car' marks entries that came from an archive rather than the
+        // chain, so the profile sync leaves them alone
+        sqlx::query(
+            "INSERT INTO user_profiles (
+                owner, cid, file_hash, file_name, file_size_in_bytes, is_assigned, last_charged_at,
+                main_req_hash, selected_validator, total_replicas, block_number, processed_timestamp, profile_cid,
+                source, miner_ids, created_at, type, is_folder
+            ) VALUES (?, ?, ?, ?, ?, ?, 0, 'car', '', 0, 0, CURRENT_TIMESTAMP, '', ?, '[]', strftime('%s', 'now'), ?, ?)"
+        )
+        .bind(account_id)
+        .bind(&root_cid)
+        .bind(hex::encode(root_cid.as_bytes()))
+        .bind(&file_name)
+        .bind(size)
+        .bind(false)
+        .bind(car_path.to_string_lossy().to_string())
+        .bind(file_type)
+        .bind(is_folder)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to record {}: {}", file_name, e))?;
+    }
+
+    println!(
+        "[CAR] Imported {} as '{}' ({} roots, {} blocks)",
+        root_cid,
+        file_name,
+        roots.len(),
+        block_count
+    );
+    Ok(CarImportReport {
+        root_cid,
+        file_name,
+        is_folder,
+        size,
+        roots: roots.len(),
+        blocks: block_count,
+        indexed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::types::{EntryKind, ManifestEntry};
+    use crate::utils::erasure::{encrypt_and_encode_file, ErasureParams};
+    use crate::utils::manifest::folder_manifest_bytes;
+    use crate::utils::test_http::mock_ipfs;
+    use crate::utils::trustless::{raw_cid_for, write_car};
+
+    fn car_file(bytes: &[u8]) -> tempfile::NamedTempFile {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(bytes).unwrap();
+        file
+    }
+
+    #[test]
+    fn reads_root_object_from_car_on_disk() {
+        let object = br#"{"original_file":{}}"#.to_vec();
+        let other = vec![7u8; 300_000];
+        let (root, shard) = (raw_cid_for(&object), raw_cid_for(&other));
+        let car = write_car(
+            &[root.clone(), shard.clone()],
+            &[(root.clone(), object.clone()), (shard, other)],
+        );
+        let file = car_file(&car);
+
+        let root_object = read_car_root(file.path()).unwrap();
+        assert_eq!(root_object.roots[0].to_string_form(), root.to_string_form());
+        assert_eq!(root_object.block_count, 2);
+        assert_eq!(root_object.size, object.len() as i64);
+    }
+
+    #[test]
+    fn rejects_corrupt_or_truncated_car() {
+        let block = vec![1u8; 1000];
+        let cid = raw_cid_for(&block);
+        let car = write_car(std::slice::from_ref(&cid), &[(cid.clone(), block)]);
+
+        let mut corrupt = car.clone();
+        *corrupt.last_mut().unwrap() ^= 1;
+        assert!(read_car_root(car_file(&corrupt).path()).is_err());
+        assert!(read_car_root(car_file(&car[..car.len() - 10]).path()).is_err());
+    }
+
+    fn file_entry(name: &str, cid: &str, size: u64) -> ManifestEntry {
+        ManifestEntry {
+            name: name.to_string(),
+            kind: EntryKind::File,
+            cid: cid.to_string(),
+            size,
+            mtime: None,
+            mode: None,
+            content_hash: None,
+        }
+    }
+
+    async fn exported(ipfs: &IpfsClient, cid: &str) -> (CarExportReport, CarRoot) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("export.car");
+        let report = export_car(ipfs, cid, &path).await.unwrap();
+        (report, read_car_root(&path).unwrap())
+    }
+
+    #[tokio::test]
+    async fn exports_plain_public_files_and_folders() {
+        let (ipfs, _store) = mock_ipfs().await;
+        let data = vec![5u8; 300_000];
+        let file_cid = ipfs.add("photo.jpg", data.clone()).await.unwrap();
+        let (report, root) = exported(&ipfs, &file_cid).await;
+        assert!(!report.is_folder);
+        assert_eq!((report.objects, report.blocks), (1, 3));
+        assert_eq!(root.roots[0].to_string_form(), file_cid);
+        assert_eq!((root.file_name, root.is_folder, root.size, root.file_type), (None, false, 300_000, "public"));
+
+        let other_cid = ipfs.add("notes.txt", b"notes".to_vec()).await.unwrap();
+        let manifest = folder_manifest_bytes(
+            "album",
+            vec![file_entry("photo.jpg", &file_cid, 300_000), file_entry("notes.txt", &other_cid, 5)],
+        )
+        .unwrap();
+        let folder_cid = ipfs.add("album", manifest).await.unwrap();
+        let (report, root) = exported(&ipfs, &folder_cid).await;
+        assert!(report.is_folder);
+        assert_eq!(report.objects, 3);
+        assert_eq!(root.file_name.as_deref(), Some("album"));
+        assert_eq!((root.is_folder, root.size, root.file_type), (true, 300_005, "public"));
+        assert_eq!(object_cids(&ipfs, &folder_cid).await.unwrap(), vec![folder_cid, file_cid, other_cid]);
+    }
+
+    #[tokio::test]
+    async fn exports_through_file_entries_and_types_private_folders() {
+        let (ipfs, _store) = mock_ipfs().await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("secret.txt");
+        std::fs::write(&source, vec![9u8; 5000]).unwrap();
+        let params = ErasureParams { k: 2, m: 3, chunk_size: 4096, compression: None };
+        let (metadata, metadata_cid) = encrypt_and_encode_file(
+            &source,
+            params,
+            Some(sodiumoxide::crypto::secretbox::gen_key().0.to_vec()),
+            &ipfs,
+        )
+        .await
+        .unwrap();
+
+        let entry = serde_json::to_vec(&FileEntry {
+            file_name: "secret.txt".to_string(),
+            file_size: 5000,
+            cid: metadata_cid.clone(),
+            mtime: None,
+            mode: None,
+        })
+        .unwrap();
+        let entry_cid = ipfs.add("secret.txt", entry).await.unwrap();
+        let (report, root) = exported(&ipfs, &entry_cid).await;
+        // The entry, the metadata it points at and every shard
+        assert_eq!(report.objects, 2 + metadata.chunks.len());
+        assert_eq!(root.file_name.as_deref(), Some("secret.txt"));
+        assert_eq!((root.is_folder, root.size, root.file_type), (false, 5000, "private"));
+
+        let manifest = folder_manifest_bytes("vault", vec![file_entry("secret.txt", &metadata_cid, 5000)]).unwrap();
+        let folder_cid = ipfs.add("vault", manifest).await.unwrap();
+        let (_, root) = exported(&ipfs, &folder_cid).await;
+        assert_eq!((root.is_folder, root.file_type), (true, "private"));
+    }
+
+    #[test]
+    fn manifest_shaped_objects_must_parse() {
+        assert!(matches!(parse_stored_object("x", b"just bytes").unwrap(), StoredObject::Plain));
+        assert!(matches!(parse_stored_object("x", b"{\"a\":1}").unwrap(), StoredObject::Plain));
+        assert!(parse_stored_object("x", br#"{"version":99,"original_folder_name":"f","entries":[]}"#).is_err());
+    }
+}
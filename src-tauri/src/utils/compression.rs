@@ -0,0 +1,205 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use crate::commands::types::Compression;
+
+const ZSTD_LEVEL: i32 = 3;
+
+// Formats that are compressed already; zstd would only add overhead
+const COMPRESSED_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "br", "bz2", "docx", "epub", "flac", "gif", "gz", "heic", "jar",
+    "jpeg", "jpg", "lz", "lz4", "lzma", "m4a", "m4v", "mkv", "mov", "mp3", "mp4", "odt", "ogg",
+    "opus", "png", "pptx", "rar", "tbz2", "tgz", "txz", "webm", "webp", "xlsx", "xz", "zip",
+    "zst",
+];
+
+// Leading bytes of the same kinds of files, for when the extension says nothing
+const COMPRESSED_MAGIC: &[&[u8]] = &[
+    b"\x1f\x8b",             // gzip
+    b"PK\x03\x04",           // zip and the office formats built on it
+    b"\x28\xb5\x2f\xfd",     // zstd
+    b"\xfd7zXZ\x00",         // xz
+    b"BZh",                  // bzip2
+    b"7z\xbc\xaf\x27\x1c",   // 7z
+    b"Rar!\x1a\x07",         // rar
+    b"\x89PNG\r\n\x1a\n",    // png
+    b"\xff\xd8\xff",         // jpeg
+    b"GIF8",                 // gif
+    b"\x1a\x45\xdf\xa3",     // matroska / webm
+    b"OggS",                 // ogg
+    b"fLaC",                 // flac
+    b"ID3",                  // mp3
+];
+
+/// Whether compressing a file would be wasted effort, judged by its extension and
+/// its first bytes.
+pub fn is_already_compressed(path: &Path, head: &[u8]) -> bool {
+    let by_extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| COMPRESSED_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+        .unwrap_or(false);
+    // MP4 and friends start with a box size, then "ftyp"
+    let iso_media = head.len() >= 8 && &head[4..8] == b"ftyp";
+    by_extension || iso_media || COMPRESSED_MAGIC.iter().any(|magic| head.starts_with(magic))
+}
+
+/// Streaming compressor: feed plaintext with `update`, collect the compressed bytes it
+/// returns, then append whatever `finish` returns.
+pub struct Compressor {
+    encoder: zstd::stream::write::Encoder<'static, Vec<u8>>,
+}
+
+impl Compressor {
+    pub fn new(compression: Compression) -> Result<Self, String> {
+        match compression {
+            Compression::Zstd => Ok(Compressor {
+                encoder: zstd::stream::write::Encoder::new(Vec::new(), ZSTD_LEVEL)
+                    .map_err(|e| format!("Failed to start compression: {}", e))?,
+            }),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+        self.encoder
+            .write_all(data)
+            .map_err(|e| format!("Failed to compress: {}", e))?;
+        Ok(std::mem::take(self.encoder.get_mut()))
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        self.encoder
+            .finish()
+            .map_err(|e| format!("Failed to compress: {}", e))
+    }
+}
+
+/// Streaming counterpart of `Compressor`. A truncated stream is not reported here; the
+/// caller's hash check over the output catches it. Output past `max_len`, the size the
+/// file is recorded to have, is refused, so a small forged stream cannot expand without
+/// bound before that check.
+pub struct Decompressor {
+    decoder: zstd::stream::write::Decoder<'static, Bounded>,
+}
+
+impl Decompressor {
+    pub fn new(compression: Compression, max_len: u64) -> Result<Self, String> {
+        match compression {
+            Compression::Zstd => Ok(Decompressor {
+                decoder: zstd::stream::write::Decoder::new(Bounded { data: Vec::new(), remaining: max_len })
+                    .map_err(|e| format!("Failed to start decompression: {}", e))?,
+            }),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+        self.decoder
+            .write_all(data)
+            .and_then(|_| self.decoder.flush())
+            .map_err(|e| format!("Failed to decompress: {}", e))?;
+        Ok(std::mem::take(&mut self.decoder.get_mut().data))
+    }
+}
+
+// Output buffer that fails once more than `remaining` bytes have gone into it
+struct Bounded {
+    data: Vec<u8>,
+    remaining: u64,
+}
+
+impl Write for Bounded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.len() as u64 > self.remaining {
+            return Err(io::Error::other("output is larger than the recorded file size"));
+        }
+        self.remaining -= buf.len() as u64;
+        self.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Compresses the file at `input` into `output`.
+pub fn compress_file(input: &Path, output: &Path, compression: Compression) -> Result<(), String> {
+    let reader = File::open(input).map_err(|e| format!("Failed to open {}: {}", input.display(), e))?;
+    let writer = File::create(output).map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+    match compression {
+        Compression::Zstd => zstd::stream::copy_encode(reader, writer, ZSTD_LEVEL),
+    }
+    .map_err(|e| format!("Failed to compress {}: {}", input.display(), e))
+}
+
+/// Expands a file written by `compress_file`.
+pub fn expand_file(input: &Path, output: &Path, compression: Compression) -> Result<(), String> {
+    let reader = File::open(input).map_err(|e| format!("Failed to open {}: {}", input.display(), e))?;
+    let writer = File::create(output).map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+    match compression {
+        Compression::Zstd => zstd::stream::copy_decode(reader, writer),
+    }
+    .map_err(|e| format!("Failed to decompress {}: {}", input.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn log_lines(count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|i| format!("2026-10-19T08:{:02}:{:02}Z INFO served request {} in {} ms\n", i / 60 % 60, i % 60, i * 7, i % 97))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[test]
+    fn streams_round_trip_in_pieces() {
+        let plain = log_lines(5000);
+        let mut compressor = Compressor::new(Compression::Zstd).unwrap();
+        let mut compressed = Vec::new();
+        for piece in plain.chunks(1000) {
+            compressed.extend(compressor.update(piece).unwrap());
+        }
+        compressed.extend(compressor.finish().unwrap());
+        assert!(compressed.len() * 5 < plain.len());
+
+        let mut decompressor = Decompressor::new(Compression::Zstd, plain.len() as u64).unwrap();
+        let mut expanded = Vec::new();
+        for piece in compressed.chunks(333) {
+            expanded.extend(decompressor.update(piece).unwrap());
+        }
+        assert_eq!(expanded, plain);
+    }
+
+    #[test]
+    fn expansion_stops_at_the_recorded_size() {
+        // A few kilobytes that expand to 64 MiB
+        let bomb = zstd::stream::encode_all(&vec![0u8; 64 << 20][..], ZSTD_LEVEL).unwrap();
+        assert!(bomb.len() < 64 << 10);
+        let mut decompressor = Decompressor::new(Compression::Zstd, 1 << 20).unwrap();
+        let error = decompressor.update(&bomb).unwrap_err();
+        assert!(error.contains("larger than the recorded file size"), "{}", error);
+    }
+
+    #[test]
+    fn files_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let plain = log_lines(2000);
+        std::fs::write(dir.path().join("app.log"), &plain).unwrap();
+        compress_file(&dir.path().join("app.log"), &dir.path().join("app.log.zst"), Compression::Zstd).unwrap();
+        expand_file(&dir.path().join("app.log.zst"), &dir.path().join("out.log"), Compression::Zstd).unwrap();
+        assert_eq!(std::fs::read(dir.path().join("out.log")).unwrap(), plain);
+        assert!(expand_file(&dir.path().join("app.log"), &dir.path().join("bad.log"), Compression::Zstd).is_err());
+    }
+
+    #[test]
+    fn compressed_formats_are_recognised() {
+        assert!(is_already_compressed(Path::new("photo.JPG"), b""));
+        assert!(is_already_compressed(Path::new("archive"), b"\x1f\x8b\x08\x00"));
+        assert!(is_already_compressed(Path::new("clip"), b"\x00\x00\x00\x18ftypmp42"));
+        assert!(is_already_compressed(Path::new("backup.bin"), b"PK\x03\x04rest"));
+        assert!(!is_already_compressed(Path::new("app.log"), b"2026-10-19 INFO"));
+        assert!(!is_already_compressed(Path::new("notes"), b""));
+    }
+}
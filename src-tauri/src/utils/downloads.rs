@@ -0,0 +1,708 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use base64::{engine::general_purpose, Engine as _};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use tauri::{AppHandle, Emitter};
+use tokio::task::JoinHandle;
+use crate::commands::ipfs_commands::{
+    apply_failure_policy, download_and_decrypt_file, download_and_decrypt_folder, download_folder_from_ipfs,
+    download_public_folder_from_ipfs, fetch_file_metadata, public_download_folder,
+};
+use crate::utils::erasure::{
+    partial_path, reconstruct_file_resumable, reconstructed_len, ReconstructCheckpoint,
+};
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::key_store::{hold_secrets, open_secret, seal_secret};
+use crate::commands::types::FolderFailurePolicy;
+use crate::utils::manifest::parse_folder_manifest;
+use crate::utils::settings::{get_usize_setting, DOWNLOAD_CONCURRENCY};
+use crate::DB_POOL;
+
+pub const QUEUED: &str = "queued";
+pub const RUNNING: &str = "running";
+pub const PAUSED: &str = "paused";
+pub const COMPLETED: &str = "completed";
+pub const FAILED: &str = "failed";
+pub const CANCELLED: &str = "cancelled";
+
+/// Emitted with a `DownloadJob` whenever a job changes state or makes progress.
+pub const DOWNLOAD_PROGRESS_EVENT: &str = "download_progress";
+const DEFAULT_CONCURRENCY: usize = 2;
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// What a job downloads; the same arguments as the matching download command. The
+/// key is taken out when the job is queued and kept sealed on its own, so saved and
+/// listed requests never carry it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum DownloadRequest {
+    File {
+        metadata_cid: String,
+        output_file: String,
+        encryption_key: Option<String>,
+        #[serde(default)]
+        source: String,
+    },
+    Folder {
+        folder_metadata_cid: String,
+        folder_name: String,
+        output_dir: String,
+        encryption_key: Option<String>,
+        #[serde(default)]
+        source: String,
+    },
+    PublicFolder {
+        folder_metadata_cid: String,
+        folder_name: String,
+        output_dir: String,
+        #[serde(default)]
+        source: String,
+    },
+}
+
+impl DownloadRequest {
+    // S3 and local copies go through the AWS CLI, which reports no progress and
+    // restarts from scratch
+    fn is_ipfs(&self) -> bool {
+        let cid = match self {
+            DownloadRequest::File { metadata_cid, .. } => metadata_cid,
+            DownloadRequest::Folder { folder_metadata_cid, .. }
+            | DownloadRequest::PublicFolder { folder_metadata_cid, .. } => folder_metadata_cid,
+        };
+        cid != "s3" && cid != "local"
+    }
+
+    // Removes and returns the base64 key of a file or folder request
+    fn take_key(&mut self) -> Option<String> {
+        match self {
+            DownloadRequest::File { encryption_key, .. } | DownloadRequest::Folder { encryption_key, .. } => {
+                encryption_key.take()
+            }
+            DownloadRequest::PublicFolder { .. } => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DownloadJob {
+    pub id: String,
+    pub request: DownloadRequest,
+    pub status: String,
+    pub bytes_done: u64,
+    // Unknown until the metadata is in, and for S3 downloads
+    pub bytes_total: Option<u64>,
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+// A job with a task behind it. Removing the entry is what claims the right to write
+// the job's final status, so a pause and a finishing task never both do.
+struct ActiveJob {
+    task: JoinHandle<()>,
+    progress: Arc<AtomicU64>,
+    checkpoint: Arc<Mutex<Option<ReconstructCheckpoint>>>,
+}
+
+static ACTIVE: Lazy<Mutex<HashMap<String, ActiveJob>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+static APP_HANDLE: OnceCell<AppHandle> = OnceCell::new();
+// Keeps two pumps from starting the same queued job
+static PUMP_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+
+fn row_to_request(row: &sqlx::sqlite::SqliteRow) -> Result<DownloadRequest, String> {
+    let request: String = row.get("request");
+    serde_json::from_str(&request).map_err(|e| format!("Invalid saved download request: {}", e))
+}
+
+fn row_to_job(row: &sqlx::sqlite::SqliteRow) -> Result<DownloadJob, String> {
+    let request = row_to_request(row)?;
+    let bytes_done: i64 = row.get("bytes_done");
+    let bytes_total: Option<i64> = row.get("bytes_total");
+    Ok(DownloadJob {
+        id: row.get("id"),
+        request,
+        status: row.get("status"),
+        bytes_done: bytes_done as u64,
+        bytes_total: bytes_total.map(|b| b as u64),
+        error: row.get("error"),
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    })
+}
+
+pub async fn get_download(id: &str) -> Result<DownloadJob, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let row = sqlx::query("SELECT * FROM download_jobs WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to look up download {}: {}", id, e))?
+        .ok_or_else(|| format!("No download with id {}", id))?;
+    row_to_job(&row)
+}
+
+pub async fn list_downloads() -> Result<Vec<DownloadJob>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query("SELECT * FROM download_jobs ORDER BY created_at, rowid")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to list downloads: {}", e))?;
+    rows.iter().map(row_to_job).collect()
+}
+
+async fn emit_job(id: &str) {
+    let Some(app) = APP_HANDLE.get() else {
+        return;
+    };
+    match get_download(id).await {
+        Ok(job) => {
+            if let Err(e) = app.emit(DOWNLOAD_PROGRESS_EVENT, job) {
+                eprintln!("[Downloads] Failed to emit progress for {}: {}", id, e);
+            }
+        }
+        Err(e) => eprintln!("[Downloads] {}", e),
+    }
+}
+
+async fn set_status(id: &str, status: &str, error: Option<&str>) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query("UPDATE download_jobs SET status = ?, error = ?, updated_at = ? WHERE id = ?")
+        .bind(status)
+        .bind(error)
+        .bind(chrono::Utc::now().timestamp())
+        .bind(id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to update download {}: {}", id, e))?;
+    emit_job(id).await;
+    Ok(())
+}
+
+async fn save_progress(
+    id: &str,
+    bytes_done: u64,
+    checkpoint: Option<&ReconstructCheckpoint>,
+) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let checkpoint = match checkpoint {
+        Some(checkpoint) => serde_json::to_string(checkpoint)
+            .map_err(|e| format!("Failed to serialize checkpoint: {}", e))?,
+        None => String::new(),
+    };
+    sqlx::query(
+        "UPDATE download_jobs SET bytes_done = ?, checkpoint = CASE WHEN ? = '' THEN checkpoint ELSE ? END,
+         updated_at = ? WHERE id = ?",
+    )
+    .bind(bytes_done as i64)
+    .bind(&checkpoint)
+    .bind(&checkpoint)
+    .bind(chrono::Utc::now().timestamp())
+    .bind(id)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to save progress of {}: {}", id, e))?;
+    Ok(())
+}
+
+async fn set_total(id: &str, bytes_total: u64) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query("UPDATE download_jobs SET bytes_total = ? WHERE id = ?")
+        .bind(bytes_total as i64)
+        .bind(id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to update download {}: {}", id, e))?;
+    Ok(())
+}
+
+fn row_to_key(row: &sqlx::sqlite::SqliteRow) -> Result<Option<Vec<u8>>, String> {
+    let sealed: Option<Vec<u8>> = row.get("sealed_key");
+    sealed.map(|sealed| open_secret(&sealed)).transpose()
+}
+
+/// Starts the manager once the database is ready: jobs that were running when the app
+/// closed are queued again (files pick up from their last checkpoint), and progress
+/// events start flowing.
+pub async fn init_downloads(app: AppHandle) {
+    let _ = APP_HANDLE.set(app);
+    if let Some(pool) = DB_POOL.get() {
+        if let Err(e) = sqlx::query("UPDATE download_jobs SET status = ? WHERE status = ?")
+            .bind(QUEUED)
+            .bind(RUNNING)
+            .execute(pool)
+            .await
+        {
+            eprintln!("[Downloads] Failed to requeue interrupted downloads: {}", e);
+        }
+    }
+
+    tokio::spawn(async {
+        let mut reported: HashMap<String, u64> = HashMap::new();
+        loop {
+            tokio::time::sleep(PROGRESS_INTERVAL).await;
+            let snapshot: Vec<(String, u64, Option<ReconstructCheckpoint>)> = ACTIVE
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(id, job)| {
+                    let checkpoint = job.checkpoint.lock().unwrap().clone();
+                    (id.clone(), job.progress.load(Ordering::Relaxed), checkpoint)
+                })
+                .collect();
+            reported.retain(|id, _| snapshot.iter().any(|(active, _, _)| active == id));
+            for (id, bytes_done, checkpoint) in snapshot {
+                if reported.get(&id) == Some(&bytes_done) {
+                    continue;
+                }
+                reported.insert(id.clone(), bytes_done);
+                if let Err(e) = save_progress(&id, bytes_done, checkpoint.as_ref()).await {
+                    eprintln!("[Downloads] {}", e);
+                }
+                emit_job(&id).await;
+            }
+        }
+    });
+
+    pump().await;
+}
+
+/// Queues a download and starts it if a slot is free.
+pub async fn enqueue_download(mut request: DownloadRequest) -> Result<DownloadJob, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let hold = hold_secrets().await;
+    let sealed_key = match decode_key(request.take_key())? {
+        Some(key) => Some(seal_secret(&hold, &key).await?),
+        None => None,
+    };
+    let json = serde_json::to_string(&request)
+        .map_err(|e| format!("Failed to serialize download request: {}", e))?;
+    let id = uuid::Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().timestamp();
+    sqlx::query(
+        "INSERT INTO download_jobs (id, request, sealed_key, status, created_at, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&id)
+    .bind(&json)
+    .bind(sealed_key)
+    .bind(QUEUED)
+    .bind(now)
+    .bind(now)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to queue download: {}", e))?;
+    drop(hold);
+    emit_job(&id).await;
+    pump().await;
+    get_download(&id).await
+}
+
+/// Starts queued jobs, oldest first, until the concurrency limit is reached. Called
+/// whenever a job is queued or finishes, and when the limit changes.
+pub async fn pump() {
+    let _guard = PUMP_LOCK.lock().await;
+    let Some(pool) = DB_POOL.get() else {
+        return;
+    };
+    let limit = get_usize_setting(DOWNLOAD_CONCURRENCY, DEFAULT_CONCURRENCY).await.max(1);
+    let running = ACTIVE.lock().unwrap().len();
+    if running >= limit {
+        return;
+    }
+
+    let rows = match sqlx::query(
+        "SELECT * FROM download_jobs WHERE status = ? ORDER BY created_at, rowid LIMIT ?",
+    )
+    .bind(QUEUED)
+    .bind((limit - running) as i64)
+    .fetch_all(pool)
+    .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            eprintln!("[Downloads] Failed to read the queue: {}", e);
+            return;
+        }
+    };
+
+    for row in rows {
+        // A locked key store fails the job; it can be resumed once unlocked
+        let (job, key) = match row_to_job(&row).and_then(|job| row_to_key(&row).map(|key| (job, key))) {
+            Ok(job) => job,
+            Err(e) => {
+                let id: String = row.get("id");
+                let _ = set_status(&id, FAILED, Some(&e)).await;
+                continue;
+            }
+        };
+        let checkpoint: String = row.get("checkpoint");
+        let checkpoint: Option<ReconstructCheckpoint> = serde_json::from_str(&checkpoint).ok();
+        let attempts: i64 = row.get("attempts");
+        if let Err(e) = sqlx::query("UPDATE download_jobs SET attempts = attempts + 1 WHERE id = ?")
+            .bind(&job.id)
+            .execute(pool)
+            .await
+        {
+            eprintln!("[Downloads] Failed to update download {}: {}", job.id, e);
+        }
+        if let Err(e) = set_status(&job.id, RUNNING, None).await {
+            eprintln!("[Downloads] {}", e);
+            continue;
+        }
+        start_job(job, key, checkpoint, attempts > 0);
+    }
+}
+
+fn start_job(job: DownloadJob, key: Option<Vec<u8>>, checkpoint: Option<ReconstructCheckpoint>, resumed: bool) {
+    let progress = Arc::new(AtomicU64::new(0));
+    let shared_checkpoint = Arc::new(Mutex::new(checkpoint.clone()));
+    // Held until the entry is in ACTIVE, so a fast job cannot finish before it exists
+    let mut active = ACTIVE.lock().unwrap();
+
+    let DownloadJob { id, request, .. } = job;
+    let task_id = id.clone();
+    let task_progress = progress.clone();
+    let task_checkpoint = shared_checkpoint.clone();
+    let task = tokio::spawn(async move {
+        let id = task_id;
+        let result = run_job(&id, request, key, checkpoint, resumed, task_progress.clone(), task_checkpoint.clone()).await;
+
+        let still_active = ACTIVE.lock().unwrap().remove(&id).is_some();
+        if still_active {
+            let checkpoint = task_checkpoint.lock().unwrap().clone();
+            if let Err(e) = save_progress(&id, task_progress.load(Ordering::Relaxed), checkpoint.as_ref()).await {
+                eprintln!("[Downloads] {}", e);
+            }
+            let outcome = match result {
+                Ok(()) => {
+                    println!("[Downloads] Download {} completed", id);
+                    set_status(&id, COMPLETED, None).await
+                }
+                Err(e) => {
+                    eprintln!("[Downloads] Download {} failed: {}", id, e);
+                    set_status(&id, FAILED, Some(&e)).await
+                }
+            };
+            if let Err(e) = outcome {
+                eprintln!("[Downloads] {}", e);
+            }
+        }
+        // Frees a slot for the next queued job
+        pump().await;
+    });
+
+    active.insert(
+        id,
+        ActiveJob {
+            task,
+            progress,
+            checkpoint: shared_checkpoint,
+        },
+    );
+}
+
+fn decode_key(encryption_key: Option<String>) -> Result<Option<Vec<u8>>, String> {
+    encryption_key
+        .map(|key_b64| general_purpose::STANDARD.decode(&key_b64))
+        .transpose()
+        .map_err(|e| format!("Failed to decode base64 key: {}", e))
+}
+
+async fn run_job(
+    id: &str,
+    request: DownloadRequest,
+    key: Option<Vec<u8>>,
+    checkpoint: Option<ReconstructCheckpoint>,
+    resumed: bool,
+    progress: Arc<AtomicU64>,
+    shared_checkpoint: Arc<Mutex<Option<ReconstructCheckpoint>>>,
+) -> Result<(), String> {
+    if !request.is_ipfs() {
+        let encryption_key = key.map(|key| general_purpose::STANDARD.encode(key));
+        return match request {
+            DownloadRequest::File { metadata_cid, output_file, source, .. } => {
+                download_and_decrypt_file(String::new(), metadata_cid, output_file, encryption_key, source).await
+            }
+            DownloadRequest::Folder { folder_metadata_cid, folder_name, output_dir, source, .. } => {
+                download_and_decrypt_folder(String::new(), folder_metadata_cid, folder_name, output_dir, encryption_key, source, None)
+                    .await
+                    .map(|_| ())
+            }
+            DownloadRequest::PublicFolder { folder_metadata_cid, folder_name, output_dir, source } => {
+                public_download_folder(String::new(), folder_metadata_cid, folder_name, output_dir, source).await
+            }
+        };
+    }
+
+    let ipfs = IpfsClient::from_settings().await;
+    match request {
+        DownloadRequest::File { metadata_cid, output_file, .. } => {
+            let metadata = fetch_file_metadata(&ipfs, &metadata_cid).await?;
+            set_total(id, reconstructed_len(&metadata)).await?;
+            let report = reconstruct_file_resumable(
+                &metadata,
+                Path::new(&output_file),
+                key,
+                &ipfs,
+                checkpoint.unwrap_or_default(),
+                &mut |checkpoint| {
+                    progress.store(checkpoint.bytes_written, Ordering::Relaxed);
+                    *shared_checkpoint.lock().unwrap() = Some(checkpoint.clone());
+                },
+            )
+            .await?;
+            if !report.unavailable_shares.is_empty() {
+                eprintln!(
+                    "[Downloads] Rebuilt {} despite {} unavailable shares",
+                    metadata.original_file.name,
+                    report.unavailable_shares.len()
+                );
+            }
+            Ok(())
+        }
+        DownloadRequest::Folder { folder_metadata_cid, folder_name, output_dir, .. } => {
+            let key = key.map(Arc::new);
+            let manifest = parse_folder_manifest(&ipfs.cat(&folder_metadata_cid).await?, &folder_name)?;
+            set_total(id, manifest.entries.iter().map(|e| e.size).sum()).await?;
+            let report = download_folder_from_ipfs(
+                &ipfs,
+                &folder_metadata_cid,
+                &folder_name,
+                Path::new(&output_dir),
+                key,
+                Some(progress),
+                resumed,
+            )
+            .await?;
+            // A job either finishes or fails; resuming it skips what already landed
+            apply_failure_policy(report, FolderFailurePolicy::Strict).map(|_| ())
+        }
+        DownloadRequest::PublicFolder { folder_metadata_cid, folder_name, output_dir, .. } => {
+            let manifest = parse_folder_manifest(&ipfs.cat(&folder_metadata_cid).await?, &folder_name)?;
+            set_total(id, manifest.entries.iter().map(|e| e.size).sum()).await?;
+            let report = download_public_folder_from_ipfs(
+                &ipfs,
+                &folder_metadata_cid,
+                &folder_name,
+                Path::new(&output_dir),
+                Some(progress),
+                resumed,
+            )
+            .await?;
+            apply_failure_policy(report, FolderFailurePolicy::Strict).map(|_| ())
+        }
+    }
+}
+
+// Stops the job's task if it has one. Returns whether it was running.
+async fn stop_job(id: &str) -> bool {
+    let active = ACTIVE.lock().unwrap().remove(id);
+    match active {
+        Some(job) => {
+            job.task.abort();
+            let checkpoint = job.checkpoint.lock().unwrap().clone();
+            if let Err(e) = save_progress(id, job.progress.load(Ordering::Relaxed), checkpoint.as_ref()).await {
+                eprintln!("[Downloads] {}", e);
+            }
+            // Spawned, as pause and cancel call this holding the pump lock
+            tokio::spawn(pump());
+            true
+        }
+        None => false,
+    }
+}
+
+/// Pauses a queued or running job. A running file keeps its partial data and carries on
+/// from the last finished chunk when resumed.
+pub async fn pause_download(id: &str) -> Result<DownloadJob, String> {
+    // Keeps the pump from starting the job between the check and the update
+    let _guard = PUMP_LOCK.lock().await;
+    let job = get_download(id).await?;
+    let pausable = if job.status == QUEUED {
+        true
+    } else if job.status == RUNNING {
+        stop_job(id).await
+    } else {
+        false
+    };
+    if !pausable {
+        return Err(format!("Download {} is {} and cannot be paused", id, job.status));
+    }
+    set_status(id, PAUSED, None).await?;
+    get_download(id).await
+}
+
+/// Queues a paused or failed job again.
+pub async fn resume_download(id: &str) -> Result<DownloadJob, String> {
+    let job = get_download(id).await?;
+    if job.status != PAUSED && job.status != FAILED {
+        return Err(format!("Download {} is {} and cannot be resumed", id, job.status));
+    }
+    set_status(id, QUEUED, None).await?;
+    pump().await;
+    get_download(id).await
+}
+
+/// Stops a job for good and deletes a file's partial data. Files a folder job already
+/// finished are left in place.
+pub async fn cancel_download(id: &str) -> Result<DownloadJob, String> {
+    let _guard = PUMP_LOCK.lock().await;
+    let job = get_download(id).await?;
+    if job.status == COMPLETED || job.status == CANCELLED {
+        return Err(format!("Download {} is already {}", id, job.status));
+    }
+    stop_job(id).await;
+    if let DownloadRequest::File { output_file, .. } = &job.request {
+        let part = partial_path(Path::new(output_file));
+        if let Err(e) = tokio::fs::remove_file(&part).await {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                eprintln!("[Downloads] Failed to remove {}: {}", part.display(), e);
+            }
+        }
+    }
+    set_status(id, CANCELLED, None).await?;
+    get_download(id).await
+}
+
+/// Forgets completed, failed and cancelled jobs. Returns how many were removed.
+pub async fn clear_finished_downloads() -> Result<u64, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let result = sqlx::query("DELETE FROM download_jobs WHERE status IN (?, ?, ?)")
+        .bind(COMPLETED)
+        .bind(FAILED)
+        .bind(CANCELLED)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to clear downloads: {}", e))?;
+    Ok(result.rows_affected())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sha2::{Digest, Sha256};
+    use crate::commands::types::{EntryKind, ManifestEntry};
+    use crate::utils::ipfs::{save_ipfs_config, IpfsConfig};
+    use crate::utils::manifest::folder_manifest_bytes;
+    use crate::utils::settings::set_setting;
+    use crate::utils::test_db::test_db;
+    use crate::utils::test_http::{mock_ipfs, unixfs_file};
+
+    // A node that takes every request and never answers, so jobs stay running
+    async fn stalled_node() -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                held.push(stream);
+            }
+        });
+        url
+    }
+
+    async fn use_node(api_url: String) {
+        save_ipfs_config(IpfsConfig { api_url, api_headers: HashMap::new(), gateways: Vec::new() })
+            .await
+            .unwrap();
+    }
+
+    async fn wait_for(id: &str, status: &str) -> DownloadJob {
+        for _ in 0..200 {
+            let job = get_download(id).await.unwrap();
+            if job.status == status {
+                return job;
+            }
+            tokio::time::sleep(Duration::from_millis(25)).await;
+        }
+        panic!("download {} never became {}", id, status);
+    }
+
+    // One test, as the queue, its slots and the node it downloads from are all global
+    #[tokio::test]
+    async fn jobs_are_queued_paused_resumed_and_cancelled() {
+        let pool = test_db().await;
+        set_setting(DOWNLOAD_CONCURRENCY, "1").await.unwrap();
+        use_node(stalled_node().await).await;
+        let dir = tempfile::tempdir().unwrap();
+        let metadata_cid = unixfs_file(b"never served").0.to_string_form();
+        let file_request = |name: &str, encryption_key: Option<String>| DownloadRequest::File {
+            metadata_cid: metadata_cid.clone(),
+            output_file: dir.path().join(name).to_string_lossy().to_string(),
+            encryption_key,
+            source: String::new(),
+        };
+
+        // The key is sealed on its own, never saved in the request
+        let key = vec![7u8; 32];
+        let key_b64 = general_purpose::STANDARD.encode(&key);
+        let first = enqueue_download(file_request("first.bin", Some(key_b64.clone()))).await.unwrap();
+        assert_eq!(first.status, RUNNING);
+        let row = sqlx::query("SELECT * FROM download_jobs WHERE id = ?")
+            .bind(&first.id)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        let saved: String = row.get("request");
+        assert!(!saved.contains(&key_b64));
+        assert_eq!(row_to_key(&row).unwrap(), Some(key));
+
+        // The only slot is taken, so the next job waits, and can be paused and resumed there
+        let second = enqueue_download(file_request("second.bin", None)).await.unwrap();
+        assert_eq!(second.status, QUEUED);
+        assert_eq!(pause_download(&second.id).await.unwrap().status, PAUSED);
+        assert!(pause_download(&second.id).await.is_err());
+        assert_eq!(resume_download(&second.id).await.unwrap().status, QUEUED);
+
+        // Cancelling the running job drops its partial data and frees the slot
+        let part = partial_path(&dir.path().join("first.bin"));
+        std::fs::write(&part, b"partial").unwrap();
+        assert_eq!(cancel_download(&first.id).await.unwrap().status, CANCELLED);
+        assert!(!part.exists());
+        assert!(cancel_download(&first.id).await.is_err());
+        assert!(resume_download(&first.id).await.is_err());
+        wait_for(&second.id, RUNNING).await;
+
+        // A running job stops when paused and starts again when resumed
+        assert_eq!(pause_download(&second.id).await.unwrap().status, PAUSED);
+        assert!(!ACTIVE.lock().unwrap().contains_key(&second.id));
+        assert_eq!(resume_download(&second.id).await.unwrap().status, RUNNING);
+        assert_eq!(cancel_download(&second.id).await.unwrap().status, CANCELLED);
+
+        // Public folders come from IPFS too, with their size and progress known
+        let (ipfs, _) = mock_ipfs().await;
+        let data = b"a file in a public folder".to_vec();
+        let entry = ManifestEntry {
+            name: "notes.txt".to_string(),
+            kind: EntryKind::File,
+            cid: ipfs.add("notes.txt", data.clone()).await.unwrap(),
+            size: data.len() as u64,
+            mtime: None,
+            mode: None,
+            content_hash: Some(hex::encode(Sha256::digest(&data))),
+        };
+        let manifest = folder_manifest_bytes("shared", vec![entry]).unwrap();
+        let folder_metadata_cid = ipfs.add("shared.json", manifest).await.unwrap();
+        use_node(ipfs.api_url().to_string()).await;
+        let public = enqueue_download(DownloadRequest::PublicFolder {
+            folder_metadata_cid,
+            folder_name: "shared".to_string(),
+            output_dir: dir.path().to_string_lossy().to_string(),
+            source: String::new(),
+        })
+        .await
+        .unwrap();
+        let public = wait_for(&public.id, COMPLETED).await;
+        assert_eq!(public.bytes_total, Some(data.len() as u64));
+        assert_eq!(public.bytes_done, data.len() as u64);
+        assert_eq!(std::fs::read(dir.path().join("shared").join("notes.txt")).unwrap(), data);
+
+        assert_eq!(clear_finished_downloads().await.unwrap(), 3);
+        assert!(list_downloads().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,1661 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use futures::future::try_join_all;
+use futures::stream::{FuturesUnordered, StreamExt};
+use serde::{Deserialize, Serialize};
+use sodiumoxide::crypto::secretbox;
+use reed_solomon_erasure::galois_8::ReedSolomon;
+use sha2::{Digest, Sha256};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use crate::commands::types::{
+    ChunkInfo, Cipher, CidInfo, Compression, EntryKind, ErasureCodingInfo, ManifestEntry, Metadata,
+    OriginalFileInfo,
+};
+use crate::commands::substrate_tx::account_secret;
+use crate::sync_compat::remote_name_for;
+use crate::sync_metadata::FileMetadata;
+use crate::utils::accounts::{candidate_keys, parse_key_id, resolve_encryption_key};
+use crate::utils::compression::{is_already_compressed, Compressor, Decompressor};
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::key_rotation::replace_references;
+use crate::utils::manifest::folder_manifest_bytes;
+use crate::utils::pins::list_local_pins;
+use crate::utils::settings::{
+    get_bool_setting, get_usize_setting, COMPRESS_UPLOADS, ERASURE_CHUNK_SIZE, ERASURE_K, ERASURE_M,
+};
+use crate::utils::stream_crypto::{
+    key_id, FileDecryptor, LegacySecretboxDecryptor, StreamDecryptor, StreamEncryptor, StreamHeader,
+    DECRYPTION_FAILED, LEGACY_HEADER_BYTES, STREAM_HEADER_BYTES,
+};
+
+pub const DEFAULT_K: usize = 3;
+pub const DEFAULT_M: usize = 5;
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+// GF(2^8) Reed-Solomon supports at most 256 shards in total
+const MAX_SHARDS: usize = 256;
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+const SHARD_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+// A share that failed is tried once more, after every untried share
+const SHARD_FETCH_ATTEMPTS: usize = 2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct ErasureParams {
+    pub k: usize,
+    pub m: usize,
+    pub chunk_size: usize,
+    // Files that are already compressed are stored as they are regardless
+    pub compression: Option<Compression>,
+}
+
+impl ErasureParams {
+    /// Explicit values win, then the saved settings, then the defaults.
+    pub async fn resolve(
+        k: Option<usize>,
+        m: Option<usize>,
+        chunk_size: Option<usize>,
+        compress: Option<bool>,
+    ) -> Result<Self, String> {
+        let params = ErasureParams {
+            k: match k {
+                Some(k) => k,
+                None => get_usize_setting(ERASURE_K, DEFAULT_K).await,
+            },
+            m: match m {
+                Some(m) => m,
+                None => get_usize_setting(ERASURE_M, DEFAULT_M).await,
+            },
+            chunk_size: match chunk_size {
+                Some(size) => size,
+                None => get_usize_setting(ERASURE_CHUNK_SIZE, DEFAULT_CHUNK_SIZE).await,
+            },
+            compression: match compress {
+                Some(compress) => compress,
+                None => get_bool_setting(COMPRESS_UPLOADS, false).await,
+            }
+            .then_some(Compression::Zstd),
+        };
+        params.validate()?;
+        Ok(params)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.k == 0 {
+            return Err("k must be at least 1".to_string());
+        }
+        if self.m <= self.k {
+            return Err(format!("m ({}) must be greater than k ({})", self.m, self.k));
+        }
+        if self.m > MAX_SHARDS {
+            return Err(format!("m ({}) must not exceed {}", self.m, MAX_SHARDS));
+        }
+        if self.chunk_size == 0 {
+            return Err("chunk size must be greater than zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// Groups shard entries by the original chunk they belong to.
+pub fn group_chunks(metadata: &Metadata) -> HashMap<usize, Vec<&ChunkInfo>> {
+    let mut chunk_map: HashMap<usize, Vec<&ChunkInfo>> = HashMap::new();
+    for chunk in &metadata.chunks {
+        chunk_map.entry(chunk.original_chunk).or_default().push(chunk);
+    }
+    chunk_map
+}
+
+/// Number of ciphertext bytes stored in chunk `orig_idx`; every chunk but the last is
+/// exactly `chunk_size` long.
+pub fn chunk_len(metadata: &Metadata, orig_idx: usize, chunk_count: usize) -> usize {
+    let chunk_size = metadata.erasure_coding.chunk_size;
+    if orig_idx + 1 < chunk_count {
+        chunk_size
+    } else {
+        metadata.erasure_coding.encrypted_size.saturating_sub(chunk_size * orig_idx)
+    }
+}
+
+/// Rebuilds the missing data shards and returns the first `chunk_bytes_needed` bytes of
+/// the chunk.
+pub fn decode_chunk(
+    k: usize,
+    m: usize,
+    shards: &mut [Option<Vec<u8>>],
+    chunk_bytes_needed: usize,
+) -> Result<Vec<u8>, String> {
+    let available_count = shards.iter().filter(|s| s.is_some()).count();
+    if available_count < k {
+        return Err(format!("found {} shards, need {}", available_count, k));
+    }
+
+    let r = ReedSolomon::new(k, m - k).map_err(|e| format!("ReedSolomon error: {e}"))?;
+    r.reconstruct_data(shards)
+        .map_err(|e| format!("Reconstruction failed: {e}"))?;
+
+    let mut chunk_data = Vec::with_capacity(chunk_bytes_needed);
+    for shard in shards.iter().take(k).flatten() {
+        let bytes_to_take = std::cmp::min(chunk_bytes_needed - chunk_data.len(), shard.len());
+        chunk_data.extend_from_slice(&shard[..bytes_to_take]);
+        if chunk_data.len() == chunk_bytes_needed {
+            break;
+        }
+    }
+    Ok(chunk_data)
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UnavailableShare {
+    pub original_chunk: usize,
+    pub share_idx: usize,
+    pub cid: String,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, Serialize, Default)]
+pub struct ReconstructReport {
+    pub chunks: usize,
+    pub unavailable_shares: Vec<UnavailableShare>,
+}
+
+async fn fetch_shard(ipfs: &IpfsClient, cid: &str) -> Result<Vec<u8>, String> {
+    match tokio::time::timeout(SHARD_FETCH_TIMEOUT, ipfs.cat(cid)).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("timed out after {}s", SHARD_FETCH_TIMEOUT.as_secs())),
+    }
+}
+
+/// Fetches shards of one chunk concurrently until `k` of them are in hand.
+///
+/// Data shares are tried first since they need no decoding. Up to `k` requests run at
+/// once, each with its own timeout; a failure starts the next candidate and the failed
+/// share is retried once the untried ones are exhausted. Requests still in flight when
+/// `k` shards have arrived are dropped. Shares that never came back are returned next
+/// to the shards, whether or not the chunk can be rebuilt.
+pub async fn fetch_chunk_shards(
+    ipfs: &IpfsClient,
+    k: usize,
+    m: usize,
+    candidates: &[&ChunkInfo],
+) -> (Vec<Option<Vec<u8>>>, Vec<UnavailableShare>) {
+    let mut shards: Vec<Option<Vec<u8>>> = vec![None; m];
+    let mut failures: HashMap<usize, (usize, UnavailableShare)> = HashMap::new();
+    let mut queue: VecDeque<&ChunkInfo> = {
+        let mut sorted: Vec<&ChunkInfo> = candidates
+            .iter()
+            .copied()
+            .filter(|c| c.share_idx < m)
+            .collect();
+        sorted.sort_by_key(|c| c.share_idx);
+        sorted.dedup_by_key(|c| c.share_idx);
+        sorted.into()
+    };
+
+    let fetch = |chunk: &ChunkInfo| {
+        let cid = chunk.cid.cid.clone();
+        let share_idx = chunk.share_idx;
+        async move { (share_idx, fetch_shard(ipfs, &cid).await) }
+    };
+
+    let mut in_flight = FuturesUnordered::new();
+    let mut received = 0usize;
+    while received < k {
+        while in_flight.len() < k - received {
+            match queue.pop_front() {
+                Some(chunk) => in_flight.push(fetch(chunk)),
+                None => break,
+            }
+        }
+        let (share_idx, result) = match in_flight.next().await {
+            Some(done) => done,
+            None => break,
+        };
+        match result {
+            Ok(data) => {
+                shards[share_idx] = Some(data);
+                failures.remove(&share_idx);
+                received += 1;
+            }
+            Err(reason) => {
+                let chunk = candidates
+                    .iter()
+                    .copied()
+                    .find(|c| c.share_idx == share_idx)
+                    .expect("fetched share comes from candidates");
+                let attempts = failures.get(&share_idx).map(|(n, _)| *n).unwrap_or(0) + 1;
+                if attempts < SHARD_FETCH_ATTEMPTS {
+                    queue.push_back(chunk);
+                }
+                failures.insert(
+                    share_idx,
+                    (
+                        attempts,
+                        UnavailableShare {
+                            original_chunk: chunk.original_chunk,
+                            share_idx,
+                            cid: chunk.cid.cid.clone(),
+                            reason,
+                        },
+                    ),
+                );
+            }
+        }
+    }
+
+    let mut unavailable: Vec<UnavailableShare> = failures.into_values().map(|(_, u)| u).collect();
+    unavailable.sort_by_key(|u| u.share_idx);
+    (shards, unavailable)
+}
+
+/// How far a reconstruction got: chunks fully written, the plaintext bytes they hold
+/// and the stream header decryption needs to pick up again. `reconstruct_file_resumable`
+/// carries on from one of these.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ReconstructCheckpoint {
+    pub chunks_done: usize,
+    pub bytes_written: u64,
+    pub header: Vec<u8>,
+}
+
+/// Told about every chunk once it has been written and flushed.
+pub type ChunkObserver<'a> = dyn FnMut(&ReconstructCheckpoint) + Send + 'a;
+
+// Fetches, decodes and decrypts the chunks from `checkpoint.chunks_done` on into
+// `writer`, advancing `checkpoint` as each one is flushed, and verifies the end of the
+// stream. With a `decompressor` the decrypted bytes are expanded before they are hashed
+// and written.
+#[allow(clippy::too_many_arguments)]
+async fn reconstruct_chunks<W: AsyncWrite + Unpin>(
+    metadata: &Metadata,
+    ipfs: &IpfsClient,
+    decryptor: FileDecryptor,
+    mut decompressor: Option<Decompressor>,
+    hasher: &mut Sha256,
+    writer: &mut W,
+    checkpoint: &mut ReconstructCheckpoint,
+    report: &mut ReconstructReport,
+    on_chunk: &mut ChunkObserver<'_>,
+) -> Result<(), String> {
+    let k = metadata.erasure_coding.k;
+    let m = metadata.erasure_coding.m;
+    let chunk_map = group_chunks(metadata);
+    report.chunks = chunk_map.len();
+    let mut decryptor = Some(decryptor);
+
+    for orig_idx in checkpoint.chunks_done..chunk_map.len() {
+        let available_chunks = chunk_map.get(&orig_idx).ok_or("Missing chunk info")?;
+        let (mut shards, unavailable) = fetch_chunk_shards(ipfs, k, m, available_chunks).await;
+        for share in &unavailable {
+            eprintln!(
+                "[erasure] Share {} of chunk {} ({}) unavailable: {}",
+                share.share_idx, orig_idx, share.cid, share.reason
+            );
+        }
+
+        let chunk_bytes_needed = chunk_len(metadata, orig_idx, chunk_map.len());
+        let chunk_data = decode_chunk(k, m, &mut shards, chunk_bytes_needed)
+            .map_err(|e| {
+                let missing: Vec<String> = unavailable
+                    .iter()
+                    .map(|u| format!("share {} ({}): {}", u.share_idx, u.cid, u.reason))
+                    .collect();
+                format!(
+                    "Chunk {} of '{}': {}. Unavailable: [{}]",
+                    orig_idx,
+                    metadata.original_file.name,
+                    e,
+                    missing.join("; ")
+                )
+            })?;
+        drop(shards);
+        report.unavailable_shares.extend(unavailable);
+
+        let active = decryptor.as_mut().ok_or("Decryptor already finished")?;
+        let mut decrypted = active.update(&chunk_data)?;
+        if checkpoint.header.is_empty() {
+            if let Some(header) = active.header() {
+                checkpoint.header = header.to_vec();
+            }
+        }
+        if orig_idx + 1 == chunk_map.len() {
+            let last = decryptor.take().ok_or("Decryptor already finished")?;
+            decrypted.extend(last.finish()?);
+        }
+        let written = write_plaintext(decrypted, decompressor.as_mut(), hasher, writer).await?;
+
+        checkpoint.chunks_done = orig_idx + 1;
+        checkpoint.bytes_written += written;
+        on_chunk(checkpoint);
+    }
+    // Resumed with every chunk already fetched
+    if let Some(decryptor) = decryptor {
+        write_plaintext(decryptor.finish()?, decompressor.as_mut(), hasher, writer).await?;
+    }
+    Ok(())
+}
+
+async fn write_plaintext<W: AsyncWrite + Unpin>(
+    decrypted: Vec<u8>,
+    decompressor: Option<&mut Decompressor>,
+    hasher: &mut Sha256,
+    writer: &mut W,
+) -> Result<u64, String> {
+    let plaintext = match decompressor {
+        Some(decompressor) => decompressor.update(&decrypted)?,
+        None => decrypted,
+    };
+    hasher.update(&plaintext);
+    writer
+        .write_all(&plaintext)
+        .await
+        .map_err(|e| format!("Failed to write output file: {}", e))?;
+    writer
+        .flush()
+        .await
+        .map_err(|e| format!("Failed to write output file: {}", e))?;
+    Ok(plaintext.len() as u64)
+}
+
+fn new_decryptor(metadata: &Metadata, key: &secretbox::Key) -> FileDecryptor {
+    match metadata.erasure_coding.cipher {
+        Some(Cipher::XChaCha20Poly1305StreamV1) => FileDecryptor::Stream(StreamDecryptor::new(key)),
+        None => FileDecryptor::Legacy(Box::new(LegacySecretboxDecryptor::new(key))),
+    }
+}
+
+// Rebuilds the decryptor a saved checkpoint needs, with the checkpoint to carry on
+// from, or None when it cannot be used. A stream restarts at the first chunk it had not
+// fully written, refetching the erasure chunk holding its start, since the ciphertext
+// held back in memory at the time is gone; legacy files are replayed instead.
+fn resume_decryptor(
+    metadata: &Metadata,
+    key: &secretbox::Key,
+    checkpoint: &ReconstructCheckpoint,
+    on_disk: u64,
+) -> Result<Option<(FileDecryptor, ReconstructCheckpoint)>, String> {
+    if checkpoint.chunks_done == 0 || on_disk < checkpoint.bytes_written {
+        return Ok(None);
+    }
+    match metadata.erasure_coding.cipher {
+        None => {
+            if checkpoint.header.len() != LEGACY_HEADER_BYTES {
+                return Ok(None);
+            }
+            let mut decryptor = LegacySecretboxDecryptor::new(key);
+            decryptor.resume(&checkpoint.header)?;
+            Ok(Some((FileDecryptor::Legacy(Box::new(decryptor)), checkpoint.clone())))
+        }
+        Some(Cipher::XChaCha20Poly1305StreamV1) => {
+            if checkpoint.header.len() != STREAM_HEADER_BYTES {
+                return Ok(None);
+            }
+            let header = StreamHeader::parse(&checkpoint.header)?;
+            let last_index = header.chunk_count(metadata.erasure_coding.encrypted_size as u64) - 1;
+            let index = (checkpoint.bytes_written / header.chunk_size as u64).min(last_index);
+            let offset = header.chunk_offset(index);
+            let erasure_chunk_size = metadata.erasure_coding.chunk_size as u64;
+            let decryptor = StreamDecryptor::resume_at(
+                key,
+                &checkpoint.header,
+                index,
+                (offset % erasure_chunk_size) as usize,
+            )?;
+            let resumed = ReconstructCheckpoint {
+                chunks_done: (offset / erasure_chunk_size) as usize,
+                bytes_written: index * header.chunk_size as u64,
+                header: checkpoint.header.clone(),
+            };
+            Ok(Some((FileDecryptor::Stream(decryptor), resumed)))
+        }
+    }
+}
+
+// Keys to try for `metadata`: the one given, else the one its metadata or a saved
+// stream header names, else every key for files that name none
+async fn keys_for(
+    metadata: &Metadata,
+    encryption_key: Option<Vec<u8>>,
+    header: &[u8],
+) -> Result<Vec<secretbox::Key>, String> {
+    let id = match &metadata.erasure_coding.key_id {
+        Some(id) => Some(parse_key_id(id)?),
+        None => StreamHeader::parse(header).ok().map(|header| header.key_id),
+    };
+    candidate_keys(encryption_key, id.as_ref().map(|id| &id[..])).await
+}
+
+// Whether attempt `i` of `of` failed to decrypt and another key is left to try
+fn is_wrong_key<T>(result: &Result<T, String>, i: usize, of: usize) -> bool {
+    match result {
+        Err(e) if e.starts_with(DECRYPTION_FAILED) && i + 1 < of => {
+            println!("[erasure] Key {} of {} did not fit, trying the next", i + 1, of);
+            true
+        }
+        _ => false,
+    }
+}
+
+fn verify_hash(metadata: &Metadata, hasher: Sha256) -> Result<(), String> {
+    let actual_hash = format!("{:x}", hasher.finalize());
+    if actual_hash != metadata.original_file.hash {
+        return Err(format!(
+            "Hash mismatch for {}: expected {}, got {}",
+            metadata.original_file.name, metadata.original_file.hash, actual_hash
+        ));
+    }
+    Ok(())
+}
+
+async fn create_parent_dir(output_path: &Path) -> Result<&Path, String> {
+    let parent = output_path
+        .parent()
+        .filter(|p| !p.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+    tokio::fs::create_dir_all(parent)
+        .await
+        .map_err(|e| format!("Failed to create directory {}: {}", parent.display(), e))?;
+    Ok(parent)
+}
+
+fn check_has_chunks(metadata: &Metadata) -> Result<(), String> {
+    if metadata.chunks.is_empty() {
+        return Err(format!(
+            "Cannot reconstruct file '{}': metadata contains no chunk information.",
+            metadata.original_file.name
+        ));
+    }
+    Ok(())
+}
+
+/// Reconstructs an erasure-coded, encrypted file one chunk at a time.
+///
+/// Each chunk is decoded, decrypted and hashed as soon as its shards arrive and written
+/// to a temp file next to `output_path`, so memory stays bounded by the chunk size. The
+/// temp file only replaces `output_path` once the MAC and the SHA-256 both check out.
+/// The report lists shares that could not be fetched even though the file was rebuilt.
+///
+/// Without `encryption_key` the stored key the metadata names is used; files that name
+/// none are tried with every stored key, newest first.
+pub async fn reconstruct_file_streaming(
+    metadata: &Metadata,
+    output_path: &Path,
+    encryption_key: Option<Vec<u8>>,
+    ipfs: &IpfsClient,
+) -> Result<ReconstructReport, String> {
+    reconstruct_file_observed(metadata, output_path, encryption_key, ipfs, &mut |_| {}).await
+}
+
+/// `reconstruct_file_streaming`, calling `on_chunk` after each chunk is written.
+pub async fn reconstruct_file_observed(
+    metadata: &Metadata,
+    output_path: &Path,
+    encryption_key: Option<Vec<u8>>,
+    ipfs: &IpfsClient,
+    on_chunk: &mut ChunkObserver<'_>,
+) -> Result<ReconstructReport, String> {
+    check_has_chunks(metadata)?;
+    let keys = keys_for(metadata, encryption_key, &[]).await?;
+    let mut result = Err(DECRYPTION_FAILED.to_string());
+    for (i, key) in keys.iter().enumerate() {
+        result = reconstruct_observed_with_key(metadata, output_path, key, ipfs, on_chunk).await;
+        if !is_wrong_key(&result, i, keys.len()) {
+            break;
+        }
+    }
+    result
+}
+
+async fn reconstruct_observed_with_key(
+    metadata: &Metadata,
+    output_path: &Path,
+    key: &secretbox::Key,
+    ipfs: &IpfsClient,
+    on_chunk: &mut ChunkObserver<'_>,
+) -> Result<ReconstructReport, String> {
+    let parent = create_parent_dir(output_path).await?;
+    let temp_file = tempfile::NamedTempFile::new_in(parent)
+        .map_err(|e| format!("Failed to create temp file: {}", e))?;
+    let std_file = temp_file
+        .as_file()
+        .try_clone()
+        .map_err(|e| format!("Failed to open temp file: {}", e))?;
+    let mut writer = tokio::io::BufWriter::new(tokio::fs::File::from_std(std_file));
+
+    let mut hasher = Sha256::new();
+    let mut checkpoint = ReconstructCheckpoint::default();
+    let mut report = ReconstructReport::default();
+    let decompressor = metadata
+        .erasure_coding
+        .compression
+        .map(|compression| Decompressor::new(compression, metadata.original_file.size as u64))
+        .transpose()?;
+    reconstruct_chunks(
+        metadata,
+        ipfs,
+        new_decryptor(metadata, key),
+        decompressor,
+        &mut hasher,
+        &mut writer,
+        &mut checkpoint,
+        &mut report,
+        on_chunk,
+    )
+    .await?;
+    drop(writer);
+    verify_hash(metadata, hasher)?;
+
+    temp_file
+        .persist(output_path)
+        .map_err(|e| format!("Failed to move file into place: {}", e))?;
+    println!(
+        "File written to {} with size {}, expected original size: {}",
+        output_path.display(),
+        checkpoint.bytes_written,
+        metadata.original_file.size
+    );
+    Ok(report)
+}
+
+/// Where `reconstruct_file_resumable` keeps the plaintext until it is verified.
+pub fn partial_path(output_path: &Path) -> PathBuf {
+    let mut name = output_path.as_os_str().to_owned();
+    name.push(".part");
+    PathBuf::from(name)
+}
+
+/// Bytes `reconstruct_file_resumable` has written once it is done, for progress: the
+/// file size, or the size of the compressed stream for compressed files.
+pub fn reconstructed_len(metadata: &Metadata) -> u64 {
+    let coding = &metadata.erasure_coding;
+    match (coding.compression, coding.compressed_size) {
+        (None, _) => metadata.original_file.size as u64,
+        (Some(_), Some(size)) => size as u64,
+        // Compressed before the size was recorded, which means legacy secretbox
+        (Some(_), None) => coding.encrypted_size.saturating_sub(LEGACY_HEADER_BYTES) as u64,
+    }
+}
+
+/// Like `reconstruct_file_streaming`, but writes to `partial_path(output_path)` and can
+/// carry on from a saved `checkpoint`, e.g. after the app was closed mid-download.
+/// The partial file is cut back to the checkpoint and replayed through the hash (and
+/// the MAC, for legacy files), so only the remaining chunks are fetched. A missing or
+/// short partial file starts over. Verification failures delete it, since its contents
+/// cannot be trusted.
+///
+/// For compressed files the partial file holds the decrypted but still compressed
+/// stream, which is what a resume continues; it is expanded once decryption completes.
+pub async fn reconstruct_file_resumable(
+    metadata: &Metadata,
+    output_path: &Path,
+    encryption_key: Option<Vec<u8>>,
+    ipfs: &IpfsClient,
+    checkpoint: ReconstructCheckpoint,
+    on_chunk: &mut ChunkObserver<'_>,
+) -> Result<ReconstructReport, String> {
+    check_has_chunks(metadata)?;
+    let keys = keys_for(metadata, encryption_key, &checkpoint.header).await?;
+    // A wrong key deletes the partial file, so later keys start over
+    let mut checkpoint = Some(checkpoint);
+    let mut result = Err(DECRYPTION_FAILED.to_string());
+    for (i, key) in keys.iter().enumerate() {
+        let from = checkpoint.take().unwrap_or_default();
+        result = reconstruct_resumable_with_key(metadata, output_path, key, ipfs, from, on_chunk).await;
+        if !is_wrong_key(&result, i, keys.len()) {
+            break;
+        }
+    }
+    result
+}
+
+async fn reconstruct_resumable_with_key(
+    metadata: &Metadata,
+    output_path: &Path,
+    key: &secretbox::Key,
+    ipfs: &IpfsClient,
+    mut checkpoint: ReconstructCheckpoint,
+    on_chunk: &mut ChunkObserver<'_>,
+) -> Result<ReconstructReport, String> {
+    let parent = create_parent_dir(output_path).await?;
+    let part_path = partial_path(output_path);
+
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .read(true)
+        .write(true)
+        .open(&part_path)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", part_path.display(), e))?;
+    let on_disk = file
+        .metadata()
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", part_path.display(), e))?
+        .len();
+
+    let mut hasher = Sha256::new();
+    let decryptor = match resume_decryptor(metadata, key, &checkpoint, on_disk)? {
+        Some((mut decryptor, resumed)) => {
+            checkpoint = resumed;
+            let mut remaining = checkpoint.bytes_written;
+            let mut buf = vec![0u8; READ_BUFFER_SIZE];
+            while remaining > 0 {
+                let want = remaining.min(buf.len() as u64) as usize;
+                file.read_exact(&mut buf[..want])
+                    .await
+                    .map_err(|e| format!("Failed to read {}: {}", part_path.display(), e))?;
+                hasher.update(&buf[..want]);
+                decryptor.replay(&buf[..want])?;
+                remaining -= want as u64;
+            }
+            println!(
+                "[erasure] Resuming {} at chunk {} ({} bytes already written)",
+                metadata.original_file.name, checkpoint.chunks_done, checkpoint.bytes_written
+            );
+            decryptor
+        }
+        None => {
+            checkpoint = ReconstructCheckpoint::default();
+            new_decryptor(metadata, key)
+        }
+    };
+    file.set_len(checkpoint.bytes_written)
+        .await
+        .map_err(|e| format!("Failed to truncate {}: {}", part_path.display(), e))?;
+    file.seek(std::io::SeekFrom::Start(checkpoint.bytes_written))
+        .await
+        .map_err(|e| format!("Failed to seek in {}: {}", part_path.display(), e))?;
+
+    let mut writer = tokio::io::BufWriter::new(file);
+    let mut report = ReconstructReport::default();
+    let reconstructed = reconstruct_chunks(
+        metadata,
+        ipfs,
+        decryptor,
+        None,
+        &mut hasher,
+        &mut writer,
+        &mut checkpoint,
+        &mut report,
+        on_chunk,
+    )
+    .await;
+    drop(writer);
+    // A failed tag or hash means the partial data cannot be trusted
+    let verified = match (reconstructed, metadata.erasure_coding.compression) {
+        (Err(e), _) if e.starts_with(DECRYPTION_FAILED) => Err(e),
+        (Err(e), _) => return Err(e),
+        (Ok(()), None) => verify_hash(metadata, hasher).map(|_| None),
+        (Ok(()), Some(compression)) => expand_partial(metadata, compression, &part_path, parent).await.map(Some),
+    };
+    let expanded = match verified {
+        Ok(expanded) => expanded,
+        Err(e) => {
+            let _ = tokio::fs::remove_file(&part_path).await;
+            return Err(e);
+        }
+    };
+
+    match expanded {
+        Some(temp_file) => {
+            temp_file
+                .persist(output_path)
+                .map_err(|e| format!("Failed to move file into place: {}", e))?;
+            let _ = tokio::fs::remove_file(&part_path).await;
+        }
+        None => tokio::fs::rename(&part_path, output_path)
+            .await
+            .map_err(|e| format!("Failed to move file into place: {}", e))?,
+    }
+    println!(
+        "File written to {} with size {}, expected original size: {}",
+        output_path.display(),
+        checkpoint.bytes_written,
+        metadata.original_file.size
+    );
+    Ok(report)
+}
+
+// Decompresses a verified partial file into a temp file in `dir`, checking the hash of
+// what comes out
+async fn expand_partial(
+    metadata: &Metadata,
+    compression: Compression,
+    part_path: &Path,
+    dir: &Path,
+) -> Result<tempfile::NamedTempFile, String> {
+    let temp_file = tempfile::NamedTempFile::new_in(dir)
+        .map_err(|e| format!("Failed to create temp file: {}", e))?;
+    let std_file = temp_file
+        .as_file()
+        .try_clone()
+        .map_err(|e| format!("Failed to open temp file: {}", e))?;
+    let mut writer = tokio::io::BufWriter::new(tokio::fs::File::from_std(std_file));
+    let mut reader = tokio::fs::File::open(part_path)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", part_path.display(), e))?;
+    let mut decompressor = Decompressor::new(compression, metadata.original_file.size as u64)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        let n = reader
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", part_path.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        let plaintext = decompressor.update(&buf[..n])?;
+        hasher.update(&plaintext);
+        writer
+            .write_all(&plaintext)
+            .await
+            .map_err(|e| format!("Failed to write output file: {}", e))?;
+    }
+    writer
+        .flush()
+        .await
+        .map_err(|e| format!("Failed to write output file: {}", e))?;
+    verify_hash(metadata, hasher)?;
+    Ok(temp_file)
+}
+
+/// Splits a chunk into `k` zero-padded data shards and adds `m - k` parity shards.
+/// `decode_chunk` drops the padding again using the chunk length from the metadata.
+pub fn encode_chunk(k: usize, m: usize, chunk: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let shard_len = chunk.len().div_ceil(k).max(1);
+    let mut shards: Vec<Vec<u8>> = (0..m)
+        .map(|i| {
+            let start = (i * shard_len).min(chunk.len());
+            let end = ((i + 1) * shard_len).min(chunk.len());
+            let mut shard = if i < k { chunk[start..end].to_vec() } else { Vec::new() };
+            shard.resize(shard_len, 0);
+            shard
+        })
+        .collect();
+
+    let r = ReedSolomon::new(k, m - k).map_err(|e| format!("ReedSolomon error: {e}"))?;
+    r.encode(&mut shards)
+        .map_err(|e| format!("Encoding failed: {e}"))?;
+    Ok(shards)
+}
+
+fn format_size(bytes: usize) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.2} {}", size, UNITS[unit])
+    }
+}
+
+// Fills `buf` as far as the reader allows; returns fewer bytes only at EOF.
+async fn read_full<R: AsyncReadExt + Unpin>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..]).await?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// Encrypts `file_path`, erasure codes it chunk by chunk, adds every shard to IPFS and
+/// publishes the metadata JSON. Returns the metadata and its CID.
+///
+/// The ciphertext is in `stream_crypto`'s chunked format, staged in a temp file so memory
+/// stays bounded by the chunk size. With
+/// `params.compression` the plaintext is compressed first unless it looks compressed
+/// already; the metadata records which.
+pub async fn encrypt_and_encode_file(
+    file_path: &Path,
+    params: ErasureParams,
+    encryption_key: Option<Vec<u8>>,
+    ipfs: &IpfsClient,
+) -> Result<(Metadata, String), String> {
+    params.validate()?;
+    let key = resolve_encryption_key(encryption_key).await?;
+    let file_name = file_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("Invalid file name: {}", file_path.display()))?
+        .to_string();
+    let extension = file_path
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or_default()
+        .to_string();
+
+    // Pass 1: encrypt into a temp file, leaving room for the header
+    let temp_file = tempfile::NamedTempFile::new()
+        .map_err(|e| format!("Failed to create temp file: {}", e))?;
+    let std_file = temp_file
+        .as_file()
+        .try_clone()
+        .map_err(|e| format!("Failed to open temp file: {}", e))?;
+    let mut writer = tokio::io::BufWriter::new(tokio::fs::File::from_std(std_file));
+
+    let mut source = tokio::fs::File::open(file_path)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", file_path.display(), e))?;
+    let mut head = [0u8; 16];
+    let head_len = read_full(&mut source, &mut head)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", file_path.display(), e))?;
+    source
+        .seek(std::io::SeekFrom::Start(0))
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", file_path.display(), e))?;
+    let compression = params
+        .compression
+        .filter(|_| !is_already_compressed(file_path, &head[..head_len]));
+    let mut compressor = compression.map(Compressor::new).transpose()?;
+
+    let mut encryptor = StreamEncryptor::new(&key);
+    let header = encryptor.header();
+    writer
+        .write_all(&header)
+        .await
+        .map_err(|e| format!("Failed to write temp file: {}", e))?;
+    let mut encrypted_size = header.len();
+    let mut hasher = Sha256::new();
+    let mut original_size = 0usize;
+    let mut stored_size = 0usize;
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        let n = source
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", file_path.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        original_size += n;
+        let data = match compressor.as_mut() {
+            Some(compressor) => compressor.update(&buf[..n])?,
+            None => buf[..n].to_vec(),
+        };
+        stored_size += data.len();
+        let sealed = encryptor.update(&data);
+        encrypted_size += sealed.len();
+        writer
+            .write_all(&sealed)
+            .await
+            .map_err(|e| format!("Failed to write temp file: {}", e))?;
+    }
+    let mut tail = Vec::new();
+    if let Some(compressor) = compressor {
+        let rest = compressor.finish()?;
+        stored_size += rest.len();
+        tail = encryptor.update(&rest);
+    }
+    tail.extend(encryptor.finish());
+    encrypted_size += tail.len();
+    writer
+        .write_all(&tail)
+        .await
+        .map_err(|e| format!("Failed to write temp file: {}", e))?;
+    writer
+        .flush()
+        .await
+        .map_err(|e| format!("Failed to write temp file: {}", e))?;
+    drop(writer);
+
+    // Pass 2: erasure code each chunk and add the shards
+    let file_id = uuid::Uuid::new_v4().to_string();
+    let mut reader = tokio::fs::File::open(temp_file.path())
+        .await
+        .map_err(|e| format!("Failed to open temp file: {}", e))?;
+    let mut chunk = vec![0u8; params.chunk_size];
+    let mut chunks = Vec::new();
+    let mut orig_idx = 0usize;
+    loop {
+        let n = read_full(&mut reader, &mut chunk)
+            .await
+            .map_err(|e| format!("Failed to read temp file: {}", e))?;
+        if n == 0 {
+            break;
+        }
+        let shards = encode_chunk(params.k, params.m, &chunk[..n])?;
+        let uploads = shards.into_iter().enumerate().map(|(share_idx, shard)| {
+            let name = format!("{}_chunk_{}_{}.ec", file_id, orig_idx, share_idx);
+            let size = shard.len();
+            async move {
+                let cid = ipfs.add(&name, shard).await?;
+                Ok::<ChunkInfo, String>(ChunkInfo {
+                    name: name.clone(),
+                    path: String::new(),
+                    original_chunk: orig_idx,
+                    share_idx,
+                    size,
+                    cid: CidInfo {
+                        cid,
+                        filename: name,
+                        size_bytes: size,
+                        encrypted: true,
+                        size_formatted: format_size(size),
+                    },
+                })
+            }
+        });
+        chunks.extend(try_join_all(uploads).await?);
+        orig_idx += 1;
+        if n < params.chunk_size {
+            break;
+        }
+    }
+
+    let mut metadata = Metadata {
+        original_file: OriginalFileInfo {
+            name: file_name.clone(),
+            size: original_size,
+            hash: format!("{:x}", hasher.finalize()),
+            extension,
+        },
+        erasure_coding: ErasureCodingInfo {
+            k: params.k,
+            m: params.m,
+            chunk_size: params.chunk_size,
+            encrypted: true,
+            file_id,
+            encrypted_size,
+            compressed_size: compression.map(|_| stored_size),
+            compression,
+            cipher: Some(Cipher::XChaCha20Poly1305StreamV1),
+            key_id: Some(hex::encode(key_id(&key))),
+        },
+        chunks,
+        metadata_cid: None,
+    };
+
+    let metadata_bytes = serde_json::to_vec(&metadata)
+        .map_err(|e| format!("Failed to serialize metadata: {}", e))?;
+    let metadata_cid =
+        ipfs.add(&format!("{}.ec_metadata", file_name), metadata_bytes).await?;
+    println!(
+        "[erasure] Uploaded {} ({} chunks, k={}, m={}, {} of {} bytes stored) with metadata CID {}",
+        file_name, orig_idx, params.k, params.m, stored_size, original_size, metadata_cid
+    );
+    metadata.metadata_cid = Some(metadata_cid.clone());
+    Ok((metadata, metadata_cid))
+}
+
+/// Uploads every file below `folder_path` with `encrypt_and_encode_file` and publishes
+/// a v2 folder manifest per directory, in the layout `download_and_decrypt_folder`
+/// walks. Returns the CID of the top-level manifest.
+pub async fn encrypt_and_encode_folder(
+    folder_path: &Path,
+    params: ErasureParams,
+    encryption_key: Option<Vec<u8>>,
+    ipfs: &IpfsClient,
+) -> Result<String, String> {
+    encode_folder_tree(folder_path, params, encryption_key, ipfs)
+        .await
+        .map(|(cid, _)| cid)
+}
+
+// Returns the manifest CID and the total size of the files below, which the parent
+// records on the subfolder's entry.
+async fn encode_folder_tree(
+    folder_path: &Path,
+    params: ErasureParams,
+    encryption_key: Option<Vec<u8>>,
+    ipfs: &IpfsClient,
+) -> Result<(String, u64), String> {
+    let folder_name = folder_path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .ok_or_else(|| format!("Invalid folder name: {}", folder_path.display()))?
+        .to_string();
+
+    let mut dir_entries = Vec::new();
+    let mut read_dir = tokio::fs::read_dir(folder_path)
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", folder_path.display(), e))?;
+    while let Some(entry) = read_dir
+        .next_entry()
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", folder_path.display(), e))?
+    {
+        dir_entries.push(entry.path());
+    }
+    dir_entries.sort();
+
+    let mut entries = Vec::with_capacity(dir_entries.len());
+    let mut total_size = 0u64;
+    for path in dir_entries {
+        let name = match path.file_name().and_then(|n| n.to_str()) {
+            Some(name) => remote_name_for(name).await,
+            None => continue,
+        };
+        let file_metadata = FileMetadata::read_from(&path, false).ok();
+        if path.is_dir() {
+            let (cid, size) = Box::pin(encode_folder_tree(
+                &path,
+                params,
+                encryption_key.clone(),
+                ipfs,
+            ))
+            .await?;
+            total_size += size;
+            entries.push(ManifestEntry {
+                name,
+                kind: EntryKind::Folder,
+                cid,
+                size,
+                mtime: file_metadata.as_ref().and_then(|m| m.mtime),
+                mode: file_metadata.and_then(|m| m.mode),
+                content_hash: None,
+            });
+        } else if path.is_file() {
+            let (metadata, cid) =
+                encrypt_and_encode_file(&path, params, encryption_key.clone(), ipfs).await?;
+            total_size += metadata.original_file.size as u64;
+            entries.push(ManifestEntry {
+                name,
+                kind: EntryKind::File,
+                cid,
+                size: metadata.original_file.size as u64,
+                mtime: file_metadata.as_ref().and_then(|m| m.mtime),
+                mode: file_metadata.and_then(|m| m.mode),
+                content_hash: Some(metadata.original_file.hash),
+            });
+        }
+    }
+
+    let manifest_bytes = folder_manifest_bytes(&folder_name, entries)?;
+    let cid = ipfs
+        .add(&format!("{}.s.folder.ec_metadata", folder_name), manifest_bytes)
+        .await?;
+    Ok((cid, total_size))
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ChunkHealth {
+    pub original_chunk: usize,
+    pub available_shares: usize,
+    pub total_shares: usize,
+    // How many more shares can be lost before the chunk is unrecoverable; negative
+    // when it already is
+    pub tolerates: i64,
+    pub missing: Vec<UnavailableShare>,
+    pub repaired_shares: Vec<usize>,
+    pub summary: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub metadata_cid: String,
+    pub file_name: String,
+    pub k: usize,
+    pub m: usize,
+    pub chunks: Vec<ChunkHealth>,
+    pub healthy: bool,
+    pub recoverable: bool,
+    // Set when repair published new metadata
+    pub new_metadata_cid: Option<String>,
+}
+
+// Rebuilds chunk `orig_idx` from `k` of the `present` shares and adds the `missing`
+// ones to IPFS again, replacing their entries in `metadata`. Returns the repaired shares.
+async fn repair_chunk(
+    ipfs: &IpfsClient,
+    metadata: &mut Metadata,
+    orig_idx: usize,
+    present: &[ChunkInfo],
+    missing: &[UnavailableShare],
+) -> Result<Vec<usize>, String> {
+    let (k, m) = (metadata.erasure_coding.k, metadata.erasure_coding.m);
+    let candidates: Vec<&ChunkInfo> = present.iter().collect();
+    let (mut shards, _) = fetch_chunk_shards(ipfs, k, m, &candidates).await;
+    let fetched = shards.iter().filter(|s| s.is_some()).count();
+    if fetched < k {
+        return Err(format!("only {} of the {} shares needed could be fetched", fetched, k));
+    }
+    let r = ReedSolomon::new(k, m - k).map_err(|e| format!("ReedSolomon error: {e}"))?;
+    r.reconstruct(&mut shards)
+        .map_err(|e| format!("Reconstruction of chunk {} failed: {e}", orig_idx))?;
+
+    let mut repaired = Vec::with_capacity(missing.len());
+    for share in missing {
+        let shard = shards[share.share_idx]
+            .clone()
+            .ok_or_else(|| format!("Share {} of chunk {} was not rebuilt", share.share_idx, orig_idx))?;
+        let name = format!(
+            "{}_chunk_{}_{}.ec",
+            metadata.erasure_coding.file_id, orig_idx, share.share_idx
+        );
+        let size = shard.len();
+        let cid = ipfs.add(&name, shard).await?;
+        let info = ChunkInfo {
+            name: name.clone(),
+            path: String::new(),
+            original_chunk: orig_idx,
+            share_idx: share.share_idx,
+            size,
+            cid: CidInfo {
+                cid,
+                filename: name,
+                size_bytes: size,
+                encrypted: metadata.erasure_coding.encrypted,
+                size_formatted: format_size(size),
+            },
+        };
+        metadata
+            .chunks
+            .retain(|c| !(c.original_chunk == orig_idx && c.share_idx == share.share_idx));
+        metadata.chunks.push(info);
+        repaired.push(share.share_idx);
+    }
+    Ok(repaired)
+}
+
+/// Checks that every share of every chunk listed in the metadata at `metadata_cid` can
+/// still be found and reports the redundancy left per chunk. Shares pinned on this node
+/// count as present; for the rest the node looks up the shard's root block (`block stat`),
+/// asking the network if it lacks it, so nothing is downloaded in full.
+///
+/// With `repair`, chunks that lost shares but still have `k` are rebuilt from `k` fetched
+/// shards, the missing shares are added to IPFS again and updated metadata is published.
+/// Chunks are handled one at a time so memory stays bounded by the chunk size.
+pub async fn check_erasure_health(
+    ipfs: &IpfsClient,
+    metadata_cid: &str,
+    repair: bool,
+) -> Result<HealthReport, String> {
+    let metadata_bytes = ipfs
+        .cat(metadata_cid)
+        .await
+        .map_err(|e| format!("Failed to download metadata: {}", e))?;
+    let mut metadata: Metadata = serde_json::from_slice(&metadata_bytes)
+        .map_err(|e| format!("Failed to parse file metadata: {}", e))?;
+
+    let k = metadata.erasure_coding.k;
+    let m = metadata.erasure_coding.m;
+    ErasureParams { k, m, chunk_size: metadata.erasure_coding.chunk_size, compression: None }.validate()?;
+    let chunk_count = group_chunks(&metadata).len();
+
+    let pinned: HashSet<String> = match list_local_pins(ipfs, Some("recursive")).await {
+        Ok(pins) => pins.into_iter().map(|p| p.cid).collect(),
+        Err(e) => {
+            eprintln!("[erasure] Checking every share through the network: {}", e);
+            HashSet::new()
+        }
+    };
+
+    let mut report = HealthReport {
+        metadata_cid: metadata_cid.to_string(),
+        file_name: metadata.original_file.name.clone(),
+        k,
+        m,
+        chunks: Vec::with_capacity(chunk_count),
+        healthy: true,
+        recoverable: true,
+        new_metadata_cid: None,
+    };
+    let mut metadata_changed = false;
+
+    for orig_idx in 0..chunk_count {
+        let listed: Vec<ChunkInfo> = metadata
+            .chunks
+            .iter()
+            .filter(|c| c.original_chunk == orig_idx && c.share_idx < m)
+            .cloned()
+            .collect();
+
+        let pinned = &pinned;
+        let results: Vec<(ChunkInfo, Result<(), String>)> = futures::stream::iter(listed)
+            .map(|chunk| async move {
+                let result = if pinned.contains(&chunk.cid.cid) {
+                    Ok(())
+                } else {
+                    match tokio::time::timeout(SHARD_FETCH_TIMEOUT, ipfs.block_stat(&chunk.cid.cid)).await {
+                        Ok(result) => result.map(|_| ()),
+                        Err(_) => Err(format!("timed out after {}s", SHARD_FETCH_TIMEOUT.as_secs())),
+                    }
+                };
+                (chunk, result)
+            })
+            .buffer_unordered(8)
+            .collect()
+            .await;
+
+        let mut present: Vec<ChunkInfo> = Vec::new();
+        let mut missing = Vec::new();
+        for (chunk, result) in results {
+            match result {
+                Ok(()) => present.push(chunk),
+                Err(reason) => missing.push(UnavailableShare {
+                    original_chunk: orig_idx,
+                    share_idx: chunk.share_idx,
+                    cid: chunk.cid.cid.clone(),
+                    reason,
+                }),
+            }
+        }
+        // Shares the metadata does not list at all are just as lost
+        for share_idx in 0..m {
+            let accounted = present.iter().any(|c| c.share_idx == share_idx)
+                || missing.iter().any(|u| u.share_idx == share_idx);
+            if !accounted {
+                missing.push(UnavailableShare {
+                    original_chunk: orig_idx,
+                    share_idx,
+                    cid: String::new(),
+                    reason: "not listed in metadata".to_string(),
+                });
+            }
+        }
+        missing.sort_by_key(|u| u.share_idx);
+
+        let available = m - missing.len();
+        let mut repaired_shares = Vec::new();
+        let mut repair_error = None;
+        if repair && available >= k && available < m {
+            match repair_chunk(ipfs, &mut metadata, orig_idx, &present, &missing).await {
+                Ok(repaired) => {
+                    repaired_shares = repaired;
+                    metadata_changed = true;
+                }
+                Err(e) => repair_error = Some(e),
+            }
+        }
+
+        let available_after = available + repaired_shares.len();
+        let tolerates = available_after as i64 - k as i64;
+        let mut summary = format!(
+            "chunk {}: {} of {} shares, {}",
+            orig_idx,
+            available,
+            m,
+            match available as i64 - k as i64 {
+                t if t < 0 => format!("unrecoverable, {} more needed", -t),
+                0 => "tolerates no more loss".to_string(),
+                t => format!("tolerates {} more loss{}", t, if t == 1 { "" } else { "es" }),
+            }
+        );
+        if !repaired_shares.is_empty() {
+            summary.push_str(&format!(", repaired {} shares", repaired_shares.len()));
+        }
+        if let Some(e) = repair_error {
+            summary.push_str(&format!(", repair failed: {}", e));
+        }
+        println!("[erasure] {}: {}", metadata.original_file.name, summary);
+
+        if available_after < m {
+            report.healthy = false;
+        }
+        if tolerates < 0 {
+            report.recoverable = false;
+        }
+        report.chunks.push(ChunkHealth {
+            original_chunk: orig_idx,
+            available_shares: available,
+            total_shares: m,
+            tolerates,
+            missing,
+            repaired_shares,
+            summary,
+        });
+    }
+
+    if metadata_changed {
+        metadata
+            .chunks
+            .sort_by_key(|c| (c.original_chunk, c.share_idx));
+        metadata.metadata_cid = None;
+        let bytes = serde_json::to_vec(&metadata)
+            .map_err(|e| format!("Failed to serialize metadata: {}", e))?;
+        let new_cid = ipfs
+            .add(&format!("{}.ec_metadata", metadata.original_file.name), bytes)
+            .await?;
+        println!(
+            "[erasure] Published repaired metadata for {}: {}",
+            metadata.original_file.name, new_cid
+        );
+        report.new_metadata_cid = Some(new_cid);
+    }
+
+    Ok(report)
+}
+
+/// Repairs the file behind `metadata_cid` and moves `account_id`'s storage requests and
+/// records over to the repaired metadata, so the new copy is the one that stays stored.
+pub async fn repair_erasure_file(
+    ipfs: &IpfsClient,
+    account_id: &str,
+    mnemonic: &str,
+    metadata_cid: &str,
+) -> Result<HealthReport, String> {
+    account_secret(mnemonic, account_id)?;
+    let report = check_erasure_health(ipfs, metadata_cid, true).await?;
+    if let Some(new_cid) = &report.new_metadata_cid {
+        replace_references(ipfs, account_id, mnemonic, metadata_cid, new_cid).await?;
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_db::test_db;
+    use crate::utils::test_http::{mock_ipfs, mock_ipfs_logged};
+
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 31 % 251) as u8).collect()
+    }
+
+    #[test]
+    fn decode_rebuilds_chunk_with_up_to_m_minus_k_shards_missing() {
+        let (k, m) = (3, 6);
+        for len in [1usize, 2, 3, 100, 4096, 4097] {
+            let chunk = sample(len);
+            let shards = encode_chunk(k, m, &chunk).unwrap();
+            assert_eq!(shards.len(), m);
+            for missing in 0..=(m - k) {
+                // Drop data shards first, the case that needs real reconstruction
+                let mut partial: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+                for shard in partial.iter_mut().take(missing) {
+                    *shard = None;
+                }
+                assert_eq!(decode_chunk(k, m, &mut partial, len).unwrap(), chunk, "len {} missing {}", len, missing);
+
+                let mut partial: Vec<Option<Vec<u8>>> = shards.iter().cloned().map(Some).collect();
+                for shard in partial.iter_mut().rev().take(missing) {
+                    *shard = None;
+                }
+                assert_eq!(decode_chunk(k, m, &mut partial, len).unwrap(), chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn decode_fails_with_fewer_than_k_shards() {
+        let shards = encode_chunk(3, 5, &sample(1000)).unwrap();
+        let mut partial: Vec<Option<Vec<u8>>> = shards.into_iter().map(Some).collect();
+        for shard in partial.iter_mut().take(3) {
+            *shard = None;
+        }
+        assert!(decode_chunk(3, 5, &mut partial, 1000).is_err());
+    }
+
+    #[tokio::test]
+    async fn encrypted_upload_reconstructs_from_mock_ipfs() {
+        let (ipfs, store) = mock_ipfs().await;
+        let dir = tempfile::tempdir().unwrap();
+        let plain = sample(250_000);
+        let source = dir.path().join("report.bin");
+        std::fs::write(&source, &plain).unwrap();
+        let key = secretbox::gen_key();
+        let params = ErasureParams { k: 3, m: 5, chunk_size: 64 * 1024, compression: None };
+
+        let (metadata, metadata_cid) =
+            encrypt_and_encode_file(&source, params, Some(key.0.to_vec()), &ipfs).await.unwrap();
+        let chunk_count = metadata.erasure_coding.encrypted_size.div_ceil(params.chunk_size);
+        assert_eq!(metadata.chunks.len(), chunk_count * params.m);
+        assert_eq!(metadata.original_file.size, plain.len());
+        assert!(store.lock().unwrap().contains_key(&metadata_cid));
+        // Shards only ever hold ciphertext
+        let first_shard = store.lock().unwrap()[&metadata.chunks[0].cid.cid].clone();
+        assert!(!plain.windows(64).any(|w| first_shard.starts_with(w)));
+
+        let output = dir.path().join("out/report.bin");
+        let report = reconstruct_file_streaming(&metadata, &output, Some(key.0.to_vec()), &ipfs)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), plain);
+        assert!(report.unavailable_shares.is_empty());
+
+        // Losing m - k shares of every chunk is survivable
+        for chunk in metadata.chunks.iter().filter(|c| c.share_idx < params.m - params.k) {
+            store.lock().unwrap().remove(&chunk.cid.cid);
+        }
+        std::fs::remove_file(&output).unwrap();
+        let report = reconstruct_file_streaming(&metadata, &output, Some(key.0.to_vec()), &ipfs)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), plain);
+        assert_eq!(report.unavailable_shares.len(), chunk_count * (params.m - params.k));
+
+        // One more is not
+        let extra = metadata.chunks.iter().find(|c| c.share_idx == params.m - params.k).unwrap();
+        store.lock().unwrap().remove(&extra.cid.cid);
+        std::fs::remove_file(&output).unwrap();
+        assert!(reconstruct_file_streaming(&metadata, &output, Some(key.0.to_vec()), &ipfs)
+            .await
+            .is_err());
+        assert!(!output.exists());
+    }
+
+    #[tokio::test]
+    async fn wrong_key_does_not_reconstruct() {
+        let (ipfs, _store) = mock_ipfs().await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("a.txt");
+        std::fs::write(&source, sample(5000)).unwrap();
+        let params = ErasureParams { k: 2, m: 4, chunk_size: 1024, compression: None };
+        let (metadata, _) =
+            encrypt_and_encode_file(&source, params, Some(secretbox::gen_key().0.to_vec()), &ipfs)
+                .await
+                .unwrap();
+        let output = dir.path().join("b.txt");
+        let result =
+            reconstruct_file_streaming(&metadata, &output, Some(secretbox::gen_key().0.to_vec()), &ipfs).await;
+        assert!(result.unwrap_err().starts_with(DECRYPTION_FAILED));
+        assert!(!output.exists());
+    }
+
+    fn log_lines(count: usize) -> Vec<u8> {
+        (0..count)
+            .map(|i| format!("2026-10-19T08:{:02}:{:02}Z INFO served request {} in {} ms\n", i / 60 % 60, i % 60, i * 7, i % 97))
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    // Log lines carrying request ids, so that the compressed stream still spans
+    // several encrypted stream chunks
+    fn request_log(count: usize) -> Vec<u8> {
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        (0..count)
+            .map(|i| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                format!("2026-10-19T08:{:02}:{:02}Z INFO request {:016x} served in {} ms\n", i / 60 % 60, i % 60, state, i % 97)
+            })
+            .collect::<String>()
+            .into_bytes()
+    }
+
+    #[tokio::test]
+    async fn compressed_upload_round_trips_and_resumes() {
+        let (ipfs, store) = mock_ipfs().await;
+        let dir = tempfile::tempdir().unwrap();
+        let plain = request_log(20000);
+        let source = dir.path().join("server.log");
+        std::fs::write(&source, &plain).unwrap();
+        let key = secretbox::gen_key();
+        let params = ErasureParams { k: 2, m: 4, chunk_size: 16 * 1024, compression: Some(Compression::Zstd) };
+        let (metadata, _) = encrypt_and_encode_file(&source, params, Some(key.0.to_vec()), &ipfs).await.unwrap();
+        let coding = &metadata.erasure_coding;
+        assert_eq!(coding.compression, Some(Compression::Zstd));
+        let compressed_size = coding.compressed_size.unwrap();
+        assert!(compressed_size * 2 < plain.len() && compressed_size > 4 * 64 * 1024);
+        assert_eq!(reconstructed_len(&metadata), compressed_size as u64);
+
+        let output = dir.path().join("out/server.log");
+        reconstruct_file_streaming(&metadata, &output, Some(key.0.to_vec()), &ipfs).await.unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), plain);
+
+        // A chunk that cannot be rebuilt stops the download after the ones before it
+        let chunk_count = metadata.chunks.iter().map(|c| c.original_chunk).max().unwrap() + 1;
+        let stalled = chunk_count / 2;
+        assert!(stalled > 0);
+        let taken: Vec<(String, Vec<u8>)> = metadata
+            .chunks
+            .iter()
+            .filter(|c| c.original_chunk == stalled && c.share_idx < params.m - params.k + 1)
+            .map(|c| {
+                let cid = c.cid.cid.clone();
+                let data = store.lock().unwrap().remove(&cid).unwrap();
+                (cid, data)
+            })
+            .collect();
+        let output = dir.path().join("resumed/server.log");
+        let mut saved = ReconstructCheckpoint::default();
+        let interrupted = reconstruct_file_resumable(
+            &metadata,
+            &output,
+            Some(key.0.to_vec()),
+            &ipfs,
+            ReconstructCheckpoint::default(),
+            &mut |checkpoint| saved = checkpoint.clone(),
+        )
+        .await;
+        assert!(interrupted.is_err());
+        assert_eq!(saved.chunks_done, stalled);
+        // The partial file holds the compressed stream, not the plaintext
+        let part = partial_path(&output);
+        assert_eq!(std::fs::metadata(&part).unwrap().len(), saved.bytes_written);
+        assert!(saved.bytes_written < compressed_size as u64);
+
+        store.lock().unwrap().extend(taken);
+        let mut chunks_fetched = 0;
+        reconstruct_file_resumable(&metadata, &output, Some(key.0.to_vec()), &ipfs, saved.clone(), &mut |_| {
+            chunks_fetched += 1
+        })
+        .await
+        .unwrap();
+        // Resumed from the start of the encrypted stream chunk the partial file ends in
+        assert!(chunks_fetched >= chunk_count - stalled && chunks_fetched < chunk_count, "{}", chunks_fetched);
+        assert_eq!(std::fs::read(&output).unwrap(), plain);
+        assert!(!part.exists());
+    }
+
+    #[tokio::test]
+    async fn compressed_looking_files_are_stored_as_they_are() {
+        let (ipfs, _store) = mock_ipfs().await;
+        let dir = tempfile::tempdir().unwrap();
+        let key = secretbox::gen_key();
+        let params = ErasureParams { k: 2, m: 4, chunk_size: 4096, compression: Some(Compression::Zstd) };
+        // Text, but named like a photo
+        let photo = dir.path().join("photo.jpg");
+        std::fs::write(&photo, log_lines(500)).unwrap();
+        let (metadata, _) = encrypt_and_encode_file(&photo, params, Some(key.0.to_vec()), &ipfs).await.unwrap();
+        assert_eq!(metadata.erasure_coding.compression, None);
+        assert_eq!(reconstructed_len(&metadata), metadata.original_file.size as u64);
+
+        let output = dir.path().join("out/photo.jpg");
+        reconstruct_file_streaming(&metadata, &output, Some(key.0.to_vec()), &ipfs).await.unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), log_lines(500));
+    }
+
+    #[tokio::test]
+    async fn compressed_files_cannot_expand_past_their_recorded_size() {
+        let (ipfs, _store) = mock_ipfs().await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("server.log");
+        std::fs::write(&source, log_lines(3000)).unwrap();
+        let key = secretbox::gen_key();
+        let params = ErasureParams { k: 2, m: 4, chunk_size: 1024, compression: Some(Compression::Zstd) };
+        let (mut metadata, _) = encrypt_and_encode_file(&source, params, Some(key.0.to_vec()), &ipfs).await.unwrap();
+        metadata.original_file.size /= 2;
+
+        let output = dir.path().join("out/server.log");
+        let error = reconstruct_file_streaming(&metadata, &output, Some(key.0.to_vec()), &ipfs).await.unwrap_err();
+        assert!(error.contains("larger than the recorded file size"), "{}", error);
+        assert!(!output.exists());
+    }
+
+    #[tokio::test]
+    async fn health_check_stats_shares_and_repair_moves_records() {
+        let pool = test_db().await;
+        let (ipfs, store, log) = mock_ipfs_logged().await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("notes.bin");
+        std::fs::write(&source, sample(10_000)).unwrap();
+        let params = ErasureParams { k: 2, m: 4, chunk_size: 4096, compression: None };
+        let (metadata, metadata_cid) =
+            encrypt_and_encode_file(&source, params, Some(secretbox::gen_key().0.to_vec()), &ipfs)
+                .await
+                .unwrap();
+        let lost = metadata.chunks.iter().find(|c| c.original_chunk == 0 && c.share_idx == 1).unwrap();
+        store.lock().unwrap().remove(&lost.cid.cid);
+
+        log.lock().unwrap().clear();
+        let report = check_erasure_health(&ipfs, &metadata_cid, false).await.unwrap();
+        assert!(!report.healthy && report.recoverable);
+        assert_eq!(report.chunks[0].available_shares, 3);
+        assert_eq!(report.chunks[0].missing[0].cid, lost.cid.cid);
+        assert!(report.chunks[1..].iter().all(|c| c.missing.is_empty()));
+        assert!(report.new_metadata_cid.is_none());
+        // Shares are only looked up, never downloaded
+        let shards: HashSet<&str> = metadata.chunks.iter().map(|c| c.cid.cid.as_str()).collect();
+        let exported: Vec<String> = log
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|request| request.strip_prefix("/api/v0/dag/export?arg=").map(str::to_string))
+            .collect();
+        assert!(exported.iter().all(|cid| !shards.contains(cid.as_str())));
+        assert!(log.lock().unwrap().iter().any(|request| request.contains(&lost.cid.cid)));
+
+        let (alice, seed) = ("5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY", "//Alice");
+        sqlx::query(
+            "INSERT INTO user_profiles (owner, cid, file_hash, file_name, main_req_hash, block_number)
+             VALUES (?, ?, ?, 'notes.bin', 'car', 0)",
+        )
+        .bind(alice)
+        .bind(&metadata_cid)
+        .bind(hex::encode(&metadata_cid))
+        .execute(pool)
+        .await
+        .unwrap();
+        assert!(repair_erasure_file(&ipfs, alice, "bad", &metadata_cid).await.is_err());
+
+        let report = repair_erasure_file(&ipfs, alice, seed, &metadata_cid).await.unwrap();
+        assert_eq!(report.chunks[0].repaired_shares, vec![1]);
+        let new_cid = report.new_metadata_cid.unwrap();
+        let (cid, file_hash): (String, String) =
+            sqlx::query_as("SELECT cid, file_hash FROM user_profiles WHERE owner = ? AND file_name = 'notes.bin'")
+                .bind(alice)
+                .fetch_one(pool)
+                .await
+                .unwrap();
+        assert_eq!((cid, file_hash), (new_cid.clone(), hex::encode(&new_cid)));
+
+        let report = check_erasure_health(&ipfs, &new_cid, false).await.unwrap();
+        assert!(report.healthy);
+        assert!(report.chunks.iter().all(|c| c.available_shares == 4));
+    }
+
+    #[tokio::test]
+    async fn health_check_reports_unrecoverable_chunks() {
+        let (ipfs, store) = mock_ipfs().await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("a.bin");
+        std::fs::write(&source, sample(3000)).unwrap();
+        let params = ErasureParams { k: 2, m: 3, chunk_size: 1024, compression: None };
+        let (metadata, metadata_cid) =
+            encrypt_and_encode_file(&source, params, Some(secretbox::gen_key().0.to_vec()), &ipfs)
+                .await
+                .unwrap();
+        for chunk in metadata.chunks.iter().filter(|c| c.original_chunk == 0 && c.share_idx < 2) {
+            store.lock().unwrap().remove(&chunk.cid.cid);
+        }
+        // Too few shares are left to rebuild, so repair leaves the metadata alone
+        let report = check_erasure_health(&ipfs, &metadata_cid, true).await.unwrap();
+        assert!(!report.recoverable);
+        assert_eq!(report.chunks[0].tolerates, -1);
+        assert!(report.chunks[0].repaired_shares.is_empty());
+        assert!(report.new_metadata_cid.is_none());
+    }
+}
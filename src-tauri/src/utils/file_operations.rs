@@ -1,6 +1,9 @@
 // use crate::commands::substrate_tx::{ FileHashWrapper, FileInputWrapper};
 use crate::utils::sync::{get_private_sync_path, get_public_sync_path};
 use crate::DB_POOL;
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::pins::unpin_stored_object;
+use crate::utils::trustless::Cid;
 use std::fs;
 use std::path::{Path, PathBuf};
 use crate::sync_shared::collect_files_recursively;
@@ -71,15 +74,30 @@ pub async fn unpin_user_file_by_name(file_name: &str, _seed_phrase: &str) -> Res
 
             match hashes_result {
                 Ok(hashes) if !hashes.is_empty() => {
-                    if let Some((_file_hash,)) = hashes.first() {                        
-                        
-                        // Also delete from file_paths table
-                        let _ = sqlx::query("DELETE FROM file_paths WHERE file_name = ?")
-                            .bind(variant)
-                            .execute(pool)
-                            .await;
+                    // file_hash is the hex-encoded CID string of the metadata or manifest
+                    let ipfs = IpfsClient::from_settings().await;
+                    let mut unpin_error = None;
+                    for (file_hash,) in &hashes {
+                        // S3-backed entries have no CID, so there is nothing pinned for them
+                        let cid = match hex::decode(file_hash).ok().and_then(|b| String::from_utf8(b).ok()) {
+                            Some(cid) if Cid::parse(&cid).is_ok() => cid,
+                            _ => continue,
+                        };
+                        match unpin_stored_object(&ipfs, &cid).await {
+                            Ok(count) => println!("[Unpin] Unpinned {} CIDs for '{}'", count, variant),
+                            Err(e) => unpin_error = Some(format!("Failed to unpin '{}': {}", variant, e)),
+                        }
                     }
-                    return Err("Found empty hash result despite non-empty hashes".to_string());
+
+                    // Also delete from file_paths table
+                    let _ = sqlx::query("DELETE FROM file_paths WHERE file_name = ?")
+                        .bind(&variant)
+                        .execute(pool)
+                        .await;
+                    return match unpin_error {
+                        Some(e) => Err(e),
+                        None => Ok(()),
+                    };
                 },
                 Ok(_) => {
                 },
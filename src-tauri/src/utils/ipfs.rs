@@ -1,41 +1,494 @@
-use reqwest::blocking::Client;
+use std::collections::HashMap;
+use std::io::{Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use reqwest::header::{HeaderName, HeaderValue, ACCEPT};
+use reqwest::multipart::Part;
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use crate::constants::ipfs::{API_URL, DEFAULT_GATEWAYS};
+use crate::utils::key_store::{hold_secrets, open_text, seal_text};
+use crate::utils::settings::{get_setting, masked_secret, set_setting, IPFS_CONFIG};
+use crate::utils::trustless::{
+    car_size_budget, file_from_car, write_file_from_car, Blocks, Cid, CAR_ROOT_WINDOW,
+};
 
+// Only bounds connecting, so a daemon that is down fails fast while large reads still
+// have as long as they need
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const CAR_CONTENT_TYPE: &str = "application/vnd.ipld.car; version=1";
+// Read size when streaming a file from disk into a request body
+const UPLOAD_READ_BYTES: usize = 256 * 1024;
 
-pub fn download_from_ipfs(api_url: &str, cid: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
-    let client = Client::new();
+/// Where the app talks to IPFS: a local or remote Kubo RPC API, optional headers for it
+/// (e.g. `Authorization` for a remote node), and trustless (CAR-serving) gateways tried
+/// in order for reads when the API cannot serve them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IpfsConfig {
+    pub api_url: String,
+    #[serde(default)]
+    pub api_headers: HashMap<String, String>,
+    #[serde(default)]
+    pub gateways: Vec<String>,
+}
 
-    let res = client
-        .post(&format!("{}/api/v0/cat?arg={}", api_url, cid))
-        .send()?
-        .error_for_status()?;
+impl Default for IpfsConfig {
+    fn default() -> Self {
+        IpfsConfig {
+            api_url: std::env::var("IPFS_NODE_URL").unwrap_or_else(|_| API_URL.to_string()),
+            api_headers: HashMap::new(),
+            gateways: DEFAULT_GATEWAYS.iter().map(|g| g.to_string()).collect(),
+        }
+    }
+}
 
-    let bytes = res.bytes()?.to_vec();
-    Ok(bytes)
+fn normalize_url(url: &str, what: &str) -> Result<String, String> {
+    let url = url.trim().trim_end_matches('/');
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("Invalid {} '{}': {}", what, url, e))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(format!("Invalid {} '{}': must be http or https", what, url));
+    }
+    Ok(url.to_string())
 }
 
-pub async fn download_from_ipfs_async(api_url: &str, cid: &str) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
-    let client = reqwest::Client::new();
+impl IpfsConfig {
+    /// Trims trailing slashes and rejects URLs and headers reqwest would refuse later.
+    pub fn validated(self) -> Result<Self, String> {
+        let api_url = normalize_url(&self.api_url, "IPFS API URL")?;
+        for (name, value) in &self.api_headers {
+            HeaderName::from_bytes(name.as_bytes())
+                .map_err(|_| format!("Invalid header name '{}'", name))?;
+            HeaderValue::from_str(value).map_err(|_| format!("Invalid value for header '{}'", name))?;
+        }
+        let gateways = self
+            .gateways
+            .iter()
+            .filter(|g| !g.trim().is_empty())
+            .map(|g| normalize_url(g, "gateway URL"))
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(IpfsConfig {
+            api_url,
+            api_headers: self.api_headers,
+            gateways,
+        })
+    }
+
+    /// The same config with header values hidden, for showing in the UI.
+    pub fn masked(&self) -> Self {
+        IpfsConfig {
+            api_headers: self
+                .api_headers
+                .iter()
+                .map(|(name, value)| (name.clone(), masked_secret(value)))
+                .collect(),
+            ..self.clone()
+        }
+    }
 
-    let res = client
-        .post(&format!("{}/api/v0/cat?arg={}", api_url, cid))
-        .send()
-        .await?
-        .error_for_status()?;
-        println!("IPFS response status for CID {}: {}", cid, res.status());
-    let bytes = res.bytes().await?.to_vec();
-    Ok(bytes)
+    /// Puts back the saved value of every header the UI returned unchanged from `masked`.
+    pub fn unmasked(mut self, saved: &IpfsConfig) -> Self {
+        for (name, value) in self.api_headers.iter_mut() {
+            if let Some(saved_value) = saved.api_headers.get(name) {
+                if *value == masked_secret(saved_value) {
+                    *value = saved_value.clone();
+                }
+            }
+        }
+        self
+    }
 }
 
-pub async fn download_content_from_ipfs(api_url: &str, cid: &str) -> Result<Vec<u8>, String> {
-    let client = reqwest::Client::new();
-    let res = client
-        .post(&format!("{}/api/v0/cat?arg={}", api_url, cid))
-        .send()
+/// Saved configuration, or the default (local daemon) when nothing is saved yet or the
+/// DB is not ready. Header values stay out while the key store is locked.
+pub async fn load_ipfs_config() -> IpfsConfig {
+    match get_setting(IPFS_CONFIG).await {
+        Ok(Some(json)) => match serde_json::from_str::<IpfsConfig>(&json) {
+            Ok(mut config) => {
+                let opened: Result<HashMap<_, _>, String> = config
+                    .api_headers
+                    .iter()
+                    .map(|(name, value)| Ok((name.clone(), open_text(value)?)))
+                    .collect();
+                match opened {
+                    Ok(headers) => config.api_headers = headers,
+                    Err(e) => {
+                        eprintln!("[IPFS] Leaving out saved API headers: {}", e);
+                        config.api_headers.clear();
+                    }
+                }
+                config
+            }
+            Err(e) => {
+                eprintln!("[IPFS] Ignoring invalid saved IPFS config: {}", e);
+                IpfsConfig::default()
+            }
+        },
+        _ => IpfsConfig::default(),
+    }
+}
+
+/// Validates and saves `config`, with its header values sealed by the key store.
+pub async fn save_ipfs_config(config: IpfsConfig) -> Result<IpfsConfig, String> {
+    let config = config.validated()?;
+    let hold = hold_secrets().await;
+    let mut sealed = config.clone();
+    for value in sealed.api_headers.values_mut() {
+        *value = seal_text(&hold, value).await?;
+    }
+    let json = serde_json::to_string(&sealed)
+        .map_err(|e| format!("Failed to serialize IPFS config: {}", e))?;
+    set_setting(IPFS_CONFIG, &json).await?;
+    Ok(config)
+}
+
+/// A multipart part that streams the file at `path` from disk rather than loading it
+/// into memory. The length is known up front, so the request is not chunked.
+pub async fn file_part(path: &Path, file_name: &str) -> Result<Part, String> {
+    let file = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let len = file
+        .metadata()
         .await
-        .map_err(|e| e.to_string())?
-        .error_for_status()
-        .map_err(|e| e.to_string())?;
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?
+        .len();
+    let chunks = futures::stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let mut buf = vec![0u8; UPLOAD_READ_BYTES];
+        match file.read(&mut buf).await {
+            Ok(0) => None,
+            Ok(n) => {
+                buf.truncate(n);
+                Some((Ok(buf), Some(file)))
+            }
+            Err(e) => Some((Err(e), None)),
+        }
+    });
+    Ok(Part::stream_with_length(reqwest::Body::wrap_stream(chunks), len)
+        .file_name(file_name.to_string()))
+}
+
+/// The single way the app reaches IPFS. Cheap to clone.
+#[derive(Clone)]
+pub struct IpfsClient {
+    config: Arc<IpfsConfig>,
+    http: reqwest::Client,
+    request_timeout: Option<Duration>,
+}
+
+impl IpfsClient {
+    pub fn new(config: IpfsConfig) -> Self {
+        let http = reqwest::Client::builder()
+            .connect_timeout(CONNECT_TIMEOUT)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        IpfsClient {
+            config: Arc::new(config),
+            http,
+            request_timeout: None,
+        }
+    }
+
+    /// Bounds every request, including each gateway attempt, by `timeout`. Without it
+    /// a `cat` for content the node cannot find blocks until the caller gives up.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.request_timeout = Some(timeout);
+        self
+    }
+
+    fn bounded(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        match self.request_timeout {
+            Some(timeout) => request.timeout(timeout),
+            None => request,
+        }
+    }
+
+    pub async fn from_settings() -> Self {
+        IpfsClient::new(load_ipfs_config().await)
+    }
+
+    pub fn api_url(&self) -> &str {
+        &self.config.api_url
+    }
+
+    pub fn gateways(&self) -> &[String] {
+        &self.config.gateways
+    }
+
+    /// POST to a Kubo RPC endpoint such as `cat?arg=<cid>`, with the configured headers.
+    pub fn api_post(&self, endpoint: &str) -> reqwest::RequestBuilder {
+        let mut request = self
+            .http
+            .post(format!("{}/api/v0/{}", self.config.api_url, endpoint));
+        for (name, value) in &self.config.api_headers {
+            request = request.header(name.as_str(), value.as_str());
+        }
+        self.bounded(request)
+    }
+
+    /// Verified content for `cid`. Blocks are fetched as a CAR, from the API first and
+    /// then from each gateway, and only returned once every block has been hashed and
+    /// the file rebuilt from the DAG, so a bad node or gateway can only fail the fetch.
+    pub async fn cat(&self, cid: &str) -> Result<Vec<u8>, String> {
+        self.cat_with_blocks(cid).await.map(|(data, _)| data)
+    }
+
+    /// Like `cat`, but also hands back the verified blocks, for callers that archive them.
+    pub async fn cat_with_blocks(&self, cid: &str) -> Result<(Vec<u8>, Blocks), String> {
+        let root = Cid::parse(cid)?;
+        let mut errors = Vec::new();
+        for (source, request) in self.car_sources(cid) {
+            let mut car = Vec::new();
+            match self
+                .fetch_car(request, &root, &mut car)
+                .await
+                .and_then(|_| file_from_car(&root, &car))
+            {
+                Ok(fetched) => {
+                    if let Some(api_error) = errors.first() {
+                        println!("[IPFS] Fetched {} from gateway {} ({})", cid, source, api_error);
+                    }
+                    return Ok(fetched);
+                }
+                Err(e) => errors.push(format!("{}: {}", source, e)),
+            }
+        }
+        Err(format!("Failed to fetch {}: {}", cid, errors.join("; ")))
+    }
+
+    /// Like `cat`, for files too large to hold in memory: the CAR is spooled to a temp
+    /// file and the verified content written to `output` a block at a time. Returns its
+    /// size.
+    pub async fn cat_to_file(&self, cid: &str, output: &Path) -> Result<u64, String> {
+        let root = Cid::parse(cid)?;
+        let mut errors = Vec::new();
+        for (source, request) in self.car_sources(cid) {
+            match self.spool_car(request, &root, output).await {
+                Ok(size) => {
+                    if let Some(api_error) = errors.first() {
+                        println!("[IPFS] Fetched {} from gateway {} ({})", cid, source, api_error);
+                    }
+                    return Ok(size);
+                }
+                Err(e) => errors.push(format!("{}: {}", source, e)),
+            }
+        }
+        let _ = tokio::fs::remove_file(output).await;
+        Err(format!("Failed to fetch {}: {}", cid, errors.join("; ")))
+    }
+
+    // Where a CAR for `cid` is fetched from, in order: the API, then each gateway
+    fn car_sources(&self, cid: &str) -> Vec<(String, reqwest::RequestBuilder)> {
+        let mut sources = vec![("API".to_string(), self.api_post(&format!("dag/export?arg={}", cid)))];
+        for gateway in &self.config.gateways {
+            let request = self
+                .http
+                .get(format!("{}/ipfs/{}?format=car", gateway, cid))
+                .header(ACCEPT, CAR_CONTENT_TYPE);
+            sources.push((gateway.clone(), self.bounded(request)));
+        }
+        sources
+    }
+
+    // Copies the CAR `request` answers with into `out`. It is only read as far as the
+    // size its root block declares allows.
+    async fn fetch_car<W: AsyncWrite + Unpin>(
+        &self,
+        request: reqwest::RequestBuilder,
+        root: &Cid,
+        out: &mut W,
+    ) -> Result<(), String> {
+        let mut response = request
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| e.to_string())?;
+        // The start of the CAR, kept until the root block has arrived
+        let mut prefix = Vec::new();
+        let mut received = 0u64;
+        let mut budget = None;
+        while let Some(chunk) = response.chunk().await.map_err(|e| e.to_string())? {
+            received += chunk.len() as u64;
+            if budget.is_none() {
+                prefix.extend_from_slice(&chunk);
+                budget = car_size_budget(&prefix, root)?;
+                if budget.is_some() {
+                    prefix = Vec::new();
+                }
+            }
+            match budget {
+                Some(limit) if received > limit => {
+                    return Err(format!(
+                        "CAR for {} is larger than the {} bytes its root allows",
+                        root.to_string_form(),
+                        limit
+                    ));
+                }
+                None if received > CAR_ROOT_WINDOW => {
+                    return Err(format!("CAR for {} does not start with its root block", root.to_string_form()));
+                }
+                _ => {}
+            }
+            out.write_all(&chunk)
+                .await
+                .map_err(|e| format!("Failed to store CAR: {}", e))?;
+        }
+        out.flush().await.map_err(|e| format!("Failed to store CAR: {}", e))
+    }
+
+    // Fetches the CAR into a temp file, then rebuilds the file from it into `output`
+    async fn spool_car(&self, request: reqwest::RequestBuilder, root: &Cid, output: &Path) -> Result<u64, String> {
+        let mut car = tempfile::tempfile().map_err(|e| format!("Failed to create temp file: {}", e))?;
+        let spool = car.try_clone().map_err(|e| format!("Failed to create temp file: {}", e))?;
+        self.fetch_car(request, root, &mut tokio::fs::File::from_std(spool)).await?;
+
+        let (cid, output) = (root.clone(), output.to_path_buf());
+        tokio::task::spawn_blocking(move || {
+            car.rewind().map_err(|e| format!("Failed to read CAR: {}", e))?;
+            let file = std::fs::File::create(&output)
+                .map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+            let mut out = std::io::BufWriter::new(file);
+            let size = write_file_from_car(&cid, &mut car, &mut out)?;
+            out.flush().map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+            Ok(size)
+        })
+        .await
+        .map_err(|e| format!("Failed to rebuild {}: {}", root.to_string_form(), e))?
+    }
+
+    /// Adds `data` (pinned, CIDv0 like the rest of the app) and returns its CID.
+    pub async fn add(&self, file_name: &str, data: Vec<u8>) -> Result<String, String> {
+        let part = reqwest::multipart::Part::bytes(data).file_name(file_name.to_string());
+        self.add_part(file_name, part).await
+    }
+
+    /// Like `add`, streaming the file at `path` from disk.
+    pub async fn add_file(&self, path: &Path, file_name: &str) -> Result<String, String> {
+        self.add_part(file_name, file_part(path, file_name).await?).await
+    }
+
+    async fn add_part(&self, file_name: &str, part: Part) -> Result<String, String> {
+        let form = reqwest::multipart::Form::new().part("file", part);
+
+        let res = self
+            .api_post("add?pin=true")
+            .multipart(form)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to add {} to IPFS: {}", file_name, e))?
+            .error_for_status()
+            .map_err(|e| format!("Failed to add {} to IPFS: {}", file_name, e))?;
+
+        let body: serde_json::Value = res
+            .json()
+            .await
+            .map_err(|e| format!("Invalid IPFS add response for {}: {}", file_name, e))?;
+        body.get("Hash")
+            .and_then(|h| h.as_str())
+            .map(|h| h.to_string())
+            .ok_or_else(|| format!("IPFS add response for {} has no Hash", file_name))
+    }
+
+    /// Size of the block `cid`. The node asks the network for blocks it does not have,
+    /// so this tells whether content can be found without downloading all of it.
+    pub async fn block_stat(&self, cid: &str) -> Result<u64, String> {
+        let body: serde_json::Value = self
+            .api_post(&format!("block/stat?arg={}", cid))
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| format!("Failed to stat block {}: {}", cid, e))?
+            .json()
+            .await
+            .map_err(|e| format!("Invalid block/stat response for {}: {}", cid, e))?;
+        body.get("Size")
+            .and_then(|s| s.as_u64())
+            .ok_or_else(|| format!("block/stat response for {} has no Size", cid))
+    }
+
+    /// Imports a CAR into the node and pins its roots.
+    pub async fn dag_import(&self, car_path: &Path) -> Result<(), String> {
+        let part = file_part(car_path, "import.car").await?;
+        let form = reqwest::multipart::Form::new().part("file", part);
+
+        let res = self
+            .api_post("dag/import?pin-roots=true")
+            .multipart(form)
+            .send()
+            .await
+            .map_err(|e| format!("Failed to import CAR: {}", e))?
+            .error_for_status()
+            .map_err(|e| format!("Failed to import CAR: {}", e))?;
+
+        // One JSON object per line; pin failures are reported per root
+        let body = res
+            .text()
+            .await
+            .map_err(|e| format!("Invalid dag/import response: {}", e))?;
+        for line in body.lines().filter(|l| !l.trim().is_empty()) {
+            let value: serde_json::Value = serde_json::from_str(line)
+                .map_err(|e| format!("Invalid dag/import response: {}", e))?;
+            if let Some(root) = value.get("Root") {
+                let pin_error = root.get("PinErrorMsg").and_then(|e| e.as_str()).unwrap_or("");
+                if !pin_error.is_empty() {
+                    return Err(format!("Failed to pin imported root {}: {}", root["Cid"], pin_error));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_http::{mock_ipfs, serve, unixfs_file};
+    use crate::utils::trustless::{raw_cid_for, write_car};
+
+    #[tokio::test]
+    async fn cat_stops_reading_cars_past_their_declared_size() {
+        let (root, _, mut blocks) = unixfs_file(b"small file");
+        blocks.reverse();
+        // A small file followed by MiBs of blocks it never links to
+        let filler = vec![0u8; CAR_ROOT_WINDOW as usize + 1];
+        let filler_block = (raw_cid_for(&filler), filler);
+        let mut padded = blocks.clone();
+        padded.push(filler_block.clone());
+        let padded = write_car(std::slice::from_ref(&root), &padded);
+        // And one whose root would only come after them
+        let mut rootless = vec![filler_block];
+        rootless.extend(blocks.clone());
+        let rootless = write_car(std::slice::from_ref(&root), &rootless);
+        let plain = write_car(std::slice::from_ref(&root), &blocks);
+        let url = serve(move |request| match request.query("arg")[0].as_str() {
+            "padded" => (200, padded.clone()),
+            "rootless" => (200, rootless.clone()),
+            _ => (200, plain.clone()),
+        })
+        .await;
+        let ipfs = IpfsClient::new(IpfsConfig { api_url: url, api_headers: HashMap::new(), gateways: Vec::new() });
+
+        assert_eq!(ipfs.cat(&root.to_string_form()).await.unwrap(), b"small file");
+        let err = ipfs.fetch_car(ipfs.api_post("dag/export?arg=padded"), &root, &mut Vec::new()).await.unwrap_err();
+        assert!(err.contains("larger than"), "{}", err);
+        let err = ipfs.fetch_car(ipfs.api_post("dag/export?arg=rootless"), &root, &mut Vec::new()).await.unwrap_err();
+        assert!(err.contains("does not start with its root block"), "{}", err);
+    }
+
+    #[tokio::test]
+    async fn cat_to_file_rebuilds_content_from_a_spooled_car() {
+        let (ipfs, _) = mock_ipfs().await;
+        let data: Vec<u8> = (0..1_000_000u32).map(|i| (i % 251) as u8).collect();
+        let cid = ipfs.add("big.bin", data.clone()).await.unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("big.bin");
+
+        assert_eq!(ipfs.cat_to_file(&cid, &output).await.unwrap(), data.len() as u64);
+        assert_eq!(std::fs::read(&output).unwrap(), data);
 
-    let bytes = res.bytes().await.map_err(|e| e.to_string())?.to_vec();
-    Ok(bytes)
+        let missing = raw_cid_for(b"not added").to_string_form();
+        assert!(ipfs.cat_to_file(&missing, &output).await.is_err());
+        assert!(!output.exists());
+    }
 }
@@ -0,0 +1,691 @@
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use serde::Serialize;
+use sqlx::Row;
+use crate::utils::ipfs::{file_part, IpfsClient};
+use crate::utils::pins::{pin_local, unpin_local};
+use crate::utils::share::is_cid_shared;
+use crate::utils::sync::get_public_sync_path;
+use crate::DB_POOL;
+
+// MFS directory published trees are staged under, one subdirectory per key or share link
+const MFS_ROOT: &str = "/hippius-ipns";
+const KEY_PREFIX: &str = "hippius-";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct IpnsRecord {
+    pub owner: String,
+    // File or folder relative to the public sync folder, '/'-separated
+    pub path: String,
+    pub key_name: String,
+    pub ipns_name: String,
+    pub link: String,
+    pub last_cid: String,
+    pub published_at: i64,
+}
+
+fn row_to_record(row: &sqlx::sqlite::SqliteRow) -> IpnsRecord {
+    let ipns_name: String = row.get("ipns_name");
+    IpnsRecord {
+        owner: row.get("owner"),
+        path: row.get("path"),
+        key_name: row.get("key_name"),
+        link: format!("/ipns/{}", ipns_name),
+        ipns_name,
+        last_cid: row.get("last_cid"),
+        published_at: row.get("published_at"),
+    }
+}
+
+/// Checks that `path` names something inside the public sync folder and returns it
+/// normalized ("a/b") along with its location on disk.
+pub async fn resolve_public_path(path: &str) -> Result<(String, PathBuf), String> {
+    let rel = Path::new(path.trim_matches('/'));
+    let mut parts = Vec::new();
+    for component in rel.components() {
+        match component {
+            Component::Normal(part) => parts.push(part.to_string_lossy().to_string()),
+            Component::CurDir => {}
+            _ => return Err(format!("'{}' is not a path inside the public sync folder", path)),
+        }
+    }
+    if parts.is_empty() {
+        return Err("A file or folder inside the public sync folder is required".to_string());
+    }
+    let root = get_public_sync_path().await?;
+    let local = parts.iter().fold(PathBuf::from(root), |acc, part| acc.join(part));
+    if !local.exists() {
+        return Err(format!("'{}' does not exist in the public sync folder", path));
+    }
+    Ok((parts.join("/"), local))
+}
+
+// Same exclusions the public S3 sync uses, plus hidden entries
+fn is_published(name: &str) -> bool {
+    !name.starts_with('.') && name != "Thumbs.db" && !name.ends_with(".tmp")
+}
+
+async fn api_call(request: reqwest::RequestBuilder, what: &str) -> Result<reqwest::Response, String> {
+    let res = request
+        .send()
+        .await
+        .map_err(|e| format!("Failed to {}: {}", what, e))?;
+    let status = res.status();
+    if !status.is_success() {
+        let body = res.text().await.unwrap_or_default();
+        return Err(format!("Failed to {}: {} {}", what, status, body.trim()));
+    }
+    Ok(res)
+}
+
+async fn api_json(request: reqwest::RequestBuilder, what: &str) -> Result<serde_json::Value, String> {
+    api_call(request, what)
+        .await?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid response while trying to {}: {}", what, e))
+}
+
+fn string_field(value: &serde_json::Value, field: &str, what: &str) -> Result<String, String> {
+    value
+        .get(field)
+        .and_then(|v| v.as_str())
+        .map(|v| v.to_string())
+        .ok_or_else(|| format!("Response to {} has no {}", what, field))
+}
+
+/// Adds one file to the node without pinning it; the published root is pinned instead.
+async fn add_unpinned(ipfs: &IpfsClient, path: &Path) -> Result<String, String> {
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let form = reqwest::multipart::Form::new().part("file", file_part(path, &name).await?);
+    let body: serde_json::Value = ipfs
+        .api_post("add?pin=false&cid-version=1&raw-leaves=true")
+        .multipart(form)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to add {} to IPFS: {}", path.display(), e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid IPFS add response for {}: {}", path.display(), e))?;
+    string_field(&body, "Hash", "add")
+}
+
+// A file or folder below a staged directory, by its '/'-separated path
+struct LocalEntry {
+    path: String,
+    is_dir: bool,
+    size: i64,
+    mtime: i64,
+}
+
+// Everything under `dir` that gets published, parents before their children. Symlinks
+// are left out like the sync leaves them out.
+fn walk_published(dir: &Path) -> std::io::Result<Vec<LocalEntry>> {
+    let mut entries = Vec::new();
+    let walker = walkdir::WalkDir::new(dir)
+        .min_depth(1)
+        .follow_links(false)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| is_published(&e.file_name().to_string_lossy()));
+    for entry in walker {
+        let entry = entry?;
+        if entry.file_type().is_symlink() {
+            continue;
+        }
+        let Ok(meta) = entry.metadata() else {
+            continue;
+        };
+        let Ok(rel) = entry.path().strip_prefix(dir) else {
+            continue;
+        };
+        let path = rel
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        let mtime = meta
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or(0);
+        if meta.is_dir() {
+            entries.push(LocalEntry { path, is_dir: true, size: 0, mtime });
+        } else if meta.is_file() {
+            entries.push(LocalEntry { path, is_dir: false, size: meta.len() as i64, mtime });
+        }
+    }
+    Ok(entries)
+}
+
+// What was last staged under `staging`: path -> (is_dir, size, mtime)
+async fn staged_entries(staging: &str) -> Result<HashMap<String, (bool, i64, i64)>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query("SELECT path, is_dir, size, mtime FROM ipns_staged_files WHERE staging = ?")
+        .bind(staging)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to query ipns_staged_files: {}", e))?;
+    Ok(rows
+        .iter()
+        .map(|row| (row.get("path"), (row.get("is_dir"), row.get("size"), row.get("mtime"))))
+        .collect())
+}
+
+async fn forget_staged(staging: &str, path: Option<&str>) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let query = match path {
+        Some(path) => sqlx::query(
+            "DELETE FROM ipns_staged_files WHERE staging = ? AND (path = ? OR substr(path, 1, length(?) + 1) = ? || '/')",
+        )
+        .bind(staging)
+        .bind(path)
+        .bind(path)
+        .bind(path),
+        None => sqlx::query("DELETE FROM ipns_staged_files WHERE staging = ?").bind(staging),
+    };
+    query
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to update ipns_staged_files: {}", e))?;
+    Ok(())
+}
+
+async fn record_staged(staging: &str, entry: &LocalEntry, cid: &str) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query(
+        "INSERT INTO ipns_staged_files (staging, path, is_dir, size, mtime, cid) VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT(staging, path) DO UPDATE SET
+            is_dir = excluded.is_dir, size = excluded.size, mtime = excluded.mtime, cid = excluded.cid"
+    )
+    .bind(staging)
+    .bind(&entry.path)
+    .bind(entry.is_dir)
+    .bind(entry.size)
+    .bind(entry.mtime)
+    .bind(cid)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to record staged {}: {}", entry.path, e))?;
+    Ok(())
+}
+
+// Brings the MFS copy of `dir` at `mfs_dir` up to date: files whose size or
+// modification time changed since the last staging are added again, removed ones are
+// dropped. Returns how many files the tree holds and how many were added.
+async fn stage_dir(ipfs: &IpfsClient, staging: &str, dir: &Path, mfs_dir: &str) -> Result<(usize, usize), String> {
+    let root = dir.to_path_buf();
+    let local = tokio::task::spawn_blocking(move || walk_published(&root))
+        .await
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?
+        .map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+
+    let mut staged = staged_entries(staging).await?;
+    // The node may have lost the staging directory since; start over then
+    if !staged.is_empty()
+        && api_call(ipfs.api_post("files/stat").query(&[("arg", mfs_dir)]), "stat MFS directory").await.is_err()
+    {
+        staged.clear();
+    }
+    if staged.is_empty() {
+        forget_staged(staging, None).await?;
+        clear_mfs_dir(ipfs, mfs_dir).await?;
+    }
+    api_call(
+        ipfs.api_post("files/mkdir?parents=true&cid-version=1").query(&[("arg", mfs_dir)]),
+        "create MFS directory",
+    )
+    .await?;
+
+    // Gone, or turned from a file into a folder or back
+    let current: HashMap<&str, bool> = local.iter().map(|e| (e.path.as_str(), e.is_dir)).collect();
+    let mut removed: Vec<String> = staged
+        .iter()
+        .filter(|(path, (is_dir, _, _))| current.get(path.as_str()) != Some(is_dir))
+        .map(|(path, _)| path.clone())
+        .collect();
+    removed.sort();
+    for path in &removed {
+        if !staged.contains_key(path) {
+            // Went with a folder removed before it
+            continue;
+        }
+        clear_mfs_dir(ipfs, &format!("{}/{}", mfs_dir, path)).await?;
+        forget_staged(staging, Some(path)).await?;
+        let prefix = format!("{}/", path);
+        staged.retain(|p, _| p != path && !p.starts_with(&prefix));
+    }
+
+    let (mut files, mut added) = (0, 0);
+    for entry in &local {
+        let target = format!("{}/{}", mfs_dir, entry.path);
+        let previous = staged.get(&entry.path);
+        if entry.is_dir {
+            if previous.is_none() {
+                api_call(
+                    ipfs.api_post("files/mkdir?parents=true&cid-version=1").query(&[("arg", target.as_str())]),
+                    "create MFS directory",
+                )
+                .await?;
+                record_staged(staging, entry, "").await?;
+            }
+            continue;
+        }
+        files += 1;
+        if previous == Some(&(false, entry.size, entry.mtime)) {
+            continue;
+        }
+        let cid = add_unpinned(ipfs, &dir.join(&entry.path)).await?;
+        if previous.is_some() {
+            clear_mfs_dir(ipfs, &target).await?;
+        }
+        copy_into_mfs(ipfs, &cid, &target).await?;
+        record_staged(staging, entry, &cid).await?;
+        added += 1;
+    }
+    Ok((files, added))
+}
+
+async fn copy_into_mfs(ipfs: &IpfsClient, cid: &str, target: &str) -> Result<(), String> {
+    api_call(
+        ipfs.api_post("files/cp").query(&[("arg", format!("/ipfs/{}", cid).as_str()), ("arg", target)]),
+        &format!("copy {} to {}", cid, target),
+    )
+    .await
+    .map(|_| ())
+}
+
+async fn clear_mfs_dir(ipfs: &IpfsClient, mfs_dir: &str) -> Result<(), String> {
+    match api_call(
+        ipfs.api_post("files/rm?recursive=true&force=true").query(&[("arg", mfs_dir)]),
+        &format!("clear {}", mfs_dir),
+    )
+    .await
+    {
+        Err(e) if !e.contains("does not exist") => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Drops a staging directory once its root is pinned or no longer needed.
+pub async fn clear_staging(ipfs: &IpfsClient, staging: &str) -> Result<(), String> {
+    clear_mfs_dir(ipfs, &format!("{}/{}", MFS_ROOT, staging)).await?;
+    forget_staged(staging, None).await
+}
+
+/// Adds the file or folder at `local` to the node, unpinned, and returns its root CID.
+/// Folders are kept in the MFS staging directory `staging` between calls and only what
+/// changed on disk is added again; removed files drop out of the new root.
+pub async fn build_root(ipfs: &IpfsClient, staging: &str, local: &Path) -> Result<String, String> {
+    if local.is_file() {
+        return add_unpinned(ipfs, local).await;
+    }
+    let mfs_dir = format!("{}/{}", MFS_ROOT, staging);
+    let (files, added) = stage_dir(ipfs, staging, local, &mfs_dir).await?;
+    let stat = api_json(
+        ipfs.api_post("files/stat").query(&[("arg", mfs_dir.as_str())]),
+        "stat MFS directory",
+    )
+    .await?;
+    let cid = string_field(&stat, "Hash", "files/stat")?;
+    println!("[IPNS] Staged {} ({} files, {} added) as {}", local.display(), files, added, cid);
+    Ok(cid)
+}
+
+async fn generate_key(ipfs: &IpfsClient) -> Result<(String, String), String> {
+    let key_name = format!("{}{}", KEY_PREFIX, uuid::Uuid::new_v4());
+    let body = api_json(
+        ipfs.api_post("key/gen?type=ed25519").query(&[("arg", key_name.as_str())]),
+        "generate IPNS key",
+    )
+    .await?;
+    let ipns_name = string_field(&body, "Id", "key/gen")?;
+    Ok((key_name, ipns_name))
+}
+
+async fn remove_key(ipfs: &IpfsClient, key_name: &str) -> Result<(), String> {
+    api_call(ipfs.api_post("key/rm").query(&[("arg", key_name)]), "remove IPNS key")
+        .await
+        .map(|_| ())
+}
+
+/// Points `key_name` at `cid`. Offline publishing is allowed so a node without peers
+/// still records the update; it is announced once the node is connected.
+async fn publish(ipfs: &IpfsClient, key_name: &str, cid: &str) -> Result<String, String> {
+    let body = api_json(
+        ipfs.api_post("name/publish?allow-offline=true")
+            .query(&[("arg", format!("/ipfs/{}", cid).as_str()), ("key", key_name)]),
+        "publish IPNS record",
+    )
+    .await?;
+    string_field(&body, "Name", "name/publish")
+}
+
+/// Pins the new root and releases the one it replaces.
+async fn swap_pin(ipfs: &IpfsClient, old_cid: &str, new_cid: &str) -> Result<(), String> {
+    pin_local(ipfs, new_cid).await?;
+    // A share link may still hold the old root
+    if !old_cid.is_empty() && old_cid != new_cid && !is_cid_shared(old_cid).await.unwrap_or(true) {
+        if let Err(e) = unpin_local(ipfs, old_cid).await {
+            eprintln!("[IPNS] Failed to unpin previous root {}: {}", old_cid, e);
+        }
+    }
+    Ok(())
+}
+
+async fn get_record(owner: &str, path: &str) -> Result<Option<IpnsRecord>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let row = sqlx::query("SELECT * FROM ipns_names WHERE owner = ? AND path = ?")
+        .bind(owner)
+        .bind(path)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to query ipns_names: {}", e))?;
+    Ok(row.as_ref().map(row_to_record))
+}
+
+async fn save_record(record: &IpnsRecord) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query(
+        "INSERT INTO ipns_names (owner, path, key_name, ipns_name, last_cid, published_at)
+         VALUES (?, ?, ?, ?, ?, ?)
+         ON CONFLICT(owner, path) DO UPDATE SET
+            key_name = excluded.key_name,
+            ipns_name = excluded.ipns_name,
+            last_cid = excluded.last_cid,
+            published_at = excluded.published_at"
+    )
+    .bind(&record.owner)
+    .bind(&record.path)
+    .bind(&record.key_name)
+    .bind(&record.ipns_name)
+    .bind(&record.last_cid)
+    .bind(record.published_at)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to save IPNS name for {}: {}", record.path, e))?;
+    Ok(())
+}
+
+/// IPNS names published by `owner`.
+pub async fn list_ipns_names(owner: &str) -> Result<Vec<IpnsRecord>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query("SELECT * FROM ipns_names WHERE owner = ? ORDER BY path")
+        .bind(owner)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to query ipns_names: {}", e))?;
+    Ok(rows.iter().map(row_to_record).collect())
+}
+
+/// Publishes the public file or folder at `path` (relative to the public sync folder)
+/// under a node-managed IPNS key, creating the key on first use. The name stays the
+/// same across republishes; only the CID it points to changes. With `force` false the
+/// record is left alone when the content has not changed.
+pub async fn publish_public_path(
+    ipfs: &IpfsClient,
+    owner: &str,
+    path: &str,
+    force: bool,
+) -> Result<IpnsRecord, String> {
+    let (path, local) = resolve_public_path(path).await?;
+    let existing = get_record(owner, &path).await?;
+    let (key_name, ipns_name, old_cid) = match &existing {
+        Some(record) => (record.key_name.clone(), record.ipns_name.clone(), record.last_cid.clone()),
+        None => {
+            let (key_name, ipns_name) = generate_key(ipfs).await?;
+            (key_name, ipns_name, String::new())
+        }
+    };
+
+    let cid = match build_root(ipfs, &key_name, &local).await {
+        Ok(cid) => cid,
+        Err(e) => {
+            if existing.is_none() {
+                let _ = remove_key(ipfs, &key_name).await;
+            }
+            return Err(e);
+        }
+    };
+    if !force && cid == old_cid {
+        if let Some(record) = existing {
+            return Ok(record);
+        }
+    }
+
+    if let Err(e) = publish(ipfs, &key_name, &cid).await {
+        if existing.is_none() {
+            let _ = remove_key(ipfs, &key_name).await;
+        }
+        return Err(e);
+    }
+    swap_pin(ipfs, &old_cid, &cid).await?;
+
+    let record = IpnsRecord {
+        owner: owner.to_string(),
+        link: format!("/ipns/{}", ipns_name),
+        path,
+        key_name,
+        ipns_name,
+        last_cid: cid,
+        published_at: chrono::Utc::now().timestamp(),
+    };
+    save_record(&record).await?;
+    println!("[IPNS] Published {} as {} -> {}", record.path, record.link, record.last_cid);
+    Ok(record)
+}
+
+// Whether a change at index path `changed` affects what is published for `published`
+fn touches(published: &str, changed: &str) -> bool {
+    changed == published
+        || changed
+            .strip_prefix(published)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
+
+/// Republishes the IPNS names `owner` has whose file or folder contains one of
+/// `changed_paths` (index paths from the public sync), leaving the rest alone so an
+/// unchanged tree is not re-read and re-added every cycle. Failures are logged per name
+/// so one broken entry does not hold up the rest.
+pub async fn republish_public_names(ipfs: &IpfsClient, owner: &str, changed_paths: &[String]) {
+    let records = match list_ipns_names(owner).await {
+        Ok(records) => records,
+        Err(e) => {
+            eprintln!("[IPNS] Failed to load IPNS names for {}: {}", owner, e);
+            return;
+        }
+    };
+    for record in records
+        .into_iter()
+        .filter(|r| changed_paths.iter().any(|changed| touches(&r.path, changed)))
+    {
+        if let Err(e) = publish_public_path(ipfs, owner, &record.path, false).await {
+            eprintln!("[IPNS] Failed to republish {}: {}", record.path, e);
+        }
+    }
+}
+
+/// Replaces the key behind `path` with a fresh one and publishes the current content
+/// under it. The old name is dropped along with its key, so links using it stop
+/// following updates.
+pub async fn rotate_ipns_key(ipfs: &IpfsClient, owner: &str, path: &str) -> Result<IpnsRecord, String> {
+    let (path, local) = resolve_public_path(path).await?;
+    let old = get_record(owner, &path)
+        .await?
+        .ok_or_else(|| format!("'{}' has not been published to IPNS", path))?;
+
+    let (key_name, ipns_name) = generate_key(ipfs).await?;
+    let cid = build_root(ipfs, &key_name, &local).await?;
+    if let Err(e) = publish(ipfs, &key_name, &cid).await {
+        let _ = remove_key(ipfs, &key_name).await;
+        return Err(e);
+    }
+    swap_pin(ipfs, &old.last_cid, &cid).await?;
+
+    let record = IpnsRecord {
+        owner: owner.to_string(),
+        link: format!("/ipns/{}", ipns_name),
+        path,
+        key_name,
+        ipns_name,
+        last_cid: cid,
+        published_at: chrono::Utc::now().timestamp(),
+    };
+    save_record(&record).await?;
+
+    if let Err(e) = remove_key(ipfs, &old.key_name).await {
+        eprintln!("[IPNS] Failed to remove old key {}: {}", old.key_name, e);
+    }
+    let _ = clear_staging(ipfs, &old.key_name).await;
+    println!("[IPNS] Rotated {} from {} to {}", record.path, old.link, record.link);
+    Ok(record)
+}
+
+/// Resolves an IPNS name ("k51...", "/ipns/k51..." or a DNSLink domain) to the
+/// `/ipfs/...` path it currently points to.
+pub async fn resolve_ipns_name(ipfs: &IpfsClient, name: &str) -> Result<String, String> {
+    let name = name.trim();
+    let arg = if name.starts_with("/ipns/") {
+        name.to_string()
+    } else {
+        format!("/ipns/{}", name)
+    };
+    let body = api_json(
+        ipfs.api_post("name/resolve?recursive=true").query(&[("arg", arg.as_str())]),
+        "resolve IPNS name",
+    )
+    .await?;
+    string_field(&body, "Path", "name/resolve")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use crate::utils::ipfs::IpfsConfig;
+    use crate::utils::test_db::test_db;
+    use crate::utils::test_http::serve;
+    use crate::utils::trustless::raw_cid_for;
+
+    // MFS path -> CID ('' for directories), and the names of every file added
+    type Mfs = Arc<Mutex<BTreeMap<String, String>>>;
+    type Added = Arc<Mutex<Vec<String>>>;
+
+    async fn mock_mfs() -> (IpfsClient, Mfs, Added) {
+        let (mfs, added) = (Mfs::default(), Added::default());
+        let (served, adds) = (mfs.clone(), added.clone());
+        let url = serve(move |request| {
+            let args = request.query("arg");
+            let mut mfs = served.lock().unwrap();
+            match request.path() {
+                "/api/v0/add" => {
+                    let data = request.first_part();
+                    adds.lock().unwrap().push(String::from_utf8_lossy(&data).to_string());
+                    let cid = raw_cid_for(&data).to_string_form();
+                    (200, serde_json::json!({ "Hash": cid }).to_string().into_bytes())
+                }
+                "/api/v0/files/mkdir" => {
+                    mfs.insert(args[0].clone(), String::new());
+                    (200, Vec::new())
+                }
+                "/api/v0/files/cp" if !mfs.contains_key(&args[1]) => {
+                    mfs.insert(args[1].clone(), args[0].trim_start_matches("/ipfs/").to_string());
+                    (200, Vec::new())
+                }
+                "/api/v0/files/cp" => (500, b"directory already has entry by that name".to_vec()),
+                "/api/v0/files/rm" => {
+                    let below = format!("{}/", args[0]);
+                    mfs.retain(|path, _| *path != args[0] && !path.starts_with(&below));
+                    (200, Vec::new())
+                }
+                "/api/v0/files/stat" if mfs.contains_key(&args[0]) => {
+                    // Stands in for the directory CID: changes whenever anything below does
+                    let below = format!("{}/", args[0]);
+                    let listing: String = mfs
+                        .iter()
+                        .filter(|(path, _)| path.starts_with(&below))
+                        .map(|(path, cid)| format!("{}={};", path, cid))
+                        .collect();
+                    let cid = raw_cid_for(listing.as_bytes()).to_string_form();
+                    (200, serde_json::json!({ "Hash": cid }).to_string().into_bytes())
+                }
+                "/api/v0/files/stat" => (500, b"file does not exist".to_vec()),
+                _ => (404, Vec::new()),
+            }
+        })
+        .await;
+        let ipfs = IpfsClient::new(IpfsConfig { api_url: url, api_headers: HashMap::new(), gateways: Vec::new() });
+        (ipfs, mfs, added)
+    }
+
+    fn take(added: &Added) -> Vec<String> {
+        let mut taken = std::mem::take(&mut *added.lock().unwrap());
+        taken.sort();
+        taken
+    }
+
+    #[tokio::test]
+    async fn restaging_only_adds_what_changed() {
+        test_db().await;
+        let (ipfs, mfs, added) = mock_mfs().await;
+        let dir = tempfile::tempdir().unwrap();
+        let site = dir.path().join("site");
+        std::fs::create_dir_all(site.join("css")).unwrap();
+        std::fs::write(site.join("index.html"), "index").unwrap();
+        std::fs::write(site.join("css/main.css"), "css").unwrap();
+        std::fs::write(site.join("draft.tmp"), "draft").unwrap();
+
+        let first = build_root(&ipfs, "restage", &site).await.unwrap();
+        assert_eq!(take(&added), vec!["css", "index"]);
+        let staged: Vec<String> = mfs.lock().unwrap().keys().cloned().collect();
+        assert_eq!(
+            staged,
+            vec![
+                "/hippius-ipns/restage",
+                "/hippius-ipns/restage/css",
+                "/hippius-ipns/restage/css/main.css",
+                "/hippius-ipns/restage/index.html",
+            ]
+        );
+
+        // Nothing changed on disk, nothing is added
+        assert_eq!(build_root(&ipfs, "restage", &site).await.unwrap(), first);
+        assert!(take(&added).is_empty());
+
+        std::fs::write(site.join("index.html"), "index, edited").unwrap();
+        std::fs::remove_dir_all(site.join("css")).unwrap();
+        std::fs::write(site.join("css"), "now a file").unwrap();
+        let second = build_root(&ipfs, "restage", &site).await.unwrap();
+        assert_ne!(second, first);
+        assert_eq!(take(&added), vec!["index, edited", "now a file"]);
+        let staged: Vec<String> = mfs.lock().unwrap().keys().cloned().collect();
+        assert_eq!(
+            staged,
+            vec!["/hippius-ipns/restage", "/hippius-ipns/restage/css", "/hippius-ipns/restage/index.html"]
+        );
+        assert_eq!(mfs.lock().unwrap()["/hippius-ipns/restage/css"], raw_cid_for(b"now a file").to_string_form());
+
+        // Once the staging directory is gone everything is added again
+        clear_staging(&ipfs, "restage").await.unwrap();
+        assert_eq!(build_root(&ipfs, "restage", &site).await.unwrap(), second);
+        assert_eq!(take(&added).len(), 2);
+        mfs.lock().unwrap().clear();
+        assert_eq!(build_root(&ipfs, "restage", &site).await.unwrap(), second);
+        assert_eq!(take(&added).len(), 2);
+    }
+
+    #[test]
+    fn only_changes_inside_a_published_path_touch_it() {
+        assert!(touches("photos", "photos"));
+        assert!(touches("photos", "photos/2024/a.jpg"));
+        assert!(!touches("photos", "photos-old/a.jpg"));
+        assert!(!touches("photos/2024", "photos"));
+        assert!(!touches("notes.txt", "notes.txt.bak"));
+    }
+}
@@ -0,0 +1,742 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use sodiumoxide::crypto::secretbox;
+use sqlx::Row;
+use tauri::{AppHandle, Emitter};
+use crate::commands::ipfs_commands::fetch_file_metadata;
+use crate::commands::substrate_tx::{account_secret, request_file_storage, request_file_unpin};
+use crate::commands::types::EntryKind;
+use crate::utils::accounts::{
+    create_and_store_encryption_key, find_encryption_key, import_encryption_key,
+    resolve_encryption_key,
+};
+use crate::utils::car::object_cids;
+use crate::utils::erasure::{encrypt_and_encode_file, reconstruct_file_streaming, ErasureParams};
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::key_store;
+use crate::utils::manifest::{folder_manifest_bytes, parse_folder_manifest};
+use crate::utils::pins::unpin_local;
+use crate::utils::stream_crypto::key_id;
+use crate::DB_POOL;
+
+pub const RUNNING: &str = "running";
+pub const COMPLETED: &str = "completed";
+pub const FAILED: &str = "failed";
+
+// Item states
+pub const PENDING: &str = "pending";
+pub const DONE: &str = "done";
+
+/// Emitted with a `KeyRotation` whenever a rotation starts, finishes an item or ends.
+pub const KEY_ROTATION_EVENT: &str = "key_rotation_progress";
+
+static APP_HANDLE: OnceCell<AppHandle> = OnceCell::new();
+// Rotations run one at a time; each one re-encrypts its items in order
+static RUN_LOCK: Lazy<tokio::sync::Mutex<()>> = Lazy::new(|| tokio::sync::Mutex::new(()));
+// Signing mnemonics of the rotations this run started or retried, by rotation ID. They
+// are never written to the DB, so a rotation a shutdown interrupts asks for it again.
+static MNEMONICS: Lazy<std::sync::Mutex<HashMap<String, String>>> = Lazy::new(Default::default);
+
+/// Error of a rotation left unfinished by a shutdown; retrying it continues it.
+pub const INTERRUPTED: &str = "Interrupted before it finished; retry it with the account's mnemonic";
+
+/// Something to re-encrypt: a file's metadata CID or a private folder's manifest CID.
+/// `name` stands in for the folder name legacy manifests do not record.
+#[derive(Debug, Clone, Serialize)]
+pub struct RotationTarget {
+    pub cid: String,
+    pub kind: EntryKind,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RotationItem {
+    pub cid: String,
+    pub kind: EntryKind,
+    pub name: String,
+    pub status: String,
+    // CID of the re-encrypted copy; the same as `cid` when nothing needed rotating
+    pub new_cid: Option<String>,
+    pub error: Option<String>,
+}
+
+/// A rotation and its items. An item is done once the account's records and storage
+/// requests point at `new_cid` and the old copy is unpinned.
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyRotation {
+    pub id: String,
+    // Whose private items are rotated
+    pub account_id: String,
+    // Hex ID of the key everything is re-encrypted under
+    pub key_id: String,
+    pub status: String,
+    pub total: usize,
+    pub done: usize,
+    pub failed: usize,
+    pub error: Option<String>,
+    pub items: Vec<RotationItem>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+fn kind_str(kind: EntryKind) -> &'static str {
+    match kind {
+        EntryKind::File => "file",
+        EntryKind::Folder => "folder",
+    }
+}
+
+fn parse_kind(kind: &str) -> EntryKind {
+    if kind == "folder" {
+        EntryKind::Folder
+    } else {
+        EntryKind::File
+    }
+}
+
+pub async fn get_key_rotation(id: &str) -> Result<KeyRotation, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let row = sqlx::query("SELECT * FROM key_rotations WHERE id = ?")
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to look up key rotation {}: {}", id, e))?
+        .ok_or_else(|| format!("No key rotation with id {}", id))?;
+    let item_rows = sqlx::query("SELECT * FROM key_rotation_items WHERE rotation_id = ? ORDER BY rowid")
+        .bind(id)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to read key rotation {}: {}", id, e))?;
+
+    let items: Vec<RotationItem> = item_rows
+        .iter()
+        .map(|row| RotationItem {
+            cid: row.get("cid"),
+            kind: parse_kind(row.get::<String, _>("kind").as_str()),
+            name: row.get("name"),
+            status: row.get("status"),
+            new_cid: row.get("new_cid"),
+            error: row.get("error"),
+        })
+        .collect();
+    Ok(KeyRotation {
+        id: row.get("id"),
+        account_id: row.get("account_id"),
+        key_id: row.get("key_id"),
+        status: row.get("status"),
+        total: items.len(),
+        done: items.iter().filter(|item| item.status == DONE).count(),
+        failed: items.iter().filter(|item| item.status == FAILED).count(),
+        error: row.get("error"),
+        items,
+        created_at: row.get("created_at"),
+        updated_at: row.get("updated_at"),
+    })
+}
+
+pub async fn list_key_rotations() -> Result<Vec<KeyRotation>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query("SELECT id FROM key_rotations ORDER BY created_at, rowid")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to list key rotations: {}", e))?;
+    let mut rotations = Vec::with_capacity(rows.len());
+    for row in rows {
+        rotations.push(get_key_rotation(row.get::<String, _>("id").as_str()).await?);
+    }
+    Ok(rotations)
+}
+
+async fn emit_rotation(id: &str) {
+    let Some(app) = APP_HANDLE.get() else {
+        return;
+    };
+    match get_key_rotation(id).await {
+        Ok(rotation) => {
+            if let Err(e) = app.emit(KEY_ROTATION_EVENT, rotation) {
+                eprintln!("[KeyRotation] Failed to emit progress for {}: {}", id, e);
+            }
+        }
+        Err(e) => eprintln!("[KeyRotation] {}", e),
+    }
+}
+
+async fn set_status(id: &str, status: &str, error: Option<&str>) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query("UPDATE key_rotations SET status = ?, error = ?, updated_at = ? WHERE id = ?")
+        .bind(status)
+        .bind(error)
+        .bind(chrono::Utc::now().timestamp())
+        .bind(id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to update key rotation {}: {}", id, e))?;
+    emit_rotation(id).await;
+    Ok(())
+}
+
+async fn set_item(
+    id: &str,
+    cid: &str,
+    status: &str,
+    new_cid: Option<&str>,
+    error: Option<&str>,
+) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query(
+        "UPDATE key_rotation_items SET status = ?, new_cid = ?, error = ? WHERE rotation_id = ? AND cid = ?",
+    )
+    .bind(status)
+    .bind(new_cid)
+    .bind(error)
+    .bind(id)
+    .bind(cid)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to update key rotation {}: {}", id, e))?;
+    sqlx::query("UPDATE key_rotations SET updated_at = ? WHERE id = ?")
+        .bind(chrono::Utc::now().timestamp())
+        .bind(id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to update key rotation {}: {}", id, e))?;
+    emit_rotation(id).await;
+    Ok(())
+}
+
+/// Marks rotations the last shutdown interrupted as failed. Their mnemonic went with
+/// the shutdown, so they continue, at their first unfinished item, once retried with it.
+pub async fn init_key_rotations(app: AppHandle) {
+    let _ = APP_HANDLE.set(app);
+    let Some(pool) = DB_POOL.get() else {
+        return;
+    };
+    let interrupted = sqlx::query("UPDATE key_rotations SET status = ?, error = ?, updated_at = ? WHERE status = ?")
+        .bind(FAILED)
+        .bind(INTERRUPTED)
+        .bind(chrono::Utc::now().timestamp())
+        .bind(RUNNING)
+        .execute(pool)
+        .await;
+    match interrupted {
+        Ok(result) if result.rows_affected() > 0 => {
+            println!("[KeyRotation] {} interrupted rotation(s) wait for a retry", result.rows_affected())
+        }
+        Ok(_) => {}
+        Err(e) => eprintln!("[KeyRotation] Failed to mark interrupted rotations: {}", e),
+    }
+}
+
+/// Restarts rotations left running by a locked key store. A rotation that is still
+/// going is picked up again once it finishes, with nothing left to do.
+pub async fn resume_key_rotations() {
+    let Some(pool) = DB_POOL.get() else {
+        return;
+    };
+    let rows = match sqlx::query("SELECT id FROM key_rotations WHERE status = ? ORDER BY created_at, rowid")
+        .bind(RUNNING)
+        .fetch_all(pool)
+        .await
+    {
+        Ok(rows) => rows,
+        Err(e) => {
+            eprintln!("[KeyRotation] Failed to read interrupted rotations: {}", e);
+            return;
+        }
+    };
+    for row in rows {
+        let id: String = row.get("id");
+        if !MNEMONICS.lock().unwrap().contains_key(&id) {
+            continue;
+        }
+        println!("[KeyRotation] Resuming rotation {}", id);
+        tokio::spawn(async move { run_rotation(&id).await });
+    }
+}
+
+// The key to rotate to: `new_key`, stored first if the DB lacks it, or a fresh one
+async fn target_key(new_key: Option<Vec<u8>>) -> Result<secretbox::Key, String> {
+    match new_key {
+        Some(bytes) => {
+            let key = secretbox::Key::from_slice(&bytes).ok_or("Invalid key length".to_string())?;
+            if find_encryption_key(&key_id(&key)).await?.is_none() {
+                import_encryption_key(bytes).await?;
+            }
+            Ok(key)
+        }
+        None => {
+            create_and_store_encryption_key().await?;
+            resolve_encryption_key(None).await
+        }
+    }
+}
+
+// Suffixes of top-level private items in the profile, longest first
+const PROFILE_SUFFIXES: &[&str] = &[".folder.ec_metadata", "-folder.ec_metadata", ".ec_metadata"];
+
+/// The account's private files and folders as its profile records them, one target per
+/// distinct CID. Files inside folders are rotated with their folder.
+pub async fn private_items(account_id: &str) -> Result<Vec<RotationTarget>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query(
+        "SELECT file_hash, file_name, is_folder FROM user_profiles
+         WHERE owner = ? AND type = 'private' AND file_hash IS NOT NULL AND file_hash != ''
+         ORDER BY id",
+    )
+    .bind(account_id)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to list private items of {}: {}", account_id, e))?;
+
+    let mut seen = HashSet::new();
+    let mut targets = Vec::new();
+    for row in rows {
+        let file_hash: String = row.get("file_hash");
+        let Some(cid) = hex::decode(&file_hash).ok().and_then(|bytes| String::from_utf8(bytes).ok()) else {
+            eprintln!("[KeyRotation] Skipping record with invalid file hash {}", file_hash);
+            continue;
+        };
+        if !seen.insert(cid.clone()) {
+            continue;
+        }
+        let file_name: String = row.get("file_name");
+        let name = PROFILE_SUFFIXES
+            .iter()
+            .find_map(|suffix| file_name.strip_suffix(suffix))
+            .unwrap_or(&file_name)
+            .to_string();
+        let kind = if row.get::<bool, _>("is_folder") {
+            EntryKind::Folder
+        } else {
+            EntryKind::File
+        };
+        targets.push(RotationTarget { cid, kind, name });
+    }
+    Ok(targets)
+}
+
+/// Starts re-encrypting every private item of `account_id` under `new_key`, or under a
+/// newly created key, and returns the rotation. Files already under that key are left
+/// as they are. `mnemonic` signs the storage requests for the new copies; it is only
+/// kept in memory, for as long as the rotation runs.
+pub async fn start_key_rotation(
+    account_id: &str,
+    mnemonic: &str,
+    new_key: Option<Vec<u8>>,
+) -> Result<KeyRotation, String> {
+    account_secret(mnemonic, account_id)?;
+    let targets = private_items(account_id).await?;
+    if targets.is_empty() {
+        return Err("Nothing to rotate".to_string());
+    }
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let key = target_key(new_key).await?;
+    let id = uuid::Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().timestamp();
+
+    let mut tx = pool
+        .begin()
+        .await
+        .map_err(|e| format!("Failed to start key rotation: {}", e))?;
+    sqlx::query(
+        "INSERT INTO key_rotations (id, account_id, key_id, status, created_at, updated_at)
+         VALUES (?, ?, ?, ?, ?, ?)",
+    )
+    .bind(&id)
+    .bind(account_id)
+    .bind(hex::encode(key_id(&key)))
+    .bind(RUNNING)
+    .bind(now)
+    .bind(now)
+    .execute(&mut *tx)
+    .await
+    .map_err(|e| format!("Failed to start key rotation: {}", e))?;
+    for target in &targets {
+        sqlx::query(
+            "INSERT OR IGNORE INTO key_rotation_items (rotation_id, cid, kind, name, status) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(&id)
+        .bind(&target.cid)
+        .bind(kind_str(target.kind))
+        .bind(&target.name)
+        .bind(PENDING)
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("Failed to start key rotation: {}", e))?;
+    }
+    tx.commit()
+        .await
+        .map_err(|e| format!("Failed to start key rotation: {}", e))?;
+
+    println!("[KeyRotation] Rotation {} started for {} item(s)", id, targets.len());
+    MNEMONICS.lock().unwrap().insert(id.clone(), mnemonic.to_string());
+    emit_rotation(&id).await;
+    let task_id = id.clone();
+    tokio::spawn(async move { run_rotation(&task_id).await });
+    get_key_rotation(&id).await
+}
+
+/// Runs the failed and unfinished items of a failed rotation again, signing with
+/// `mnemonic` as `start_key_rotation` does.
+pub async fn retry_key_rotation(id: &str, mnemonic: &str) -> Result<KeyRotation, String> {
+    let rotation = get_key_rotation(id).await?;
+    if rotation.status != FAILED {
+        return Err(format!("Key rotation {} is {} and cannot be retried", id, rotation.status));
+    }
+    account_secret(mnemonic, &rotation.account_id)?;
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query("UPDATE key_rotation_items SET status = ?, error = NULL WHERE rotation_id = ? AND status = ?")
+        .bind(PENDING)
+        .bind(id)
+        .bind(FAILED)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to update key rotation {}: {}", id, e))?;
+    MNEMONICS.lock().unwrap().insert(id.to_string(), mnemonic.to_string());
+    set_status(id, RUNNING, None).await?;
+    let task_id = id.to_string();
+    tokio::spawn(async move { run_rotation(&task_id).await });
+    get_key_rotation(id).await
+}
+
+async fn run_rotation(id: &str) {
+    let _guard = RUN_LOCK.lock().await;
+    let result = rotate_pending(id).await;
+    // Stays running, with its mnemonic, so unlocking resumes it
+    if result.as_ref().err().map(String::as_str) == Some(key_store::LOCKED) {
+        println!("[KeyRotation] Rotation {} is waiting for the key store to be unlocked", id);
+        return;
+    }
+    MNEMONICS.lock().unwrap().remove(id);
+    if let Err(e) = result {
+        eprintln!("[KeyRotation] Rotation {} stopped: {}", id, e);
+        if let Err(e) = set_status(id, FAILED, Some(&e)).await {
+            eprintln!("[KeyRotation] {}", e);
+        }
+    }
+}
+
+// The signing mnemonic of a running rotation
+fn rotation_mnemonic(id: &str) -> Result<String, String> {
+    MNEMONICS
+        .lock()
+        .unwrap()
+        .get(id)
+        .cloned()
+        .ok_or_else(|| format!("Key rotation {} needs the account's mnemonic again; retry it", id))
+}
+
+// Works through the pending items, recording each outcome as it goes, then settles
+// the rotation's status. An item whose copy was made before a failure keeps its
+// `new_cid`, so a retry only redoes the swap.
+async fn rotate_pending(id: &str) -> Result<(), String> {
+    let rotation = get_key_rotation(id).await?;
+    let key_bytes = hex::decode(&rotation.key_id)
+        .map_err(|e| format!("Invalid key ID {}: {}", rotation.key_id, e))?;
+    let key = find_encryption_key(&key_bytes)
+        .await?
+        .ok_or_else(|| format!("Key {} is no longer in this app", rotation.key_id))?;
+    let mnemonic = rotation_mnemonic(id)?;
+    let ipfs = IpfsClient::from_settings().await;
+
+    for item in rotation.items.iter().filter(|item| item.status == PENDING) {
+        let new_cid = match &item.new_cid {
+            Some(new_cid) => Ok(new_cid.clone()),
+            None => match item.kind {
+                EntryKind::File => rotate_file(&ipfs, &item.cid, &key).await,
+                EntryKind::Folder => rotate_folder(&ipfs, &item.cid, &item.name, &key).await,
+            },
+        };
+        let new_cid = match new_cid {
+            Ok(new_cid) => new_cid,
+            Err(e) => {
+                eprintln!("[KeyRotation] Failed to rotate {}: {}", item.cid, e);
+                set_item(id, &item.cid, FAILED, None, Some(&e)).await?;
+                continue;
+            }
+        };
+        if new_cid == item.cid {
+            set_item(id, &item.cid, DONE, Some(&new_cid), None).await?;
+            continue;
+        }
+        set_item(id, &item.cid, PENDING, Some(&new_cid), None).await?;
+        match replace_references(&ipfs, &rotation.account_id, &mnemonic, &item.cid, &new_cid).await {
+            Ok(()) => {
+                println!("[KeyRotation] Rotated {} to {}", item.cid, new_cid);
+                set_item(id, &item.cid, DONE, Some(&new_cid), None).await?;
+            }
+            Err(e) => {
+                eprintln!("[KeyRotation] Rotated {} to {}, but failed to swap it in: {}", item.cid, new_cid, e);
+                set_item(id, &item.cid, FAILED, Some(&new_cid), Some(&e)).await?;
+            }
+        }
+    }
+
+    let rotation = get_key_rotation(id).await?;
+    if rotation.failed == 0 {
+        println!("[KeyRotation] Rotation {} completed", id);
+        set_status(id, COMPLETED, None).await
+    } else {
+        let error = format!("{} of {} item(s) failed", rotation.failed, rotation.total);
+        set_status(id, FAILED, Some(&error)).await
+    }
+}
+
+/// Points everything that refers to `old_cid` at `new_cid`: storage requests on chain
+/// first, then the account's profile records. The old copy is unpinned last, keeping
+/// whatever the new one still uses and anything shared with another account.
+pub(crate) async fn replace_references(
+    ipfs: &IpfsClient,
+    account_id: &str,
+    mnemonic: &str,
+    old_cid: &str,
+    new_cid: &str,
+) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let old_hash = hex::encode(old_cid);
+    let rows = sqlx::query(
+        "SELECT DISTINCT file_name FROM user_profiles
+         WHERE owner = ? AND file_hash = ? AND main_req_hash NOT IN ('s3', 'car')",
+    )
+    .bind(account_id)
+    .bind(&old_hash)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to look up records of {}: {}", old_cid, e))?;
+    for row in &rows {
+        request_file_storage(mnemonic, row.get::<String, _>("file_name").as_str(), new_cid).await?;
+    }
+    if !rows.is_empty() {
+        request_file_unpin(mnemonic, old_cid).await?;
+    }
+
+    sqlx::query(
+        "UPDATE user_profiles SET file_hash = ?, cid = CASE WHEN cid = ? THEN ? ELSE cid END
+         WHERE owner = ? AND file_hash = ?",
+    )
+    .bind(hex::encode(new_cid))
+    .bind(old_cid)
+    .bind(new_cid)
+    .bind(account_id)
+    .bind(&old_hash)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to update records of {}: {}", old_cid, e))?;
+
+    let shared: Option<(i64,)> = sqlx::query_as("SELECT 1 FROM account_shares WHERE sender = ? AND cid = ? LIMIT 1")
+        .bind(account_id)
+        .bind(old_cid)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to look up shares of {}: {}", old_cid, e))?;
+    if shared.is_some() {
+        println!("[Storage] Keeping {} pinned; it is shared with another account", old_cid);
+        return Ok(());
+    }
+    let kept: HashSet<String> = object_cids(ipfs, new_cid).await?.into_iter().collect();
+    for cid in object_cids(ipfs, old_cid).await? {
+        if !kept.contains(&cid) {
+            unpin_local(ipfs, &cid).await?;
+        }
+    }
+    Ok(())
+}
+
+// Decrypts the file with whichever stored key it needs and encodes it again under
+// `key` with the same erasure parameters. Returns the new metadata CID.
+async fn rotate_file(ipfs: &IpfsClient, metadata_cid: &str, key: &secretbox::Key) -> Result<String, String> {
+    let metadata = fetch_file_metadata(ipfs, metadata_cid).await?;
+    let target = hex::encode(key_id(key));
+    if metadata.erasure_coding.key_id.as_deref() == Some(target.as_str()) {
+        return Ok(metadata_cid.to_string());
+    }
+
+    let dir = tempfile::tempdir().map_err(|e| format!("Failed to create temp dir: {}", e))?;
+    // The upload records the file name, so the plaintext keeps it
+    let name = Path::new(&metadata.original_file.name)
+        .file_name()
+        .map(|name| name.to_os_string())
+        .unwrap_or_else(|| "file".into());
+    let plaintext = dir.path().join(name);
+    reconstruct_file_streaming(&metadata, &plaintext, None, ipfs).await?;
+
+    let coding = &metadata.erasure_coding;
+    let params = ErasureParams {
+        k: coding.k,
+        m: coding.m,
+        chunk_size: coding.chunk_size,
+        compression: coding.compression,
+    };
+    let (_, new_cid) = encrypt_and_encode_file(&plaintext, params, Some(key.0.to_vec()), ipfs).await?;
+    Ok(new_cid)
+}
+
+// Rotates every file below the folder and publishes a manifest pointing at the new
+// copies. Returns the new manifest CID, or the old one when nothing changed.
+async fn rotate_folder(
+    ipfs: &IpfsClient,
+    manifest_cid: &str,
+    name: &str,
+    key: &secretbox::Key,
+) -> Result<String, String> {
+    let manifest = parse_folder_manifest(&ipfs.cat(manifest_cid).await?, name)?;
+    let folder_name = manifest.original_folder_name;
+    let mut entries = manifest.entries;
+    let mut changed = false;
+    for entry in &mut entries {
+        let new_cid = match entry.kind {
+            EntryKind::File => rotate_file(ipfs, &entry.cid, key).await,
+            EntryKind::Folder => Box::pin(rotate_folder(ipfs, &entry.cid, &entry.name, key)).await,
+        }
+        .map_err(|e| format!("{}/{}: {}", folder_name, entry.name, e))?;
+        if new_cid != entry.cid {
+            entry.cid = new_cid;
+            changed = true;
+        }
+    }
+    if !changed {
+        return Ok(manifest_cid.to_string());
+    }
+    let manifest_bytes = folder_manifest_bytes(&folder_name, entries)?;
+    ipfs.add(&format!("{}.s.folder.ec_metadata", folder_name), manifest_bytes)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_db::test_db;
+    use crate::utils::test_http::{mock_ipfs, mock_ipfs_logged};
+    use crate::utils::erasure::encrypt_and_encode_folder;
+
+    fn params() -> ErasureParams {
+        ErasureParams { k: 2, m: 3, chunk_size: 4096, compression: None }
+    }
+
+    // A key the DB knows, so that files under it can be read without naming it
+    async fn stored_key() -> secretbox::Key {
+        let key = secretbox::gen_key();
+        import_encryption_key(key.0.to_vec()).await.unwrap();
+        key
+    }
+
+    #[tokio::test]
+    async fn files_are_reencrypted_only_under_a_different_key() {
+        test_db().await;
+        let (ipfs, _store) = mock_ipfs().await;
+        let (old_key, new_key) = (stored_key().await, stored_key().await);
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("report.txt");
+        let plain = b"quarterly numbers\n".repeat(800);
+        std::fs::write(&source, &plain).unwrap();
+        let (metadata, cid) = encrypt_and_encode_file(&source, params(), Some(old_key.0.to_vec()), &ipfs)
+            .await
+            .unwrap();
+
+        assert_eq!(rotate_file(&ipfs, &cid, &old_key).await.unwrap(), cid);
+
+        let new_cid = rotate_file(&ipfs, &cid, &new_key).await.unwrap();
+        assert_ne!(new_cid, cid);
+        let rotated = fetch_file_metadata(&ipfs, &new_cid).await.unwrap();
+        assert_eq!(rotated.erasure_coding.key_id, Some(hex::encode(key_id(&new_key))));
+        assert_eq!(rotated.original_file.name, metadata.original_file.name);
+        let coding = (rotated.erasure_coding.k, rotated.erasure_coding.m, rotated.erasure_coding.chunk_size);
+        assert_eq!(coding, (2, 3, 4096));
+        let output = dir.path().join("out.txt");
+        reconstruct_file_streaming(&rotated, &output, Some(new_key.0.to_vec()), &ipfs).await.unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), plain);
+    }
+
+    #[tokio::test]
+    async fn folders_get_a_manifest_pointing_at_the_new_copies() {
+        test_db().await;
+        let (ipfs, _store) = mock_ipfs().await;
+        let (old_key, new_key) = (stored_key().await, stored_key().await);
+        let dir = tempfile::tempdir().unwrap();
+        let folder = dir.path().join("projects");
+        std::fs::create_dir_all(folder.join("drafts")).unwrap();
+        std::fs::write(folder.join("plan.txt"), b"the plan").unwrap();
+        std::fs::write(folder.join("drafts/idea.txt"), b"an idea").unwrap();
+        let cid = encrypt_and_encode_folder(&folder, params(), Some(old_key.0.to_vec()), &ipfs).await.unwrap();
+        let old_manifest = parse_folder_manifest(&ipfs.cat(&cid).await.unwrap(), "projects").unwrap();
+
+        let new_cid = rotate_folder(&ipfs, &cid, "projects", &new_key).await.unwrap();
+        assert_ne!(new_cid, cid);
+        let manifest = parse_folder_manifest(&ipfs.cat(&new_cid).await.unwrap(), "").unwrap();
+        assert_eq!(manifest.original_folder_name, "projects");
+        assert_eq!(manifest.entries.len(), old_manifest.entries.len());
+        for (entry, old) in manifest.entries.iter().zip(&old_manifest.entries) {
+            assert_eq!((entry.name.as_str(), entry.kind), (old.name.as_str(), old.kind));
+            assert_ne!(entry.cid, old.cid);
+            let file_cid = match entry.kind {
+                EntryKind::File => entry.cid.clone(),
+                EntryKind::Folder => {
+                    let sub = parse_folder_manifest(&ipfs.cat(&entry.cid).await.unwrap(), &entry.name).unwrap();
+                    sub.entries[0].cid.clone()
+                }
+            };
+            let rotated = fetch_file_metadata(&ipfs, &file_cid).await.unwrap();
+            assert_eq!(rotated.erasure_coding.key_id, Some(hex::encode(key_id(&new_key))));
+        }
+
+        // Everything is under the new key already
+        assert_eq!(rotate_folder(&ipfs, &new_cid, "projects", &new_key).await.unwrap(), new_cid);
+    }
+
+    #[tokio::test]
+    async fn shared_copies_stay_pinned_when_references_move() {
+        let pool = test_db().await;
+        let (ipfs, _store, log) = mock_ipfs_logged().await;
+        let key = stored_key().await;
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("a.txt"), b"first").unwrap();
+        std::fs::write(dir.path().join("b.txt"), b"second").unwrap();
+        let mut cids = Vec::new();
+        for name in ["a.txt", "b.txt"] {
+            let (_, cid) = encrypt_and_encode_file(&dir.path().join(name), params(), Some(key.0.to_vec()), &ipfs)
+                .await
+                .unwrap();
+            cids.push(cid);
+        }
+        let (old_cid, new_cid) = (&cids[0], &cids[1]);
+        for owner in ["rot-sharer", "rot-keeper"] {
+            sqlx::query(
+                "INSERT INTO user_profiles (owner, cid, file_hash, file_name, main_req_hash, block_number)
+                 VALUES (?, ?, ?, 'a.txt.ec_metadata', 's3', 0)",
+            )
+            .bind(owner)
+            .bind(old_cid)
+            .bind(hex::encode(old_cid))
+            .execute(pool)
+            .await
+            .unwrap();
+        }
+        sqlx::query(
+            "INSERT INTO account_shares (id, sender, recipient, name, cid, wrapped_key, created_at)
+             VALUES ('rot-share', 'rot-sharer', 'friend', 'a.txt', ?, '', 0)",
+        )
+        .bind(old_cid)
+        .execute(pool)
+        .await
+        .unwrap();
+
+        let unpins = |log: &std::sync::Mutex<Vec<String>>| {
+            log.lock().unwrap().iter().filter(|request| request.starts_with("/api/v0/pin/rm")).count()
+        };
+        replace_references(&ipfs, "rot-sharer", "words", old_cid, new_cid).await.unwrap();
+        let (cid, file_hash): (String, String) =
+            sqlx::query_as("SELECT cid, file_hash FROM user_profiles WHERE owner = 'rot-sharer'")
+                .fetch_one(pool)
+                .await
+                .unwrap();
+        assert_eq!((cid.as_str(), file_hash), (new_cid.as_str(), hex::encode(new_cid)));
+        assert_eq!(unpins(&log), 0);
+
+        // Without a share the old copy goes
+        replace_references(&ipfs, "rot-keeper", "words", old_cid, new_cid).await.unwrap();
+        let old_objects = object_cids(&ipfs, old_cid).await.unwrap();
+        assert_eq!(unpins(&log), old_objects.len());
+    }
+}
@@ -0,0 +1,721 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use base64::{engine::general_purpose, Engine as _};
+use once_cell::sync::{Lazy, OnceCell};
+use serde::Serialize;
+use sodiumoxide::crypto::pwhash::argon2id13;
+use sodiumoxide::crypto::secretbox;
+use sqlx::{Row, Sqlite, SqlitePool, Transaction};
+use tauri::{AppHandle, Emitter};
+use crate::utils::ipfs::IpfsConfig;
+use crate::utils::key_rotation::resume_key_rotations;
+use crate::utils::pins::PinningService;
+use crate::utils::settings::{
+    get_usize_setting, IPFS_CONFIG, KEY_STORE_AUTO_LOCK_MINUTES, PINNING_SERVICES,
+};
+use crate::DB_POOL;
+
+/// Emitted with a `KeyStoreStatus` at startup and whenever the store locks or unlocks.
+pub const KEY_STORE_EVENT: &str = "key_store_status";
+pub const LOCKED: &str = "Encryption keys are locked; unlock the app with its passphrase";
+const DEFAULT_AUTO_LOCK_MINUTES: usize = 15;
+const AUTO_LOCK_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+const MIN_PASSPHRASE_CHARS: usize = 8;
+
+// A wrapped key is the magic, a nonce and the sealed key; raw keys are 32 bytes, so the
+// two never collide
+const WRAPPED_MAGIC: &[u8] = b"HPKW";
+// Prefix of wrapped seed phrases, which live in a text column
+const WRAPPED_TEXT_PREFIX: &str = "hpkw1:";
+// Sealed with the passphrase key so a wrong passphrase is caught before anything is
+// unwrapped with it
+const CHECK_PLAINTEXT: &[u8] = b"hippius-key-store-v1";
+
+struct Unlocked {
+    key: secretbox::Key,
+    last_used: Instant,
+}
+
+static UNLOCKED: Mutex<Option<Unlocked>> = Mutex::new(None);
+// Shared while a secret is sealed and written, exclusive while a passphrase change
+// rewraps the store, so nothing sealed under a replaced key is written after the rewrap
+static SECRETS_LOCK: Lazy<tokio::sync::RwLock<()>> = Lazy::new(Default::default);
+static APP_HANDLE: OnceCell<AppHandle> = OnceCell::new();
+
+#[derive(Debug, Clone, Serialize)]
+pub struct KeyStoreStatus {
+    // Whether a passphrase protects the stored secrets
+    pub enabled: bool,
+    pub unlocked: bool,
+    // 0 when auto-lock is off
+    pub auto_lock_minutes: usize,
+}
+
+// Argon2id parameters and check value, as saved in the key_store table
+struct StoreParams {
+    salt: argon2id13::Salt,
+    ops_limit: usize,
+    mem_limit: usize,
+    check_value: Vec<u8>,
+}
+
+async fn load_params(pool: &SqlitePool) -> Result<Option<StoreParams>, String> {
+    let row = sqlx::query("SELECT salt, ops_limit, mem_limit, check_value FROM key_store WHERE id = 1")
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("DB error (fetch key store): {}", e))?;
+    let Some(row) = row else {
+        return Ok(None);
+    };
+    let salt: Vec<u8> = row.get("salt");
+    Ok(Some(StoreParams {
+        salt: argon2id13::Salt::from_slice(&salt).ok_or("Invalid key store salt")?,
+        ops_limit: row.get::<i64, _>("ops_limit") as usize,
+        mem_limit: row.get::<i64, _>("mem_limit") as usize,
+        check_value: row.get("check_value"),
+    }))
+}
+
+// Argon2id is slow on purpose, so it runs off the async workers
+async fn derive_key(
+    passphrase: &str,
+    salt: argon2id13::Salt,
+    ops_limit: usize,
+    mem_limit: usize,
+) -> Result<secretbox::Key, String> {
+    let passphrase = passphrase.as_bytes().to_vec();
+    tokio::task::spawn_blocking(move || {
+        let mut key = secretbox::Key([0u8; secretbox::KEYBYTES]);
+        argon2id13::derive_key(
+            &mut key.0,
+            &passphrase,
+            &salt,
+            argon2id13::OpsLimit(ops_limit),
+            argon2id13::MemLimit(mem_limit),
+        )
+        .map_err(|_| "Failed to derive a key from the passphrase".to_string())?;
+        Ok(key)
+    })
+    .await
+    .map_err(|e| format!("Key derivation task failed: {}", e))?
+}
+
+// The passphrase key for `params`, if `passphrase` is the right one
+async fn open_with_passphrase(params: &StoreParams, passphrase: &str) -> Result<secretbox::Key, String> {
+    let key = derive_key(passphrase, params.salt, params.ops_limit, params.mem_limit).await?;
+    match unwrap_with(&params.check_value, &key) {
+        Ok(check) if check == CHECK_PLAINTEXT => Ok(key),
+        _ => Err("Wrong passphrase".to_string()),
+    }
+}
+
+// Fresh salt and check value for a new passphrase
+async fn new_params(passphrase: &str) -> Result<(StoreParams, secretbox::Key), String> {
+    if passphrase.chars().count() < MIN_PASSPHRASE_CHARS {
+        return Err(format!("The passphrase needs at least {} characters", MIN_PASSPHRASE_CHARS));
+    }
+    let salt = argon2id13::gen_salt();
+    let ops_limit = argon2id13::OPSLIMIT_MODERATE.0;
+    let mem_limit = argon2id13::MEMLIMIT_MODERATE.0;
+    let key = derive_key(passphrase, salt, ops_limit, mem_limit).await?;
+    let params = StoreParams {
+        salt,
+        ops_limit,
+        mem_limit,
+        check_value: wrap_with(CHECK_PLAINTEXT, &key),
+    };
+    Ok((params, key))
+}
+
+fn wrap_with(plain: &[u8], key: &secretbox::Key) -> Vec<u8> {
+    let nonce = secretbox::gen_nonce();
+    let mut out = WRAPPED_MAGIC.to_vec();
+    out.extend_from_slice(nonce.as_ref());
+    out.extend(secretbox::seal(plain, &nonce, key));
+    out
+}
+
+fn is_wrapped(stored: &[u8]) -> bool {
+    stored.len() >= WRAPPED_MAGIC.len() + secretbox::NONCEBYTES + secretbox::MACBYTES
+        && stored.starts_with(WRAPPED_MAGIC)
+}
+
+fn unwrap_with(stored: &[u8], key: &secretbox::Key) -> Result<Vec<u8>, String> {
+    if !is_wrapped(stored) {
+        return Err("Not a wrapped secret".to_string());
+    }
+    let (nonce, sealed) = stored[WRAPPED_MAGIC.len()..].split_at(secretbox::NONCEBYTES);
+    let nonce = secretbox::Nonce::from_slice(nonce).ok_or("Invalid nonce")?;
+    secretbox::open(sealed, &nonce, key).map_err(|_| "Failed to unwrap a stored secret".to_string())
+}
+
+// The passphrase key while unlocked, counting the call as use for auto-lock
+fn current_key() -> Option<secretbox::Key> {
+    let mut unlocked = UNLOCKED.lock().unwrap();
+    unlocked.as_mut().map(|state| {
+        state.last_used = Instant::now();
+        state.key.clone()
+    })
+}
+
+/// Holds off passphrase changes while alive. Sealing a secret takes one, and the
+/// caller keeps it until the sealed value is written.
+pub struct SecretsHold {
+    _guard: tokio::sync::RwLockReadGuard<'static, ()>,
+}
+
+pub async fn hold_secrets() -> SecretsHold {
+    SecretsHold { _guard: SECRETS_LOCK.read().await }
+}
+
+/// Wraps a secret for storage when a passphrase is set, and returns it unchanged when
+/// none is. Fails while the store is locked.
+pub async fn seal_secret(_hold: &SecretsHold, plain: &[u8]) -> Result<Vec<u8>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    seal_in(pool, plain).await
+}
+
+async fn seal_in(pool: &SqlitePool, plain: &[u8]) -> Result<Vec<u8>, String> {
+    if load_params(pool).await?.is_none() {
+        return Ok(plain.to_vec());
+    }
+    let key = current_key().ok_or(LOCKED)?;
+    Ok(wrap_with(plain, &key))
+}
+
+/// Reverses `seal_secret`. Values stored without a passphrase come back as they are.
+pub fn open_secret(stored: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_wrapped(stored) {
+        return Ok(stored.to_vec());
+    }
+    let key = current_key().ok_or(LOCKED)?;
+    unwrap_with(stored, &key)
+}
+
+/// `seal_secret` for text columns.
+pub async fn seal_text(_hold: &SecretsHold, plain: &str) -> Result<String, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    seal_text_in(pool, plain).await
+}
+
+async fn seal_text_in(pool: &SqlitePool, plain: &str) -> Result<String, String> {
+    let sealed = seal_in(pool, plain.as_bytes()).await?;
+    if is_wrapped(&sealed) {
+        Ok(format!("{}{}", WRAPPED_TEXT_PREFIX, general_purpose::STANDARD.encode(sealed)))
+    } else {
+        Ok(plain.to_string())
+    }
+}
+
+/// `open_secret` for text columns.
+pub fn open_text(stored: &str) -> Result<String, String> {
+    let Some(encoded) = stored.strip_prefix(WRAPPED_TEXT_PREFIX) else {
+        return Ok(stored.to_string());
+    };
+    let sealed = general_purpose::STANDARD
+        .decode(encoded)
+        .map_err(|e| format!("Invalid wrapped secret: {}", e))?;
+    String::from_utf8(open_secret(&sealed)?).map_err(|_| "Invalid wrapped secret".to_string())
+}
+
+pub async fn key_store_status() -> Result<KeyStoreStatus, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let unlocked = UNLOCKED.lock().unwrap().is_some();
+    Ok(KeyStoreStatus {
+        enabled: load_params(pool).await?.is_some(),
+        unlocked,
+        auto_lock_minutes: get_usize_setting(KEY_STORE_AUTO_LOCK_MINUTES, DEFAULT_AUTO_LOCK_MINUTES).await,
+    })
+}
+
+async fn emit_status() {
+    let Some(app) = APP_HANDLE.get() else {
+        return;
+    };
+    match key_store_status().await {
+        Ok(status) => {
+            if let Err(e) = app.emit(KEY_STORE_EVENT, status) {
+                eprintln!("[KeyStore] Failed to emit status: {}", e);
+            }
+        }
+        Err(e) => eprintln!("[KeyStore] {}", e),
+    }
+}
+
+/// Reports the store's state so the UI can ask for the passphrase at startup, and
+/// starts the auto-lock timer.
+pub async fn init_key_store(app: AppHandle) {
+    let _ = APP_HANDLE.set(app);
+    tokio::spawn(async {
+        loop {
+            tokio::time::sleep(AUTO_LOCK_CHECK_INTERVAL).await;
+            let minutes = get_usize_setting(KEY_STORE_AUTO_LOCK_MINUTES, DEFAULT_AUTO_LOCK_MINUTES).await;
+            if minutes > 0 && lock_if_idle(Duration::from_secs(minutes as u64 * 60)) {
+                println!("[KeyStore] Locked after {} minutes without use", minutes);
+                emit_status().await;
+            }
+        }
+    });
+    emit_status().await;
+}
+
+/// Locks the store if no secret has been used for `idle`. Returns whether it did.
+pub fn lock_if_idle(idle: Duration) -> bool {
+    let mut unlocked = UNLOCKED.lock().unwrap();
+    let idle_long_enough = unlocked
+        .as_ref()
+        .is_some_and(|state| state.last_used.elapsed() >= idle);
+    if idle_long_enough {
+        *unlocked = None;
+    }
+    idle_long_enough
+}
+
+fn set_unlocked(key: Option<secretbox::Key>) {
+    *UNLOCKED.lock().unwrap() = key.map(|key| Unlocked {
+        key,
+        last_used: Instant::now(),
+    });
+}
+
+pub async fn unlock_key_store(passphrase: &str) -> Result<KeyStoreStatus, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    unlock_in(pool, passphrase).await?;
+    println!("[KeyStore] Unlocked");
+    emit_status().await;
+    tokio::spawn(resume_key_rotations());
+    key_store_status().await
+}
+
+async fn unlock_in(pool: &SqlitePool, passphrase: &str) -> Result<(), String> {
+    let params = load_params(pool).await?.ok_or("No passphrase is set")?;
+    let key = open_with_passphrase(&params, passphrase).await?;
+    set_unlocked(Some(key));
+    Ok(())
+}
+
+pub async fn lock_key_store() -> Result<KeyStoreStatus, String> {
+    set_unlocked(None);
+    println!("[KeyStore] Locked");
+    emit_status().await;
+    key_store_status().await
+}
+
+/// Forgets the passphrase key without touching the DB, for when the store's tables
+/// have just been cleared.
+pub fn forget_key_store() {
+    set_unlocked(None);
+}
+
+// Rewrites every stored secret from `from` to `to`, where None means unwrapped
+async fn rewrap_all(
+    tx: &mut Transaction<'_, Sqlite>,
+    from: Option<&secretbox::Key>,
+    to: Option<&secretbox::Key>,
+) -> Result<(), String> {
+    let open = |stored: &[u8]| match from {
+        Some(key) if is_wrapped(stored) => unwrap_with(stored, key),
+        _ => Ok(stored.to_vec()),
+    };
+    let seal = |plain: &[u8]| match to {
+        Some(key) => wrap_with(plain, key),
+        None => plain.to_vec(),
+    };
+
+    let rows = sqlx::query("SELECT id, key FROM encryption_keys")
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(|e| format!("DB error (fetch keys): {}", e))?;
+    for row in rows {
+        let id: i64 = row.get("id");
+        let stored: Vec<u8> = row.get("key");
+        sqlx::query("UPDATE encryption_keys SET key = ? WHERE id = ?")
+            .bind(seal(&open(&stored)?))
+            .bind(id)
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| format!("DB error (update key): {}", e))?;
+    }
+
+    let rows = sqlx::query("SELECT account_id, secret_key FROM sharing_keys")
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(|e| format!("DB error (fetch sharing keys): {}", e))?;
+    for row in rows {
+        let account_id: String = row.get("account_id");
+        let stored: Vec<u8> = row.get("secret_key");
+        sqlx::query("UPDATE sharing_keys SET secret_key = ? WHERE account_id = ?")
+            .bind(seal(&open(&stored)?))
+            .bind(account_id)
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| format!("DB error (update sharing key): {}", e))?;
+    }
+
+    let rows = sqlx::query("SELECT id, sealed_key FROM download_jobs WHERE sealed_key IS NOT NULL")
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(|e| format!("DB error (fetch download keys): {}", e))?;
+    for row in rows {
+        let id: String = row.get("id");
+        let stored: Vec<u8> = row.get("sealed_key");
+        sqlx::query("UPDATE download_jobs SET sealed_key = ? WHERE id = ?")
+            .bind(seal(&open(&stored)?))
+            .bind(id)
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| format!("DB error (update download key): {}", e))?;
+    }
+
+    // Text columns keep wrapped values base64-encoded behind a prefix
+    let rewrap_text = |stored: &str| -> Result<String, String> {
+        let plain = match stored.strip_prefix(WRAPPED_TEXT_PREFIX) {
+            Some(encoded) => open(
+                &general_purpose::STANDARD
+                    .decode(encoded)
+                    .map_err(|e| format!("Invalid wrapped secret: {}", e))?,
+            )?,
+            None => stored.as_bytes().to_vec(),
+        };
+        match to {
+            Some(_) => Ok(format!("{}{}", WRAPPED_TEXT_PREFIX, general_purpose::STANDARD.encode(seal(&plain)))),
+            None => String::from_utf8(plain).map_err(|_| "Invalid wrapped secret".to_string()),
+        }
+    };
+
+    let rows = sqlx::query("SELECT id, sub_account_seed_phrase FROM sub_accounts")
+        .fetch_all(&mut **tx)
+        .await
+        .map_err(|e| format!("DB error (fetch sub accounts): {}", e))?;
+    for row in rows {
+        let id: i64 = row.get("id");
+        let stored: String = row.get("sub_account_seed_phrase");
+        sqlx::query("UPDATE sub_accounts SET sub_account_seed_phrase = ? WHERE id = ?")
+            .bind(rewrap_text(&stored)?)
+            .bind(id)
+            .execute(&mut **tx)
+            .await
+            .map_err(|e| format!("DB error (update sub account): {}", e))?;
+    }
+
+    // Credentials inside settings: IPFS API header values and pinning service tokens
+    if let Some(json) = read_setting(tx, IPFS_CONFIG).await? {
+        let mut config: IpfsConfig = serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid saved IPFS config: {}", e))?;
+        for value in config.api_headers.values_mut() {
+            *value = rewrap_text(value)?;
+        }
+        let json = serde_json::to_string(&config)
+            .map_err(|e| format!("Failed to serialize IPFS config: {}", e))?;
+        write_setting(tx, IPFS_CONFIG, &json).await?;
+    }
+    if let Some(json) = read_setting(tx, PINNING_SERVICES).await? {
+        let mut services: Vec<PinningService> = serde_json::from_str(&json)
+            .map_err(|e| format!("Invalid saved pinning services: {}", e))?;
+        for service in &mut services {
+            service.access_token = rewrap_text(&service.access_token)?;
+        }
+        let json = serde_json::to_string(&services)
+            .map_err(|e| format!("Failed to serialize pinning services: {}", e))?;
+        write_setting(tx, PINNING_SERVICES, &json).await?;
+    }
+    Ok(())
+}
+
+async fn read_setting(tx: &mut Transaction<'_, Sqlite>, key: &str) -> Result<Option<String>, String> {
+    sqlx::query_scalar::<_, String>("SELECT value FROM app_settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(&mut **tx)
+        .await
+        .map_err(|e| format!("DB error (fetch setting '{}'): {}", key, e))
+}
+
+async fn write_setting(tx: &mut Transaction<'_, Sqlite>, key: &str, value: &str) -> Result<(), String> {
+    sqlx::query("UPDATE app_settings SET value = ?, updated_at = CURRENT_TIMESTAMP WHERE key = ?")
+        .bind(value)
+        .bind(key)
+        .execute(&mut **tx)
+        .await
+        .map_err(|e| format!("DB error (update setting '{}'): {}", key, e))?;
+    Ok(())
+}
+
+async fn save_params(tx: &mut Transaction<'_, Sqlite>, params: &StoreParams) -> Result<(), String> {
+    sqlx::query(
+        "INSERT OR REPLACE INTO key_store (id, salt, ops_limit, mem_limit, check_value) VALUES (1, ?, ?, ?, ?)",
+    )
+    .bind(params.salt.0.to_vec())
+    .bind(params.ops_limit as i64)
+    .bind(params.mem_limit as i64)
+    .bind(&params.check_value)
+    .execute(&mut **tx)
+    .await
+    .map_err(|e| format!("DB error (save key store): {}", e))?;
+    Ok(())
+}
+
+/// Protects every stored key and seed phrase with `passphrase`. The store stays
+/// unlocked afterwards.
+pub async fn set_passphrase(passphrase: &str) -> Result<KeyStoreStatus, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    set_passphrase_in(pool, passphrase).await?;
+    println!("[KeyStore] Passphrase set");
+    emit_status().await;
+    key_store_status().await
+}
+
+async fn set_passphrase_in(pool: &SqlitePool, passphrase: &str) -> Result<(), String> {
+    let _exclusive = SECRETS_LOCK.write().await;
+    if load_params(pool).await?.is_some() {
+        return Err("A passphrase is already set; change it instead".to_string());
+    }
+    let (params, key) = new_params(passphrase).await?;
+    let mut tx = pool.begin().await.map_err(|e| format!("DB error (begin): {}", e))?;
+    rewrap_all(&mut tx, None, Some(&key)).await?;
+    save_params(&mut tx, &params).await?;
+    tx.commit().await.map_err(|e| format!("DB error (commit): {}", e))?;
+    set_unlocked(Some(key));
+    Ok(())
+}
+
+/// Re-wraps every stored secret under `new_passphrase`, in one transaction.
+pub async fn change_passphrase(old_passphrase: &str, new_passphrase: &str) -> Result<KeyStoreStatus, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    change_passphrase_in(pool, old_passphrase, new_passphrase).await?;
+    println!("[KeyStore] Passphrase changed");
+    emit_status().await;
+    key_store_status().await
+}
+
+async fn change_passphrase_in(pool: &SqlitePool, old_passphrase: &str, new_passphrase: &str) -> Result<(), String> {
+    let _exclusive = SECRETS_LOCK.write().await;
+    let params = load_params(pool).await?.ok_or("No passphrase is set")?;
+    let old_key = open_with_passphrase(&params, old_passphrase).await?;
+    let (params, new_key) = new_params(new_passphrase).await?;
+    let mut tx = pool.begin().await.map_err(|e| format!("DB error (begin): {}", e))?;
+    rewrap_all(&mut tx, Some(&old_key), Some(&new_key)).await?;
+    save_params(&mut tx, &params).await?;
+    tx.commit().await.map_err(|e| format!("DB error (commit): {}", e))?;
+    set_unlocked(Some(new_key));
+    Ok(())
+}
+
+/// Stores every secret unwrapped again and drops the passphrase.
+pub async fn remove_passphrase(passphrase: &str) -> Result<KeyStoreStatus, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    remove_passphrase_in(pool, passphrase).await?;
+    println!("[KeyStore] Passphrase removed");
+    emit_status().await;
+    key_store_status().await
+}
+
+async fn remove_passphrase_in(pool: &SqlitePool, passphrase: &str) -> Result<(), String> {
+    let _exclusive = SECRETS_LOCK.write().await;
+    let params = load_params(pool).await?.ok_or("No passphrase is set")?;
+    let key = open_with_passphrase(&params, passphrase).await?;
+    let mut tx = pool.begin().await.map_err(|e| format!("DB error (begin): {}", e))?;
+    rewrap_all(&mut tx, Some(&key), None).await?;
+    sqlx::query("DELETE FROM key_store")
+        .execute(&mut *tx)
+        .await
+        .map_err(|e| format!("DB error (delete key store): {}", e))?;
+    tx.commit().await.map_err(|e| format!("DB error (commit): {}", e))?;
+    set_unlocked(None);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sqlx::sqlite::SqlitePoolOptions;
+    use crate::utils::schema::ensure_table_schema;
+
+    // The unlocked key is process-wide, so these tests take turns. Each gets a database
+    // of its own, leaving the shared test database without a passphrase.
+    static SERIAL: tokio::sync::Mutex<()> = tokio::sync::Mutex::const_new(());
+
+    async fn store_db(dir: &tempfile::TempDir) -> SqlitePool {
+        let _ = sodiumoxide::init();
+        let path = dir.path().join("key-store.db");
+        let pool = SqlitePoolOptions::new()
+            .connect(&format!("sqlite://{}?mode=rwc", path.display()))
+            .await
+            .unwrap();
+        ensure_table_schema(&pool).await.unwrap();
+        pool
+    }
+
+    async fn stored_key(pool: &SqlitePool) -> Vec<u8> {
+        sqlx::query_scalar("SELECT key FROM encryption_keys WHERE key_name = 'k1'")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn stored_phrase(pool: &SqlitePool) -> String {
+        sqlx::query_scalar("SELECT sub_account_seed_phrase FROM sub_accounts WHERE account_id = 'alice'")
+            .fetch_one(pool)
+            .await
+            .unwrap()
+    }
+
+    async fn stored_token(pool: &SqlitePool) -> String {
+        let json: String = sqlx::query_scalar("SELECT value FROM app_settings WHERE key = ?")
+            .bind(PINNING_SERVICES)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        let services: Vec<PinningService> = serde_json::from_str(&json).unwrap();
+        services[0].access_token.clone()
+    }
+
+    #[tokio::test]
+    async fn passphrase_set_change_and_remove_rewrap_every_secret() {
+        let _serial = SERIAL.lock().await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool = store_db(&dir).await;
+        let phrase = "bottom drive obey lake curtain smoke basket hold race lonely fit walk";
+        sqlx::query("INSERT INTO encryption_keys (key_name, key) VALUES ('k1', ?)")
+            .bind(vec![7u8; 32])
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO sharing_keys (account_id, secret_key) VALUES ('alice', ?)")
+            .bind(vec![9u8; 32])
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("INSERT INTO sub_accounts (account_id, sub_account_seed_phrase) VALUES ('alice', ?)")
+            .bind(phrase)
+            .execute(&pool)
+            .await
+            .unwrap();
+        let services = vec![PinningService {
+            name: "remote".to_string(),
+            endpoint: "https://pins.example.com".to_string(),
+            access_token: "token-123".to_string(),
+        }];
+        sqlx::query("INSERT INTO app_settings (key, value) VALUES (?, ?)")
+            .bind(PINNING_SERVICES)
+            .bind(serde_json::to_string(&services).unwrap())
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        assert!(set_passphrase_in(&pool, "short").await.is_err());
+        set_passphrase_in(&pool, "correct horse").await.unwrap();
+        let wrapped = stored_key(&pool).await;
+        assert!(wrapped.starts_with(WRAPPED_MAGIC));
+        assert_eq!(open_secret(&wrapped).unwrap(), vec![7u8; 32]);
+        let sharing: Vec<u8> = sqlx::query_scalar("SELECT secret_key FROM sharing_keys WHERE account_id = 'alice'")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(open_secret(&sharing).unwrap(), vec![9u8; 32]);
+        assert!(stored_phrase(&pool).await.starts_with(WRAPPED_TEXT_PREFIX));
+        assert_eq!(open_text(&stored_phrase(&pool).await).unwrap(), phrase);
+        assert_eq!(open_text(&stored_token(&pool).await).unwrap(), "token-123");
+        assert!(set_passphrase_in(&pool, "another one").await.is_err());
+
+        // A wrong passphrase leaves everything as it was
+        assert_eq!(
+            change_passphrase_in(&pool, "wrong horse", "battery staple").await.unwrap_err(),
+            "Wrong passphrase"
+        );
+        assert_eq!(stored_key(&pool).await, wrapped);
+        assert_eq!(remove_passphrase_in(&pool, "wrong horse").await.unwrap_err(), "Wrong passphrase");
+        assert_eq!(unlock_in(&pool, "wrong horse").await.unwrap_err(), "Wrong passphrase");
+
+        change_passphrase_in(&pool, "correct horse", "battery staple").await.unwrap();
+        let rewrapped = stored_key(&pool).await;
+        assert_ne!(rewrapped, wrapped);
+        assert_eq!(open_secret(&rewrapped).unwrap(), vec![7u8; 32]);
+        assert_eq!(open_text(&stored_phrase(&pool).await).unwrap(), phrase);
+        assert_eq!(unlock_in(&pool, "correct horse").await.unwrap_err(), "Wrong passphrase");
+        unlock_in(&pool, "battery staple").await.unwrap();
+
+        remove_passphrase_in(&pool, "battery staple").await.unwrap();
+        assert_eq!(stored_key(&pool).await, vec![7u8; 32]);
+        assert_eq!(stored_phrase(&pool).await, phrase);
+        assert_eq!(stored_token(&pool).await, "token-123");
+        assert!(load_params(&pool).await.unwrap().is_none());
+        assert_eq!(remove_passphrase_in(&pool, "battery staple").await.unwrap_err(), "No passphrase is set");
+    }
+
+    #[tokio::test]
+    async fn idle_store_locks_until_unlocked() {
+        let _serial = SERIAL.lock().await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool = store_db(&dir).await;
+        set_passphrase_in(&pool, "correct horse").await.unwrap();
+        let sealed = seal_in(&pool, b"secret").await.unwrap();
+
+        assert!(!lock_if_idle(Duration::from_secs(3600)));
+        assert_eq!(open_secret(&sealed).unwrap(), b"secret");
+        assert!(lock_if_idle(Duration::ZERO));
+        assert!(!lock_if_idle(Duration::ZERO));
+        assert_eq!(open_secret(&sealed).unwrap_err(), LOCKED);
+        assert_eq!(seal_in(&pool, b"another").await.unwrap_err(), LOCKED);
+        // Values stored before the passphrase was set stay readable
+        assert_eq!(open_secret(b"plain").unwrap(), b"plain");
+
+        assert!(unlock_in(&pool, "wrong horse").await.is_err());
+        assert_eq!(open_secret(&sealed).unwrap_err(), LOCKED);
+        unlock_in(&pool, "correct horse").await.unwrap();
+        assert_eq!(open_secret(&sealed).unwrap(), b"secret");
+
+        remove_passphrase_in(&pool, "correct horse").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn text_is_sealed_only_behind_a_passphrase() {
+        let _serial = SERIAL.lock().await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool = store_db(&dir).await;
+        assert_eq!(seal_text_in(&pool, "seed words").await.unwrap(), "seed words");
+        assert_eq!(open_text("seed words").unwrap(), "seed words");
+
+        set_passphrase_in(&pool, "correct horse").await.unwrap();
+        let first = seal_text_in(&pool, "seed words").await.unwrap();
+        let second = seal_text_in(&pool, "seed words").await.unwrap();
+        assert!(first.starts_with(WRAPPED_TEXT_PREFIX) && !first.contains("seed words"));
+        assert_ne!(first, second);
+        assert_eq!(open_text(&first).unwrap(), "seed words");
+        assert_eq!(open_text(&second).unwrap(), "seed words");
+        assert!(open_text("hpkw1:not base64!").is_err());
+
+        remove_passphrase_in(&pool, "correct horse").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn passphrase_changes_wait_for_secrets_being_stored() {
+        let _serial = SERIAL.lock().await;
+        let dir = tempfile::tempdir().unwrap();
+        let pool = store_db(&dir).await;
+
+        let hold = hold_secrets().await;
+        let setting = tokio::spawn({
+            let pool = pool.clone();
+            async move { set_passphrase_in(&pool, "correct horse").await }
+        });
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(!setting.is_finished());
+
+        // Sealed before the passphrase exists, so stored as is, and still written
+        // before the rewrap starts
+        let sealed = seal_text_in(&pool, "late phrase").await.unwrap();
+        sqlx::query("INSERT INTO sub_accounts (account_id, sub_account_seed_phrase) VALUES ('alice', ?)")
+            .bind(&sealed)
+            .execute(&pool)
+            .await
+            .unwrap();
+        drop(hold);
+        setting.await.unwrap().unwrap();
+
+        let stored = stored_phrase(&pool).await;
+        assert!(stored.starts_with(WRAPPED_TEXT_PREFIX));
+        assert_eq!(open_text(&stored).unwrap(), "late phrase");
+
+        remove_passphrase_in(&pool, "correct horse").await.unwrap();
+    }
+}
@@ -0,0 +1,157 @@
+use std::path::{Component, Path};
+use crate::commands::types::{EntryKind, FileEntry, FolderMetadata, ManifestEntry};
+
+pub const FOLDER_MANIFEST_VERSION: u32 = 2;
+const LEGACY_MANIFEST_VERSION: u32 = 1;
+
+// Legacy entries carry their kind in a name suffix, longest first so ".s.folder" wins
+// over ".folder"
+const LEGACY_FOLDER_SUFFIXES: &[&str] = &[
+    ".s.folder.ec_metadata",
+    ".folder.ec_metadata",
+    ".s.folder",
+    ".folder",
+];
+const LEGACY_FILE_SUFFIXES: &[&str] = &[".ff.ec_metadata", ".ff"];
+
+/// Converts a legacy entry. Names with no known suffix are taken to be files, as the
+/// legacy readers did, and keep their name unchanged.
+fn legacy_entry(entry: FileEntry) -> ManifestEntry {
+    let (kind, name) = if let Some(name) = LEGACY_FOLDER_SUFFIXES
+        .iter()
+        .find_map(|suffix| entry.file_name.strip_suffix(suffix))
+    {
+        // Public uploads wrote some subfolders with a stray ".s" prefix as well
+        let name = if entry.file_name.ends_with(".ec_metadata") {
+            name
+        } else {
+            name.strip_prefix(".s").unwrap_or(name)
+        };
+        (EntryKind::Folder, name)
+    } else {
+        let name = LEGACY_FILE_SUFFIXES
+            .iter()
+            .find_map(|suffix| entry.file_name.strip_suffix(suffix))
+            .unwrap_or(&entry.file_name);
+        (EntryKind::File, name)
+    };
+    ManifestEntry {
+        name: name.to_string(),
+        kind,
+        cid: entry.cid,
+        size: entry.file_size as u64,
+        mtime: entry.mtime,
+        mode: entry.mode,
+        content_hash: None,
+    }
+}
+
+/// Reads a folder manifest in any format the app has written: a v2 `FolderMetadata`
+/// object or a legacy `Vec<FileEntry>`. `folder_name` fills in the name legacy
+/// manifests do not record.
+pub fn parse_folder_manifest(data: &[u8], folder_name: &str) -> Result<FolderMetadata, String> {
+    let value: serde_json::Value = serde_json::from_slice(data)
+        .map_err(|e| format!("Folder manifest is not valid JSON: {}", e))?;
+
+    let manifest = if value.is_array() {
+        let legacy: Vec<FileEntry> = serde_json::from_value(value)
+            .map_err(|e| format!("Invalid legacy folder manifest: {}", e))?;
+        FolderMetadata {
+            version: LEGACY_MANIFEST_VERSION,
+            original_folder_name: folder_name.to_string(),
+            entries: legacy.into_iter().map(legacy_entry).collect(),
+        }
+    } else {
+        let version = value
+            .get("version")
+            .and_then(|v| v.as_u64())
+            .ok_or("Folder manifest has no version")?;
+        if version > FOLDER_MANIFEST_VERSION as u64 {
+            return Err(format!(
+                "Folder manifest version {} is newer than this app supports ({})",
+                version, FOLDER_MANIFEST_VERSION
+            ));
+        }
+        serde_json::from_value(value)
+            .map_err(|e| format!("Invalid folder manifest: {}", e))?
+    };
+    for entry in &manifest.entries {
+        check_entry_name(&entry.name)?;
+    }
+    Ok(manifest)
+}
+
+// Entry names are joined onto the folder being downloaded, so each has to be exactly
+// one plain path component on this platform
+pub(crate) fn check_entry_name(name: &str) -> Result<(), String> {
+    let mut components = Path::new(name).components();
+    let plain = matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(part)), None) if part == name
+    );
+    if plain && !name.contains(['/', '\\']) {
+        Ok(())
+    } else {
+        Err(format!("Folder manifest has an invalid entry name {:?}", name))
+    }
+}
+
+/// Serializes a v2 manifest for `folder_name`.
+pub fn folder_manifest_bytes(folder_name: &str, entries: Vec<ManifestEntry>) -> Result<Vec<u8>, String> {
+    serde_json::to_vec(&FolderMetadata {
+        version: FOLDER_MANIFEST_VERSION,
+        original_folder_name: folder_name.to_string(),
+        entries,
+    })
+    .map_err(|e| format!("Failed to serialize folder manifest: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_entries_without_a_suffix_are_files() {
+        let legacy = br#"[
+            {"file_name":"notes.txt.ff.ec_metadata","file_size":5,"cid":"c1"},
+            {"file_name":"photos.s.folder","file_size":0,"cid":"c2"},
+            {"file_name":"README","file_size":9,"cid":"c3"}
+        ]"#;
+        let manifest = parse_folder_manifest(legacy, "top").unwrap();
+        let entries: Vec<(&str, EntryKind, u64)> = manifest
+            .entries
+            .iter()
+            .map(|e| (e.name.as_str(), e.kind, e.size))
+            .collect();
+        assert_eq!(
+            entries,
+            vec![
+                ("notes.txt", EntryKind::File, 5),
+                ("photos", EntryKind::Folder, 0),
+                ("README", EntryKind::File, 9),
+            ]
+        );
+    }
+
+    #[test]
+    fn entry_names_that_leave_the_folder_are_rejected() {
+        let manifest = |name: &str| {
+            serde_json::to_vec(&serde_json::json!({
+                "version": 2,
+                "original_folder_name": "top",
+                "entries": [{"name": name, "kind": "file", "cid": "c1", "size": 1}],
+            }))
+            .unwrap()
+        };
+        assert!(parse_folder_manifest(&manifest("notes.txt"), "top").is_ok());
+        assert!(parse_folder_manifest(&manifest("..notes"), "top").is_ok());
+        for name in ["", ".", "..", "../../.bashrc", "a/b", "a\\b", "/etc/passwd", "./x"] {
+            assert!(parse_folder_manifest(&manifest(name), "top").is_err(), "{:?}", name);
+        }
+        // Legacy manifests too, after their suffix is stripped
+        let legacy = br#"[{"file_name":"...folder","file_size":0,"cid":"c2"}]"#;
+        assert!(parse_folder_manifest(legacy, "top").is_err());
+        let legacy = br#"[{"file_name":"../x.ff","file_size":0,"cid":"c2"}]"#;
+        assert!(parse_folder_manifest(legacy, "top").is_err());
+    }
+}
@@ -1,5 +1,24 @@
+pub mod account_share;
 pub mod accounts;
 pub mod binary;
+pub mod car;
+pub mod compression;
+pub mod downloads;
+pub mod erasure;
 pub mod file_operations;
 pub mod ipfs;
+pub mod ipns;
+pub mod key_rotation;
+pub mod key_store;
+pub mod manifest;
+pub mod pins;
+pub mod schema;
+pub mod settings;
+pub mod share;
+pub mod stream_crypto;
 pub mod sync;
+#[cfg(test)]
+pub mod test_db;
+#[cfg(test)]
+pub mod test_http;
+pub mod trustless;
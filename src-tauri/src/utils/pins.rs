@@ -0,0 +1,591 @@
+use std::collections::HashMap;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use sqlx::Row;
+use crate::utils::car::object_cids;
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::key_store::{hold_secrets, open_text, seal_text};
+use crate::utils::settings::{get_setting, masked_secret, set_setting, PINNING_SERVICES};
+use crate::DB_POOL;
+
+const SERVICE_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LocalPin {
+    pub cid: String,
+    pub pin_type: String,
+}
+
+/// Pins on the local node. `pin_type` is "recursive", "direct", "indirect" or "all"
+/// (Kubo's default).
+pub async fn list_local_pins(ipfs: &IpfsClient, pin_type: Option<&str>) -> Result<Vec<LocalPin>, String> {
+    let body: serde_json::Value = ipfs
+        .api_post(&format!("pin/ls?type={}", pin_type.unwrap_or("all")))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to list pins: {}", e))?
+        .json()
+        .await
+        .map_err(|e| format!("Invalid pin/ls response: {}", e))?;
+
+    let mut pins: Vec<LocalPin> = body
+        .get("Keys")
+        .and_then(|k| k.as_object())
+        .map(|keys| {
+            keys.iter()
+                .map(|(cid, info)| LocalPin {
+                    cid: cid.clone(),
+                    pin_type: info
+                        .get("Type")
+                        .and_then(|t| t.as_str())
+                        .unwrap_or_default()
+                        .to_string(),
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    pins.sort_by(|a, b| a.cid.cmp(&b.cid));
+    Ok(pins)
+}
+
+pub async fn pin_local(ipfs: &IpfsClient, cid: &str) -> Result<(), String> {
+    ipfs.api_post(&format!("pin/add?arg={}", cid))
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to pin {}: {}", cid, e))?;
+    Ok(())
+}
+
+/// Unpins `cid`. Content that is not pinned counts as success, so callers can unpin a
+/// whole file without first checking what is still pinned.
+pub async fn unpin_local(ipfs: &IpfsClient, cid: &str) -> Result<(), String> {
+    let res = ipfs
+        .api_post(&format!("pin/rm?arg={}", cid))
+        .send()
+        .await
+        .map_err(|e| format!("Failed to unpin {}: {}", cid, e))?;
+    if res.status().is_success() {
+        return Ok(());
+    }
+    let status = res.status();
+    let body = res.text().await.unwrap_or_default();
+    if body.contains("not pinned") {
+        return Ok(());
+    }
+    Err(format!("Failed to unpin {}: {} {}", cid, status, body.trim()))
+}
+
+/// Unpins the metadata or manifest at `root_cid` and everything it references.
+/// Returns how many CIDs were unpinned.
+pub async fn unpin_stored_object(ipfs: &IpfsClient, root_cid: &str) -> Result<usize, String> {
+    let cids = object_cids(ipfs, root_cid).await?;
+    for cid in &cids {
+        unpin_local(ipfs, cid).await?;
+    }
+    Ok(cids.len())
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct GcReport {
+    pub removed: usize,
+    pub errors: Vec<String>,
+}
+
+/// Runs `repo gc` on the local node.
+pub async fn run_gc(ipfs: &IpfsClient) -> Result<GcReport, String> {
+    let body = ipfs
+        .api_post("repo/gc")
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| format!("Failed to run garbage collection: {}", e))?
+        .text()
+        .await
+        .map_err(|e| format!("Invalid repo/gc response: {}", e))?;
+
+    // One JSON object per removed block, or per error
+    let mut report = GcReport { removed: 0, errors: Vec::new() };
+    for line in body.lines().filter(|l| !l.trim().is_empty()) {
+        let value: serde_json::Value = serde_json::from_str(line)
+            .map_err(|e| format!("Invalid repo/gc response: {}", e))?;
+        match value.get("Error").and_then(|e| e.as_str()) {
+            Some(error) if !error.is_empty() => report.errors.push(error.to_string()),
+            _ => report.removed += 1,
+        }
+    }
+    println!("[Pins] Garbage collection removed {} blocks", report.removed);
+    Ok(report)
+}
+
+/// A remote service implementing the IPFS Pinning Service API.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PinningService {
+    pub name: String,
+    pub endpoint: String,
+    pub access_token: String,
+}
+
+impl PinningService {
+    /// The same service with the token hidden, for showing in the UI.
+    pub fn masked(&self) -> Self {
+        PinningService {
+            access_token: masked_secret(&self.access_token),
+            ..self.clone()
+        }
+    }
+}
+
+/// Saved services with their tokens opened. Fails while the key store is locked.
+pub async fn load_pinning_services() -> Result<Vec<PinningService>, String> {
+    let Some(json) = get_setting(PINNING_SERVICES).await? else {
+        return Ok(Vec::new());
+    };
+    let mut services: Vec<PinningService> = serde_json::from_str(&json)
+        .map_err(|e| format!("Invalid saved pinning services: {}", e))?;
+    for service in &mut services {
+        service.access_token = open_text(&service.access_token)?;
+    }
+    Ok(services)
+}
+
+// Tokens are stored sealed by the key store
+async fn save_pinning_services(services: &[PinningService]) -> Result<(), String> {
+    let hold = hold_secrets().await;
+    let mut sealed = services.to_vec();
+    for service in &mut sealed {
+        service.access_token = seal_text(&hold, &service.access_token).await?;
+    }
+    let json = serde_json::to_string(&sealed)
+        .map_err(|e| format!("Failed to serialize pinning services: {}", e))?;
+    set_setting(PINNING_SERVICES, &json).await
+}
+
+/// Adds or replaces the service called `name`.
+pub async fn register_pinning_service(service: PinningService) -> Result<(), String> {
+    let name = service.name.trim().to_string();
+    if name.is_empty() {
+        return Err("Pinning service name must not be empty".to_string());
+    }
+    let endpoint = service.endpoint.trim().trim_end_matches('/').to_string();
+    let parsed = reqwest::Url::parse(&endpoint)
+        .map_err(|e| format!("Invalid pinning service endpoint '{}': {}", endpoint, e))?;
+    if parsed.scheme() != "http" && parsed.scheme() != "https" {
+        return Err(format!("Invalid pinning service endpoint '{}': must be http or https", endpoint));
+    }
+    if service.access_token.trim().is_empty() {
+        return Err("Pinning service access token must not be empty".to_string());
+    }
+
+    let mut services = load_pinning_services().await?;
+    services.retain(|s| s.name != name);
+    services.push(PinningService {
+        name,
+        endpoint,
+        access_token: service.access_token.trim().to_string(),
+    });
+    save_pinning_services(&services).await
+}
+
+/// Forgets the service and the pins tracked for it. Pins stay on the service.
+pub async fn remove_pinning_service(name: &str) -> Result<(), String> {
+    let mut services = load_pinning_services().await?;
+    let before = services.len();
+    services.retain(|s| s.name != name);
+    if services.len() == before {
+        return Err(format!("Unknown pinning service '{}'", name));
+    }
+    save_pinning_services(&services).await?;
+
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query("DELETE FROM remote_pins WHERE service = ?")
+        .bind(name)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to forget pins for '{}': {}", name, e))?;
+    Ok(())
+}
+
+async fn find_service(name: &str) -> Result<PinningService, String> {
+    load_pinning_services()
+        .await?
+        .into_iter()
+        .find(|s| s.name == name)
+        .ok_or_else(|| format!("Unknown pinning service '{}'", name))
+}
+
+/// `PinStatus` from the spec; only the fields the app uses.
+#[derive(Debug, Clone, Deserialize)]
+struct PinStatus {
+    requestid: String,
+    status: String,
+    pin: PinObject,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PinObject {
+    cid: String,
+    #[serde(default)]
+    name: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct PinResults {
+    results: Vec<PinStatus>,
+}
+
+struct ServiceClient {
+    service: PinningService,
+    http: reqwest::Client,
+}
+
+impl ServiceClient {
+    fn new(service: PinningService) -> Self {
+        let http = reqwest::Client::builder()
+            .timeout(SERVICE_TIMEOUT)
+            .build()
+            .unwrap_or_else(|_| reqwest::Client::new());
+        ServiceClient { service, http }
+    }
+
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.http
+            .request(method, format!("{}{}", self.service.endpoint, path))
+            .bearer_auth(&self.service.access_token)
+    }
+
+    async fn send<T: serde::de::DeserializeOwned>(&self, request: reqwest::RequestBuilder) -> Result<T, String> {
+        let res = request
+            .send()
+            .await
+            .map_err(|e| format!("{}: {}", self.service.name, e))?;
+        let status = res.status();
+        if !status.is_success() {
+            // Failure bodies are {"error": {"reason", "details"}}
+            let body = res.text().await.unwrap_or_default();
+            return Err(format!("{}: {} {}", self.service.name, status, body.trim()));
+        }
+        res.json()
+            .await
+            .map_err(|e| format!("{}: invalid response: {}", self.service.name, e))
+    }
+
+    async fn add(&self, cid: &str, name: &str, origins: &[String]) -> Result<PinStatus, String> {
+        let body = serde_json::json!({ "cid": cid, "name": name, "origins": origins });
+        self.send(self.request(reqwest::Method::POST, "/pins").json(&body)).await
+    }
+
+    async fn get(&self, request_id: &str) -> Result<PinStatus, String> {
+        self.send(self.request(reqwest::Method::GET, &format!("/pins/{}", request_id)))
+            .await
+    }
+
+    async fn existing(&self, cids: &[String]) -> Result<HashMap<String, PinStatus>, String> {
+        let mut found = HashMap::new();
+        // The spec caps the cid filter at 10 per request, and lists only pinned
+        // objects unless asked, which would re-request pins still in flight
+        for batch in cids.chunks(10) {
+            let results: PinResults = self
+                .send(self.request(reqwest::Method::GET, "/pins").query(&[
+                    ("cid", batch.join(",")),
+                    ("status", "queued,pinning,pinned,failed".to_string()),
+                    ("limit", "1000".to_string()),
+                ]))
+                .await?;
+            for pin in results.results {
+                found.insert(pin.pin.cid.clone(), pin);
+            }
+        }
+        Ok(found)
+    }
+
+    async fn delete(&self, request_id: &str) -> Result<(), String> {
+        let res = self
+            .request(reqwest::Method::DELETE, &format!("/pins/{}", request_id))
+            .send()
+            .await
+            .map_err(|e| format!("{}: {}", self.service.name, e))?;
+        if res.status().is_success() || res.status() == reqwest::StatusCode::NOT_FOUND {
+            Ok(())
+        } else {
+            Err(format!("{}: {}", self.service.name, res.status()))
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RemotePin {
+    pub service: String,
+    pub cid: String,
+    pub request_id: String,
+    pub status: String,
+    pub name: String,
+    pub updated_at: i64,
+}
+
+async fn record_remote_pin(service: &str, pin: &PinStatus, name: &str) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query(
+        "INSERT INTO remote_pins (service, cid, request_id, status, name, created_at, updated_at)
+         VALUES (?, ?, ?, ?, ?, strftime('%s', 'now'), strftime('%s', 'now'))
+         ON CONFLICT(service, cid) DO UPDATE SET
+            request_id = excluded.request_id, status = excluded.status, updated_at = excluded.updated_at"
+    )
+    .bind(service)
+    .bind(&pin.pin.cid)
+    .bind(&pin.requestid)
+    .bind(&pin.status)
+    .bind(pin.pin.name.as_deref().unwrap_or(name))
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to record pin {}: {}", pin.pin.cid, e))?;
+    Ok(())
+}
+
+// Multiaddrs of the local node, sent as origins so the service can fetch from us directly
+async fn local_origins(ipfs: &IpfsClient) -> Vec<String> {
+    let body: serde_json::Value = match ipfs.api_post("id").send().await {
+        Ok(res) => res.json().await.unwrap_or_default(),
+        Err(_) => return Vec::new(),
+    };
+    body.get("Addresses")
+        .and_then(|a| a.as_array())
+        .map(|addrs| {
+            addrs
+                .iter()
+                .filter_map(|a| a.as_str())
+                .filter(|a| !a.contains("/127.0.0.1/") && !a.contains("/::1/"))
+                .map(|a| a.to_string())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Asks `service_name` to pin the metadata or manifest at `root_cid` and every CID it
+/// references (nested manifests, file metadata and shards). CIDs the service already
+/// holds are not requested again. Returns the tracked status of each CID.
+pub async fn pin_to_service(
+    ipfs: &IpfsClient,
+    service_name: &str,
+    root_cid: &str,
+    name: &str,
+) -> Result<Vec<RemotePin>, String> {
+    let client = ServiceClient::new(find_service(service_name).await?);
+    let cids = object_cids(ipfs, root_cid).await?;
+    let existing = client.existing(&cids).await?;
+    let origins = local_origins(ipfs).await;
+
+    for cid in &cids {
+        let pin = match existing.get(cid) {
+            Some(pin) if pin.status != "failed" => pin.clone(),
+            _ => client.add(cid, name, &origins).await?,
+        };
+        record_remote_pin(service_name, &pin, name).await?;
+    }
+    println!("[Pins] Requested {} CIDs for {} on {}", cids.len(), root_cid, service_name);
+    list_remote_pins(Some(service_name), Some(&cids)).await
+}
+
+/// Re-reads the status of every tracked pin on `service_name` that has not settled yet
+/// ("queued" or "pinning").
+pub async fn refresh_remote_pins(service_name: &str) -> Result<Vec<RemotePin>, String> {
+    let client = ServiceClient::new(find_service(service_name).await?);
+    let pending: Vec<RemotePin> = list_remote_pins(Some(service_name), None)
+        .await?
+        .into_iter()
+        .filter(|p| p.status == "queued" || p.status == "pinning")
+        .collect();
+
+    for pin in &pending {
+        match client.get(&pin.request_id).await {
+            Ok(status) => record_remote_pin(service_name, &status, &pin.name).await?,
+            Err(e) => eprintln!("[Pins] Failed to refresh {} on {}: {}", pin.cid, service_name, e),
+        }
+    }
+    list_remote_pins(Some(service_name), None).await
+}
+
+/// Removes the remote pins for `root_cid` and everything it references.
+pub async fn unpin_from_service(ipfs: &IpfsClient, service_name: &str, root_cid: &str) -> Result<usize, String> {
+    let client = ServiceClient::new(find_service(service_name).await?);
+    let cids = object_cids(ipfs, root_cid).await?;
+    let tracked = list_remote_pins(Some(service_name), Some(&cids)).await?;
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    for pin in &tracked {
+        client.delete(&pin.request_id).await?;
+        sqlx::query("DELETE FROM remote_pins WHERE service = ? AND cid = ?")
+            .bind(service_name)
+            .bind(&pin.cid)
+            .execute(pool)
+            .await
+            .map_err(|e| format!("Failed to forget pin {}: {}", pin.cid, e))?;
+    }
+    Ok(tracked.len())
+}
+
+/// Tracked remote pins, optionally limited to one service and/or a set of CIDs.
+pub async fn list_remote_pins(
+    service_name: Option<&str>,
+    cids: Option<&[String]>,
+) -> Result<Vec<RemotePin>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query(
+        "SELECT service, cid, request_id, status, name, updated_at FROM remote_pins
+         WHERE (? IS NULL OR service = ?) ORDER BY service, name, cid"
+    )
+    .bind(service_name)
+    .bind(service_name)
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to read remote pins: {}", e))?;
+
+    Ok(rows
+        .iter()
+        .map(|r| RemotePin {
+            service: r.get("service"),
+            cid: r.get("cid"),
+            request_id: r.get("request_id"),
+            status: r.get("status"),
+            name: r.get("name"),
+            updated_at: r.get("updated_at"),
+        })
+        .filter(|p| cids.is_none_or(|cids| cids.contains(&p.cid)))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use crate::utils::erasure::{encrypt_and_encode_file, ErasureParams};
+    use crate::utils::test_db::test_db;
+    use crate::utils::test_http::{mock_ipfs, serve, Request};
+
+    fn pin_status(cid: &str, status: &str) -> Vec<u8> {
+        serde_json::json!({
+            "requestid": format!("req-{}", cid),
+            "status": status,
+            "created": "2024-01-01T00:00:00Z",
+            "pin": { "cid": cid, "name": "report" },
+            "delegates": []
+        })
+        .to_string()
+        .into_bytes()
+    }
+
+    fn mock_service(endpoint: String) -> ServiceClient {
+        ServiceClient::new(PinningService {
+            name: "mock".to_string(),
+            endpoint,
+            access_token: "secret-token".to_string(),
+        })
+    }
+
+    #[tokio::test]
+    async fn service_client_speaks_the_pinning_service_api() {
+        let seen: Arc<Mutex<Vec<(String, String, String)>>> = Arc::default();
+        let log = seen.clone();
+        let endpoint = serve(move |request: Request| {
+            let auth = request.headers.get("authorization").cloned().unwrap_or_default();
+            log.lock().unwrap().push((request.method.clone(), request.target.clone(), auth));
+            match (request.method.as_str(), request.path()) {
+                ("POST", "/pins") => {
+                    let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+                    assert_eq!(body["name"], "report");
+                    assert_eq!(body["origins"][0], "/ip4/10.0.0.2/tcp/4001");
+                    (202, pin_status(body["cid"].as_str().unwrap(), "queued"))
+                }
+                ("GET", "/pins/req-QmA") => (200, pin_status("QmA", "pinned")),
+                ("DELETE", "/pins/req-QmA") => (202, Vec::new()),
+                ("DELETE", "/pins/req-gone") => (404, Vec::new()),
+                _ => (500, b"{\"error\":{\"reason\":\"INTERNAL\"}}".to_vec()),
+            }
+        })
+        .await;
+        let client = mock_service(endpoint);
+
+        let added = client
+            .add("QmA", "report", &["/ip4/10.0.0.2/tcp/4001".to_string()])
+            .await
+            .unwrap();
+        assert_eq!((added.requestid.as_str(), added.status.as_str()), ("req-QmA", "queued"));
+        let fetched = client.get("req-QmA").await.unwrap();
+        assert_eq!((fetched.pin.cid.as_str(), fetched.status.as_str()), ("QmA", "pinned"));
+        client.delete("req-QmA").await.unwrap();
+        // Already gone counts as deleted
+        client.delete("req-gone").await.unwrap();
+        assert!(client.delete("req-other").await.is_err());
+        let error = client.get("req-other").await.unwrap_err();
+        assert!(error.contains("500") && error.contains("INTERNAL"), "{}", error);
+
+        let seen = seen.lock().unwrap();
+        assert_eq!(seen.len(), 6);
+        assert!(seen.iter().all(|(_, _, auth)| auth == "Bearer secret-token"));
+        assert_eq!((seen[0].0.as_str(), seen[0].1.as_str()), ("POST", "/pins"));
+    }
+
+    #[tokio::test]
+    async fn pin_to_service_requests_only_missing_cids() {
+        test_db().await;
+        let (ipfs, _store) = mock_ipfs().await;
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("report.pdf");
+        std::fs::write(&source, vec![42u8; 10_000]).unwrap();
+        let params = ErasureParams { k: 2, m: 3, chunk_size: 4096, compression: None };
+        let key = sodiumoxide::crypto::secretbox::gen_key().0.to_vec();
+        let (metadata, metadata_cid) = encrypt_and_encode_file(&source, params, Some(key), &ipfs)
+            .await
+            .unwrap();
+
+        // The service already holds the metadata object and is still pinning the first shard,
+        // which like the spec it lists only when asked for that status
+        let mut shard_cids: Vec<String> = metadata.chunks.iter().map(|c| c.cid.cid.clone()).collect();
+        shard_cids.sort();
+        shard_cids.dedup();
+        let in_flight = shard_cids[0].clone();
+        let held = [(metadata_cid.clone(), "pinned"), (in_flight.clone(), "pinning")];
+        let added: Arc<Mutex<Vec<String>>> = Arc::default();
+        let log = added.clone();
+        let endpoint = serve(move |request: Request| match (request.method.as_str(), request.path()) {
+            ("GET", "/pins") => {
+                let statuses = request.query("status").first().cloned().unwrap_or_else(|| "pinned".to_string());
+                let results: Vec<serde_json::Value> = request.query("cid")[0]
+                    .split(',')
+                    .filter_map(|cid| held.iter().find(|(held, _)| held == cid))
+                    .filter(|(_, status)| statuses.split(',').any(|s| s == *status))
+                    .map(|(cid, status)| serde_json::from_slice(&pin_status(cid, status)).unwrap())
+                    .collect();
+                let body = serde_json::json!({ "count": results.len(), "results": results });
+                (200, body.to_string().into_bytes())
+            }
+            ("POST", "/pins") => {
+                let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+                let cid = body["cid"].as_str().unwrap().to_string();
+                log.lock().unwrap().push(cid.clone());
+                (202, pin_status(&cid, "queued"))
+            }
+            _ => (404, Vec::new()),
+        })
+        .await;
+        register_pinning_service(PinningService {
+            name: "mock-pin-to-service".to_string(),
+            endpoint,
+            access_token: "token".to_string(),
+        })
+        .await
+        .unwrap();
+
+        let pins = pin_to_service(&ipfs, "mock-pin-to-service", &metadata_cid, "report")
+            .await
+            .unwrap();
+        let mut requested = added.lock().unwrap().clone();
+        requested.sort();
+        assert_eq!(requested, shard_cids[1..]);
+        assert_eq!(pins.len(), shard_cids.len() + 1);
+        let root = pins.iter().find(|p| p.cid == metadata_cid).unwrap();
+        assert_eq!((root.status.as_str(), root.request_id.as_str()), ("pinned", format!("req-{}", metadata_cid).as_str()));
+        assert_eq!(pins.iter().find(|p| p.cid == in_flight).unwrap().status, "pinning");
+        assert!(pins.iter().filter(|p| p.cid != metadata_cid && p.cid != in_flight).all(|p| p.status == "queued"));
+    }
+}
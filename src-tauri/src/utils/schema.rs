@@ -0,0 +1,411 @@
+use sqlx::sqlite::SqlitePool;
+use sqlx::Row;
+
+/// Creates every table the app uses and adds columns missing from older databases.
+/// Setup runs it at startup; tests run it on their own database.
+pub async fn ensure_table_schema(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    // Define the expected table schemas
+    const TABLE_SCHEMAS: &[(&str, &[(&str, &str)])] = &[
+        (
+            "user_profiles",
+            &[
+                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
+                ("owner", "TEXT NOT NULL"),
+                ("cid", "TEXT NOT NULL"),
+                ("file_hash", "TEXT"),
+                ("file_name", "TEXT"),
+                ("file_size_in_bytes", "INTEGER"),
+                ("is_assigned", "BOOLEAN"),
+                ("last_charged_at", "INTEGER"),
+                ("main_req_hash", "TEXT"),
+                ("selected_validator", "TEXT"),
+                ("total_replicas", "INTEGER"),
+                ("block_number", "INTEGER NOT NULL"),
+                ("processed_timestamp", "TIMESTAMP DEFAULT CURRENT_TIMESTAMP"),
+                ("profile_cid", "TEXT"),
+                ("source", "TEXT"),
+                ("miner_ids", "TEXT"),
+                ("created_at", "INTEGER"),
+                ("type", "TEXT DEFAULT 'public'"),
+                ("is_folder", "BOOLEAN DEFAULT 0"),
+            ],
+        ),
+        (
+            "sync_folder_files",
+            &[
+                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
+                ("owner", "TEXT NOT NULL"),
+                ("cid", "TEXT NOT NULL"),
+                ("file_hash", "TEXT"),
+                ("file_name", "TEXT"),
+                ("file_size_in_bytes", "INTEGER"),
+                ("is_assigned", "BOOLEAN"),
+                ("last_charged_at", "INTEGER"),
+                ("main_req_hash", "TEXT"),
+                ("selected_validator", "TEXT"),
+                ("total_replicas", "INTEGER"),
+                ("block_number", "INTEGER NOT NULL"),
+                ("processed_timestamp", "TIMESTAMP DEFAULT CURRENT_TIMESTAMP"),
+                ("profile_cid", "TEXT"),
+                ("source", "TEXT"),
+                ("miner_ids", "TEXT"),
+                ("type", "TEXT"),
+                ("is_folder", "BOOLEAN"),
+            ],
+        ),
+        (
+            "file_paths",
+            &[
+                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
+                ("file_name", "TEXT NOT NULL"),
+                ("file_hash", "TEXT NOT NULL"),
+                ("timestamp", "INTEGER NOT NULL"),
+                ("path", "TEXT NOT NULL"),
+            ],
+        ),
+        (
+            "sub_accounts",
+            &[
+                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
+                ("account_id", "TEXT NOT NULL"),
+                ("sub_account_seed_phrase", "TEXT NOT NULL"),
+                ("created_at", "TIMESTAMP DEFAULT CURRENT_TIMESTAMP"),
+            ],
+        ),
+        (
+            "file_index",
+            &[
+                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
+                ("owner", "TEXT NOT NULL"),
+                ("scope", "TEXT NOT NULL"),
+                ("path", "TEXT NOT NULL"),
+                ("parent_path", "TEXT NOT NULL DEFAULT ''"),
+                ("name", "TEXT NOT NULL"),
+                ("is_folder", "BOOLEAN DEFAULT 0"),
+                ("size", "INTEGER DEFAULT 0"),
+                ("mtime", "INTEGER DEFAULT 0"),
+                ("content_hash", "TEXT"),
+                ("has_marker", "BOOLEAN DEFAULT 0"),
+                ("created_at", "INTEGER"),
+                ("updated_at", "INTEGER"),
+            ],
+        ),
+        (
+            "remote_pins",
+            &[
+                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
+                ("service", "TEXT NOT NULL"),
+                ("cid", "TEXT NOT NULL"),
+                ("request_id", "TEXT NOT NULL"),
+                ("status", "TEXT NOT NULL"),
+                ("name", "TEXT NOT NULL DEFAULT ''"),
+                ("created_at", "INTEGER"),
+                ("updated_at", "INTEGER"),
+            ],
+        ),
+        (
+            "ipns_names",
+            &[
+                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
+                ("owner", "TEXT NOT NULL"),
+                ("path", "TEXT NOT NULL"),
+                ("key_name", "TEXT NOT NULL"),
+                ("ipns_name", "TEXT NOT NULL"),
+                ("last_cid", "TEXT NOT NULL DEFAULT ''"),
+                ("published_at", "INTEGER NOT NULL DEFAULT 0"),
+            ],
+        ),
+        (
+            "ipns_staged_files",
+            &[
+                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
+                ("staging", "TEXT NOT NULL"),
+                ("path", "TEXT NOT NULL"),
+                ("is_dir", "BOOLEAN NOT NULL DEFAULT 0"),
+                ("size", "INTEGER NOT NULL DEFAULT 0"),
+                ("mtime", "INTEGER NOT NULL DEFAULT 0"),
+                ("cid", "TEXT NOT NULL DEFAULT ''"),
+            ],
+        ),
+        (
+            "share_links",
+            &[
+                ("id", "TEXT PRIMARY KEY"),
+                ("owner", "TEXT NOT NULL"),
+                ("path", "TEXT NOT NULL"),
+                ("kind", "TEXT NOT NULL"),
+                ("url", "TEXT NOT NULL"),
+                ("cid", "TEXT NOT NULL DEFAULT ''"),
+                ("pinned", "BOOLEAN NOT NULL DEFAULT 0"),
+                ("created_at", "INTEGER NOT NULL"),
+                ("expires_at", "INTEGER"),
+                ("revoked_at", "INTEGER"),
+            ],
+        ),
+        (
+            "account_shares",
+            &[
+                ("id", "TEXT NOT NULL"),
+                ("sender", "TEXT NOT NULL"),
+                ("recipient", "TEXT NOT NULL"),
+                ("name", "TEXT NOT NULL"),
+                ("is_folder", "BOOLEAN NOT NULL DEFAULT 0"),
+                ("size", "INTEGER NOT NULL DEFAULT 0"),
+                ("cid", "TEXT NOT NULL"),
+                ("wrapped_key", "TEXT NOT NULL"),
+                ("created_at", "INTEGER NOT NULL"),
+            ],
+        ),
+        (
+            "shared_with_me",
+            &[
+                ("id", "TEXT NOT NULL"),
+                ("sender", "TEXT NOT NULL"),
+                ("recipient", "TEXT NOT NULL"),
+                ("name", "TEXT NOT NULL"),
+                ("is_folder", "BOOLEAN NOT NULL DEFAULT 0"),
+                ("size", "INTEGER NOT NULL DEFAULT 0"),
+                ("cid", "TEXT NOT NULL"),
+                ("wrapped_key", "TEXT NOT NULL"),
+                ("created_at", "INTEGER NOT NULL"),
+            ],
+        ),
+        (
+            "share_indexes",
+            &[
+                ("recipient", "TEXT NOT NULL"),
+                ("sender", "TEXT NOT NULL"),
+                ("index_cid", "TEXT NOT NULL"),
+            ],
+        ),
+        (
+            "download_jobs",
+            &[
+                ("id", "TEXT PRIMARY KEY"),
+                ("request", "TEXT NOT NULL"),
+                // The request's key, sealed by the key store
+                ("sealed_key", "BLOB"),
+                ("status", "TEXT NOT NULL"),
+                ("bytes_done", "INTEGER NOT NULL DEFAULT 0"),
+                ("bytes_total", "INTEGER"),
+                ("checkpoint", "TEXT NOT NULL DEFAULT ''"),
+                ("attempts", "INTEGER NOT NULL DEFAULT 0"),
+                ("error", "TEXT"),
+                ("created_at", "INTEGER NOT NULL"),
+                ("updated_at", "INTEGER NOT NULL"),
+            ],
+        ),
+        (
+            "encryption_keys",
+            &[
+                ("id", "INTEGER PRIMARY KEY AUTOINCREMENT"),
+                ("key_name", "TEXT NOT NULL UNIQUE"),
+                ("key", "BLOB NOT NULL"),
+                // Index of keys derived from the account mnemonic; NULL for random keys
+                ("derivation_index", "INTEGER"),
+                // Account a derived key belongs to; indexes count up per account
+                ("account_id", "TEXT"),
+            ],
+        ),
+        (
+            "key_rotations",
+            &[
+                ("id", "TEXT PRIMARY KEY"),
+                ("account_id", "TEXT NOT NULL"),
+                ("key_id", "TEXT NOT NULL"),
+                ("status", "TEXT NOT NULL"),
+                ("error", "TEXT"),
+                ("created_at", "INTEGER NOT NULL"),
+                ("updated_at", "INTEGER NOT NULL"),
+            ],
+        ),
+        (
+            "key_rotation_items",
+            &[
+                ("rotation_id", "TEXT NOT NULL"),
+                ("cid", "TEXT NOT NULL"),
+                ("kind", "TEXT NOT NULL"),
+                ("name", "TEXT NOT NULL DEFAULT ''"),
+                ("status", "TEXT NOT NULL"),
+                ("new_cid", "TEXT"),
+                ("error", "TEXT"),
+            ],
+        ),
+        (
+            // Argon2id parameters of the passphrase protecting stored secrets; no row
+            // means no passphrase is set
+            "key_store",
+            &[
+                ("id", "INTEGER PRIMARY KEY CHECK (id = 1)"),
+                ("salt", "BLOB NOT NULL"),
+                ("ops_limit", "INTEGER NOT NULL"),
+                ("mem_limit", "INTEGER NOT NULL"),
+                ("check_value", "BLOB NOT NULL"),
+            ],
+        ),
+    ];
+
+    for (table_name, columns) in TABLE_SCHEMAS {
+        // Create table if it doesn't exist with basic structure
+        let create_table = format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            table_name,
+            columns
+                .iter()
+                .map(|(name, typ)| format!("{} {}", name, typ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        sqlx::query(&create_table).execute(pool).await?;
+
+        // Check and add any missing columns
+        let pragma_sql = format!("PRAGMA table_info({})", table_name);
+        let columns_info = sqlx::query(&pragma_sql)
+            .fetch_all(pool)
+            .await?;
+
+        for (column_name, column_type) in *columns {
+            let column_exists = columns_info.iter().any(|row| {
+                let name: String = row.get("name");
+                name == *column_name
+            });
+
+            if !column_exists {
+                println!("[Setup] Adding column {} to table {}", column_name, table_name);
+                sqlx::query(
+                    &format!("ALTER TABLE {} ADD COLUMN {} {}", table_name, column_name, column_type)
+                )
+                .execute(pool)
+                .await?;
+            }
+        }
+    }
+
+    // Relative paths are unique per owner/scope; upserts in sync_index rely on this
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_file_index_path ON file_index (owner, scope, path)"
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE INDEX IF NOT EXISTS idx_file_index_parent ON file_index (owner, scope, parent_path)"
+    )
+    .execute(pool)
+    .await?;
+
+    // One tracked request per CID and service; status updates upsert on this
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_remote_pins_cid ON remote_pins (service, cid)"
+    )
+    .execute(pool)
+    .await?;
+
+    // One IPNS name per published path; republishing upserts on this
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_ipns_names_path ON ipns_names (owner, path)"
+    )
+    .execute(pool)
+    .await?;
+
+    // What is staged in MFS per staging directory; restaging upserts on this
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_ipns_staged_files_path ON ipns_staged_files (staging, path)"
+    )
+    .execute(pool)
+    .await?;
+
+    // Share ids are unique per sender; saving a share upserts on this
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_account_shares_id ON account_shares (sender, id)"
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_shared_with_me_id ON shared_with_me (sender, id)"
+    )
+    .execute(pool)
+    .await?;
+
+    // Last share index CID applied per sender, so unchanged indexes are not refetched
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_share_indexes_sender ON share_indexes (recipient, sender)"
+    )
+    .execute(pool)
+    .await?;
+
+    // A CID is rotated once per rotation; item updates match on this
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS idx_key_rotation_items_cid ON key_rotation_items (rotation_id, cid)"
+    )
+    .execute(pool)
+    .await?;
+
+    // Create other tables that don't need schema migration
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS sync_paths (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            path TEXT NOT NULL,
+            type TEXT NOT NULL UNIQUE,
+            timestamp INTEGER NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS wss_endpoint (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            endpoint TEXT NOT NULL,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS file_paths (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            file_name TEXT NOT NULL,
+            file_hash TEXT NOT NULL,
+            timestamp INTEGER NOT NULL,
+            path TEXT NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS app_settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    // Sharing secret keys, sealed by the key store, for the profile sync to open shares
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS sharing_keys (
+            account_id TEXT PRIMARY KEY,
+            secret_key BLOB NOT NULL
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS sub_accounts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            account_id TEXT NOT NULL,
+            sub_account_seed_phrase TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )"
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
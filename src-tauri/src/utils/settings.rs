@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use sqlx::Row;
+use crate::DB_POOL;
+
+// Keys stored in the app_settings table
+pub const PRESERVE_XATTRS: &str = "preserve_xattrs";
+// Percent-encode names this platform rejects when writing downloads, and decode them on upload
+pub const MAP_INCOMPATIBLE_NAMES: &str = "map_incompatible_names";
+pub const ERASURE_K: &str = "erasure_k";
+pub const ERASURE_M: &str = "erasure_m";
+pub const ERASURE_CHUNK_SIZE: &str = "erasure_chunk_size";
+// zstd-compress private uploads, both encrypted ones and private folder sync
+pub const COMPRESS_UPLOADS: &str = "compress_uploads";
+// Downloads the download manager runs at once
+pub const DOWNLOAD_CONCURRENCY: &str = "download_concurrency";
+// JSON-encoded utils::ipfs::IpfsConfig
+pub const IPFS_CONFIG: &str = "ipfs_config";
+// JSON-encoded Vec<utils::pins::PinningService>
+pub const PINNING_SERVICES: &str = "pinning_services";
+// Minutes without use before the key store locks itself; 0 keeps it unlocked
+pub const KEY_STORE_AUTO_LOCK_MINUTES: &str = "key_store_auto_lock_minutes";
+
+// The only keys the generic settings commands read or write. Anything else, such as
+// IPFS_CONFIG and PINNING_SERVICES, holds credentials and has its own commands.
+pub const GENERAL_SETTINGS: &[&str] = &[
+    PRESERVE_XATTRS,
+    MAP_INCOMPATIBLE_NAMES,
+    ERASURE_K,
+    ERASURE_M,
+    ERASURE_CHUNK_SIZE,
+    COMPRESS_UPLOADS,
+    DOWNLOAD_CONCURRENCY,
+    KEY_STORE_AUTO_LOCK_MINUTES,
+];
+
+// Secrets shorter than this are hidden entirely; showing four characters of them would
+// give away too much
+const MASK_TAIL_MIN_LEN: usize = 12;
+
+/// `value` with all but its last four characters hidden, for showing credentials in the UI.
+/// Short values are hidden entirely.
+pub fn masked_secret(value: &str) -> String {
+    if value.is_empty() {
+        return String::new();
+    }
+    if value.chars().count() < MASK_TAIL_MIN_LEN {
+        return "****".to_string();
+    }
+    let tail_start = value.char_indices().rev().nth(3).map(|(i, _)| i).unwrap_or(0);
+    format!("****{}", &value[tail_start..])
+}
+
+pub async fn get_setting(key: &str) -> Result<Option<String>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query_scalar::<_, String>("SELECT value FROM app_settings WHERE key = ?")
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to read setting '{}': {}", key, e))
+}
+
+pub async fn set_setting(key: &str, value: &str) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query(
+        "INSERT INTO app_settings (key, value, updated_at) VALUES (?, ?, CURRENT_TIMESTAMP)
+         ON CONFLICT(key) DO UPDATE SET value = excluded.value, updated_at = CURRENT_TIMESTAMP"
+    )
+    .bind(key)
+    .bind(value)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to save setting '{}': {}", key, e))?;
+    Ok(())
+}
+
+pub async fn get_bool_setting(key: &str, default: bool) -> bool {
+    match get_setting(key).await {
+        Ok(Some(value)) => matches!(value.as_str(), "true" | "1"),
+        _ => default,
+    }
+}
+
+pub async fn get_usize_setting(key: &str, default: usize) -> usize {
+    match get_setting(key).await {
+        Ok(Some(value)) => value.trim().parse().unwrap_or(default),
+        _ => default,
+    }
+}
+
+pub async fn get_all_settings() -> Result<HashMap<String, String>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query("SELECT key, value FROM app_settings")
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to read settings: {}", e))?;
+    Ok(rows
+        .iter()
+        .map(|r| (r.get::<String, _>("key"), r.get::<String, _>("value")))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn masked_secret_hides_short_values_entirely() {
+        assert_eq!(masked_secret(""), "");
+        assert_eq!(masked_secret("abcd"), "****");
+        assert_eq!(masked_secret("Bearer abc"), "****");
+        assert_eq!(masked_secret("Bearer abcd1234"), "****1234");
+        // The tail is cut on a character boundary
+        assert_eq!(masked_secret("token-ünïcødé"), "****cødé");
+    }
+}
@@ -0,0 +1,582 @@
+use std::path::{Path, PathBuf};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde::Serialize;
+use sodiumoxide::crypto::secretbox;
+use sqlx::Row;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use crate::commands::types::Metadata;
+use crate::utils::erasure::reconstruct_file_streaming;
+use crate::utils::ipfs::IpfsClient;
+use crate::utils::ipns::{build_root, clear_staging, resolve_public_path};
+use crate::utils::pins::{pin_local, unpin_local};
+use crate::utils::stream_crypto::{StreamDecryptor, StreamEncryptor};
+use crate::DB_POOL;
+
+pub const GATEWAY_LINK: &str = "gateway";
+pub const ENCRYPTED_LINK: &str = "encrypted";
+
+const READ_BUFFER_SIZE: usize = 1024 * 1024;
+// How often expired links have their pins released
+const EXPIRY_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShareLink {
+    pub id: String,
+    pub owner: String,
+    // File or folder relative to the public sync folder; the file name for encrypted links
+    pub path: String,
+    pub kind: String,
+    // Encrypted links only carry their key when first created; listed later, the URL
+    // points at the ciphertext alone
+    pub url: String,
+    pub cid: Option<String>,
+    pub created_at: i64,
+    pub expires_at: Option<i64>,
+    pub revoked_at: Option<i64>,
+    pub active: bool,
+}
+
+fn row_to_link(row: &sqlx::sqlite::SqliteRow, now: i64) -> ShareLink {
+    let cid: String = row.get("cid");
+    let expires_at: Option<i64> = row.get("expires_at");
+    let revoked_at: Option<i64> = row.get("revoked_at");
+    ShareLink {
+        id: row.get("id"),
+        owner: row.get("owner"),
+        path: row.get("path"),
+        kind: row.get("kind"),
+        url: row.get("url"),
+        cid: if cid.is_empty() { None } else { Some(cid) },
+        created_at: row.get("created_at"),
+        active: revoked_at.is_none() && !matches!(expires_at, Some(at) if at <= now),
+        expires_at,
+        revoked_at,
+    }
+}
+
+/// Gateway URL for `cid`; files get a `filename` so browsers save them under their name.
+fn gateway_url(ipfs: &IpfsClient, cid: &str, file_name: Option<&str>) -> Result<url::Url, String> {
+    let gateway = ipfs
+        .gateways()
+        .first()
+        .ok_or("No IPFS gateway is configured to share through")?;
+    let mut url = url::Url::parse(&format!("{}/ipfs/{}", gateway, cid))
+        .map_err(|e| format!("Invalid gateway URL '{}': {}", gateway, e))?;
+    if let Some(name) = file_name {
+        url.query_pairs_mut().append_pair("filename", name);
+    }
+    Ok(url)
+}
+
+async fn record_link(link: &ShareLink, pinned: bool) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query(
+        "INSERT INTO share_links (id, owner, path, kind, url, cid, pinned, created_at, expires_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(&link.id)
+    .bind(&link.owner)
+    .bind(&link.path)
+    .bind(&link.kind)
+    .bind(&link.url)
+    .bind(link.cid.as_deref().unwrap_or(""))
+    .bind(pinned)
+    .bind(link.created_at)
+    .bind(link.expires_at)
+    .execute(pool)
+    .await
+    .map_err(|e| format!("Failed to record share link for {}: {}", link.path, e))?;
+    println!("[Share] Created {} link for {}", link.kind, link.path);
+    Ok(())
+}
+
+/// Creates a link to the public file or folder at `path` (relative to the public sync
+/// folder) that works outside the app.
+///
+/// `gateway` links add the item to the local node and point a gateway at its CID;
+/// `expires_in_secs` is optional and, once reached, the app releases its pin.
+pub async fn create_share_link(
+    ipfs: &IpfsClient,
+    owner: &str,
+    path: &str,
+    kind: &str,
+    expires_in_secs: Option<u64>,
+) -> Result<ShareLink, String> {
+    let (path, local) = resolve_public_path(path).await?;
+    if expires_in_secs == Some(0) {
+        return Err("Link expiry must be at least one second".to_string());
+    }
+    let id = uuid::Uuid::new_v4().to_string();
+    let now = chrono::Utc::now().timestamp();
+
+    let (url, cid, expires_at) = match kind {
+        GATEWAY_LINK => {
+            let staging = format!("share-{}", id);
+            let cid = build_root(ipfs, &staging, &local).await?;
+            pin_local(ipfs, &cid).await?;
+            if let Err(e) = clear_staging(ipfs, &staging).await {
+                eprintln!("[Share] Failed to clear {}: {}", staging, e);
+            }
+            let file_name = local
+                .is_file()
+                .then(|| path.rsplit('/').next().unwrap_or(&path));
+            let url = gateway_url(ipfs, &cid, file_name)?.to_string();
+            (url, Some(cid), expires_in_secs.map(|secs| now + secs as i64))
+        }
+        other => return Err(format!("Unknown share link kind '{}'", other)),
+    };
+
+    let link = ShareLink {
+        id,
+        owner: owner.to_string(),
+        path,
+        kind: kind.to_string(),
+        url,
+        cid,
+        created_at: now,
+        expires_at,
+        revoked_at: None,
+        active: true,
+    };
+    record_link(&link, link.cid.is_some()).await?;
+    Ok(link)
+}
+
+/// What an encrypted share link is made from: a file on disk, or a stored file given by
+/// its metadata CID, which is rebuilt and decrypted with the account key first.
+pub enum ShareSource {
+    Local(PathBuf),
+    Stored {
+        metadata_cid: String,
+        encryption_key: Option<Vec<u8>>,
+    },
+}
+
+// Encrypts the file at `path` into `sealed`, in the chunked stream format `decrypt_file`
+// opens
+async fn seal_file(path: &Path, sealed: &Path, key: &secretbox::Key) -> Result<(), String> {
+    let mut source = tokio::fs::File::open(path)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    let mut out = tokio::fs::File::create(sealed)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", sealed.display(), e))?;
+    let write_error = |e: std::io::Error| format!("Failed to write {}: {}", sealed.display(), e);
+    let mut encryptor = StreamEncryptor::new(key);
+    out.write_all(&encryptor.header()).await.map_err(write_error)?;
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        let n = source
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        out.write_all(&encryptor.update(&buf[..n])).await.map_err(write_error)?;
+    }
+    out.write_all(&encryptor.finish()).await.map_err(write_error)?;
+    out.flush().await.map_err(write_error)
+}
+
+// Decrypts the stream format at `sealed` into `output`
+async fn open_sealed(sealed: &Path, output: &Path, key: &secretbox::Key) -> Result<(), String> {
+    let mut source = tokio::fs::File::open(sealed)
+        .await
+        .map_err(|e| format!("Failed to open {}: {}", sealed.display(), e))?;
+    let mut out = tokio::fs::File::create(output)
+        .await
+        .map_err(|e| format!("Failed to create {}: {}", output.display(), e))?;
+    let write_error = |e: std::io::Error| format!("Failed to write {}: {}", output.display(), e);
+    let mut decryptor = StreamDecryptor::new(key);
+    let mut buf = vec![0u8; READ_BUFFER_SIZE];
+    loop {
+        let n = source
+            .read(&mut buf)
+            .await
+            .map_err(|e| format!("Failed to read {}: {}", sealed.display(), e))?;
+        if n == 0 {
+            break;
+        }
+        out.write_all(&decryptor.update(&buf[..n])?).await.map_err(write_error)?;
+    }
+    out.write_all(&decryptor.finish()?).await.map_err(write_error)?;
+    out.flush().await.map_err(write_error)
+}
+
+/// Shares one file with someone who has no account: the file is encrypted under a fresh
+/// key, the ciphertext is added to IPFS, and the key travels in the link's `#fragment`,
+/// which browsers never send to the gateway. Revoking releases the ciphertext's pin.
+/// Only the returned link has the key; the app records the CID and file name alone, so
+/// the link cannot be shown again later.
+pub async fn create_encrypted_share_link(
+    ipfs: &IpfsClient,
+    owner: &str,
+    source: ShareSource,
+    expires_in_secs: Option<u64>,
+) -> Result<ShareLink, String> {
+    if expires_in_secs == Some(0) {
+        return Err("Link expiry must be at least one second".to_string());
+    }
+
+    // Holds the rebuilt plaintext of a stored file, and the sealed copy until it is added
+    let work = tempfile::tempdir().map_err(|e| format!("Failed to create temp directory: {}", e))?;
+    let (file_name, plaintext_path) = match source {
+        ShareSource::Local(path) => {
+            if !path.is_file() {
+                return Err(format!("{} is not a file", path.display()));
+            }
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            (name, path)
+        }
+        ShareSource::Stored { metadata_cid, encryption_key } => {
+            let metadata: Metadata = serde_json::from_slice(&ipfs.cat(&metadata_cid).await?)
+                .map_err(|e| format!("{} is not file metadata: {}", metadata_cid, e))?;
+            let path = work.path().join("plaintext");
+            reconstruct_file_streaming(&metadata, &path, encryption_key, ipfs).await?;
+            (metadata.original_file.name, path)
+        }
+    };
+
+    let key = secretbox::gen_key();
+    let sealed = work.path().join("sealed");
+    seal_file(&plaintext_path, &sealed, &key).await?;
+    let cid = ipfs.add_file(&sealed, &format!("{}.enc", file_name)).await?;
+    drop(work);
+
+    let mut url = gateway_url(ipfs, &cid, None)?;
+    let now = chrono::Utc::now().timestamp();
+    let mut link = ShareLink {
+        id: uuid::Uuid::new_v4().to_string(),
+        owner: owner.to_string(),
+        path: file_name,
+        kind: ENCRYPTED_LINK.to_string(),
+        url: url.to_string(),
+        cid: Some(cid),
+        created_at: now,
+        expires_at: expires_in_secs.map(|secs| now + secs as i64),
+        revoked_at: None,
+        active: true,
+    };
+    record_link(&link, true).await?;
+
+    // The key is never stored: only this returned copy of the link carries it
+    let fragment = url::form_urlencoded::Serializer::new(String::new())
+        .append_pair("key", &URL_SAFE_NO_PAD.encode(key.0))
+        .append_pair("name", &link.path)
+        .finish();
+    url.set_fragment(Some(&fragment));
+    link.url = url.to_string();
+    Ok(link)
+}
+
+/// Downloads and decrypts an encrypted share link. `output_path` may be a directory, in
+/// which case the file keeps the name carried in the link. Returns the written path.
+pub async fn open_share_link(ipfs: &IpfsClient, link: &str, output_path: &Path) -> Result<PathBuf, String> {
+    let url = url::Url::parse(link.trim()).map_err(|e| format!("Invalid share link: {}", e))?;
+    let cid = url
+        .path_segments()
+        .and_then(|mut segments| {
+            segments.find(|s| *s == "ipfs")?;
+            segments.next()
+        })
+        .filter(|cid| !cid.is_empty())
+        .ok_or("Share link does not point at an /ipfs/ path")?
+        .to_string();
+
+    let mut key = None;
+    let mut name = None;
+    for (field, value) in url::form_urlencoded::parse(url.fragment().unwrap_or("").as_bytes()) {
+        match field.as_ref() {
+            "key" => key = Some(value.to_string()),
+            "name" => name = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    let key = URL_SAFE_NO_PAD
+        .decode(key.ok_or("Share link has no key in its fragment")?)
+        .map_err(|e| format!("Invalid key in share link: {}", e))?;
+    let key = secretbox::Key::from_slice(&key).ok_or("Invalid key in share link")?;
+
+    let output = if output_path.is_dir() {
+        // Only the last component, so a crafted name cannot escape the directory
+        let file_name = name
+            .as_deref()
+            .and_then(|n| Path::new(n).file_name())
+            .map(|n| n.to_os_string())
+            .unwrap_or_else(|| cid.clone().into());
+        output_path.join(file_name)
+    } else {
+        output_path.to_path_buf()
+    };
+
+    // The ciphertext is fetched to a temp file and decrypted beside the output, which is
+    // only replaced once the whole file has been authenticated
+    let work = tempfile::tempdir().map_err(|e| format!("Failed to create temp directory: {}", e))?;
+    let sealed = work.path().join("sealed");
+    ipfs.cat_to_file(&cid, &sealed).await?;
+    let parent = match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let opened = tempfile::NamedTempFile::new_in(parent)
+        .map_err(|e| format!("Failed to create a temp file in {}: {}", parent.display(), e))?
+        .into_temp_path();
+    open_sealed(&sealed, &opened, &key).await?;
+    opened
+        .persist(&output)
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    println!("[Share] Opened shared {} into {}", cid, output.display());
+    Ok(output)
+}
+
+/// Share links created by `owner`, newest first. Expired and revoked links are only
+/// included with `include_inactive`.
+pub async fn list_share_links(owner: &str, include_inactive: bool) -> Result<Vec<ShareLink>, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let rows = sqlx::query("SELECT * FROM share_links WHERE owner = ? ORDER BY created_at DESC, rowid DESC")
+        .bind(owner)
+        .fetch_all(pool)
+        .await
+        .map_err(|e| format!("Failed to query share_links: {}", e))?;
+    let now = chrono::Utc::now().timestamp();
+    Ok(rows
+        .iter()
+        .map(|row| row_to_link(row, now))
+        .filter(|link| include_inactive || link.active)
+        .collect())
+}
+
+/// Whether an active link still needs `cid` pinned.
+pub async fn is_cid_shared(cid: &str) -> Result<bool, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM share_links WHERE cid = ? AND pinned = 1")
+        .bind(cid)
+        .fetch_one(pool)
+        .await
+        .map_err(|e| format!("Failed to query share_links: {}", e))?;
+    Ok(count > 0)
+}
+
+// Drops the link's hold on its CID, unpinning it unless another link, an IPNS name, a
+// stored file or a remote pin still needs the same content
+async fn release_pin(ipfs: &IpfsClient, id: &str, cid: &str) -> Result<(), String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    sqlx::query("UPDATE share_links SET pinned = 0 WHERE id = ?")
+        .bind(id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to update share link {}: {}", id, e))?;
+    let (needed,): (i64,) = sqlx::query_as(
+        "SELECT (SELECT COUNT(*) FROM ipns_names WHERE last_cid = ?)
+              + (SELECT COUNT(*) FROM user_profiles WHERE cid = ? OR file_hash = ?)
+              + (SELECT COUNT(*) FROM remote_pins WHERE cid = ?)"
+    )
+    .bind(cid)
+    .bind(cid)
+    .bind(hex::encode(cid))
+    .bind(cid)
+    .fetch_one(pool)
+    .await
+    .map_err(|e| format!("Failed to look up what else uses {}: {}", cid, e))?;
+    if needed == 0 && !is_cid_shared(cid).await? {
+        unpin_local(ipfs, cid).await?;
+    } else {
+        println!("[Share] Keeping {} pinned; it is still in use", cid);
+    }
+    Ok(())
+}
+
+/// Releases the pins held by gateway and encrypted links that have expired.
+pub async fn expire_share_links(ipfs: &IpfsClient) -> Result<usize, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let expired: Vec<(String, String)> = sqlx::query_as(
+        "SELECT id, cid FROM share_links WHERE pinned = 1 AND expires_at IS NOT NULL AND expires_at <= ?"
+    )
+    .bind(chrono::Utc::now().timestamp())
+    .fetch_all(pool)
+    .await
+    .map_err(|e| format!("Failed to query share_links: {}", e))?;
+    for (id, cid) in &expired {
+        release_pin(ipfs, id, cid).await?;
+    }
+    if !expired.is_empty() {
+        println!("[Share] Released {} expired share links", expired.len());
+    }
+    Ok(expired.len())
+}
+
+/// Releases expired links' pins now and then every few minutes, so the node stops
+/// providing shared content soon after a link expires.
+pub fn init_share_links() {
+    tokio::spawn(async {
+        loop {
+            if let Err(e) = expire_share_links(&IpfsClient::from_settings().await).await {
+                eprintln!("[Share] Failed to release expired links: {}", e);
+            }
+            tokio::time::sleep(EXPIRY_CHECK_INTERVAL).await;
+        }
+    });
+}
+
+/// Revokes a link. Its pin is released, so the local node stops providing the content
+/// (copies cached elsewhere stay reachable by CID).
+pub async fn revoke_share_link(ipfs: &IpfsClient, owner: &str, id: &str) -> Result<ShareLink, String> {
+    let pool = DB_POOL.get().ok_or("DB_POOL not initialized")?;
+    let row = sqlx::query("SELECT * FROM share_links WHERE owner = ? AND id = ?")
+        .bind(owner)
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+        .map_err(|e| format!("Failed to query share_links: {}", e))?
+        .ok_or_else(|| format!("Share link {} not found", id))?;
+    let now = chrono::Utc::now().timestamp();
+    let mut link = row_to_link(&row, now);
+    if link.revoked_at.is_some() {
+        return Ok(link);
+    }
+
+    sqlx::query("UPDATE share_links SET revoked_at = ? WHERE id = ?")
+        .bind(now)
+        .bind(id)
+        .execute(pool)
+        .await
+        .map_err(|e| format!("Failed to revoke share link {}: {}", id, e))?;
+    let pinned: bool = row.get("pinned");
+    if let (true, Some(cid)) = (pinned, &link.cid) {
+        release_pin(ipfs, id, cid).await?;
+    }
+
+    link.revoked_at = Some(now);
+    link.active = false;
+    println!("[Share] Revoked {} link {} for {}", link.kind, id, link.path);
+    Ok(link)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use crate::utils::ipfs::IpfsConfig;
+    use crate::utils::test_db::test_db;
+    use crate::utils::test_http::mock_ipfs_logged;
+
+    async fn pinned_link(owner: &str, cid: &str, expires_at: Option<i64>) -> ShareLink {
+        let link = ShareLink {
+            id: uuid::Uuid::new_v4().to_string(),
+            owner: owner.to_string(),
+            path: format!("{}.txt", cid),
+            kind: GATEWAY_LINK.to_string(),
+            url: format!("https://gw.example/ipfs/{}", cid),
+            cid: Some(cid.to_string()),
+            created_at: 1,
+            expires_at,
+            revoked_at: None,
+            active: true,
+        };
+        record_link(&link, true).await.unwrap();
+        link
+    }
+
+    fn unpinned(log: &std::sync::Mutex<Vec<String>>, cid: &str) -> bool {
+        log.lock().unwrap().iter().any(|t| *t == format!("/api/v0/pin/rm?arg={}", cid))
+    }
+
+    #[tokio::test]
+    async fn revoking_keeps_pins_the_account_still_uses() {
+        let pool = test_db().await;
+        let (ipfs, _, log) = mock_ipfs_logged().await;
+        let owner = "share-revoke";
+
+        sqlx::query("INSERT INTO user_profiles (owner, cid, block_number) VALUES (?, 'QmShareStored', 0)")
+            .bind(owner).execute(pool).await.unwrap();
+        sqlx::query("INSERT INTO user_profiles (owner, cid, file_hash, block_number) VALUES (?, 'other', ?, 0)")
+            .bind(owner).bind(hex::encode("QmShareHashed")).execute(pool).await.unwrap();
+        sqlx::query("INSERT INTO remote_pins (service, cid, request_id, status) VALUES ('svc', 'QmShareRemote', 'r', 'pinned')")
+            .execute(pool).await.unwrap();
+        sqlx::query("INSERT INTO ipns_names (owner, path, key_name, ipns_name, last_cid) VALUES (?, 'site', 'k', 'n', 'QmShareSite')")
+            .bind(owner).execute(pool).await.unwrap();
+        let _other = pinned_link(owner, "QmShareTwice", None).await;
+
+        for cid in ["QmShareStored", "QmShareHashed", "QmShareRemote", "QmShareSite", "QmShareTwice"] {
+            let link = pinned_link(owner, cid, None).await;
+            let revoked = revoke_share_link(&ipfs, owner, &link.id).await.unwrap();
+            assert!(!revoked.active && revoked.revoked_at.is_some());
+            assert!(!unpinned(&log, cid), "{} was unpinned", cid);
+        }
+
+        let lone = pinned_link(owner, "QmShareLone", None).await;
+        assert!(revoke_share_link(&ipfs, "someone-else", &lone.id).await.is_err());
+        revoke_share_link(&ipfs, owner, &lone.id).await.unwrap();
+        assert!(unpinned(&log, "QmShareLone"));
+        // Revoking again changes nothing
+        log.lock().unwrap().clear();
+        revoke_share_link(&ipfs, owner, &lone.id).await.unwrap();
+        assert!(log.lock().unwrap().is_empty());
+        assert_eq!(list_share_links(owner, false).await.unwrap().len(), 1);
+        assert_eq!(list_share_links(owner, true).await.unwrap().len(), 7);
+    }
+
+    #[tokio::test]
+    async fn expired_links_release_their_pins() {
+        let (ipfs, _, log) = mock_ipfs_logged().await;
+        let owner = "share-expiry";
+        let expired = pinned_link(owner, "QmShareExpired", Some(2)).await;
+        let current = pinned_link(owner, "QmShareCurrent", Some(i64::MAX)).await;
+
+        expire_share_links(&ipfs).await.unwrap();
+        assert!(unpinned(&log, "QmShareExpired"));
+        assert!(!unpinned(&log, "QmShareCurrent"));
+        assert!(!is_cid_shared("QmShareExpired").await.unwrap());
+        assert!(is_cid_shared("QmShareCurrent").await.unwrap());
+
+        let listed = list_share_links(owner, true).await.unwrap();
+        assert!(!listed.iter().find(|l| l.id == expired.id).unwrap().active);
+        assert!(listed.iter().find(|l| l.id == current.id).unwrap().active);
+        assert_eq!(list_share_links(owner, false).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn encrypted_links_round_trip() {
+        test_db().await;
+        let (mock, store, _) = mock_ipfs_logged().await;
+        let ipfs = IpfsClient::new(IpfsConfig {
+            api_url: mock.api_url().to_string(),
+            api_headers: HashMap::new(),
+            gateways: vec!["https://gw.example".to_string()],
+        });
+        let dir = tempfile::tempdir().unwrap();
+        let plain: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let src = dir.path().join("report.pdf");
+        std::fs::write(&src, &plain).unwrap();
+
+        let link = create_encrypted_share_link(&ipfs, "share-encrypted", ShareSource::Local(src), None)
+            .await
+            .unwrap();
+        let cid = link.cid.clone().unwrap();
+        assert!(link.url.starts_with(&format!("https://gw.example/ipfs/{}#key=", cid)));
+        assert_ne!(store.lock().unwrap()[&cid], plain);
+        let listed = list_share_links("share-encrypted", false).await.unwrap();
+        assert_eq!(listed[0].url, format!("https://gw.example/ipfs/{}", cid));
+
+        let out = tempfile::tempdir().unwrap();
+        let written = open_share_link(&ipfs, &link.url, out.path()).await.unwrap();
+        assert_eq!(written, out.path().join("report.pdf"));
+        assert_eq!(std::fs::read(&written).unwrap(), plain);
+
+        let key = link.url.split("key=").nth(1).unwrap().split('&').next().unwrap();
+        let wrong = link.url.replace(key, &URL_SAFE_NO_PAD.encode(secretbox::gen_key().0));
+        assert!(open_share_link(&ipfs, &wrong, out.path()).await.is_err());
+        // A failed open leaves what was at the output alone, and nothing beside it
+        let kept = out.path().join("kept.bin");
+        std::fs::write(&kept, b"kept").unwrap();
+        assert!(open_share_link(&ipfs, &wrong, &kept).await.is_err());
+        assert_eq!(std::fs::read(&kept).unwrap(), b"kept");
+        assert_eq!(std::fs::read_dir(out.path()).unwrap().count(), 2);
+        let keyless = link.url.split('#').next().unwrap();
+        assert!(open_share_link(&ipfs, keyless, out.path()).await.unwrap_err().contains("no key"));
+    }
+}
@@ -0,0 +1,646 @@
+use poly1305::universal_hash::{KeyInit, UniversalHash};
+use poly1305::{Block, Poly1305};
+use sodiumoxide::crypto::aead::xchacha20poly1305_ietf as aead;
+use sodiumoxide::crypto::generichash;
+use sodiumoxide::crypto::secretbox;
+use sodiumoxide::crypto::stream::xsalsa20;
+
+const MAC_BYTES: usize = secretbox::MACBYTES;
+pub const LEGACY_HEADER_BYTES: usize = secretbox::NONCEBYTES + MAC_BYTES;
+const SALSA_BLOCK: u64 = 64;
+
+const STREAM_MAGIC: &[u8; 4] = b"HPSS";
+const STREAM_VERSION: u8 = 1;
+pub const KEY_ID_BYTES: usize = 8;
+const NONCE_PREFIX_BYTES: usize = 16;
+// magic, version, key ID, chunk size, nonce prefix
+pub const STREAM_HEADER_BYTES: usize = 4 + 1 + KEY_ID_BYTES + 4 + NONCE_PREFIX_BYTES;
+pub const DEFAULT_STREAM_CHUNK_SIZE: u32 = 64 * 1024;
+const MAX_STREAM_CHUNK_SIZE: u32 = 16 * 1024 * 1024;
+const TAG_BYTES: usize = aead::TAGBYTES;
+
+/// Start of every error for data that failed authentication, as opposed to data that
+/// could not be read.
+pub const DECRYPTION_FAILED: &str = "Decryption failed";
+
+/// Keystream and MAC state of the legacy decryptor.
+///
+/// `crypto_secretbox` is XSalsa20 + Poly1305: the first 32 keystream bytes key the MAC
+/// and the message is XORed with the keystream from byte 32 on. The MAC covers the
+/// ciphertext only.
+struct SecretboxState {
+    key: xsalsa20::Key,
+    nonce: xsalsa20::Nonce,
+    mac: Poly1305,
+    mac_carry: Vec<u8>,
+    // Message bytes processed so far
+    position: u64,
+}
+
+impl SecretboxState {
+    fn new(key: &secretbox::Key, nonce: xsalsa20::Nonce) -> Self {
+        let key = xsalsa20::Key(key.0);
+        let mac_key = xsalsa20::stream(32, &nonce, &key);
+        SecretboxState {
+            mac: Poly1305::new(poly1305::Key::from_slice(&mac_key)),
+            key,
+            nonce,
+            mac_carry: Vec::with_capacity(16),
+            position: 0,
+        }
+    }
+
+    fn xor(&mut self, data: &[u8]) -> Vec<u8> {
+        // Keystream offset 32 + position; pad to the block boundary so one call covers
+        // the whole slice.
+        let stream_offset = 32 + self.position;
+        let skip = (stream_offset % SALSA_BLOCK) as usize;
+        let mut buf = vec![0u8; skip + data.len()];
+        buf[skip..].copy_from_slice(data);
+        xsalsa20::stream_xor_ic_inplace(&mut buf, &self.nonce, stream_offset / SALSA_BLOCK, &self.key);
+        self.position += data.len() as u64;
+        buf.drain(..skip);
+        buf
+    }
+
+    fn update_mac(&mut self, mut ciphertext: &[u8]) {
+        if !self.mac_carry.is_empty() {
+            let take = (16 - self.mac_carry.len()).min(ciphertext.len());
+            self.mac_carry.extend_from_slice(&ciphertext[..take]);
+            ciphertext = &ciphertext[take..];
+            if self.mac_carry.len() < 16 {
+                return;
+            }
+            self.mac.update(&[*Block::from_slice(&self.mac_carry)]);
+            self.mac_carry.clear();
+        }
+        let full = ciphertext.len() - ciphertext.len() % 16;
+        let blocks: Vec<Block> = ciphertext[..full].chunks(16).map(|c| *Block::from_slice(c)).collect();
+        self.mac.update(&blocks);
+        self.mac_carry.extend_from_slice(&ciphertext[full..]);
+    }
+
+    fn tag(self) -> [u8; MAC_BYTES] {
+        // compute_unpadded handles the final partial block and finalizes
+        let tag = self.mac.compute_unpadded(&self.mac_carry);
+        let mut out = [0u8; MAC_BYTES];
+        out.copy_from_slice(tag.as_slice());
+        out
+    }
+}
+
+/// Incremental opener for the legacy `nonce || secretbox::seal(..)` layout, so large
+/// files never have to be held in memory.
+///
+/// Plaintext is released as it is produced, so callers must write it somewhere
+/// temporary and only keep it once `finish` has verified the tag.
+pub struct LegacySecretboxDecryptor {
+    key: secretbox::Key,
+    header: Vec<u8>,
+    state: Option<SecretboxState>,
+}
+
+impl LegacySecretboxDecryptor {
+    pub fn new(key: &secretbox::Key) -> Self {
+        LegacySecretboxDecryptor {
+            key: key.clone(),
+            header: Vec::with_capacity(LEGACY_HEADER_BYTES),
+            state: None,
+        }
+    }
+
+    /// Feeds the next slice of the encrypted stream and returns the plaintext for it.
+    pub fn update(&mut self, mut data: &[u8]) -> Vec<u8> {
+        if self.state.is_none() {
+            let needed = LEGACY_HEADER_BYTES - self.header.len();
+            let take = needed.min(data.len());
+            self.header.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.header.len() < LEGACY_HEADER_BYTES {
+                return Vec::new();
+            }
+            let nonce = xsalsa20::Nonce::from_slice(&self.header[..secretbox::NONCEBYTES])
+                .expect("header holds a full nonce");
+            self.state = Some(SecretboxState::new(&self.key, nonce));
+        }
+        if data.is_empty() {
+            return Vec::new();
+        }
+
+        let state = self.state.as_mut().expect("state set after header");
+        state.update_mac(data);
+        state.xor(data)
+    }
+
+    /// The nonce and tag read from the front of the stream, once all 40 bytes are in.
+    pub fn header(&self) -> Option<&[u8]> {
+        self.state.as_ref().map(|_| self.header.as_slice())
+    }
+
+    /// Starts a fresh decryptor from a header saved earlier, so `replay` can bring it
+    /// back to where a previous run stopped.
+    pub fn resume(&mut self, header: &[u8]) -> Result<(), String> {
+        if self.state.is_some() || !self.header.is_empty() || header.len() != LEGACY_HEADER_BYTES {
+            return Err("Cannot resume decryption from this state".to_string());
+        }
+        self.update(header);
+        Ok(())
+    }
+
+    /// Feeds plaintext a previous run already produced. Re-encrypting it gives back the
+    /// ciphertext, which is all the MAC needs, so nothing has to be fetched again.
+    pub fn replay(&mut self, plaintext: &[u8]) -> Result<(), String> {
+        let state = self.state.as_mut().ok_or("Cannot replay before the header")?;
+        let ciphertext = state.xor(plaintext);
+        state.update_mac(&ciphertext);
+        Ok(())
+    }
+
+    /// Verifies the Poly1305 tag over everything fed in.
+    pub fn finish(self) -> Result<(), String> {
+        let state = match self.state {
+            Some(state) => state,
+            None => return Err("Encrypted data too short".to_string()),
+        };
+        let tag = state.tag();
+        if sodiumoxide::utils::memcmp(&tag, &self.header[secretbox::NONCEBYTES..]) {
+            Ok(())
+        } else {
+            Err(DECRYPTION_FAILED.to_string())
+        }
+    }
+}
+
+/// Short fingerprint of a key, written into stream headers so the key a file needs can
+/// be named without revealing it.
+pub fn key_id(key: &secretbox::Key) -> [u8; KEY_ID_BYTES] {
+    let digest = generichash::hash(b"hippius-key-id", Some(generichash::DIGEST_MIN), Some(&key.0))
+        .expect("valid generichash parameters");
+    let mut id = [0u8; KEY_ID_BYTES];
+    id.copy_from_slice(&digest.as_ref()[..KEY_ID_BYTES]);
+    id
+}
+
+// The account key is a secretbox key; the stream cipher gets its own subkey from it
+fn stream_key(key: &secretbox::Key) -> aead::Key {
+    let digest = generichash::hash(b"hippius-stream-v1", Some(aead::KEYBYTES), Some(&key.0))
+        .expect("valid generichash parameters");
+    aead::Key::from_slice(digest.as_ref()).expect("digest is a full key")
+}
+
+/// Whether `data` starts like the chunked stream format rather than legacy secretbox.
+/// A legacy file has a random nonce there, so a match is very likely but not certain.
+pub fn is_stream_format(data: &[u8]) -> bool {
+    data.len() >= STREAM_HEADER_BYTES && data.starts_with(STREAM_MAGIC)
+}
+
+/// Header of the chunked stream format.
+///
+/// The plaintext is cut into `chunk_size` pieces, each sealed on its own with
+/// XChaCha20-Poly1305 under the nonce `nonce_prefix || index` (little endian). The
+/// associated data is the header plus a flag marking the final chunk, so a stream cut
+/// short at a chunk boundary fails to open, and any chunk can be opened without the
+/// ones before it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StreamHeader {
+    pub key_id: [u8; KEY_ID_BYTES],
+    pub chunk_size: u32,
+    nonce_prefix: [u8; NONCE_PREFIX_BYTES],
+}
+
+impl StreamHeader {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if data.len() < STREAM_HEADER_BYTES || !data.starts_with(STREAM_MAGIC) {
+            return Err("Not an encrypted stream".to_string());
+        }
+        if data[4] != STREAM_VERSION {
+            return Err(format!("Unsupported encrypted stream version {}", data[4]));
+        }
+        let mut key_id = [0u8; KEY_ID_BYTES];
+        key_id.copy_from_slice(&data[5..5 + KEY_ID_BYTES]);
+        let mut size = [0u8; 4];
+        size.copy_from_slice(&data[5 + KEY_ID_BYTES..9 + KEY_ID_BYTES]);
+        let chunk_size = u32::from_le_bytes(size);
+        if chunk_size == 0 || chunk_size > MAX_STREAM_CHUNK_SIZE {
+            return Err(format!("Invalid encrypted stream chunk size {}", chunk_size));
+        }
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_BYTES];
+        nonce_prefix.copy_from_slice(&data[9 + KEY_ID_BYTES..STREAM_HEADER_BYTES]);
+        Ok(StreamHeader { key_id, chunk_size, nonce_prefix })
+    }
+
+    pub fn to_bytes(&self) -> [u8; STREAM_HEADER_BYTES] {
+        let mut out = [0u8; STREAM_HEADER_BYTES];
+        out[..4].copy_from_slice(STREAM_MAGIC);
+        out[4] = STREAM_VERSION;
+        out[5..5 + KEY_ID_BYTES].copy_from_slice(&self.key_id);
+        out[5 + KEY_ID_BYTES..9 + KEY_ID_BYTES].copy_from_slice(&self.chunk_size.to_le_bytes());
+        out[9 + KEY_ID_BYTES..].copy_from_slice(&self.nonce_prefix);
+        out
+    }
+
+    fn sealed_chunk_len(&self) -> usize {
+        self.chunk_size as usize + TAG_BYTES
+    }
+
+    /// Chunks in a stream of `encrypted_len` bytes, header included. Even an empty
+    /// file has one.
+    pub fn chunk_count(&self, encrypted_len: u64) -> u64 {
+        let body = encrypted_len.saturating_sub(STREAM_HEADER_BYTES as u64);
+        body.div_ceil(self.sealed_chunk_len() as u64).max(1)
+    }
+
+    /// Where sealed chunk `index` starts in the stream.
+    pub fn chunk_offset(&self, index: u64) -> u64 {
+        STREAM_HEADER_BYTES as u64 + index * self.sealed_chunk_len() as u64
+    }
+
+    fn nonce(&self, index: u64) -> aead::Nonce {
+        let mut nonce = [0u8; aead::NONCEBYTES];
+        nonce[..NONCE_PREFIX_BYTES].copy_from_slice(&self.nonce_prefix);
+        nonce[NONCE_PREFIX_BYTES..].copy_from_slice(&index.to_le_bytes());
+        aead::Nonce(nonce)
+    }
+
+    fn associated_data(&self, last: bool) -> Vec<u8> {
+        let mut ad = self.to_bytes().to_vec();
+        ad.push(last as u8);
+        ad
+    }
+}
+
+fn open_chunk(key: &aead::Key, header: &StreamHeader, index: u64, last: bool, sealed: &[u8]) -> Result<Vec<u8>, String> {
+    if sealed.len() < TAG_BYTES {
+        return Err("Encrypted data too short".to_string());
+    }
+    let (ciphertext, tag) = sealed.split_at(sealed.len() - TAG_BYTES);
+    let tag = aead::Tag::from_slice(tag).expect("tag has the right length");
+    let mut plaintext = ciphertext.to_vec();
+    aead::open_detached(&mut plaintext, Some(&header.associated_data(last)), &tag, &header.nonce(index), key)
+        .map_err(|_| format!("{} at chunk {}", DECRYPTION_FAILED, index))?;
+    Ok(plaintext)
+}
+
+/// Writes the chunked stream format. Output starts with `header()`; the final chunk is
+/// only sealed by `finish`, so up to one chunk of plaintext is held back.
+pub struct StreamEncryptor {
+    key: aead::Key,
+    header: StreamHeader,
+    pending: Vec<u8>,
+    index: u64,
+}
+
+impl StreamEncryptor {
+    pub fn new(key: &secretbox::Key) -> Self {
+        let mut nonce_prefix = [0u8; NONCE_PREFIX_BYTES];
+        sodiumoxide::randombytes::randombytes_into(&mut nonce_prefix);
+        StreamEncryptor {
+            key: stream_key(key),
+            header: StreamHeader {
+                key_id: key_id(key),
+                chunk_size: DEFAULT_STREAM_CHUNK_SIZE,
+                nonce_prefix,
+            },
+            pending: Vec::new(),
+            index: 0,
+        }
+    }
+
+    pub fn header(&self) -> [u8; STREAM_HEADER_BYTES] {
+        self.header.to_bytes()
+    }
+
+    fn seal(&mut self, mut chunk: Vec<u8>, last: bool) -> Vec<u8> {
+        let tag = aead::seal_detached(
+            &mut chunk,
+            Some(&self.header.associated_data(last)),
+            &self.header.nonce(self.index),
+            &self.key,
+        );
+        chunk.extend_from_slice(tag.as_ref());
+        self.index += 1;
+        chunk
+    }
+
+    pub fn update(&mut self, plaintext: &[u8]) -> Vec<u8> {
+        self.pending.extend_from_slice(plaintext);
+        let chunk_size = self.header.chunk_size as usize;
+        let mut out = Vec::new();
+        // Keep at least one byte back: the chunk it belongs to may be the last
+        while self.pending.len() > chunk_size {
+            let rest = self.pending.split_off(chunk_size);
+            let chunk = std::mem::replace(&mut self.pending, rest);
+            out.extend(self.seal(chunk, false));
+        }
+        out
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        let chunk = std::mem::take(&mut self.pending);
+        self.seal(chunk, true)
+    }
+}
+
+/// Reads the chunked stream format incrementally. Every chunk is authenticated before
+/// its plaintext is returned; `finish` opens the final chunk and so detects truncation.
+pub struct StreamDecryptor {
+    key: aead::Key,
+    key_id: [u8; KEY_ID_BYTES],
+    header_bytes: Vec<u8>,
+    header: Option<StreamHeader>,
+    pending: Vec<u8>,
+    index: u64,
+    // Bytes still to drop before chunk `index` starts, after a resume
+    skip: usize,
+}
+
+impl StreamDecryptor {
+    pub fn new(key: &secretbox::Key) -> Self {
+        StreamDecryptor {
+            key: stream_key(key),
+            key_id: key_id(key),
+            header_bytes: Vec::with_capacity(STREAM_HEADER_BYTES),
+            header: None,
+            pending: Vec::new(),
+            index: 0,
+            skip: 0,
+        }
+    }
+
+    /// Starts at sealed chunk `index` of the stream with `header`, dropping the first
+    /// `skip` bytes fed, which come before that chunk.
+    pub fn resume_at(key: &secretbox::Key, header: &[u8], index: u64, skip: usize) -> Result<Self, String> {
+        let mut decryptor = StreamDecryptor::new(key);
+        decryptor.set_header(header)?;
+        decryptor.index = index;
+        decryptor.skip = skip;
+        Ok(decryptor)
+    }
+
+    fn set_header(&mut self, data: &[u8]) -> Result<(), String> {
+        let header = StreamHeader::parse(data)?;
+        if header.key_id != self.key_id {
+            return Err(format!(
+                "{}: encrypted with key {}, not the key given",
+                DECRYPTION_FAILED,
+                hex::encode(header.key_id)
+            ));
+        }
+        self.header_bytes = data[..STREAM_HEADER_BYTES].to_vec();
+        self.header = Some(header);
+        Ok(())
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) -> Result<Vec<u8>, String> {
+        if self.header.is_none() {
+            let take = (STREAM_HEADER_BYTES - self.header_bytes.len()).min(data.len());
+            self.header_bytes.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.header_bytes.len() < STREAM_HEADER_BYTES {
+                return Ok(Vec::new());
+            }
+            let header_bytes = std::mem::take(&mut self.header_bytes);
+            self.set_header(&header_bytes)?;
+        }
+        let dropped = self.skip.min(data.len());
+        self.skip -= dropped;
+        self.pending.extend_from_slice(&data[dropped..]);
+
+        let header = self.header.as_ref().expect("header parsed above");
+        let sealed_len = header.sealed_chunk_len();
+        let mut out = Vec::new();
+        // A full chunk with more data behind it cannot be the last one
+        while self.pending.len() > sealed_len {
+            let rest = self.pending.split_off(sealed_len);
+            let sealed = std::mem::replace(&mut self.pending, rest);
+            out.extend(open_chunk(&self.key, header, self.index, false, &sealed)?);
+            self.index += 1;
+        }
+        Ok(out)
+    }
+
+    /// The stream header once it has been read.
+    pub fn header(&self) -> Option<&[u8]> {
+        self.header.as_ref().map(|_| self.header_bytes.as_slice())
+    }
+
+    /// Opens the final chunk and returns its plaintext.
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        let header = self.header.as_ref().ok_or("Encrypted data too short")?;
+        if self.skip > 0 {
+            return Err("Encrypted data too short".to_string());
+        }
+        open_chunk(&self.key, header, self.index, true, &self.pending)
+            .map_err(|_| format!("{}: the data is truncated or corrupt", DECRYPTION_FAILED))
+    }
+}
+
+/// Decrypts a whole stream held in memory.
+pub fn open_stream(data: &[u8], key: &secretbox::Key) -> Result<Vec<u8>, String> {
+    let mut decryptor = StreamDecryptor::new(key);
+    let mut plaintext = decryptor.update(data)?;
+    plaintext.extend(decryptor.finish()?);
+    Ok(plaintext)
+}
+
+/// Either decryptor, for callers that handle files in both formats.
+pub enum FileDecryptor {
+    Legacy(Box<LegacySecretboxDecryptor>),
+    Stream(StreamDecryptor),
+}
+
+impl FileDecryptor {
+    pub fn update(&mut self, data: &[u8]) -> Result<Vec<u8>, String> {
+        match self {
+            FileDecryptor::Legacy(decryptor) => Ok(decryptor.update(data)),
+            FileDecryptor::Stream(decryptor) => decryptor.update(data),
+        }
+    }
+
+    pub fn header(&self) -> Option<&[u8]> {
+        match self {
+            FileDecryptor::Legacy(decryptor) => decryptor.header(),
+            FileDecryptor::Stream(decryptor) => decryptor.header(),
+        }
+    }
+
+    /// Brings a resumed decryptor up to date with plaintext written before. Only the
+    /// legacy MAC needs it; stream chunks stand on their own.
+    pub fn replay(&mut self, plaintext: &[u8]) -> Result<(), String> {
+        match self {
+            FileDecryptor::Legacy(decryptor) => decryptor.replay(plaintext),
+            FileDecryptor::Stream(_) => Ok(()),
+        }
+    }
+
+    /// Verifies the end of the stream and returns any plaintext still held back.
+    pub fn finish(self) -> Result<Vec<u8>, String> {
+        match self {
+            FileDecryptor::Legacy(decryptor) => decryptor.finish().map(|_| Vec::new()),
+            FileDecryptor::Stream(decryptor) => decryptor.finish(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::accounts::decrypt_file;
+
+    const CHUNK: usize = DEFAULT_STREAM_CHUNK_SIZE as usize;
+
+    fn data(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i * 7 % 251) as u8).collect()
+    }
+
+    // Encrypts in uneven pieces, as a file read would
+    fn encrypt(plaintext: &[u8], key: &secretbox::Key) -> Vec<u8> {
+        let mut encryptor = StreamEncryptor::new(key);
+        let mut out = encryptor.header().to_vec();
+        for piece in plaintext.chunks(10_007) {
+            out.extend(encryptor.update(piece));
+        }
+        out.extend(encryptor.finish());
+        out
+    }
+
+    fn decrypt_in_pieces(encrypted: &[u8], key: &secretbox::Key) -> Result<Vec<u8>, String> {
+        let mut decryptor = StreamDecryptor::new(key);
+        let mut out = Vec::new();
+        for piece in encrypted.chunks(777) {
+            out.extend(decryptor.update(piece)?);
+        }
+        out.extend(decryptor.finish()?);
+        Ok(out)
+    }
+
+    // The encrypted stream with sealed chunks `a` and `b` swapped
+    fn swap_chunks(encrypted: &[u8], a: u64, b: u64) -> Vec<u8> {
+        let header = StreamHeader::parse(encrypted).unwrap();
+        let sealed = CHUNK + TAG_BYTES;
+        let (a, b) = (header.chunk_offset(a) as usize, header.chunk_offset(b) as usize);
+        let mut out = encrypted.to_vec();
+        let first = encrypted[a..a + sealed].to_vec();
+        out[a..a + sealed].copy_from_slice(&encrypted[b..b + sealed]);
+        out[b..b + sealed].copy_from_slice(&first);
+        out
+    }
+
+    #[test]
+    fn round_trips_around_chunk_boundaries() {
+        let key = secretbox::gen_key();
+        for len in [0, 1, CHUNK - 1, CHUNK, CHUNK + 1, 2 * CHUNK, 3 * CHUNK + 17] {
+            let plaintext = data(len);
+            let encrypted = encrypt(&plaintext, &key);
+            let header = StreamHeader::parse(&encrypted).unwrap();
+            let chunks = header.chunk_count(encrypted.len() as u64);
+            assert_eq!(chunks, len.div_ceil(CHUNK).max(1) as u64, "length {}", len);
+            assert_eq!(encrypted.len(), STREAM_HEADER_BYTES + len + chunks as usize * TAG_BYTES);
+            assert_eq!(open_stream(&encrypted, &key).unwrap(), plaintext, "length {}", len);
+            assert_eq!(decrypt_in_pieces(&encrypted, &key).unwrap(), plaintext, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn truncation_at_a_chunk_boundary_fails() {
+        let key = secretbox::gen_key();
+        for len in [2 * CHUNK, 3 * CHUNK + 17] {
+            let encrypted = encrypt(&data(len), &key);
+            let header = StreamHeader::parse(&encrypted).unwrap();
+            for kept in 1..header.chunk_count(encrypted.len() as u64) {
+                let cut = &encrypted[..header.chunk_offset(kept) as usize];
+                let error = open_stream(cut, &key).unwrap_err();
+                assert!(error.starts_with(DECRYPTION_FAILED), "{}", error);
+                assert!(decrypt_in_pieces(cut, &key).is_err());
+            }
+        }
+        let encrypted = encrypt(&data(10), &key);
+        assert!(open_stream(&encrypted[..STREAM_HEADER_BYTES], &key).is_err());
+        assert!(open_stream(&encrypted[..STREAM_HEADER_BYTES - 1], &key).is_err());
+    }
+
+    #[test]
+    fn reordered_or_flipped_chunks_fail() {
+        let key = secretbox::gen_key();
+        let encrypted = encrypt(&data(3 * CHUNK + 17), &key);
+        assert!(open_stream(&swap_chunks(&encrypted, 0, 1), &key).is_err());
+        assert!(open_stream(&swap_chunks(&encrypted, 1, 2), &key).is_err());
+
+        let header = StreamHeader::parse(&encrypted).unwrap();
+        for position in [
+            header.chunk_offset(0) as usize,
+            header.chunk_offset(1) as usize + 100,
+            encrypted.len() - 1,
+            // The nonce prefix, which every chunk authenticates
+            STREAM_HEADER_BYTES - 1,
+        ] {
+            let mut flipped = encrypted.clone();
+            flipped[position] ^= 0x01;
+            let error = open_stream(&flipped, &key).unwrap_err();
+            assert!(error.starts_with(DECRYPTION_FAILED), "{}", error);
+        }
+    }
+
+    #[test]
+    fn wrong_key_is_named_by_its_id() {
+        let key = secretbox::gen_key();
+        let other = secretbox::gen_key();
+        assert_ne!(key_id(&key), key_id(&other));
+        let encrypted = encrypt(&data(1000), &key);
+        assert_eq!(StreamHeader::parse(&encrypted).unwrap().key_id, key_id(&key));
+
+        let error = open_stream(&encrypted, &other).unwrap_err();
+        assert!(error.starts_with(DECRYPTION_FAILED));
+        assert!(error.contains(&hex::encode(key_id(&key))));
+        assert!(StreamDecryptor::resume_at(&other, &encrypted[..STREAM_HEADER_BYTES], 0, 0).is_err());
+    }
+
+    #[test]
+    fn resume_at_opens_any_chunk_on_its_own() {
+        let key = secretbox::gen_key();
+        let plaintext = data(3 * CHUNK + 17);
+        let encrypted = encrypt(&plaintext, &key);
+        let header_bytes = &encrypted[..STREAM_HEADER_BYTES];
+        let header = StreamHeader::parse(&encrypted).unwrap();
+        for index in 0..header.chunk_count(encrypted.len() as u64) {
+            let start = header.chunk_offset(index) as usize;
+            let mut decryptor = StreamDecryptor::resume_at(&key, header_bytes, index, 0).unwrap();
+            let mut out = decryptor.update(&encrypted[start..]).unwrap();
+            out.extend(decryptor.finish().unwrap());
+            assert_eq!(out, plaintext[index as usize * CHUNK..], "chunk {}", index);
+
+            // Bytes before the chunk, e.g. from a ranged read that starts early, are dropped
+            let mut decryptor = StreamDecryptor::resume_at(&key, header_bytes, index, 5).unwrap();
+            let mut out = decryptor.update(&encrypted[start - 5..]).unwrap();
+            out.extend(decryptor.finish().unwrap());
+            assert_eq!(out, plaintext[index as usize * CHUNK..], "chunk {}", index);
+        }
+        // Resuming at the wrong index fails instead of returning misplaced data
+        let start = header.chunk_offset(1) as usize;
+        let mut decryptor = StreamDecryptor::resume_at(&key, header_bytes, 2, 0).unwrap();
+        assert!(decryptor.update(&encrypted[start..]).is_err());
+    }
+
+    #[tokio::test]
+    async fn decrypt_file_reads_both_formats() {
+        let key = secretbox::gen_key();
+        let plaintext = data(CHUNK + 1);
+
+        let nonce = secretbox::gen_nonce();
+        let mut legacy = nonce.0.to_vec();
+        legacy.extend(secretbox::seal(&plaintext, &nonce, &key));
+        assert_eq!(decrypt_file(&legacy, Some(key.0.to_vec())).await.unwrap(), plaintext);
+        assert!(decrypt_file(&legacy, Some(secretbox::gen_key().0.to_vec())).await.is_err());
+
+        let mut decryptor = LegacySecretboxDecryptor::new(&key);
+        let mut out = Vec::new();
+        for piece in legacy.chunks(333) {
+            out.extend(decryptor.update(piece));
+        }
+        decryptor.finish().unwrap();
+        assert_eq!(out, plaintext);
+
+        let stream = encrypt(&plaintext, &key);
+        assert_eq!(decrypt_file(&stream, Some(key.0.to_vec())).await.unwrap(), plaintext);
+    }
+}
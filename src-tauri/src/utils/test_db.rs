@@ -0,0 +1,24 @@
+//! A file-backed SQLite database behind `DB_POOL`, shared by every test in the process.
+//! It carries the same schema setup creates, and tests keep their rows apart by using names of their own.
+
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+use tokio::sync::OnceCell;
+use crate::utils::schema::ensure_table_schema;
+use crate::DB_POOL;
+
+static SCHEMA: OnceCell<()> = OnceCell::const_new();
+
+/// The shared pool, with every table from `ensure_table_schema` in place.
+pub async fn test_db() -> &'static SqlitePool {
+    let pool = DB_POOL.get_or_init(|| {
+        let path = std::env::temp_dir().join(format!("hippius-test-{}.db", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        SqlitePoolOptions::new()
+            .connect_lazy(&format!("sqlite://{}?mode=rwc", path.display()))
+            .expect("test database")
+    });
+    SCHEMA
+        .get_or_init(|| async { ensure_table_schema(pool).await.expect("test schema") })
+        .await;
+    pool
+}
@@ -0,0 +1,331 @@
+//! Minimal HTTP/1.1 server for tests that talk to an IPFS API or a pinning service.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use crate::utils::ipfs::{IpfsClient, IpfsConfig};
+use sha2::{Digest, Sha256};
+use crate::utils::trustless::{write_car, write_varint, Cid, CODEC_DAG_PB};
+
+pub struct Request {
+    pub method: String,
+    // Path and query, e.g. `/api/v0/add?pin=true`
+    pub target: String,
+    // Lowercased names
+    pub headers: HashMap<String, String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn path(&self) -> &str {
+        self.target.split('?').next().unwrap_or_default()
+    }
+
+    /// Every value of query parameter `name`, percent-decoded.
+    pub fn query(&self, name: &str) -> Vec<String> {
+        let query = self.target.split_once('?').map(|(_, q)| q).unwrap_or_default();
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .filter(|(key, _)| *key == name)
+            .map(|(_, value)| percent_decode(value))
+            .collect()
+    }
+
+    /// Contents of the first part of a multipart body.
+    pub fn first_part(&self) -> Vec<u8> {
+        let content_type = self.headers.get("content-type").cloned().unwrap_or_default();
+        let boundary = content_type
+            .split("boundary=")
+            .nth(1)
+            .expect("multipart request without a boundary");
+        let start = find(&self.body, b"\r\n\r\n").expect("part without headers") + 4;
+        let end = start
+            + find(&self.body[start..], format!("\r\n--{}", boundary).as_bytes())
+                .expect("unterminated part");
+        self.body[start..end].to_vec()
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' if i + 2 < bytes.len() => {
+                let hex = std::str::from_utf8(&bytes[i + 1..i + 3]).unwrap_or("");
+                match u8::from_str_radix(hex, 16) {
+                    Ok(b) => {
+                        out.push(b);
+                        i += 3;
+                        continue;
+                    }
+                    Err(_) => out.push(b'%'),
+                }
+            }
+            b'+' => out.push(b' '),
+            b => out.push(b),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|w| w == needle)
+}
+
+async fn read_more(stream: &mut TcpStream, buf: &mut Vec<u8>) -> Option<()> {
+    let mut tmp = [0u8; 64 * 1024];
+    match stream.read(&mut tmp).await {
+        Ok(0) | Err(_) => None,
+        Ok(n) => {
+            buf.extend_from_slice(&tmp[..n]);
+            Some(())
+        }
+    }
+}
+
+async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut buf = Vec::new();
+    let head_end = loop {
+        if let Some(pos) = find(&buf, b"\r\n\r\n") {
+            break pos + 4;
+        }
+        read_more(stream, &mut buf).await?;
+    };
+    let head = String::from_utf8_lossy(&buf[..head_end]).into_owned();
+    let mut lines = head.lines();
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let target = request_line.next()?.to_string();
+    let headers: HashMap<String, String> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect();
+
+    let mut rest = buf.split_off(head_end);
+    let body = if headers.get("transfer-encoding").is_some_and(|te| te.contains("chunked")) {
+        // Streamed bodies arrive chunked: <hex size>\r\n<data>\r\n ... 0\r\n\r\n
+        let mut body = Vec::new();
+        loop {
+            let line_end = loop {
+                if let Some(pos) = find(&rest, b"\r\n") {
+                    break pos;
+                }
+                read_more(stream, &mut rest).await?;
+            };
+            let size_field = String::from_utf8_lossy(&rest[..line_end]).into_owned();
+            let size = usize::from_str_radix(size_field.split(';').next()?.trim(), 16).ok()?;
+            while rest.len() < line_end + 2 + size + 2 {
+                read_more(stream, &mut rest).await?;
+            }
+            body.extend_from_slice(&rest[line_end + 2..line_end + 2 + size]);
+            rest.drain(..line_end + 2 + size + 2);
+            if size == 0 {
+                break body;
+            }
+        }
+    } else {
+        let len = headers
+            .get("content-length")
+            .and_then(|len| len.parse().ok())
+            .unwrap_or(0);
+        while rest.len() < len {
+            read_more(stream, &mut rest).await?;
+        }
+        rest.truncate(len);
+        rest
+    };
+    Some(Request { method, target, headers, body })
+}
+
+/// Serves `handler`'s `(status, body)` for every request and returns the base URL.
+pub async fn serve<F>(handler: F) -> String
+where
+    F: Fn(Request) -> (u16, Vec<u8>) + Send + Sync + 'static,
+{
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let handler = Arc::new(handler);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let Some(request) = read_request(&mut stream).await else {
+                    return;
+                };
+                let (status, body) = handler(request);
+                let head = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                    status,
+                    body.len()
+                );
+                let _ = stream.write_all(head.as_bytes()).await;
+                let _ = stream.write_all(&body).await;
+            });
+        }
+    });
+    format!("http://{}", addr)
+}
+
+// Kubo's defaults for `ipfs add`: 256 KiB chunks, at most 174 links per node
+const CHUNK_SIZE: usize = 256 * 1024;
+const MAX_LINKS: usize = 174;
+
+fn pb_bytes(field: u64, value: &[u8], out: &mut Vec<u8>) {
+    write_varint(field << 3 | 2, out);
+    write_varint(value.len() as u64, out);
+    out.extend_from_slice(value);
+}
+
+fn pb_varint(field: u64, value: u64, out: &mut Vec<u8>) {
+    write_varint(field << 3, out);
+    write_varint(value, out);
+}
+
+fn v0_cid(block: &[u8]) -> Cid {
+    let mut multihash = vec![0x12, 32];
+    multihash.extend_from_slice(&Sha256::digest(block));
+    Cid { version: 0, codec: CODEC_DAG_PB, multihash }
+}
+
+/// A UnixFS file node: (cid, block, bytes of file data below, cumulative block size below)
+pub type Node = (Cid, Vec<u8>, u64, u64);
+
+pub fn leaf(chunk: &[u8]) -> Node {
+    let mut unixfs = Vec::new();
+    pb_varint(1, 2, &mut unixfs);
+    if !chunk.is_empty() {
+        pb_bytes(2, chunk, &mut unixfs);
+    }
+    pb_varint(3, chunk.len() as u64, &mut unixfs);
+    let mut block = Vec::new();
+    pb_bytes(1, &unixfs, &mut block);
+    let tsize = block.len() as u64;
+    (v0_cid(&block), block, chunk.len() as u64, tsize)
+}
+
+pub fn parent(children: &[Node]) -> Node {
+    let filesize: u64 = children.iter().map(|c| c.2).sum();
+    let mut unixfs = Vec::new();
+    pb_varint(1, 2, &mut unixfs);
+    pb_varint(3, filesize, &mut unixfs);
+    for child in children {
+        pb_varint(4, child.2, &mut unixfs);
+    }
+    // dag-pb puts links before data
+    let mut block = Vec::new();
+    for (cid, _, _, tsize) in children {
+        let mut link = Vec::new();
+        pb_bytes(1, &cid.to_bytes(), &mut link);
+        pb_bytes(2, b"", &mut link);
+        pb_varint(3, *tsize, &mut link);
+        pb_bytes(2, &link, &mut block);
+    }
+    pb_bytes(1, &unixfs, &mut block);
+    let tsize = block.len() as u64 + children.iter().map(|c| c.3).sum::<u64>();
+    (v0_cid(&block), block, filesize, tsize)
+}
+
+/// The dag-pb UnixFS DAG `ipfs add` builds for `data` with CIDv0: the root CID, the
+/// cumulative size Kubo reports as `Size`, and every block.
+pub fn unixfs_file(data: &[u8]) -> (Cid, u64, Vec<(Cid, Vec<u8>)>) {
+    let mut blocks = Vec::new();
+    let mut level: Vec<Node> = if data.is_empty() {
+        vec![leaf(data)]
+    } else {
+        data.chunks(CHUNK_SIZE).map(leaf).collect()
+    };
+    while level.len() > 1 {
+        let next: Vec<Node> = level.chunks(MAX_LINKS).map(parent).collect();
+        blocks.extend(level.drain(..).map(|(cid, block, _, _)| (cid, block)));
+        level = next;
+    }
+    let (root, block, _, tsize) = level.remove(0);
+    blocks.push((root.clone(), block));
+    (root, tsize, blocks)
+}
+
+/// Files the mock node holds, keyed by the CID `add` returned for them.
+pub type BlockStore = Arc<Mutex<HashMap<String, Vec<u8>>>>;
+
+/// A mock Kubo node. `add` answers like Kubo does, with the CIDv0 of a dag-pb UnixFS
+/// DAG, and `dag/export` serves that DAG as a CAR, so `IpfsClient::cat` verifies and
+/// reassembles it like real content.
+pub async fn mock_ipfs() -> (IpfsClient, BlockStore) {
+    let (ipfs, store, _) = mock_ipfs_logged().await;
+    (ipfs, store)
+}
+
+/// Like `mock_ipfs`, also returning the `path?query` of every request the node served.
+pub async fn mock_ipfs_logged() -> (IpfsClient, BlockStore, Arc<Mutex<Vec<String>>>) {
+    let store = BlockStore::default();
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let served = store.clone();
+    let logged = log.clone();
+    let url = serve(move |request| {
+        logged.lock().unwrap().push(request.target.clone());
+        match request.path() {
+            "/api/v0/add" => {
+                let data = request.first_part();
+                let (root, size, _) = unixfs_file(&data);
+                let cid = root.to_string_form();
+                served.lock().unwrap().insert(cid.clone(), data);
+                let body = serde_json::json!({ "Name": cid, "Hash": cid, "Size": size.to_string() });
+                (200, body.to_string().into_bytes())
+            }
+            "/api/v0/dag/export" => {
+                let cid = request.query("arg").remove(0);
+                match served.lock().unwrap().get(&cid) {
+                    Some(data) => {
+                        // Kubo exports the root block first
+                        let (root, _, mut blocks) = unixfs_file(data);
+                        blocks.reverse();
+                        (200, write_car(std::slice::from_ref(&root), &blocks))
+                    }
+                    None => (500, b"{\"Message\":\"block not found\"}".to_vec()),
+                }
+            }
+            "/api/v0/block/stat" => {
+                let cid = request.query("arg").remove(0);
+                match served.lock().unwrap().get(&cid) {
+                    Some(data) => {
+                        let body = serde_json::json!({ "Key": cid, "Size": data.len() });
+                        (200, body.to_string().into_bytes())
+                    }
+                    None => (500, b"{\"Message\":\"block not found\"}".to_vec()),
+                }
+            }
+            // Nothing is pinned locally, and unpinning always succeeds
+            "/api/v0/pin/ls" => (200, b"{\"Keys\":{}}".to_vec()),
+            "/api/v0/pin/rm" => (200, b"{}".to_vec()),
+            _ => (404, Vec::new()),
+        }
+    })
+    .await;
+    let config = IpfsConfig {
+        api_url: url,
+        api_headers: HashMap::new(),
+        gateways: Vec::new(),
+    };
+    (IpfsClient::new(config), store, log)
+}
+
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unixfs_file_matches_kubo() {
+        // `echo -n "" | ipfs add` and `echo "hello world" | ipfs add`
+        assert_eq!(unixfs_file(b"").0.to_string_form(), "QmbFMke1KXqnYyBBWxB74N4c5SBnJMVAiMNRcGu6x1AwQH");
+        let (root, size, blocks) = unixfs_file(b"hello world\n");
+        assert_eq!(root.to_string_form(), "QmT78zSuBmuS4z925WZfrqQ1qHaJ56DQaTfyMUF7F8ff5o");
+        assert_eq!((size, blocks.len()), (20, 1));
+        // Larger files are split into 256 KiB leaves below one root
+        let (_, _, blocks) = unixfs_file(&vec![1u8; CHUNK_SIZE * 2 + 1]);
+        assert_eq!(blocks.len(), 4);
+    }
+}
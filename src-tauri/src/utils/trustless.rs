@@ -0,0 +1,851 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use sha2::{Digest, Sha256};
+
+// Multicodec / multihash codes the app deals with
+pub const CODEC_DAG_PB: u64 = 0x70;
+pub const CODEC_RAW: u64 = 0x55;
+const HASH_IDENTITY: u64 = 0x00;
+const HASH_SHA2_256: u64 = 0x12;
+
+// UnixFS Data.Type
+const UNIXFS_RAW: u64 = 0;
+const UNIXFS_DIRECTORY: u64 = 1;
+const UNIXFS_FILE: u64 = 2;
+const UNIXFS_SYMLINK: u64 = 4;
+const UNIXFS_HAMT_SHARD: u64 = 5;
+
+// Balanced DAGs from `ipfs add` are a handful of levels deep, with at most 174 links
+// per node
+const MAX_DAG_DEPTH: usize = 64;
+const MAX_DAG_LINKS: usize = 4096;
+// Nodes visited while rebuilding one file. Repeated links to the same subtree are walked
+// each time, so this bounds DAGs that fan out onto shared blocks.
+const MAX_DAG_VISITS: usize = 1 << 20;
+
+// A CAR may be this much larger than the DAG its root declares (block framing, the
+// CARv2 index), and must reach the root block within the window
+const CAR_SLACK_BYTES: u64 = 1024 * 1024;
+pub const CAR_ROOT_WINDOW: u64 = 4 * 1024 * 1024;
+// Largest CAR section read from disk; real blocks are at most a few MiB
+const MAX_SECTION_BYTES: u64 = 8 * 1024 * 1024;
+
+const BASE32_ALPHABET: &[u8; 32] = b"abcdefghijklmnopqrstuvwxyz234567";
+
+/// Verified blocks from a CAR, keyed by multihash, with the CID each was stored under.
+pub type Blocks = HashMap<Vec<u8>, (Cid, Vec<u8>)>;
+
+/// A parsed CID. `multihash` keeps the encoded form (code, length, digest) because that
+/// is what identifies a block regardless of CID version.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cid {
+    pub version: u64,
+    pub codec: u64,
+    pub multihash: Vec<u8>,
+}
+
+pub fn read_varint(data: &[u8], pos: &mut usize) -> Result<u64, String> {
+    let mut value = 0u64;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos).ok_or("Truncated varint")?;
+        *pos += 1;
+        if shift >= 64 {
+            return Err("Varint too long".to_string());
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+pub fn write_varint(mut value: u64, out: &mut Vec<u8>) {
+    while value >= 0x80 {
+        out.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn base32_decode(input: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(input.len() * 5 / 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in input.bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c.to_ascii_lowercase())
+            .ok_or_else(|| format!("Invalid base32 character '{}'", c as char))? as u32;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Ok(out)
+}
+
+pub fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u32;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 31) as usize] as char);
+        }
+        buffer &= (1 << bits) - 1;
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 31) as usize] as char);
+    }
+    out
+}
+
+impl Cid {
+    pub fn parse(cid: &str) -> Result<Self, String> {
+        let cid = cid.trim();
+        if cid.len() == 46 && cid.starts_with("Qm") {
+            let bytes = bs58::decode(cid)
+                .into_vec()
+                .map_err(|e| format!("Invalid CID '{}': {}", cid, e))?;
+            return Cid::from_bytes(&bytes).map(|(c, _)| c);
+        }
+        let mut chars = cid.chars();
+        let bytes = match chars.next() {
+            Some('b') | Some('B') => base32_decode(chars.as_str())?,
+            Some('z') => bs58::decode(chars.as_str())
+                .into_vec()
+                .map_err(|e| format!("Invalid CID '{}': {}", cid, e))?,
+            Some('f') | Some('F') => hex::decode(chars.as_str())
+                .map_err(|e| format!("Invalid CID '{}': {}", cid, e))?,
+            _ => return Err(format!("Unsupported CID encoding: '{}'", cid)),
+        };
+        let (parsed, used) = Cid::from_bytes(&bytes)?;
+        if used != bytes.len() {
+            return Err(format!("Trailing bytes in CID '{}'", cid));
+        }
+        Ok(parsed)
+    }
+
+    /// Parses a binary CID and returns it with the number of bytes it used.
+    pub fn from_bytes(data: &[u8]) -> Result<(Self, usize), String> {
+        // CIDv0 is a bare sha2-256 multihash
+        if data.len() >= 34 && data[0] == 0x12 && data[1] == 0x20 {
+            return Ok((
+                Cid { version: 0, codec: CODEC_DAG_PB, multihash: data[..34].to_vec() },
+                34,
+            ));
+        }
+        let mut pos = 0;
+        let version = read_varint(data, &mut pos)?;
+        if version != 1 {
+            return Err(format!("Unsupported CID version {}", version));
+        }
+        let codec = read_varint(data, &mut pos)?;
+        let mh_start = pos;
+        read_varint(data, &mut pos)?;
+        let digest_len = read_varint(data, &mut pos)? as usize;
+        let end = pos
+            .checked_add(digest_len)
+            .filter(|end| *end <= data.len())
+            .ok_or("Truncated multihash")?;
+        Ok((
+            Cid { version, codec, multihash: data[mh_start..end].to_vec() },
+            end,
+        ))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        if self.version == 0 {
+            return self.multihash.clone();
+        }
+        let mut out = Vec::with_capacity(self.multihash.len() + 4);
+        write_varint(self.version, &mut out);
+        write_varint(self.codec, &mut out);
+        out.extend_from_slice(&self.multihash);
+        out
+    }
+
+    /// Canonical string form: base58btc for v0, base32 multibase for v1.
+    pub fn to_string_form(&self) -> String {
+        if self.version == 0 {
+            bs58::encode(&self.multihash).into_string()
+        } else {
+            format!("b{}", base32_encode(&self.to_bytes()))
+        }
+    }
+
+    fn hash_parts(&self) -> Result<(u64, &[u8]), String> {
+        let mut pos = 0;
+        let code = read_varint(&self.multihash, &mut pos)?;
+        let len = read_varint(&self.multihash, &mut pos)? as usize;
+        let digest = &self.multihash[pos..];
+        if digest.len() != len {
+            return Err("Multihash length mismatch".to_string());
+        }
+        Ok((code, digest))
+    }
+
+    /// Block data carried inline by identity CIDs.
+    pub fn inline_data(&self) -> Option<Vec<u8>> {
+        match self.hash_parts() {
+            Ok((HASH_IDENTITY, digest)) => Some(digest.to_vec()),
+            _ => None,
+        }
+    }
+
+    /// Checks that `block` hashes to this CID.
+    pub fn verify(&self, block: &[u8]) -> Result<(), String> {
+        let (code, digest) = self.hash_parts()?;
+        let matches = match code {
+            HASH_SHA2_256 => Sha256::digest(block).as_slice() == digest,
+            HASH_IDENTITY => block == digest,
+            other => return Err(format!("Unsupported multihash 0x{:x} in {}", other, self.to_string_form())),
+        };
+        if matches {
+            Ok(())
+        } else {
+            Err(format!("Block does not match CID {}", self.to_string_form()))
+        }
+    }
+}
+
+/// Raw sha2-256 CIDv1 for `data`, as `ipfs add --raw-leaves` would give a small file.
+pub fn raw_cid_for(data: &[u8]) -> Cid {
+    let mut multihash = vec![HASH_SHA2_256 as u8, 32];
+    multihash.extend_from_slice(&Sha256::digest(data));
+    Cid { version: 1, codec: CODEC_RAW, multihash }
+}
+
+// CARv2 starts with this fixed pragma, followed by a 40-byte header locating the
+// CARv1 payload it wraps
+const CARV2_PRAGMA: [u8; 11] = [0x0a, 0xa1, 0x67, b'v', b'e', b'r', b's', b'i', b'o', b'n', 0x02];
+const CARV2_HEADER_BYTES: usize = 40;
+
+// Bytes needed to tell a CARv2 from a CARv1 and locate its payload
+pub const CAR_PREFIX_BYTES: usize = CARV2_PRAGMA.len() + CARV2_HEADER_BYTES;
+
+/// Offset and size of the CARv1 payload a CARv2 starting with `prefix` wraps, or None
+/// when `prefix` starts a CARv1.
+pub fn car_v2_payload_range(prefix: &[u8]) -> Result<Option<(u64, u64)>, String> {
+    if !prefix.starts_with(&CARV2_PRAGMA) {
+        return Ok(None);
+    }
+    let header = prefix
+        .get(CARV2_PRAGMA.len()..CAR_PREFIX_BYTES)
+        .ok_or("Truncated CARv2 header")?;
+    // characteristics (16) | data offset (8) | data size (8) | index offset (8), little-endian
+    let offset = u64::from_le_bytes(header[16..24].try_into().unwrap());
+    let size = u64::from_le_bytes(header[24..32].try_into().unwrap());
+    Ok(Some((offset, size)))
+}
+
+/// The CARv1 data inside `data`: `data` itself for v1, the wrapped payload for v2.
+pub fn car_v1_payload(data: &[u8]) -> Result<&[u8], String> {
+    let Some((offset, size)) = car_v2_payload_range(data)? else {
+        return Ok(data);
+    };
+    let (offset, size) = (offset as usize, size as usize);
+    offset
+        .checked_add(size)
+        .and_then(|end| data.get(offset..end))
+        .ok_or_else(|| "CARv2 payload out of range".to_string())
+}
+
+/// Reads a CARv1 or CARv2 stream into verified blocks keyed by multihash. Every block is
+/// checked against the CID it is stored under; one mismatch rejects the whole CAR.
+pub fn read_car(data: &[u8]) -> Result<(Vec<Cid>, Blocks), String> {
+    let data = car_v1_payload(data)?;
+    let mut pos = 0;
+    let header_len = read_varint(data, &mut pos)? as usize;
+    let header_end = pos
+        .checked_add(header_len)
+        .filter(|end| *end <= data.len())
+        .ok_or("Truncated CAR header")?;
+    let roots = car_header_roots(&data[pos..header_end])?;
+    pos = header_end;
+
+    let mut blocks = HashMap::new();
+    while pos < data.len() {
+        let section_len = read_varint(data, &mut pos)? as usize;
+        let section_end = pos
+            .checked_add(section_len)
+            .filter(|end| *end <= data.len())
+            .ok_or("Truncated CAR section")?;
+        let (cid, used) = Cid::from_bytes(&data[pos..section_end])?;
+        let block = &data[pos + used..section_end];
+        cid.verify(block)?;
+        blocks.insert(cid.multihash.clone(), (cid, block.to_vec()));
+        pos = section_end;
+    }
+    Ok((roots, blocks))
+}
+
+// The header is dag-cbor `{"roots": [CID...], "version": 1}`; CIDs are tag 42 byte
+// strings with a leading 0x00. Only what is needed to pull out the roots is decoded.
+pub fn car_header_roots(header: &[u8]) -> Result<Vec<Cid>, String> {
+    let mut roots = Vec::new();
+    let mut pos = 0;
+    while pos + 2 < header.len() {
+        // tag(42) = 0xd8 0x2a, then a byte string
+        if header[pos] == 0xd8 && header[pos + 1] == 0x2a {
+            pos += 2;
+            let (len, start) = cbor_bytes_header(header, pos)?;
+            let end = start.checked_add(len).filter(|e| *e <= header.len()).ok_or("Truncated CAR root")?;
+            let bytes = &header[start..end];
+            if bytes.first() != Some(&0x00) {
+                return Err("Invalid CID in CAR header".to_string());
+            }
+            roots.push(Cid::from_bytes(&bytes[1..])?.0);
+            pos = end;
+        } else {
+            pos += 1;
+        }
+    }
+    Ok(roots)
+}
+
+fn cbor_bytes_header(data: &[u8], pos: usize) -> Result<(usize, usize), String> {
+    let initial = *data.get(pos).ok_or("Truncated CBOR")?;
+    if initial >> 5 != 2 {
+        return Err("Expected CBOR byte string".to_string());
+    }
+    match initial & 0x1f {
+        n @ 0..=23 => Ok((n as usize, pos + 1)),
+        24 => Ok((*data.get(pos + 1).ok_or("Truncated CBOR")? as usize, pos + 2)),
+        25 => {
+            let b = data.get(pos + 1..pos + 3).ok_or("Truncated CBOR")?;
+            Ok((u16::from_be_bytes([b[0], b[1]]) as usize, pos + 3))
+        }
+        _ => Err("CBOR byte string too long".to_string()),
+    }
+}
+
+// CBOR initial byte(s) for major type `major` with argument `len`
+fn cbor_head(major: u8, len: usize, out: &mut Vec<u8>) {
+    let major = major << 5;
+    match len {
+        0..=23 => out.push(major | len as u8),
+        24..=0xff => out.extend_from_slice(&[major | 24, len as u8]),
+        0x100..=0xffff => {
+            out.push(major | 25);
+            out.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        _ => {
+            out.push(major | 26);
+            out.extend_from_slice(&(len as u32).to_be_bytes());
+        }
+    }
+}
+
+/// CARv1 header listing `roots`; blocks follow as `write_car_block` sections.
+pub fn write_car_header(roots: &[Cid], out: &mut Vec<u8>) {
+    // {"roots": [...], "version": 1}
+    let mut header = vec![0xa2, 0x65];
+    header.extend_from_slice(b"roots");
+    cbor_head(4, roots.len(), &mut header);
+    for root in roots {
+        let mut cid_bytes = vec![0x00];
+        cid_bytes.extend_from_slice(&root.to_bytes());
+        header.extend_from_slice(&[0xd8, 0x2a]);
+        cbor_head(2, cid_bytes.len(), &mut header);
+        header.extend_from_slice(&cid_bytes);
+    }
+    header.push(0x67);
+    header.extend_from_slice(b"version");
+    header.push(0x01);
+
+    write_varint(header.len() as u64, out);
+    out.extend_from_slice(&header);
+}
+
+pub fn write_car_block(cid: &Cid, block: &[u8], out: &mut Vec<u8>) {
+    let cid_bytes = cid.to_bytes();
+    write_varint((cid_bytes.len() + block.len()) as u64, out);
+    out.extend_from_slice(&cid_bytes);
+    out.extend_from_slice(block);
+}
+
+/// Writes a CARv1 with the given roots and blocks.
+pub fn write_car(roots: &[Cid], blocks: &[(Cid, Vec<u8>)]) -> Vec<u8> {
+    let mut out = Vec::new();
+    write_car_header(roots, &mut out);
+    for (cid, block) in blocks {
+        write_car_block(cid, block, &mut out);
+    }
+    out
+}
+
+// Minimal protobuf walker: yields (field number, wire type, value) where value is the
+// varint itself or the length-delimited payload.
+enum PbValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+}
+
+fn pb_fields(data: &[u8]) -> Result<Vec<(u64, PbValue<'_>)>, String> {
+    let mut fields = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let key = read_varint(data, &mut pos)?;
+        let (field, wire_type) = (key >> 3, key & 7);
+        match wire_type {
+            0 => fields.push((field, PbValue::Varint(read_varint(data, &mut pos)?))),
+            2 => {
+                let len = read_varint(data, &mut pos)? as usize;
+                let end = pos.checked_add(len).filter(|e| *e <= data.len()).ok_or("Truncated protobuf")?;
+                fields.push((field, PbValue::Bytes(&data[pos..end])));
+                pos = end;
+            }
+            1 => pos += 8,
+            5 => pos += 4,
+            other => return Err(format!("Unsupported protobuf wire type {}", other)),
+        }
+    }
+    if pos > data.len() {
+        return Err("Truncated protobuf".to_string());
+    }
+    Ok(fields)
+}
+
+pub struct PbLink {
+    pub cid: Cid,
+    pub name: String,
+    pub tsize: u64,
+}
+
+pub struct PbNode {
+    pub links: Vec<PbLink>,
+    pub data: Option<Vec<u8>>,
+}
+
+pub fn decode_dag_pb(block: &[u8]) -> Result<PbNode, String> {
+    let mut node = PbNode { links: Vec::new(), data: None };
+    for (field, value) in pb_fields(block)? {
+        match (field, value) {
+            (1, PbValue::Bytes(data)) => node.data = Some(data.to_vec()),
+            (2, PbValue::Bytes(link)) => {
+                let mut cid = None;
+                let mut name = String::new();
+                let mut tsize = 0;
+                for (lf, lv) in pb_fields(link)? {
+                    match (lf, lv) {
+                        (1, PbValue::Bytes(hash)) => cid = Some(Cid::from_bytes(hash)?.0),
+                        (2, PbValue::Bytes(n)) => name = String::from_utf8_lossy(n).to_string(),
+                        (3, PbValue::Varint(size)) => tsize = size,
+                        _ => {}
+                    }
+                }
+                node.links.push(PbLink {
+                    cid: cid.ok_or("dag-pb link without hash")?,
+                    name,
+                    tsize,
+                });
+            }
+            _ => {}
+        }
+    }
+    Ok(node)
+}
+
+pub struct UnixFsData {
+    pub kind: u64,
+    pub data: Vec<u8>,
+    pub filesize: Option<u64>,
+}
+
+pub fn decode_unixfs(data: &[u8]) -> Result<UnixFsData, String> {
+    let mut out = UnixFsData { kind: UNIXFS_RAW, data: Vec::new(), filesize: None };
+    for (field, value) in pb_fields(data)? {
+        match (field, value) {
+            (1, PbValue::Varint(kind)) => out.kind = kind,
+            (2, PbValue::Bytes(bytes)) => out.data = bytes.to_vec(),
+            (3, PbValue::Varint(size)) => out.filesize = Some(size),
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+fn block_for<'a>(cid: &Cid, blocks: &'a Blocks) -> Result<std::borrow::Cow<'a, [u8]>, String> {
+    if let Some(data) = cid.inline_data() {
+        return Ok(std::borrow::Cow::Owned(data));
+    }
+    blocks
+        .get(&cid.multihash)
+        .map(|(_, b)| std::borrow::Cow::Borrowed(b.as_slice()))
+        .ok_or_else(|| format!("Block {} missing from CAR", cid.to_string_form()))
+}
+
+// How many bytes of file the block `cid` can expand to: its filesize when it declares
+// one, otherwise the encoded size of everything below it
+fn declared_size(cid: &Cid, block: &[u8]) -> Result<u64, String> {
+    if cid.codec != CODEC_DAG_PB {
+        return Ok(block.len() as u64);
+    }
+    let node = decode_dag_pb(block)?;
+    if let Some(size) = decode_unixfs(node.data.as_deref().unwrap_or_default())?.filesize {
+        return Ok(size);
+    }
+    Ok(node
+        .links
+        .iter()
+        .fold(block.len() as u64, |total, link| total.saturating_add(link.tsize)))
+}
+
+/// How large a CAR for `root` may grow, once `prefix` (the start of the CAR) holds the
+/// root block: `None` while it has not arrived yet. The root block is verified first, and
+/// must start within `CAR_ROOT_WINDOW` bytes.
+pub fn car_size_budget(prefix: &[u8], root: &Cid) -> Result<Option<u64>, String> {
+    if let Some(data) = root.inline_data() {
+        return Ok(Some(data.len() as u64 + CAR_SLACK_BYTES));
+    }
+    let payload = match car_v2_payload_range(prefix) {
+        Ok(Some((offset, _))) => prefix.get(offset as usize..).unwrap_or_default(),
+        Ok(None) if prefix.len() >= CAR_PREFIX_BYTES => prefix,
+        // Too short to tell the version apart yet
+        _ => return Ok(None),
+    };
+    let mut pos = 0;
+    let Ok(header_len) = read_varint(payload, &mut pos) else {
+        return Ok(None);
+    };
+    pos = pos.saturating_add(header_len as usize);
+    while pos < payload.len() {
+        if pos as u64 > CAR_ROOT_WINDOW {
+            return Err(format!("CAR for {} does not start with its root block", root.to_string_form()));
+        }
+        let mut section_pos = pos;
+        let Ok(section_len) = read_varint(payload, &mut section_pos) else {
+            return Ok(None);
+        };
+        let Some(section) = section_pos
+            .checked_add(section_len as usize)
+            .and_then(|end| payload.get(section_pos..end))
+        else {
+            return Ok(None);
+        };
+        let (cid, used) = Cid::from_bytes(section)?;
+        if cid.multihash == root.multihash {
+            let block = &section[used..];
+            cid.verify(block)?;
+            let declared = declared_size(root, block)?;
+            return Ok(Some(declared.saturating_mul(2).saturating_add(CAR_SLACK_BYTES)));
+        }
+        pos = section_pos + section.len();
+    }
+    Ok(None)
+}
+
+/// Where a DAG walk reads its blocks from.
+pub trait BlockSource {
+    fn block(&self, cid: &Cid) -> Result<Cow<'_, [u8]>, String>;
+}
+
+impl BlockSource for Blocks {
+    fn block(&self, cid: &Cid) -> Result<Cow<'_, [u8]>, String> {
+        block_for(cid, self)
+    }
+}
+
+/// The blocks of a CAR on disk, read back one at a time as a walk reaches them. They
+/// were verified when `index_car` built the index.
+pub struct CarFile<'a> {
+    pub file: &'a std::fs::File,
+    pub index: &'a CarIndex,
+}
+
+impl BlockSource for CarFile<'_> {
+    fn block(&self, cid: &Cid) -> Result<Cow<'_, [u8]>, String> {
+        if let Some(data) = cid.inline_data() {
+            return Ok(Cow::Owned(data));
+        }
+        let (_, offset, len) = self
+            .index
+            .blocks
+            .get(&cid.multihash)
+            .ok_or_else(|| format!("Block {} missing from CAR", cid.to_string_form()))?;
+        let mut block = vec![0u8; *len];
+        let mut file = self.file;
+        file.seek(SeekFrom::Start(*offset))
+            .and_then(|_| file.read_exact(&mut block))
+            .map_err(|e| format!("Failed to read CAR: {}", e))?;
+        Ok(Cow::Owned(block))
+    }
+}
+
+// Limits for one walk over a file DAG
+struct WalkBudget {
+    max_bytes: u64,
+    written: u64,
+    visits_left: usize,
+}
+
+fn append_file(
+    cid: &Cid,
+    source: &dyn BlockSource,
+    depth: usize,
+    budget: &mut WalkBudget,
+    out: &mut dyn Write,
+) -> Result<(), String> {
+    if depth > MAX_DAG_DEPTH {
+        return Err("DAG too deep".to_string());
+    }
+    budget.visits_left = budget
+        .visits_left
+        .checked_sub(1)
+        .ok_or_else(|| format!("DAG has more than {} nodes", MAX_DAG_VISITS))?;
+    let block = source.block(cid)?;
+    let mut append = |data: &[u8]| {
+        if budget.written + data.len() as u64 > budget.max_bytes {
+            return Err(format!("File is larger than the {} bytes its root declares", budget.max_bytes));
+        }
+        out.write_all(data).map_err(|e| format!("Failed to write file: {}", e))?;
+        budget.written += data.len() as u64;
+        Ok(())
+    };
+    match cid.codec {
+        CODEC_RAW => append(&block),
+        CODEC_DAG_PB => {
+            let node = decode_dag_pb(&block)?;
+            let unixfs = decode_unixfs(node.data.as_deref().unwrap_or_default())?;
+            match unixfs.kind {
+                UNIXFS_FILE | UNIXFS_RAW => {
+                    if node.links.len() > MAX_DAG_LINKS {
+                        return Err(format!(
+                            "{} has {} links, more than {}",
+                            cid.to_string_form(),
+                            node.links.len(),
+                            MAX_DAG_LINKS
+                        ));
+                    }
+                    append(&unixfs.data)?;
+                    for link in &node.links {
+                        append_file(&link.cid, source, depth + 1, budget, out)?;
+                    }
+                    Ok(())
+                }
+                UNIXFS_DIRECTORY | UNIXFS_HAMT_SHARD => {
+                    Err(format!("{} is a directory, not a file", cid.to_string_form()))
+                }
+                UNIXFS_SYMLINK => Err(format!("{} is a symlink", cid.to_string_form())),
+                other => Err(format!("Unsupported UnixFS node type {}", other)),
+            }
+        }
+        other => Err(format!("Unsupported codec 0x{:x} in {}", other, cid.to_string_form())),
+    }
+}
+
+/// Writes the file behind `root` to `out`, reading verified blocks from `source`, and
+/// returns its size. The walk stops as soon as it goes past the size the root node
+/// declares, or visits too many nodes, and the byte count must match the declared size
+/// exactly.
+pub fn write_file(root: &Cid, source: &dyn BlockSource, out: &mut dyn Write) -> Result<u64, String> {
+    let root_block = source.block(root)?;
+    let declared = declared_size(root, &root_block)?;
+    let mut budget = WalkBudget { max_bytes: declared, written: 0, visits_left: MAX_DAG_VISITS };
+    append_file(root, source, 0, &mut budget, out)?;
+    if root.codec == CODEC_DAG_PB {
+        let node = decode_dag_pb(&root_block)?;
+        if let Some(size) = decode_unixfs(node.data.as_deref().unwrap_or_default())?.filesize {
+            if size != budget.written {
+                return Err(format!(
+                    "File size mismatch for {}: expected {}, assembled {}",
+                    root.to_string_form(),
+                    size,
+                    budget.written
+                ));
+            }
+        }
+    }
+    Ok(budget.written)
+}
+
+/// Rebuilds the file behind `root` in memory; see `write_file`.
+pub fn assemble_file(root: &Cid, blocks: &Blocks) -> Result<Vec<u8>, String> {
+    let mut out = Vec::new();
+    write_file(root, blocks, &mut out)?;
+    Ok(out)
+}
+
+fn check_roots(requested: &Cid, roots: &[Cid]) -> Result<(), String> {
+    if !roots.is_empty() && !roots.iter().any(|r| r.multihash == requested.multihash) {
+        return Err(format!("CAR root does not match {}", requested.to_string_form()));
+    }
+    Ok(())
+}
+
+/// Verifies a CAR fetched for `requested` and returns the file it contains, along with
+/// the blocks it was rebuilt from.
+pub fn file_from_car(requested: &Cid, car: &[u8]) -> Result<(Vec<u8>, Blocks), String> {
+    let (roots, blocks) = read_car(car)?;
+    check_roots(requested, &roots)?;
+    let file = assemble_file(requested, &blocks)?;
+    Ok((file, blocks))
+}
+
+/// Where each block of a CAR on disk starts and how long it is, keyed by multihash.
+pub struct CarIndex {
+    pub roots: Vec<Cid>,
+    pub blocks: HashMap<Vec<u8>, (Cid, u64, usize)>,
+}
+
+// None at a clean end of input
+fn read_varint_from(reader: &mut impl Read, pos: &mut u64) -> Result<Option<u64>, String> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let mut byte = [0u8; 1];
+        match reader.read(&mut byte) {
+            Ok(0) if shift == 0 => return Ok(None),
+            Ok(0) => return Err("Truncated varint".to_string()),
+            Ok(_) => {}
+            Err(e) => return Err(format!("Failed to read CAR: {}", e)),
+        }
+        *pos += 1;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(Some(value));
+        }
+    }
+    Err("Varint too long".to_string())
+}
+
+/// Reads a CAR on disk section by section, verifying every block against its CID,
+/// without holding more than one block in memory.
+pub fn index_car(file: &mut std::fs::File) -> Result<CarIndex, String> {
+    let file_len = file
+        .metadata()
+        .map_err(|e| format!("Failed to read CAR: {}", e))?
+        .len();
+    let mut prefix = Vec::with_capacity(CAR_PREFIX_BYTES);
+    (&mut *file)
+        .take(CAR_PREFIX_BYTES as u64)
+        .read_to_end(&mut prefix)
+        .map_err(|e| format!("Failed to read CAR: {}", e))?;
+    let (start, len) = car_v2_payload_range(&prefix)?.unwrap_or((0, file_len));
+    if start.checked_add(len).is_none_or(|end| end > file_len) {
+        return Err("CARv2 payload out of range".to_string());
+    }
+    file.seek(SeekFrom::Start(start))
+        .map_err(|e| format!("Failed to read CAR: {}", e))?;
+    let mut reader = BufReader::new((&mut *file).take(len));
+    let mut pos = start;
+
+    let header_len = read_varint_from(&mut reader, &mut pos)?.ok_or("Truncated CAR header")?;
+    if header_len > MAX_SECTION_BYTES {
+        return Err("CAR header too large".to_string());
+    }
+    let mut header = vec![0u8; header_len as usize];
+    reader.read_exact(&mut header).map_err(|_| "Truncated CAR header".to_string())?;
+    pos += header_len;
+    let roots = car_header_roots(&header)?;
+
+    let mut blocks = HashMap::new();
+    let mut section = Vec::new();
+    while let Some(section_len) = read_varint_from(&mut reader, &mut pos)? {
+        if section_len > MAX_SECTION_BYTES {
+            return Err(format!("CAR section of {} bytes is too large", section_len));
+        }
+        section.resize(section_len as usize, 0);
+        reader.read_exact(&mut section).map_err(|_| "Truncated CAR section".to_string())?;
+        let (cid, used) = Cid::from_bytes(&section)?;
+        cid.verify(&section[used..])?;
+        blocks.insert(cid.multihash.clone(), (cid, pos + used as u64, section.len() - used));
+        pos += section_len;
+    }
+    Ok(CarIndex { roots, blocks })
+}
+
+/// Like `file_from_car`, for a CAR spooled to disk: the file is written to `out` a block
+/// at a time. Returns its size.
+pub fn write_file_from_car(requested: &Cid, car: &mut std::fs::File, out: &mut dyn Write) -> Result<u64, String> {
+    let index = index_car(car)?;
+    check_roots(requested, &index.roots)?;
+    write_file(requested, &CarFile { file: car, index: &index }, out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::test_http::{leaf, parent, unixfs_file, Node};
+
+    fn blocks_of(nodes: &[&Node]) -> Blocks {
+        nodes
+            .iter()
+            .map(|(cid, block, _, _)| (cid.multihash.clone(), (cid.clone(), block.clone())))
+            .collect()
+    }
+
+    #[test]
+    fn assemble_file_rebuilds_multi_leaf_dags() {
+        let data: Vec<u8> = (0..600_000u32).map(|i| (i % 251) as u8).collect();
+        let (root, _, blocks) = unixfs_file(&data);
+        let blocks: Blocks = blocks.into_iter().map(|(cid, block)| (cid.multihash.clone(), (cid, block))).collect();
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(assemble_file(&root, &blocks).unwrap(), data);
+
+        let raw = raw_cid_for(b"raw leaf");
+        let blocks: Blocks = [(raw.multihash.clone(), (raw.clone(), b"raw leaf".to_vec()))].into_iter().collect();
+        assert_eq!(assemble_file(&raw, &blocks).unwrap(), b"raw leaf");
+    }
+
+    #[test]
+    fn assemble_file_stops_at_the_declared_size() {
+        let (cid, block, _, tsize) = leaf(&[7; 1000]);
+        // The root claims 5 bytes below it
+        let root = parent(&[(cid.clone(), block.clone(), 5, tsize)]);
+        let err = assemble_file(&root.0, &blocks_of(&[&(cid, block, 1000, tsize), &root])).unwrap_err();
+        assert!(err.contains("larger than the 5 bytes"), "{}", err);
+    }
+
+    #[test]
+    fn assemble_file_bounds_fan_out_onto_shared_blocks() {
+        let empty = leaf(b"");
+        let wide = parent(&vec![empty.clone(); MAX_DAG_LINKS + 1]);
+        let err = assemble_file(&wide.0, &blocks_of(&[&empty, &wide])).unwrap_err();
+        assert!(err.contains("links"), "{}", err);
+
+        // Three levels of 174 links onto one empty leaf, millions of visits for no data
+        let level1 = parent(&vec![empty.clone(); 174]);
+        let level2 = parent(&vec![level1.clone(); 174]);
+        let level3 = parent(&vec![level2.clone(); 174]);
+        let err = assemble_file(&level3.0, &blocks_of(&[&empty, &level1, &level2, &level3])).unwrap_err();
+        assert!(err.contains("more than"), "{}", err);
+    }
+
+    #[test]
+    fn car_size_budget_comes_from_the_root_block() {
+        let data = vec![1u8; 300_000];
+        let (root, _, mut blocks) = unixfs_file(&data);
+        blocks.reverse();
+        let car = write_car(std::slice::from_ref(&root), &blocks);
+        let root_block = &blocks[0].1;
+
+        assert_eq!(car_size_budget(&car[..CAR_PREFIX_BYTES], &root).unwrap(), None);
+        let budget = car_size_budget(&car, &root).unwrap().unwrap();
+        assert_eq!(budget, 2 * data.len() as u64 + CAR_SLACK_BYTES);
+        assert!(car.len() as u64 <= budget);
+        // Stops looking as soon as the root section is complete
+        let root_end = car.len() - blocks[1..].iter().map(|(c, b)| {
+            let mut section = Vec::new();
+            write_car_block(c, b, &mut section);
+            section.len()
+        }).sum::<usize>();
+        assert!(root_end > root_block.len());
+        assert_eq!(car_size_budget(&car[..root_end], &root).unwrap(), Some(budget));
+        assert_eq!(car_size_budget(&car[..root_end - 1], &root).unwrap(), None);
+
+        // A root block that does not hash to its CID is rejected
+        let mut tampered = car[..root_end].to_vec();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(car_size_budget(&tampered, &root).is_err());
+    }
+}